@@ -1,41 +1,305 @@
 extern crate rbchunk;
 use std::env;
+use std::io::{IsTerminal, Read, Write};
 use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "json")]
+use rbchunk::json::{Json, ToJson};
 
 fn print_help() {
     println!(
-        "Usage: rbchunk [-r] [-p (PSX)] [-w (wav)] [-s (swabaudio)]
+        "Usage: rbchunk [-r] [-p (PSX)] [-w (wav)] [-o (ogg)] [-m (mp3)] [-s (swabaudio)] [-e (surplus)] [-t (toc)] [-d (dat)] [-k (keep raw)] [-c (cue)] [-g (gaps)] [-i (index 0)] [-G (cdg)] [-M (merge data)] [-F (first data)] [-E (ecm)]
+         [-n <name template>] [--tracks <1,3-5>] [-l (list)] [--stdout] [--threads <n>] [--verify] [--checksums]
+         [--read-buffer <KiB>] [--write-buffer <KiB>] [--output-dir <dir>] [--force] [--skip-existing] [--verify-existing] [--sparse] [--subchannel]
+         [--partial-sector <pad|truncate|error>] [--sector-size <bytes>]
+         [--gain <dB>] [--normalize[=<dBFS>]] [--keep-emphasis] [--resample <Hz>] [--split-indexes] [--flac[=<level>]] [--gzip[=<level>]] [--zstd[=<level>]] [--replaygain] [--musicbrainz] [--trust-sector-modes] [--mpeg] [--stats] [--json]
          <image.bin> <image.cue> <basename>
+       rbchunk doctor [-s] <image.bin> <image.cue>
+       rbchunk inspect <image.bin> <image.cue>
+       rbchunk files <image.bin> <image.cue>
+       rbchunk extract-files [-v] [--file <path>]... <image.bin> <image.cue> <basename>
+       rbchunk extract-boot-images [-v] <image.bin> <image.cue> <basename>
+       rbchunk set [flags] <disc1.cue> <disc2.cue>...
+       rbchunk normalize [-v] [-E] <image.bin> <image.cue> <basename>
+       rbchunk author [-v] [-E] <data track.iso> [<audio .wav/.cdr>...] <basename>
 Example: rbchunk foo.bin foo.cue foo
          rbchunk -ws foo.cue
-  -r  Raw mode for MODE2/2352: write all 2352 bytes from offset 0 (VCD/MPEG)
-  -p  PSX mode for MODE2/2352: write 2336 bytes from offset 24
+         curl ... | rbchunk - foo.cue --track 1 --stdout > foo.iso
+         rbchunk set \"Foo (Disc 1).cue\" \"Foo (Disc 2).cue\"
+         rbchunk author foo.iso track02.wav foo
+           (with RBCHUNK_OUTPUT_DIR set, writes Foo.m3u there)
+  -r, --raw  Raw mode for MODE2/2352: write all 2352 bytes from offset 0 (VCD/MPEG)
+  -p, --psx  PSX mode for MODE2/2352: write 2336 bytes from offset 24
       (default MODE2/2352 mode writes 2048 bytes from offset 24)
-  -w  Output audio files in WAV format
-  -s  swabaudio: swap byte order in audio tracks
-    (try this if your audio comes up corrupted)"
+  --mpeg  With -r, demux the MPEG stream of Video CD tracks from their Form 2 sectors into
+      a playable .mpg instead
+  -w, --wav  Output audio files in WAV format
+  -o, --ogg  Encode audio files as Ogg Vorbis with oggenc, at quality RBCHUNK_OGG_QUALITY or 3
+  -m, --mp3  Encode audio files as MP3 with lame, at RBCHUNK_MP3_BITRATE kbps or 128
+  --flac  Encode audio files as FLAC with flac, at compression level 5 or as given with
+      --flac=8, with the CUE sheet and CD-TEXT of the track embedded
+  --gzip, --zstd  Compress the .iso, .cdr and .bin files to .gz with gzip or .zst with zstd,
+      at level 6 and 3 or as given with --gzip=9 or --zstd=19
+  -s, --swab  swabaudio: swap byte order in audio tracks
+    (try this if your audio comes up corrupted)
+  -e, --surplus  Export data found beyond the end of the CUE layout to a separate file
+      instead of leaving it in the last track
+  -t, --toc  Export the TOC and per-track hashes in DiscImageCreator format
+  -d, --dat  Write a dat fragment with the sizes and hashes of the output files
+  -k, --keep-raw  Keep a raw 2352 byte copy (.bin) of every data track next to the .iso
+  -c, --cue  Write a CUE sheet referencing the converted files, loadable by emulators
+  -g, --gaps  Write PREGAP/POSTGAP gaps as silence in the audio tracks they belong to
+  -i, --index0  Start audio tracks at INDEX 00, keeping the pregap stored in the image
+  -G, --cdg  Write the graphics of CD+G karaoke tracks to a .cdg file
+  -M, --merge-data  Merge all data tracks into a single <basename>.iso
+  -F, --first-data  Only write the first data track, to <basename>.iso
+  -E, --ecm  Compress raw data track outputs (-r, -p, -k, normalize) with ECM to .ecm files
+  -l, --list  List the tracks and the files they would be written to, without writing anything
+  --tracks  Only extract the listed tracks and ranges of tracks, e.g. 2 or 1,3-5
+  --output-dir  Write the outputs to this directory, the basename is relative to it
+  --force  Overwrite outputs that are already there, instead of failing before writing any
+  --skip-existing  Leave tracks whose files are all there in full, writing the rest
+  --verify-existing  Keep the SHA-1 of every output as it's finished, and with
+      --skip-existing only leave files that still match it
+  --sparse  Seek over blocks of zeroes instead of writing them, leaving sparse files
+  --partial-sector  Pad a last sector the BIN file ends partway into with zeroes (pad, the
+      default), leave it out (truncate) or fail (error)
+  --sector-size  Read the BIN file in sectors of 2048, 2336, 2352 or 2448 bytes (with subchannel),
+      whatever the CUE sheet says and the data looks like
+  --trust-sector-modes  Read tracks whose sectors are in another mode than the CUE sheet says,
+      like MODE2/2352 sectors of a MODE1/2352 track, in the mode of the sectors
+  --gain  Amplify audio tracks by this many dB, or attenuate them for negative ones
+  --normalize  Amplify every audio track so its loudest sample peaks at 0 dBFS, or as given
+      with --normalize=-1
+  --keep-emphasis  Leave the pre-emphasis of audio tracks flagged PRE in .wav, .ogg, .mp3 and
+      .flac outputs instead of filtering it out
+  --split-indexes  Write every INDEX of audio tracks to a file of its own, like foo02-02.wav
+  --resample  Write audio tracks at this sample rate, like 48000, in .wav, .ogg, .mp3 and
+      .flac outputs
+  --musicbrainz  Look audio discs up on MusicBrainz with curl, for titles the CUE sheet lacks
+  --replaygain  Tag .ogg, .mp3 and .flac outputs with their ReplayGain track and album gain,
+      or list it in a .replaygain.txt for .wav outputs
+  --subchannel  Write the subchannel of images with 2448 byte sectors to a .sub file per track
+  --threads  Write this many tracks at the same time, each read separately (faster on SSDs)
+  --read-buffer, --write-buffer  KiB read from the image or written to outputs at a time
+      (1024 by default, more can help on spinning disks and network shares)
+  --verify  Check the EDC/ECC, sync and address of raw data sectors, failing with the LBAs
+      of corrupt or misplaced ones
+  --checksums  Print the CRC32, MD5 and SHA-1 of every output, computed while writing it
+  --stats  Print the bytes read and written and the MiB/s of every track and the whole
+      conversion once it's done, to compare drives, shares and buffer sizes by
+  --json  Print the layout, output files and problems found, or what -l and doctor find, as
+      a JSON document on stdout instead of messages (checksums with --checksums or -d)
+  --stdout  Write the output of the one track picked with --track to stdout
+      (a BIN file named - is read from stdin, into memory since tracks are found by seeking)
+  -n, --name  Name track outputs after a template instead of {{base}}{{track:02}}.{{ext}}, e.g.
+      \"{{base}} - {{track:02}} {{title}}.{{ext}}\", also with {{performer}}, {{mode}},
+      {{serial}} of PlayStation games, {{disc}} of multi-disc sets and {{index}}
+  --file  Path of a file or directory in the data track for extract-files to write, like
+      /DATA or /SYSTEM.CNF, more than once for several, every file without it
+  -v, --verbose  Describe the tracks found and the files written
+  -h, --help  Print this help, -V, --version the version
+Defaults can be set with RBCHUNK_OUTPUT_DIR, RBCHUNK_NAME_TEMPLATE, RBCHUNK_AUDIO_FORMAT (wav/cdr/ogg/mp3/flac),
+RBCHUNK_OGG_QUALITY, RBCHUNK_MP3_BITRATE, RBCHUNK_WAV_RATE, RBCHUNK_WAV_CHANNELS,
+RBCHUNK_WAV_BITS, RBCHUNK_AUDIO_OFFSET, RBCHUNK_THREADS, RBCHUNK_GAPS (skip/silence/append),
+RBCHUNK_DATA_TRACKS (separate/merge/first), RBCHUNK_VERBOSE, RBCHUNK_RAW, RBCHUNK_PSX,
+RBCHUNK_SWAP_AUDIO and RBCHUNK_KEEP_PARTIAL"
     );
 }
 
-fn read_args() -> rbchunk::Args {
-    let mut options: rbchunk::Args = Default::default();
-    for arg in env::args().skip(1) {
-        if arg.starts_with('-') {
-            for c in arg.chars().skip(1) {
-                match c {
-                    'r' => options.raw = true,
-                    'p' => options.psx_truncate = true,
-                    'v' => options.verbose = true,
-                    'w' => options.to_wav = true,
-                    's' => options.swap_audo_bytes = true,
-                    _ => {
-                        if c != 'h' {
-                            eprintln!("Unknown flag: {}", c);
-                        }
-                        print_help();
-                        process::exit(0);
-                    }
-                }
+/// Bar of the track being written drawn on stderr, with its throughput and time left
+fn progress_bar() -> Option<rbchunk::ProgressCallback> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+    // Start of the track being written and when the bar was last drawn
+    let state: Mutex<Option<(u32, Instant, Instant)>> = Mutex::new(None);
+    Some(Arc::new(move |p: &rbchunk::Progress| {
+        let now = Instant::now();
+        let mut state = state.lock().unwrap();
+        let (start, drawn) = match *state {
+            Some((track, start, drawn)) if track == p.track => (start, drawn),
+            _ => (now, now - Duration::from_secs(1)),
+        };
+        // Redrawing more often than the eye can follow only slows the conversion down
+        if !p.is_done() && now - drawn < Duration::from_millis(100) {
+            return;
+        }
+        *state = Some((p.track, start, now));
+
+        const WIDTH: u64 = 30;
+        let fraction = |width: u64| match p.sectors_total {
+            0 => width,
+            total => p.sectors_done * width / total,
+        };
+        let filled = fraction(WIDTH) as usize;
+        let elapsed = (now - start).as_secs_f64();
+        let speed = match elapsed > 0.0 {
+            true => p.sectors_done as f64 * 2352.0 / elapsed,
+            false => 0.0,
+        };
+        let seconds = match (p.is_done(), speed > 0.0) {
+            (true, _) => elapsed,
+            (false, true) => (p.sectors_total - p.sectors_done) as f64 * 2352.0 / speed,
+            (false, false) => 0.0,
+        } as u64;
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\rTrack {:0>2} [{}{}] {:>3}% {:>7.1} MiB/s {} {}:{:0>2}\x1b[K",
+            p.track,
+            "#".repeat(filled),
+            "-".repeat(WIDTH as usize - filled),
+            fraction(100),
+            speed / 1024.0 / 1024.0,
+            if p.is_done() { "in " } else { "ETA" },
+            seconds / 60,
+            seconds % 60
+        );
+        if p.is_done() {
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+    }))
+}
+
+fn read_args(skip: usize) -> rbchunk::ConvertOptions {
+    // Flags are applied on top of the defaults from the environment
+    let mut options = match rbchunk::ConvertOptions::from_env() {
+        Ok(options) => options,
+        Err(err) => exit_with_error("Error in environment", err),
+    };
+    options.progress = progress_bar();
+    let mut args = env::args().skip(skip);
+    while let Some(arg) = args.next() {
+        let tracks = arg
+            .strip_prefix("--tracks")
+            .or_else(|| arg.strip_prefix("--track"));
+        if arg == "--stdout" || arg == "--stats" {
+            // Picked up by main, like -l
+        } else if arg == "--file" || arg.starts_with("--file=") {
+            // Picked up by extract-files, which takes it more than once
+            if arg == "--file" {
+                args.next();
+            }
+        } else if arg == "--json" {
+            if !cfg!(feature = "json") {
+                exit_with_error(
+                    "Error in arguments",
+                    std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "JSON output is not supported in this build",
+                    ),
+                );
+            }
+        } else if arg == "--force" {
+            options.existing = rbchunk::ExistingOutputs::Overwrite;
+        } else if arg == "--skip-existing" {
+            options.existing = rbchunk::ExistingOutputs::Skip;
+        } else if arg == "--verify-existing" {
+            options.verify_existing = true;
+        } else if arg == "--sparse" {
+            options.sparse = true;
+        } else if arg == "--subchannel" {
+            options.export_subcode = true;
+        } else if arg == "--verify" {
+            options.verify = true;
+        } else if arg == "--checksums" {
+            // Only listed along with the outputs
+            options.checksums = true;
+            options.verbose = true;
+        } else if let Some(value) = tracks {
+            options.tracks = match long_value(value, &mut args).as_deref().map(str::parse) {
+                Some(Ok(selection)) => Some(selection),
+                Some(Err(err)) => exit_with_error("Error in arguments", err),
+                None => usage_error(String::from("Missing tracks for --tracks")),
+            };
+        } else if let Some(value) = arg.strip_prefix("--threads") {
+            options.threads = number_value("--threads", value, &mut args);
+        } else if let Some(value) = arg.strip_prefix("--read-buffer") {
+            options.read_buffer =
+                Some(number_value("--read-buffer", value, &mut args).saturating_mul(1024));
+        } else if let Some(value) = arg.strip_prefix("--write-buffer") {
+            options.write_buffer =
+                Some(number_value("--write-buffer", value, &mut args).saturating_mul(1024));
+        } else if let Some(value) = arg.strip_prefix("--output-dir") {
+            match long_value(value, &mut args) {
+                Some(dir) => options.output_dir = dir,
+                None => usage_error(format!("Missing directory for {}", arg)),
+            }
+        } else if let Some(value) = arg.strip_prefix("--gain") {
+            let db = decibels("--gain", long_value(value, &mut args));
+            options.gain = Some(rbchunk::Gain::Decibels(db));
+        } else if arg == "--normalize" {
+            options.gain = Some(rbchunk::Gain::Normalize(0.0));
+        } else if let Some(value) = arg.strip_prefix("--normalize=") {
+            let db = decibels("--normalize", Some(String::from(value)));
+            options.gain = Some(rbchunk::Gain::Normalize(db));
+        } else if let Some(value) = arg.strip_prefix("--resample") {
+            options.resample = Some(number_value("--resample", value, &mut args) as u32);
+        } else if arg == "--gzip" {
+            options.gzip_level.get_or_insert(6);
+        } else if let Some(value) = arg.strip_prefix("--gzip=") {
+            match value.parse() {
+                Ok(level) => options.gzip_level = Some(level),
+                Err(err) => usage_error(format!("Invalid number for --gzip: {}", err)),
+            }
+        } else if arg == "--zstd" {
+            options.zstd_level.get_or_insert(3);
+        } else if let Some(value) = arg.strip_prefix("--zstd=") {
+            match value.parse() {
+                Ok(level) => options.zstd_level = Some(level),
+                Err(err) => usage_error(format!("Invalid number for --zstd: {}", err)),
+            }
+        } else if arg == "--flac" {
+            options.flac_compression.get_or_insert(5);
+        } else if let Some(value) = arg.strip_prefix("--flac=") {
+            match value.parse() {
+                Ok(level) => options.flac_compression = Some(level),
+                Err(err) => usage_error(format!("Invalid number for --flac: {}", err)),
+            }
+        } else if arg == "--mpeg" {
+            options.demux_mpeg = true;
+        } else if arg == "--trust-sector-modes" {
+            options.trust_sector_modes = true;
+        } else if arg == "--musicbrainz" {
+            options.musicbrainz = true;
+        } else if arg == "--replaygain" {
+            options.replaygain = true;
+        } else if arg == "--split-indexes" {
+            options.split_indexes = true;
+        } else if arg == "--keep-emphasis" {
+            options.keep_emphasis = true;
+        } else if let Some(value) = arg.strip_prefix("--sector-size") {
+            options.sector_size = Some(number_value("--sector-size", value, &mut args) as u64);
+        } else if let Some(value) = arg.strip_prefix("--partial-sector") {
+            options.partial_sector = match long_value(value, &mut args).as_deref() {
+                Some("pad") => rbchunk::PartialSector::Pad,
+                Some("truncate") => rbchunk::PartialSector::Truncate,
+                Some("error") => rbchunk::PartialSector::Error,
+                Some(policy) => usage_error(format!("Unknown --partial-sector {}", policy)),
+                None => usage_error(String::from("Missing policy for --partial-sector")),
+            };
+        } else if let Some(option) = arg.strip_prefix("--") {
+            // Long names of the single letter flags, `--name` taking its template along
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (option, None),
+            };
+            let flag = match LONG_FLAGS.iter().find(|(long, _)| *long == name) {
+                Some((_, flag)) => *flag,
+                None => usage_error(format!("Unknown option {}", arg)),
+            };
+            match (flag, value) {
+                ('n', Some(template)) => options.name_template = Some(String::from(template)),
+                (_, Some(_)) => usage_error(format!("--{} doesn't take a value", name)),
+                (flag, None) => apply_flag(flag, &mut options, &mut args),
+            }
+        } else if arg.starts_with('-') && arg != "-" {
+            for flag in arg.chars().skip(1) {
+                apply_flag(flag, &mut options, &mut args);
             }
         } else if options.bin_file.is_empty() {
             options.bin_file = arg;
@@ -45,29 +309,509 @@ fn read_args() -> rbchunk::Args {
             options.output_name = arg;
         }
     }
+    if json() {
+        // The library reports on stdout, where the document goes
+        options.verbose = false;
+    }
 
     options
 }
 
+/// Long names of the single letter flags
+const LONG_FLAGS: [(&str, char); 22] = [
+    ("raw", 'r'),
+    ("psx", 'p'),
+    ("verbose", 'v'),
+    ("wav", 'w'),
+    ("ogg", 'o'),
+    ("mp3", 'm'),
+    ("swab", 's'),
+    ("surplus", 'e'),
+    ("toc", 't'),
+    ("dat", 'd'),
+    ("keep-raw", 'k'),
+    ("ecm", 'E'),
+    ("cue", 'c'),
+    ("gaps", 'g'),
+    ("index0", 'i'),
+    ("cdg", 'G'),
+    ("merge-data", 'M'),
+    ("first-data", 'F'),
+    ("list", 'l'),
+    ("name", 'n'),
+    ("help", 'h'),
+    ("version", 'V'),
+];
+
+/// Sets what the single letter `flag` stands for, taking its value from `args` if it has one
+fn apply_flag(
+    flag: char,
+    options: &mut rbchunk::ConvertOptions,
+    args: &mut impl Iterator<Item = String>,
+) {
+    match flag {
+        'r' => options.raw = true,
+        'p' => options.psx_truncate = true,
+        'v' => options.verbose = true,
+        'w' => options.to_wav = true,
+        'o' => {
+            options.ogg_quality.get_or_insert(3.0);
+        }
+        'm' => {
+            options.mp3_bitrate.get_or_insert(128);
+        }
+        's' => options.swap_audo_bytes = true,
+        'e' => options.export_surplus = true,
+        't' => options.export_toc = true,
+        'd' => options.export_dat = true,
+        'k' => options.keep_raw = true,
+        'E' => options.ecm = true,
+        'c' => options.export_cue = true,
+        'g' => options.gap_policy = rbchunk::GapPolicy::Silence,
+        'i' => options.include_index0 = true,
+        'G' => options.export_cdg = true,
+        'M' => options.data_tracks = rbchunk::DataTracks::Merge,
+        'F' => options.data_tracks = rbchunk::DataTracks::First,
+        // Picked up by main, the conversion itself is the same
+        'l' | 'h' | 'V' => {}
+        'n' => match args.next() {
+            Some(template) => options.name_template = Some(template),
+            None => usage_error(String::from("Missing template for -n")),
+        },
+        _ => usage_error(format!("Unknown flag -{}", flag)),
+    }
+}
+
+/// Exits on arguments that make no sense, pointing to the usage
+fn usage_error(message: String) -> ! {
+    let message = format!("{}, see rbchunk --help", message);
+    exit_with_error(
+        "Error in arguments",
+        rbchunk::ErrorCode::InvalidArguments.error(message),
+    )
+}
+
+/// Value of a long option like `--tracks 2` or `--tracks=2`, from `rest` following its name
+fn long_value(rest: &str, args: &mut impl Iterator<Item = String>) -> Option<String> {
+    match rest.strip_prefix('=') {
+        Some(value) => Some(String::from(value)),
+        None if rest.is_empty() => args.next(),
+        None => None,
+    }
+}
+
+/// Decibels given to the long option `option`, exiting if they're missing or not a number
+fn decibels(option: &str, value: Option<String>) -> f32 {
+    match value.map(|v| v.parse()) {
+        Some(Ok(db)) => db,
+        Some(Err(err)) => usage_error(format!("Invalid decibels for {}: {}", option, err)),
+        None => usage_error(format!("Missing decibels for {}", option)),
+    }
+}
+
+/// Number given to the long option `option`, exiting if it's missing or not a number
+fn number_value(option: &str, rest: &str, args: &mut impl Iterator<Item = String>) -> usize {
+    match long_value(rest, args).map(|v| v.parse()) {
+        Some(Ok(number)) => number,
+        Some(Err(err)) => usage_error(format!("Invalid number for {}: {}", option, err)),
+        None => usage_error(format!("Missing number for {}", option)),
+    }
+}
+
+/// Exits with the stable code of the error as status, so scripts don't have to parse messages
+fn exit_with_error(context: &str, err: std::io::Error) -> ! {
+    let code = rbchunk::ErrorCode::of(&err);
+    #[cfg(feature = "json")]
+    if json() {
+        let error = Json::object([
+            ("context", context.to_json()),
+            ("code", code.as_str().to_json()),
+            ("number", (code.number() as i64).to_json()),
+            ("message", err.to_string().to_json()),
+        ]);
+        println!("{:#}", Json::object([("error", error)]));
+        process::exit(code.number());
+    }
+    match piped() {
+        true => eprintln!("{} ({}): {}", context, code, err),
+        false => println!("{} ({}): {}", context, code, err),
+    }
+    process::exit(code.number());
+}
+
+/// Whether the output goes to stdout, leaving messages to stderr
+fn piped() -> bool {
+    env::args().skip(1).any(|a| a == "--stdout")
+}
+
+/// Whether results are printed to stdout as a JSON document, leaving messages to stderr
+///
+/// A track written to stdout with `--stdout` leaves no room for the document.
+fn json() -> bool {
+    cfg!(feature = "json") && !piped() && env::args().skip(1).any(|a| a == "--json")
+}
+
+/// Prints a message meant for people, to stderr when stdout is taken
+fn message(text: &str) {
+    match piped() || json() {
+        true => eprintln!("{}", text),
+        false => println!("{}", text),
+    }
+}
+
+/// Prints a JSON object of `members` as the only thing on stdout
+#[cfg(feature = "json")]
+fn print_json<'a>(members: impl IntoIterator<Item = (&'a str, Json)>) {
+    println!("{:#}", Json::object(members));
+}
+
+/// Converts the image, printing its layout, the outputs and what doctor finds as JSON
+#[cfg(feature = "json")]
+fn convert_json(args: rbchunk::ConvertOptions) -> ! {
+    if args.bin_file == "-" {
+        exit_with_error(
+            "Error in arguments",
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--json can't report on a BIN file read from stdin",
+            ),
+        );
+    }
+    let report = match rbchunk::convert_with_report(args.clone()) {
+        Ok(report) => report,
+        Err(err) => exit_with_error("Error on conversion", err),
+    };
+    let warnings = match rbchunk::doctor(args) {
+        Ok(diagnostics) => diagnostics,
+        Err(err) => exit_with_error("Error on diagnosis", err),
+    };
+    print_json([
+        ("format", report.metadata.format.to_json()),
+        ("metadata", report.metadata.to_json()),
+        ("tracks", report.tracks.to_json()),
+        ("outputs", report.outputs.to_json()),
+        ("stats", report.stats.to_json()),
+        ("warnings", warnings.to_json()),
+    ]);
+    process::exit(0);
+}
+
+/// Converts the image, printing how many bytes every track read and wrote and how fast
+fn convert_stats(args: rbchunk::ConvertOptions) -> ! {
+    if args.bin_file == "-" {
+        usage_error(String::from(
+            "--stats can't report on a BIN file read from stdin",
+        ));
+    }
+    match rbchunk::convert_with_report(args) {
+        Ok(report) => {
+            message("Conversion complete!");
+            message(report.stats.to_string().trim_end());
+        }
+        Err(err) => exit_with_error("Error on conversion", err),
+    }
+    process::exit(0);
+}
+
+/// Passes the only output of a conversion on to stdout
+#[derive(Default)]
+struct StdoutSink {
+    created: Mutex<Option<String>>,
+}
+
+impl rbchunk::OutputSink for StdoutSink {
+    fn create(&self, filename: &str) -> std::io::Result<Box<dyn Write>> {
+        let mut created = self.created.lock().unwrap();
+        if let Some(first) = created.as_ref() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("only {} can go to stdout, not {} as well", first, filename),
+            ));
+        }
+        *created = Some(String::from(filename));
+        Ok(Box::new(std::io::stdout()))
+    }
+
+    // What was piped already can't be taken back
+    fn remove(&self, _filename: &str) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Converts the BIN piped to stdin, read whole first since tracks are located by seeking
+fn convert_stdin(args: rbchunk::ConvertOptions) -> std::io::Result<()> {
+    if args.cue_file.is_empty() {
+        exit_with_error(
+            "Error in arguments",
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a CUE sheet is needed to read the BIN file from stdin",
+            ),
+        );
+    }
+    let cue = rbchunk::decode_sheet(&std::fs::read(&args.cue_file)?);
+    let mut bin = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut bin)?;
+    rbchunk::convert_reader(args, &cue, std::io::Cursor::new(bin))
+}
+
+/// Whether `arg` is a group of flags like `-vn` including `flag`, or its long name
+fn is_flag(arg: &str, flag: char) -> bool {
+    match arg.strip_prefix("--") {
+        Some(name) => LONG_FLAGS.contains(&(name, flag)),
+        None => arg.starts_with('-') && arg.contains(flag),
+    }
+}
+
+/// Whether a group of flags like `-vn` is followed by the value of one of them
+fn takes_value(arg: &str) -> bool {
+    [
+        "--tracks",
+        "--track",
+        "--threads",
+        "--read-buffer",
+        "--write-buffer",
+        "--output-dir",
+        "--partial-sector",
+        "--sector-size",
+        "--gain",
+        "--resample",
+        "--file",
+    ]
+    .contains(&arg)
+        || is_flag(arg, 'n')
+}
+
+/// Arguments of a subcommand that aren't flags or their values
+fn positionals() -> Vec<String> {
+    let args: Vec<String> = env::args().skip(2).collect();
+    args.iter()
+        .enumerate()
+        .filter(|(i, a)| {
+            let value = i.checked_sub(1).is_some_and(|p| takes_value(&args[p]));
+            !(a.starts_with('-') || value)
+        })
+        .map(|(_, a)| a.clone())
+        .collect()
+}
+
+fn convert_set() -> ! {
+    let images = positionals();
+    let discs = images
+        .into_iter()
+        .map(|image| {
+            // Every disc gets the same flags, but is named after its own image
+            let mut options = read_args(2);
+            options.bin_file = image;
+            options.cue_file.clear();
+            options.output_name.clear();
+            options
+        })
+        .collect();
+    match rbchunk::convert_set(discs) {
+        #[cfg(feature = "json")]
+        Ok(playlist) if json() => print_json([("outputs", vec![playlist].to_json())]),
+        Ok(playlist) => println!("Conversion complete! Playlist: {}", playlist.filename),
+        Err(err) => exit_with_error("Error on conversion", err),
+    }
+    process::exit(0);
+}
+
+fn author() -> ! {
+    let mut files = positionals();
+    if files.len() < 2 {
+        usage_error(String::from(
+            "author needs a data track and the basename of the outputs",
+        ));
+    }
+    let mut options = read_args(2);
+    options.output_name = files.pop().unwrap();
+    let iso = files.remove(0);
+    match rbchunk::author(options, &iso, &files) {
+        Ok(()) => message("Authoring complete!"),
+        Err(err) => exit_with_error("Error on authoring", err),
+    }
+    process::exit(0);
+}
+
+fn doctor(args: rbchunk::ConvertOptions) -> ! {
+    match rbchunk::doctor(args) {
+        #[cfg(feature = "json")]
+        Ok(diagnostics) if json() => print_json([("warnings", diagnostics.to_json())]),
+        Ok(diagnostics) if diagnostics.is_empty() => println!("No problems found"),
+        Ok(diagnostics) => {
+            for diagnostic in diagnostics {
+                println!("{}", diagnostic);
+            }
+        }
+        Err(err) => exit_with_error("Error on diagnosis", err),
+    }
+    process::exit(0);
+}
+
+fn inspect(args: rbchunk::ConvertOptions) -> ! {
+    match rbchunk::inspect(args) {
+        #[cfg(feature = "json")]
+        Ok(volumes) if json() => print_json([("volumes", volumes.to_json())]),
+        Ok(volumes) if volumes.is_empty() => println!("No ISO9660 volumes found"),
+        Ok(volumes) => {
+            for volume in volumes {
+                println!("{}", volume);
+                for image in &volume.boot_images {
+                    println!("{}", image);
+                }
+            }
+        }
+        Err(err) => exit_with_error("Error on inspection", err),
+    }
+    process::exit(0);
+}
+
+fn files(args: rbchunk::ConvertOptions) -> ! {
+    match rbchunk::list_files(args) {
+        #[cfg(feature = "json")]
+        Ok(files) if json() => print_json([("files", files.to_json())]),
+        Ok(files) if files.is_empty() => println!("No ISO9660 files found"),
+        Ok(files) => {
+            for file in files {
+                match file.directory {
+                    true => println!("{:>2}  {:>12}  {}/", file.track, "", file.path),
+                    false => println!("{:>2}  {:>12}  {}", file.track, file.size, file.path),
+                }
+            }
+        }
+        Err(err) => exit_with_error("Error on listing files", err),
+    }
+    process::exit(0);
+}
+
+/// Writes the files of the data tracks, or the ones picked with `--file`, to `<basename>NN/`
+fn extract_files(args: rbchunk::ConvertOptions) -> ! {
+    let args_list: Vec<String> = env::args().collect();
+    let paths: Vec<String> = args_list
+        .iter()
+        .enumerate()
+        .filter_map(|(i, arg)| match arg.strip_prefix("--file") {
+            Some("") => args_list.get(i + 1).cloned(),
+            Some(rest) => rest.strip_prefix('=').map(String::from),
+            None => None,
+        })
+        .collect();
+    match rbchunk::extract_files(args, &paths) {
+        #[cfg(feature = "json")]
+        Ok(outputs) if json() => print_json([("outputs", outputs.to_json())]),
+        Ok(outputs) => message(&format!(
+            "Extraction complete! Files written: {}",
+            outputs.len()
+        )),
+        Err(err) => exit_with_error("Error on extracting files", err),
+    }
+    process::exit(0);
+}
+
+/// Writes the El Torito boot images of the data tracks to `<basename>NN-bootN.img`
+fn extract_boot_images(args: rbchunk::ConvertOptions) -> ! {
+    match rbchunk::extract_boot_images(args) {
+        #[cfg(feature = "json")]
+        Ok(outputs) if json() => print_json([("outputs", outputs.to_json())]),
+        Ok(outputs) if outputs.is_empty() => println!("No El Torito boot images found"),
+        Ok(outputs) => message(&format!(
+            "Extraction complete! Boot images written: {}",
+            outputs.len()
+        )),
+        Err(err) => exit_with_error("Error on extracting boot images", err),
+    }
+    process::exit(0);
+}
+
 fn main() {
-    println!(
-        "rbchunk v2.0.0
+    if env::args().skip(1).any(|a| is_flag(&a, 'V')) {
+        println!("rbchunk {}", env!("CARGO_PKG_VERSION"));
+        process::exit(0);
+    }
+    let banner = concat!(
+        "rbchunk v",
+        env!("CARGO_PKG_VERSION"),
+        "
 https://github.com/luxtorpeda-dev/rbchunk
 Based on bchunk by Heikki Hannikainen <hessu@hes.iki.fi>\n"
     );
+    message(banner);
+    // Warnings went to stderr and the rest to stdout before the library had a logger
+    rbchunk::set_logger(|level, text: &str| match level {
+        rbchunk::Level::Warn => eprintln!("{}", text),
+        _ => message(text),
+    });
 
     let args = env::args();
-    if args.len() == 1 {
+    if args.len() == 1 || env::args().skip(1).any(|a| is_flag(&a, 'h')) {
         print_help();
         process::exit(0);
     }
 
-    let args = read_args();
-    match rbchunk::convert(args) {
-        Ok(()) => println!("Conversion complete!"),
-        Err(err) => {
-            println!("Error on conversion: {}", err);
-            process::exit(1);
+    if env::args().nth(1).is_some_and(|a| a == "doctor") {
+        doctor(read_args(2));
+    }
+    if env::args().nth(1).is_some_and(|a| a == "inspect") {
+        inspect(read_args(2));
+    }
+    if env::args().nth(1).is_some_and(|a| a == "files") {
+        files(read_args(2));
+    }
+    if env::args().nth(1).is_some_and(|a| a == "extract-files") {
+        extract_files(read_args(2));
+    }
+    if env::args()
+        .nth(1)
+        .is_some_and(|a| a == "extract-boot-images")
+    {
+        extract_boot_images(read_args(2));
+    }
+    if env::args().nth(1).is_some_and(|a| a == "set") {
+        convert_set();
+    }
+    if env::args().nth(1).is_some_and(|a| a == "author") {
+        author();
+    }
+    if env::args().nth(1).is_some_and(|a| a == "normalize") {
+        match rbchunk::normalize(read_args(2)) {
+            Ok(()) => message("Normalization complete!"),
+            Err(err) => exit_with_error("Error on normalization", err),
+        }
+        process::exit(0);
+    }
+
+    let mut args = read_args(1);
+    if env::args().skip(1).any(|a| is_flag(&a, 'l')) {
+        match rbchunk::plan(args) {
+            #[cfg(feature = "json")]
+            Ok(plan) if json() => print_json([("plan", plan.to_json())]),
+            Ok(plan) => print!("{}", plan),
+            Err(err) => exit_with_error("Error on listing", err),
         }
+        process::exit(0);
+    }
+    #[cfg(feature = "json")]
+    if json() {
+        convert_json(args);
+    }
+    if piped() {
+        if args.tracks.is_none() {
+            usage_error(String::from(
+                "--stdout needs the track to write picked with --track",
+            ));
+        }
+        args.output_sink = Some(Arc::new(StdoutSink::default()));
+    }
+    if env::args().skip(1).any(|a| a == "--stats") {
+        convert_stats(args);
+    }
+    let result = match args.bin_file == "-" {
+        true => convert_stdin(args),
+        false => rbchunk::convert(args),
+    };
+    match result {
+        Ok(()) => message("Conversion complete!"),
+        Err(err) => exit_with_error("Error on conversion", err),
     }
 }
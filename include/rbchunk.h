@@ -0,0 +1,73 @@
+/*
+ * C interface of rbchunk, the library being built with the `ffi` feature:
+ *
+ *   cargo rustc --lib --release --features ffi --crate-type cdylib
+ */
+
+#ifndef RBCHUNK_H
+#define RBCHUNK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Version of rbchunk_options, bumped whenever fields are added to it:
+ *   1: the fields up to user_data
+ *   2: existing
+ */
+#define RBCHUNK_OPTIONS_VERSION 2
+
+/*
+ * Called with the track being written, how many of its sectors are done and how many
+ * there are, along with the user_data of the options. With more than one thread it may
+ * be called from any of them.
+ */
+typedef void (*rbchunk_progress)(uint32_t track, uint64_t sectors_done,
+                                 uint64_t sectors_total, void *user_data);
+
+/*
+ * Settings of a conversion, named like the fields of ConvertOptions. Zero them before
+ * setting any, NULL strings and zeroes are the defaults. struct_size must be set to
+ * sizeof(rbchunk_options): the library only reads the fields within it, so programs built
+ * against an older header get the defaults for fields added since.
+ */
+typedef struct rbchunk_options {
+    size_t struct_size;
+    const char *bin_file;
+    const char *cue_file;
+    const char *output_name;
+    const char *output_dir;
+    int verbose;
+    int psx_truncate;
+    int raw;
+    int swap_audio_bytes;
+    int to_wav;
+    uint32_t threads;
+    rbchunk_progress progress;
+    void *user_data;
+    /*
+     * Since version 2, 0 fails on outputs that are already there, 1 overwrites them and 2
+     * skips complete tracks. Read as 0 when struct_size ends before it
+     */
+    int existing;
+} rbchunk_options;
+
+/*
+ * Converts the image, returning 0 or the number of the error code, the same as the exit
+ * status of the command line tool. The message of the error is copied to error_buf,
+ * NUL terminated and cut to error_buf_len bytes, unless it's NULL.
+ */
+int rbchunk_convert(const rbchunk_options *options, char *error_buf, size_t error_buf_len);
+
+/* Version of the library, e.g. "2.1.0" */
+const char *rbchunk_version(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
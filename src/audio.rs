@@ -0,0 +1,328 @@
+//! Processing the samples of audio tracks on their way to the output
+
+use std::io;
+
+use crate::disc::Disc;
+use crate::error::ErrorCode;
+use crate::track::Track;
+use crate::{ConvertOptions, SECTOR_SIZE};
+
+/// Level of the audio tracks written, for soundtracks mastered too quietly
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gain {
+    /// Amplify every sample by this many decibels, or attenuate for negative ones. Samples
+    /// beyond full scale are clipped.
+    Decibels(f32),
+    /// Amplify or attenuate every track on its own, so its loudest sample peaks at this many
+    /// decibels relative to full scale, 0 or less
+    Normalize(f32),
+}
+
+impl Gain {
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        match *self {
+            Gain::Decibels(db) if db.is_finite() => Ok(()),
+            Gain::Normalize(db) if db.is_finite() && db <= 0.0 => Ok(()),
+            Gain::Decibels(db) => Err(ErrorCode::InvalidArguments
+                .error(format!("A gain of {} dB isn't a level to amplify by", db))),
+            Gain::Normalize(db) => Err(ErrorCode::InvalidArguments.error(format!(
+                "Audio can only be normalized to 0 dBFS or less, not {}",
+                db
+            ))),
+        }
+    }
+}
+
+fn linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Multiplies the 16 bit little endian samples of audio sectors by a factor of the `Gain`
+pub(crate) struct Amplifier {
+    factor: f32,
+    /// Samples that ended up beyond full scale
+    pub(crate) clipped: u64,
+}
+
+impl Amplifier {
+    /// Amplifier of the sectors `write_to_file` writes of `track`, `None` if they stay as
+    /// they are
+    ///
+    /// Normalizing reads every sector of the track up front, to find its peak.
+    // 0 dB is compared in a guard rather than matched, floats don't belong in patterns
+    #[allow(clippy::redundant_guards)]
+    pub(crate) fn new(
+        disc: &mut dyn Disc,
+        track: &Track,
+        a: &ConvertOptions,
+    ) -> io::Result<Option<Amplifier>> {
+        let factor = match a.gain {
+            Some(_) if !track.is_audio() => return Ok(None),
+            None => return Ok(None),
+            Some(Gain::Decibels(db)) if db == 0.0 => return Ok(None),
+            Some(Gain::Decibels(db)) => linear(db),
+            Some(Gain::Normalize(db)) => match peak(disc, track, a)? {
+                // Silence stays silent
+                0 => return Ok(None),
+                peak => linear(db) * i16::MAX as f32 / peak as f32,
+            },
+        };
+        Ok(Some(Amplifier { factor, clipped: 0 }))
+    }
+
+    pub(crate) fn apply(&mut self, sector: &mut [u8]) {
+        for sample in sector.chunks_exact_mut(2) {
+            let amplified = i16::from_le_bytes([sample[0], sample[1]]) as f32 * self.factor;
+            let rounded = amplified.round();
+            if rounded > i16::MAX as f32 || rounded < i16::MIN as f32 {
+                self.clipped += 1;
+            }
+            // Casts saturate, which is the clipping
+            sample.copy_from_slice(&(rounded as i16).to_le_bytes());
+        }
+    }
+}
+
+/// Filter undoing the 50/15 µs pre-emphasis of audio tracks flagged `PRE`
+///
+/// The high shelf SoX's `deemph` effect uses for 44.1 kHz, a biquad that follows the ideal
+/// response to within a tenth of a decibel, run over the left and right channel each.
+pub(crate) struct Deemphasis {
+    b: [f64; 3],
+    a: [f64; 2],
+    /// Last two inputs and outputs of each channel
+    history: [[f64; 4]; 2],
+}
+
+impl Deemphasis {
+    const CORNER: f64 = 5283.0;
+    const SLOPE: f64 = 0.4845;
+    const GAIN: f64 = -9.477;
+
+    /// De-emphasis of the sectors `write_to_file` writes of `track`, `None` if it has no
+    /// pre-emphasis to undo
+    pub(crate) fn new(track: &Track, a: &ConvertOptions) -> Option<Deemphasis> {
+        if !track.deemphasized(a) {
+            return None;
+        }
+        // Shelf of the Audio EQ Cookbook
+        let gain = 10f64.powf(Deemphasis::GAIN / 40.0);
+        let w0 = 2.0 * std::f64::consts::PI * Deemphasis::CORNER / 44100.0;
+        let (sin, cos) = w0.sin_cos();
+        let alpha =
+            sin / 2.0 * ((gain + 1.0 / gain) * (1.0 / Deemphasis::SLOPE - 1.0) + 2.0).sqrt();
+        let shelf = 2.0 * gain.sqrt() * alpha;
+        let a0 = (gain + 1.0) - (gain - 1.0) * cos + shelf;
+        let b = [
+            gain * ((gain + 1.0) + (gain - 1.0) * cos + shelf) / a0,
+            -2.0 * gain * ((gain - 1.0) + (gain + 1.0) * cos) / a0,
+            gain * ((gain + 1.0) + (gain - 1.0) * cos - shelf) / a0,
+        ];
+        let a = [
+            2.0 * ((gain - 1.0) - (gain + 1.0) * cos) / a0,
+            ((gain + 1.0) - (gain - 1.0) * cos - shelf) / a0,
+        ];
+        Some(Deemphasis {
+            b,
+            a,
+            history: [[0.0; 4]; 2],
+        })
+    }
+
+    /// Filters the sector, carrying on from the one before it
+    pub(crate) fn apply(&mut self, sector: &mut [u8]) {
+        // Frames of a sector are whole, so every sector starts on the left channel
+        for (n, sample) in sector.chunks_exact_mut(2).enumerate() {
+            let [x1, x2, y1, y2] = &mut self.history[n % 2];
+            let x = i16::from_le_bytes([sample[0], sample[1]]) as f64;
+            let y = self.b[0] * x + self.b[1] * *x1 + self.b[2] * *x2
+                - self.a[0] * *y1
+                - self.a[1] * *y2;
+            (*x2, *x1, *y2, *y1) = (*x1, x, *y1, y);
+            // Casts saturate, for the rare overshoot of a full scale sample
+            sample.copy_from_slice(&(y.round() as i16).to_le_bytes());
+        }
+    }
+}
+
+/// Largest magnitude of the samples written of `track`, after their de-emphasis
+fn peak(disc: &mut dyn Disc, track: &Track, a: &ConvertOptions) -> io::Result<u32> {
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    let mut deemphasis = Deemphasis::new(track, a);
+    let mut peak = 0;
+    for lba in track.first_sector(a)..=track.stop_sector() {
+        a.check_cancelled()?;
+        if let Err(e) = disc.read_sector(lba, &mut sector) {
+            return Err(ErrorCode::ReadFailed.wrap(
+                &e,
+                format!("Could not read track {}: {}", track.number(), e),
+            ));
+        }
+        if let Some(deemphasis) = &mut deemphasis {
+            deemphasis.apply(&mut sector);
+        }
+        for sample in sector.chunks_exact(2) {
+            peak = peak.max(i16::from_le_bytes([sample[0], sample[1]]).unsigned_abs() as u32);
+        }
+    }
+    Ok(peak)
+}
+
+/// Sample rate of CD audio
+pub(crate) const CD_RATE: u32 = 44100;
+
+/// Frames of audio at `rate` that `frames` of CD audio are resampled to
+pub(crate) fn resampled_frames(frames: u64, rate: u32) -> u64 {
+    frames * rate as u64 / CD_RATE as u64
+}
+
+/// Converts the 16 bit stereo CD audio of a track to another sample rate, a sector at a time
+///
+/// Every output sample is interpolated from the input samples around it with a windowed sinc
+/// kernel, cut off below the lower of the two Nyquist frequencies so nothing aliases. Input
+/// before the start and after the end of the track is taken for silence.
+pub(crate) struct Resampler {
+    rate: u32,
+    /// Cutoff relative to the Nyquist frequency of CD audio
+    cutoff: f64,
+    /// Input frames either side of an output frame that go into it
+    reach: i64,
+    /// One side of the kernel, `OVERSAMPLING` values per zero crossing
+    kernel: Vec<f64>,
+    /// Input frames from `first` on, which outputs still to come depend on
+    input: Vec<[f64; 2]>,
+    first: u64,
+    pushed: u64,
+    written: u64,
+    output: Vec<u8>,
+}
+
+impl Resampler {
+    /// Zero crossings of the kernel either side of its center
+    const ZEROS: usize = 32;
+    const OVERSAMPLING: usize = 256;
+    /// Kaiser window shape, close to 90 dB of stopband attenuation
+    const BETA: f64 = 8.6;
+    /// Share of the Nyquist frequency that passes, leaving the rest for the filter to roll off
+    const PASSBAND: f64 = 0.91;
+
+    /// Resampler of the audio `write_to_file` writes of `track`, `None` if it stays at the
+    /// rate of CD audio
+    pub(crate) fn new(track: &Track, a: &ConvertOptions) -> Option<Resampler> {
+        let rate = track.resampled_rate(a)?;
+        let cutoff = Resampler::PASSBAND * (rate as f64 / CD_RATE as f64).min(1.0);
+        let length = Resampler::ZEROS * Resampler::OVERSAMPLING;
+        let kernel = (0..=length + 1)
+            .map(|n| {
+                let x = n as f64 / Resampler::OVERSAMPLING as f64;
+                let window = 1.0 - (x / Resampler::ZEROS as f64).powi(2);
+                match n {
+                    0 => 1.0,
+                    _ if window <= 0.0 => 0.0,
+                    _ => {
+                        let sinc = (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x);
+                        sinc * bessel_i0(Resampler::BETA * window.sqrt())
+                            / bessel_i0(Resampler::BETA)
+                    }
+                }
+            })
+            .collect();
+        Some(Resampler {
+            rate,
+            cutoff,
+            reach: (Resampler::ZEROS as f64 / cutoff).ceil() as i64,
+            kernel,
+            input: Vec::new(),
+            first: 0,
+            pushed: 0,
+            written: 0,
+            output: Vec::new(),
+        })
+    }
+
+    /// Resamples the 16 bit little endian stereo `samples`, returning the output frames they
+    /// complete
+    pub(crate) fn push(&mut self, samples: &[u8]) -> &mut [u8] {
+        for frame in samples.chunks_exact(4) {
+            self.input.push([
+                i16::from_le_bytes([frame[0], frame[1]]) as f64,
+                i16::from_le_bytes([frame[2], frame[3]]) as f64,
+            ]);
+        }
+        self.pushed += samples.len() as u64 / 4;
+        self.output.clear();
+        while (self.center(self.written) + self.reach) < self.pushed as i64 {
+            self.write_frame();
+        }
+        // Frames before the reach of the next output aren't needed anymore
+        let needed = (self.center(self.written) - self.reach).max(0) as u64;
+        if needed > self.first {
+            let done = (needed - self.first).min(self.input.len() as u64);
+            self.input.drain(..done as usize);
+            self.first += done;
+        }
+        &mut self.output
+    }
+
+    /// Output frames left at the end of the track, once all of it has been pushed
+    pub(crate) fn finish(&mut self) -> &mut [u8] {
+        self.output.clear();
+        while self.written < resampled_frames(self.pushed, self.rate) {
+            self.write_frame();
+        }
+        &mut self.output
+    }
+
+    /// Input frame at or just before output frame `n`
+    fn center(&self, n: u64) -> i64 {
+        (n * CD_RATE as u64 / self.rate as u64) as i64
+    }
+
+    fn write_frame(&mut self) {
+        let position = self.written * CD_RATE as u64;
+        let center = self.center(self.written);
+        let offset = (position % self.rate as u64) as f64 / self.rate as f64;
+        let mut sum = [0.0; 2];
+        let mut weights = 0.0;
+        for n in center - self.reach + 1..=center + self.reach {
+            let x = ((center - n) as f64 + offset).abs() * self.cutoff;
+            let weight = self.kernel_at(x);
+            weights += weight;
+            if n >= self.first as i64 && n < self.pushed as i64 {
+                let frame = self.input[(n - self.first as i64) as usize];
+                sum[0] += weight * frame[0];
+                sum[1] += weight * frame[1];
+            }
+        }
+        for channel in sum {
+            // The weights are divided out so a constant level stays the same, casts saturate
+            let sample = (channel / weights).round() as i16;
+            self.output.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.written += 1;
+    }
+
+    /// Kernel at `x` zero crossings from its center, interpolated between the table values
+    fn kernel_at(&self, x: f64) -> f64 {
+        let at = x * Resampler::OVERSAMPLING as f64;
+        let n = at as usize;
+        if n + 1 >= self.kernel.len() {
+            return 0.0;
+        }
+        let fraction = at - n as f64;
+        self.kernel[n] + (self.kernel[n + 1] - self.kernel[n]) * fraction
+    }
+}
+
+/// Modified Bessel function of the first kind of order zero, which shapes the Kaiser window
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
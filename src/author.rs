@@ -0,0 +1,115 @@
+//! Authoring images: a BIN of raw sectors and its CUE sheet, built from a data track and audio
+
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use crate::cue::CueDisc;
+use crate::error::ErrorCode;
+use crate::longpath::long_path;
+use crate::sector::data_mode;
+use crate::track::Mode;
+use crate::{normalize_disc, validate, ConvertOptions, SECTOR_SIZE};
+
+/// Bytes every sector of a cooked `MODE1/2048` ISO takes
+const ISO_SECTOR_SIZE: u64 = 2048;
+
+/// Builds `<name>.bin` and `<name>.cue` from `data_track`, followed by an audio track for
+/// each file of `audio`
+///
+/// This rebuilds a mixed mode image from the outputs of a conversion. The data track is an
+/// ISO of 2048 byte sectors, which get their sync, header, EDC and ECC the way a drive
+/// returns them raw, or a track of raw 2352 byte sectors like `-r` writes, kept as it is.
+/// Audio files are read as WAVE, as MP3 when built with the `mp3` feature, or as raw CD
+/// audio like `.cdr` files for any other extension. The first audio track gets the 2 second
+/// pregap mixed mode discs have after their data track. Outputs are named after the data
+/// track unless `ConvertOptions::output_name` is set.
+pub fn author(
+    options: impl Into<ConvertOptions>,
+    data_track: &str,
+    audio: &[String],
+) -> io::Result<()> {
+    let mut options = options.into();
+    options.bin_file = String::from(data_track);
+    // Only names the outputs, the CUE sheet is built here
+    options.cue_file = String::from(data_track);
+    let mut args = ConvertOptions::new(options);
+    validate(&args)?;
+
+    let sheet = authored_sheet(data_track, audio)?;
+    let mut disc = CueDisc::from_sheet(&sheet, &mut args)?;
+    normalize_disc(&mut disc, &args)
+}
+
+/// Size of the file at `path` and the header its first sector would have if it's raw
+fn read_start(path: &str) -> io::Result<(u64, [u8; 16])> {
+    let mut file = fs::File::open(long_path(path))?;
+    let size = file.metadata()?.len();
+    let mut header = [0u8; 16];
+    match file.read_exact(&mut header) {
+        Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => return Err(e),
+        _ => {}
+    }
+    Ok((size, header))
+}
+
+/// Mode of the sectors of the data track `path`, told from the sync of raw ones
+fn data_track_mode(path: &str) -> io::Result<Mode> {
+    let (size, header) = match read_start(path) {
+        Ok(start) => start,
+        Err(e) => {
+            return Err(ErrorCode::ReadFailed
+                .wrap(&e, format!("Could not read data track {}: {}", path, e)))
+        }
+    };
+    let (mode, sector_size) = match data_mode(&header) {
+        Some(1) => (Mode::Mode1_2352, SECTOR_SIZE),
+        Some(2) => (Mode::Mode2_2352, SECTOR_SIZE),
+        _ => (Mode::Mode1_2048, ISO_SECTOR_SIZE),
+    };
+    let leftover = size % sector_size;
+    if size == 0 || leftover != 0 {
+        return Err(ErrorCode::Unsupported.error(format!(
+            "{} isn't a data track of whole {} byte sectors",
+            path, sector_size
+        )));
+    }
+    Ok(mode)
+}
+
+/// CUE sheet laying out the data track and the audio files as the tracks of a disc
+fn authored_sheet(data_track: &str, audio: &[String]) -> io::Result<String> {
+    let mode = data_track_mode(data_track)?;
+    if audio.len() > 98 {
+        return Err(ErrorCode::InvalidArguments
+            .error("A disc holds at most 99 tracks, including the data track"));
+    }
+
+    let mut sheet = format!(
+        "FILE \"{}\" BINARY\n  TRACK 01 {}\n    INDEX 01 00:00:00\n",
+        data_track,
+        mode.as_ref()
+    );
+    for (i, file) in audio.iter().enumerate() {
+        let extension = Path::new(file)
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase());
+        let file_type = match extension.as_deref() {
+            Some("wav" | "wave") => "WAVE",
+            Some("mp3") => "MP3",
+            _ => "BINARY",
+        };
+        sheet += &format!(
+            "FILE \"{}\" {}\n  TRACK {:0>2} AUDIO\n",
+            file,
+            file_type,
+            i + 2
+        );
+        if i == 0 {
+            sheet += "    PREGAP 00:02:00\n";
+        }
+        sheet += "    INDEX 01 00:00:00\n";
+    }
+    Ok(sheet)
+}
@@ -0,0 +1,171 @@
+//! Conversions on a thread of their own, awaited from async code without an async runtime
+//! to depend on
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::error::ErrorCode;
+use crate::{convert_with_report, ConvertOptions, Progress, Report};
+
+/// What the conversion thread hands over to the futures awaiting it
+#[derive(Default)]
+struct State {
+    report: Option<io::Result<Report>>,
+    finished: bool,
+    progress: VecDeque<Progress>,
+    report_waker: Option<Waker>,
+    progress_waker: Option<Waker>,
+}
+
+type Shared = Arc<Mutex<State>>;
+
+fn lock(shared: &Shared) -> std::sync::MutexGuard<'_, State> {
+    // A panicking callback leaves nothing half written worth giving up on
+    match shared.lock() {
+        Ok(state) => state,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Starts converting like `convert_with_report` on a thread of its own
+///
+/// Nothing blocks the thread awaiting the `Conversion`, so it works with any async runtime
+/// or executor. `ConvertOptions::progress` is still called, from the conversion thread, and
+/// the same reports come out of the `ProgressStream`. Dropping both doesn't stop the
+/// conversion, it carries on in the background until `ConvertOptions::cancel` is set.
+pub fn convert_async(options: impl Into<ConvertOptions>) -> (Conversion, ProgressStream) {
+    let mut options = options.into();
+    let shared = Shared::default();
+
+    let callback = options.progress.take();
+    let sender = shared.clone();
+    options.progress = Some(Arc::new(move |progress: &Progress| {
+        if let Some(callback) = &callback {
+            callback(progress);
+        }
+        let mut state = lock(&sender);
+        // Reports nobody has awaited yet only matter as far as the latest one of a track
+        if let Some(last) = state.progress.back_mut() {
+            if last.track == progress.track && !last.is_done() {
+                *last = *progress;
+            } else {
+                state.progress.push_back(*progress);
+            }
+        } else {
+            state.progress.push_back(*progress);
+        }
+        if let Some(waker) = state.progress_waker.take() {
+            waker.wake();
+        }
+    }));
+
+    let worker = shared.clone();
+    let spawned = thread::Builder::new()
+        .name("rbchunk conversion".to_string())
+        .spawn(move || {
+            // Awaiting a conversion that panicked mustn't wait forever
+            let report =
+                match panic::catch_unwind(AssertUnwindSafe(|| convert_with_report(options))) {
+                    Ok(report) => report,
+                    Err(_) => Err(ErrorCode::Io.error("The conversion thread panicked")),
+                };
+            finish(&worker, report);
+        });
+    if let Err(e) = spawned {
+        finish(
+            &shared,
+            Err(ErrorCode::Io.wrap(&e, format!("Could not start the conversion thread: {}", e))),
+        );
+    }
+
+    (
+        Conversion {
+            shared: shared.clone(),
+        },
+        ProgressStream { shared },
+    )
+}
+
+fn finish(shared: &Shared, report: io::Result<Report>) {
+    let mut state = lock(shared);
+    state.report = Some(report);
+    state.finished = true;
+    if let Some(waker) = state.report_waker.take() {
+        waker.wake();
+    }
+    if let Some(waker) = state.progress_waker.take() {
+        waker.wake();
+    }
+}
+
+/// Conversion started by `convert_async`, resolving to its report once it's done
+pub struct Conversion {
+    shared: Shared,
+}
+
+impl Conversion {
+    /// Whether the conversion is over, so awaiting it won't wait
+    pub fn is_finished(&self) -> bool {
+        lock(&self.shared).finished
+    }
+}
+
+impl Future for Conversion {
+    type Output = io::Result<Report>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Report>> {
+        let mut state = lock(&self.shared);
+        match state.report.take() {
+            Some(report) => Poll::Ready(report),
+            None if state.finished => panic!("Conversion polled after it was done"),
+            None => {
+                state.report_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Progress of a conversion started by `convert_async`, as it's reported
+///
+/// Reports of a track that weren't awaited in time are merged into the latest one, the
+/// last report of every track always comes through.
+pub struct ProgressStream {
+    shared: Shared,
+}
+
+impl ProgressStream {
+    /// The next report, `None` once the conversion is over and every report was taken
+    // Named like `StreamExt::next` of the futures crate, as an async iterator rather than one
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> NextProgress<'_> {
+        NextProgress { stream: self }
+    }
+}
+
+/// Future of `ProgressStream::next`
+pub struct NextProgress<'a> {
+    stream: &'a mut ProgressStream,
+}
+
+impl Future for NextProgress<'_> {
+    type Output = Option<Progress>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Progress>> {
+        let mut state = lock(&self.stream.shared);
+        match state.progress.pop_front() {
+            Some(progress) => Poll::Ready(Some(progress)),
+            None if state.finished => Poll::Ready(None),
+            None => {
+                state.progress_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
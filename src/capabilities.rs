@@ -0,0 +1,59 @@
+//! What the current build can read and write, for front ends building their menus
+
+use crate::registry::formats;
+
+/// Formats and codecs supported by this build of the library
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Names of the image formats the auto-detector can open, in the order they are probed
+    pub input_formats: Vec<&'static str>,
+    /// Extensions of the files a conversion can produce
+    pub output_formats: Vec<&'static str>,
+    /// Encodings audio tracks can be written in
+    pub codecs: Vec<&'static str>,
+}
+
+impl Capabilities {
+    pub fn supports_input(&self, name: &str) -> bool {
+        self.input_formats
+            .iter()
+            .any(|f| f.eq_ignore_ascii_case(name))
+    }
+
+    pub fn supports_output(&self, extension: &str) -> bool {
+        self.output_formats
+            .iter()
+            .any(|f| f.eq_ignore_ascii_case(extension))
+    }
+}
+
+/// Lists what this build supports, based on its enabled features and registered formats
+pub fn capabilities() -> Capabilities {
+    let mut output_formats = vec!["iso", "bin", "cdr", "ugh", "cdg", "ecm"];
+    #[cfg(feature = "wav")]
+    output_formats.push("wav");
+    #[cfg(feature = "ogg")]
+    output_formats.push("ogg");
+    #[cfg(feature = "mp3")]
+    output_formats.push("mp3");
+    #[cfg(feature = "flac")]
+    output_formats.push("flac");
+    // Sidecar files describing the conversion
+    output_formats.extend(["cue", "dat", "txt", "xml", "m3u"]);
+
+    // Audio is written as the 16 bit PCM found on the disc unless an encoder is built in
+    #[allow(unused_mut)]
+    let mut codecs = vec!["pcm"];
+    #[cfg(feature = "ogg")]
+    codecs.push("vorbis");
+    #[cfg(feature = "mp3")]
+    codecs.push("mp3");
+    #[cfg(feature = "flac")]
+    codecs.push("flac");
+
+    Capabilities {
+        input_formats: formats().iter().map(|f| f.name()).collect(),
+        output_formats,
+        codecs,
+    }
+}
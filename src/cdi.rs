@@ -0,0 +1,400 @@
+//! DiscJuggler images, sectors of every session followed by descriptors of their tracks
+//!
+//! The descriptors are undocumented and grew fields between DiscJuggler versions, the layout
+//! read here is the one established by cdirip for versions 2, 3 and 3.5.
+
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::cue::describe_track;
+use crate::disc::{Disc, DiscMetadata};
+use crate::error::ErrorCode;
+use crate::image::ImageFile;
+use crate::log::info;
+use crate::longpath::long_path;
+use crate::registry::DiscFormat;
+use crate::track::{Mode, Track};
+use crate::ConvertOptions;
+
+const VERSION_2: u32 = 0x8000_0004;
+const VERSION_3: u32 = 0x8000_0005;
+const VERSION_35: u32 = 0x8000_0006;
+// Marks the start of each track descriptor, twice
+const TRACK_START: [u8; 10] = [0, 0, 0x01, 0, 0, 0, 0xff, 0xff, 0xff, 0xff];
+
+/// Disc backed by a DiscJuggler image
+pub struct CdiDisc {
+    tracks: Vec<Track>,
+    metadata: DiscMetadata,
+    image: ImageFile,
+}
+
+/// Reads little endian fields from the descriptors, failing rather than reading past them
+struct Descriptors<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Descriptors<'_> {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        match self.data.get(self.position..self.position + n) {
+            Some(bytes) => {
+                self.position += n;
+                Ok(bytes)
+            }
+            None => {
+                Err(ErrorCode::CueMalformed.error("CDI descriptors end in the middle of a track"))
+            }
+        }
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+/// Track descriptor, with lengths in sectors
+struct Entry {
+    mode: u32,
+    sector_size: u64,
+    pregap: u64,
+    length: u64,
+    /// Sector of `INDEX 01`, after the pregap
+    start_lba: u64,
+    /// Sectors stored in the image, the pregap and the track itself
+    total_length: u64,
+}
+
+impl CdiDisc {
+    pub(crate) const FORMAT: &'static str = "CDI";
+
+    /// Parses the descriptors at the end of the DiscJuggler image named by `args.cue_file`
+    pub fn open(args: &mut ConvertOptions) -> io::Result<CdiDisc> {
+        let mut file = match fs::File::open(long_path(&args.cue_file)) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(
+                    ErrorCode::ReadFailed.wrap(&e, format!("Could not open CDI file: {}", e))
+                )
+            }
+        };
+        let file_size = file.metadata()?.len();
+        let (version, descriptors) = read_descriptors(&mut file, file_size)?;
+        // Sectors are stored in the same file that describes them
+        if args.bin_file.is_empty() {
+            args.bin_file = args.cue_file.clone();
+        }
+
+        let mut d = Descriptors {
+            data: &descriptors,
+            position: 0,
+        };
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut strides = Vec::new();
+        let mut position = 0;
+        let sessions = d.u16()?;
+        for session in 1..=sessions {
+            let count = d.u16()?;
+            for _ in 0..count {
+                let e = read_track(&mut d, version)?;
+                let number = tracks.len() as u32 + 1;
+                let mode = match (e.mode, e.sector_size) {
+                    (0, 2352 | 2448) => Mode::Audio,
+                    (1, 2048) => Mode::Mode1_2048,
+                    (1, 2352 | 2448) => Mode::Mode1_2352,
+                    (2, 2336) => Mode::Mode2_2336,
+                    (2, 2352 | 2448) => Mode::Mode2_2352,
+                    _ => {
+                        return Err(ErrorCode::Unsupported.error(format!(
+                            "Track {} has CDI mode {} with {} byte sectors, which isn't supported",
+                            number, e.mode, e.sector_size
+                        )))
+                    }
+                };
+                if e.length == 0 || position + e.total_length * e.sector_size > file_size {
+                    return Err(ErrorCode::CueMalformed
+                        .error(format!("Track {} lies outside of the CDI file", number)));
+                }
+
+                let next = tracks.last().map_or(0, |t| t.stop_sector() + 1);
+                if e.start_lba < next {
+                    return Err(ErrorCode::CueMalformed.error(format!(
+                        "Track {} starts before the end of the previous track",
+                        number
+                    )));
+                }
+                let mut track = Track::new(number, mode, e.start_lba, e.start_lba + e.length - 1);
+                track.start = position + e.pregap * e.sector_size;
+                track.session = session as u32;
+                // Sectors before LBA 0 are in the lead-in, like the pregap of the first track
+                let stored_pregap = e.pregap.min(e.start_lba - next);
+                if stored_pregap > 0 {
+                    track.index0 = Some(e.start_lba - stored_pregap);
+                }
+                // Whatever else lies between tracks isn't stored, like the gap between sessions
+                if !tracks.is_empty() {
+                    track.pregap = e.start_lba - stored_pregap - next;
+                }
+                if args.verbose {
+                    info!("{}", describe_track(&track));
+                }
+                position += e.total_length * e.sector_size;
+                tracks.push(track);
+                strides.push(e.sector_size);
+            }
+            // Session trailer
+            d.skip(12)?;
+            if version != VERSION_2 {
+                d.skip(1)?;
+            }
+        }
+        if tracks.is_empty() {
+            return Err(ErrorCode::CueMalformed.error("No tracks found in CDI file"));
+        }
+
+        let image = ImageFile::new(file, strides, args);
+        let image_size = image.is_plain(&tracks).then_some(position);
+        Ok(CdiDisc {
+            tracks,
+            metadata: DiscMetadata {
+                format: CdiDisc::FORMAT,
+                image_size,
+                ..Default::default()
+            },
+            image,
+        })
+    }
+}
+
+/// Version from the footer and the descriptors it points to, up to the footer
+fn read_descriptors(file: &mut fs::File, file_size: u64) -> io::Result<(u32, Vec<u8>)> {
+    let not_cdi = || ErrorCode::CueMalformed.error("Not a CDI file");
+    if file_size < 8 {
+        return Err(not_cdi());
+    }
+    let mut footer = [0u8; 8];
+    file.seek(SeekFrom::End(-8))?;
+    file.read_exact(&mut footer)?;
+    let version = u32::from_le_bytes(footer[..4].try_into().unwrap());
+    let offset = u32::from_le_bytes(footer[4..].try_into().unwrap()) as u64;
+    // Version 3.5 counts back from the end of the file, the others from its start
+    let start = match version {
+        VERSION_2 | VERSION_3 => offset,
+        VERSION_35 => file_size.checked_sub(offset).ok_or_else(not_cdi)?,
+        _ => return Err(not_cdi()),
+    };
+    if start >= file_size - 8 {
+        return Err(not_cdi());
+    }
+
+    let mut descriptors = vec![0u8; (file_size - 8 - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut descriptors)?;
+    Ok((version, descriptors))
+}
+
+fn read_track(d: &mut Descriptors, version: u32) -> io::Result<Entry> {
+    // Extra data of DiscJuggler 3.00.780 and later
+    if d.u32()? != 0 {
+        d.skip(8)?;
+    }
+    for _ in 0..2 {
+        if d.take(TRACK_START.len())? != TRACK_START {
+            return Err(ErrorCode::CueMalformed.error("CDI track descriptor has no start mark"));
+        }
+    }
+    d.skip(4)?;
+    let filename_length = d.u8()? as usize;
+    d.skip(filename_length + 11 + 4 + 4)?;
+    // Extra data of DiscJuggler 4
+    if d.u32()? == 0x8000_0000 {
+        d.skip(8)?;
+    }
+    d.skip(2)?;
+    let pregap = d.u32()? as u64;
+    let length = d.u32()? as u64;
+    d.skip(6)?;
+    let mode = d.u32()?;
+    d.skip(12)?;
+    let start_lba = d.u32()? as u64;
+    let total_length = d.u32()? as u64;
+    d.skip(16)?;
+    let sector_size = match d.u32()? {
+        0 => 2048,
+        1 => 2336,
+        2 => 2352,
+        // Raw sectors followed by their subcode
+        4 => 2448,
+        value => {
+            return Err(ErrorCode::Unsupported
+                .error(format!("CDI sector size value {} isn't supported", value)))
+        }
+    };
+    d.skip(29)?;
+    if version != VERSION_2 {
+        d.skip(5)?;
+        // Extra data of DiscJuggler 3.00.780 and later
+        if d.u32()? == 0xffff_ffff {
+            d.skip(78)?;
+        }
+    }
+    Ok(Entry {
+        mode,
+        sector_size,
+        pregap,
+        length,
+        start_lba,
+        total_length,
+    })
+}
+
+impl Disc for CdiDisc {
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn read_sector(&mut self, lba: u64, sector: &mut [u8]) -> io::Result<()> {
+        self.image.read_sector(&self.tracks, lba, sector)
+    }
+
+    fn read_subcode(&mut self, lba: u64, subcode: &mut [u8]) -> io::Result<()> {
+        self.image.read_subcode(&self.tracks, lba, subcode)
+    }
+
+    fn metadata(&self) -> &DiscMetadata {
+        &self.metadata
+    }
+}
+
+pub(crate) struct CdiFormat;
+
+impl DiscFormat for CdiFormat {
+    fn name(&self) -> &'static str {
+        CdiDisc::FORMAT
+    }
+
+    fn probe(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("cdi"))
+    }
+
+    fn open(&self, args: &mut ConvertOptions) -> io::Result<Box<dyn Disc>> {
+        Ok(Box::new(CdiDisc::open(args)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TemporaryDirectory;
+
+    /// Descriptor of a track with a `sector_size` value as DiscJuggler stores it
+    fn track(
+        version: u32,
+        mode: u32,
+        sector_size: u32,
+        pregap: u32,
+        length: u32,
+        lba: u32,
+    ) -> Vec<u8> {
+        let mut d = 0u32.to_le_bytes().to_vec();
+        d.extend(TRACK_START);
+        d.extend(TRACK_START);
+        d.extend([0; 4]);
+        d.push(5);
+        d.extend(b"a.iso");
+        d.extend([0; 11 + 4 + 4 + 4 + 2]);
+        d.extend(pregap.to_le_bytes());
+        d.extend(length.to_le_bytes());
+        d.extend([0; 6]);
+        d.extend(mode.to_le_bytes());
+        d.extend([0; 12]);
+        d.extend(lba.to_le_bytes());
+        d.extend((pregap + length).to_le_bytes());
+        d.extend([0; 16]);
+        d.extend(sector_size.to_le_bytes());
+        d.extend([0; 29]);
+        if version != VERSION_2 {
+            d.extend([0; 5 + 4]);
+        }
+        d
+    }
+
+    /// Image of an audio session and a Mode 2 session after it, with descriptors of `version`
+    fn image(version: u32) -> Vec<u8> {
+        let mut image = vec![0u8; 250 * 2352 + 200 * 2336];
+        let descriptors = image.len() as u32;
+        let trailer = if version == VERSION_2 { 12 } else { 13 };
+        image.extend(2u16.to_le_bytes());
+        image.extend(1u16.to_le_bytes());
+        image.extend(track(version, 0, 2, 150, 100, 150));
+        image.extend(vec![0; trailer]);
+        image.extend(1u16.to_le_bytes());
+        image.extend(track(version, 2, 1, 150, 50, 11650));
+        image.extend(vec![0; trailer]);
+        let offset = match version {
+            VERSION_35 => image.len() as u32 + 8 - descriptors,
+            _ => descriptors,
+        };
+        image.extend(version.to_le_bytes());
+        image.extend(offset.to_le_bytes());
+        image
+    }
+
+    #[test]
+    fn opens_images_of_every_version() {
+        let directory = TemporaryDirectory::create().unwrap();
+        for version in [VERSION_2, VERSION_3, VERSION_35] {
+            let path = directory.0.join("image.cdi");
+            fs::write(&path, image(version)).unwrap();
+            let mut args = ConvertOptions {
+                cue_file: path.to_string_lossy().into_owned(),
+                ..Default::default()
+            };
+            let disc = CdiDisc::open(&mut args).unwrap();
+            let tracks = disc.tracks();
+            assert_eq!(tracks.len(), 2);
+            assert!(tracks[0].mode == Mode::Audio && tracks[1].mode == Mode::Mode2_2336);
+            assert_eq!(
+                (tracks[0].start_sector, tracks[0].stop_sector()),
+                (150, 249)
+            );
+            assert_eq!((tracks[0].index0, tracks[0].start), (Some(0), 150 * 2352));
+            // The gap between the sessions is what isn't stored before the second one
+            assert_eq!((tracks[1].session(), tracks[1].index0), (2, Some(11500)));
+            assert_eq!(tracks[1].pregap, 11650 - 150 - 250);
+            assert_eq!(tracks[1].start, 250 * 2352 + 150 * 2336);
+            assert_eq!(disc.metadata().image_size, Some(250 * 2352 + 200 * 2336));
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let directory = TemporaryDirectory::create().unwrap();
+        let path = directory.0.join("image.cdi");
+        let mut image = image(VERSION_2);
+        let footer = image.len() - 8;
+        image[footer] = 0;
+        fs::write(&path, image).unwrap();
+        let mut args = ConvertOptions {
+            cue_file: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let e = CdiDisc::open(&mut args).err().unwrap();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::CueMalformed);
+    }
+}
@@ -0,0 +1,142 @@
+//! Text encodings of CUE sheets, which are plain bytes written by whatever tool made the dump
+//!
+//! Sheets are read as UTF-8 or, with a byte order mark, as UTF-16. Anything else is taken
+//! for a legacy Windows code page: Shift-JIS when it decodes as such and the `shift-jis`
+//! feature is enabled, Windows-1252 otherwise. The commands of a sheet are all ASCII, so a
+//! wrong guess only garbles titles and file names, it doesn't keep the sheet from parsing.
+
+use std::fmt;
+
+/// Encoding a CUE sheet was found to be in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Charset {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    #[cfg(feature = "shift-jis")]
+    ShiftJis,
+    Windows1252,
+}
+
+impl Charset {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "UTF-8",
+            Charset::Utf16Le => "UTF-16LE",
+            Charset::Utf16Be => "UTF-16BE",
+            #[cfg(feature = "shift-jis")]
+            Charset::ShiftJis => "Shift-JIS",
+            Charset::Windows1252 => "Windows-1252",
+        }
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// Text of the CUE sheet `bytes`, in whichever encoding it was written
+pub fn decode_sheet(bytes: &[u8]) -> String {
+    decode(bytes).0
+}
+
+/// Text of `bytes` and the encoding it was decoded from, without a byte order mark
+pub(crate) fn decode(bytes: &[u8]) -> (String, Charset) {
+    if let Some(bytes) = bytes.strip_prefix(b"\xef\xbb\xbf") {
+        return (String::from_utf8_lossy(bytes).into_owned(), Charset::Utf8);
+    }
+    if let Some(bytes) = bytes.strip_prefix(b"\xff\xfe") {
+        return (utf16(bytes, u16::from_le_bytes), Charset::Utf16Le);
+    }
+    if let Some(bytes) = bytes.strip_prefix(b"\xfe\xff") {
+        return (utf16(bytes, u16::from_be_bytes), Charset::Utf16Be);
+    }
+    // Without a byte order mark UTF-16 still shows in the NUL halves of the ASCII commands
+    match bytes {
+        [c, 0, ..] if *c != 0 => return (utf16(bytes, u16::from_le_bytes), Charset::Utf16Le),
+        [0, c, ..] if *c != 0 => return (utf16(bytes, u16::from_be_bytes), Charset::Utf16Be),
+        _ => {}
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return (text.to_string(), Charset::Utf8);
+    }
+    let text = windows_1252(bytes);
+    // Accented letters of a Western name like "Pokémon" can pair up into valid Shift-JIS,
+    // while Japanese text always has some byte that isn't a letter in Windows-1252
+    #[cfg(feature = "shift-jis")]
+    if !text.chars().all(|c| c.is_ascii() || c.is_alphabetic()) {
+        if let Some(text) = shift_jis(bytes) {
+            return (text, Charset::ShiftJis);
+        }
+    }
+    (text, Charset::Windows1252)
+}
+
+fn utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+    // A dangling odd byte is dropped, like a truncated last character
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Characters of the bytes 0x80 to 0x9F, the rest of the code page is Latin-1
+const WINDOWS_1252: [char; 32] = [
+    '\u{20ac}', '\u{0081}', '\u{201a}', '\u{0192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02c6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008d}', '\u{017d}', '\u{008f}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02dc}', '\u{2122}', '\u{0161}', '\u{203a}', '\u{0153}', '\u{009d}', '\u{017e}', '\u{0178}',
+];
+
+fn windows_1252(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            0x80..=0x9f => WINDOWS_1252[b as usize - 0x80],
+            _ => b as char,
+        })
+        .collect()
+}
+
+/// Characters of the double byte codes of Shift-JIS, as Windows code page 932 has them
+///
+/// Little endian `u16`s for every lead byte in 0x81-0x9F and 0xE0-0xFC and trail byte in
+/// 0x40-0xFC, 0 where there's no character. Generated with Python's `cp932` codec:
+/// `bytes([lead, trail]).decode("cp932")`.
+#[cfg(feature = "shift-jis")]
+static SHIFT_JIS: &[u8; 60 * 189 * 2] = include_bytes!("shift_jis.bin");
+
+/// Text of `bytes` if every one of them is valid Shift-JIS
+#[cfg(feature = "shift-jis")]
+fn shift_jis(bytes: &[u8]) -> Option<String> {
+    let mut text = String::with_capacity(bytes.len());
+    let mut bytes = bytes.iter().copied();
+    while let Some(b) = bytes.next() {
+        let lead = match b {
+            0x00..=0x7f => {
+                text.push(b as char);
+                continue;
+            }
+            // Half width katakana
+            0xa1..=0xdf => {
+                text.push(char::from_u32(0xff61 + (b - 0xa1) as u32)?);
+                continue;
+            }
+            0x81..=0x9f => b - 0x81,
+            0xe0..=0xfc => b - 0xe0 + 0x1f,
+            _ => return None,
+        };
+        let trail = match bytes.next()? {
+            t @ 0x40..=0xfc => t - 0x40,
+            _ => return None,
+        };
+        let at = (lead as usize * 189 + trail as usize) * 2;
+        match u16::from_le_bytes([SHIFT_JIS[at], SHIFT_JIS[at + 1]]) {
+            0 => return None,
+            c => text.push(char::from_u32(c as u32)?),
+        }
+    }
+    Some(text)
+}
@@ -0,0 +1,129 @@
+//! CHD images, unpacked to a temporary CUE/BIN by `chdman` from MAME
+//!
+//! CHD hunks are compressed with zlib, LZMA, FLAC and Huffman coding, which rbchunk has no
+//! decoders for, so the image is extracted by the reference tool and read from there.
+
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::cue::CueDisc;
+use crate::disc::{Disc, DiscMetadata, Surplus};
+use crate::error::ErrorCode;
+use crate::longpath::long_path;
+use crate::registry::DiscFormat;
+use crate::temp::TemporaryDirectory;
+use crate::track::Track;
+use crate::ConvertOptions;
+
+/// Disc backed by a CHD image, read from the CUE/BIN it was extracted to
+pub struct ChdDisc {
+    inner: CueDisc,
+    metadata: DiscMetadata,
+    // Declared last, so the extracted BIN file is closed before it's removed
+    _directory: TemporaryDirectory,
+}
+
+impl ChdDisc {
+    pub(crate) const FORMAT: &'static str = "CHD";
+
+    /// Extracts the CHD image named by `args.cue_file` and opens the result
+    pub fn open(args: &mut ConvertOptions) -> io::Result<ChdDisc> {
+        let directory = TemporaryDirectory::create()?;
+        let (inner, metadata) = extract(args, &directory.0)?;
+        Ok(ChdDisc {
+            inner,
+            metadata,
+            _directory: directory,
+        })
+    }
+}
+
+fn extract(args: &mut ConvertOptions, directory: &Path) -> io::Result<(CueDisc, DiscMetadata)> {
+    let cue = directory.join("disc.cue");
+    let bin = directory.join("disc.bin");
+    let status = Command::new("chdman")
+        .arg("extractcd")
+        .arg("-i")
+        .arg(long_path(&args.cue_file))
+        .arg("-o")
+        .arg(&cue)
+        .arg("-ob")
+        .arg(&bin)
+        .stdout(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            return Err(ErrorCode::ReadFailed.error(format!(
+                "chdman could not extract {}: {}",
+                args.cue_file, status
+            )))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(ErrorCode::Unsupported
+                .error("chdman is needed to read CHD images but could not be found"))
+        }
+        Err(e) => return Err(e),
+    }
+
+    // Messages keep naming the CHD image, not the files extracted from it
+    let (image, bin_file) = (args.cue_file.clone(), args.bin_file.clone());
+    args.cue_file = cue.to_string_lossy().into_owned();
+    args.bin_file = bin.to_string_lossy().into_owned();
+    let inner = CueDisc::open(args);
+    args.cue_file = image.clone();
+    args.bin_file = match bin_file.is_empty() {
+        true => image,
+        false => bin_file,
+    };
+
+    let inner = inner?;
+    let metadata = DiscMetadata {
+        format: ChdDisc::FORMAT,
+        // Compressed, so its size says nothing about the sectors in it
+        image_size: None,
+        ..inner.metadata().clone()
+    };
+    Ok((inner, metadata))
+}
+
+impl Disc for ChdDisc {
+    fn tracks(&self) -> &[Track] {
+        self.inner.tracks()
+    }
+
+    fn read_sector(&mut self, lba: u64, sector: &mut [u8]) -> io::Result<()> {
+        self.inner.read_sector(lba, sector)
+    }
+
+    fn read_subcode(&mut self, lba: u64, subcode: &mut [u8]) -> io::Result<()> {
+        self.inner.read_subcode(lba, subcode)
+    }
+
+    fn metadata(&self) -> &DiscMetadata {
+        &self.metadata
+    }
+
+    fn surplus(&self) -> Option<&Surplus> {
+        self.inner.surplus()
+    }
+}
+
+pub(crate) struct ChdFormat;
+
+impl DiscFormat for ChdFormat {
+    fn name(&self) -> &'static str {
+        ChdDisc::FORMAT
+    }
+
+    fn probe(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("chd"))
+    }
+
+    fn open(&self, args: &mut ConvertOptions) -> io::Result<Box<dyn Disc>> {
+        Ok(Box::new(ChdDisc::open(args)?))
+    }
+}
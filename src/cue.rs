@@ -0,0 +1,1453 @@
+//! CUE sheets: reading them along with the files they name, and writing them
+
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::{IndexMut, Range};
+use std::path::Path;
+
+use crate::charset::{self, Charset};
+use crate::disc::{Disc, DiscMetadata, Surplus};
+use crate::error::ErrorCode;
+use crate::log::{info, warning};
+use crate::longpath::{file_name, long_path};
+use crate::output::{check_not_input, write_file, OutputFile};
+use crate::pairing;
+use crate::sector::{add_header, raw_mode, volume_blocks, SUBCODE_SIZE, SYNC};
+use crate::source::{FileType, Sources};
+use crate::track::{GapPolicy, Mode, PartialSector, Track};
+use crate::{ConvertOptions, SECTOR_SIZE};
+
+/// Sectors at the start of each track its mode is checked by
+const CHECKED_SECTORS: u64 = 16;
+
+/// Sectors of the lead-out closing the first session, 90 seconds, and the later ones, 30
+/// seconds, unless `REM LEAD-OUT` says otherwise
+const FIRST_LEAD_OUT: u64 = 6750;
+const LEAD_OUT: u64 = 2250;
+
+/// Sectors of the lead-in opening every session after the first, 60 seconds, unless
+/// `REM LEAD-IN` says otherwise
+const LEAD_IN: u64 = 4500;
+
+/// Disc backed by a CUE sheet and the BIN file, or files, it describes
+pub struct CueDisc {
+    tracks: Vec<Track>,
+    metadata: DiscMetadata,
+    surplus: Option<Surplus>,
+    reader: BufReader<Sources>,
+    position: u64,
+    /// Subcode stored after the last sector read, and that sector
+    subcode: [u8; SUBCODE_SIZE],
+    subcode_lba: Option<u64>,
+}
+
+impl CueDisc {
+    pub(crate) const FORMAT: &'static str = "CUE/BIN";
+
+    /// Parses the CUE sheet of `args`, filling in the BIN file from it if it wasn't supplied
+    pub fn open(args: &mut ConvertOptions) -> io::Result<CueDisc> {
+        let (tracks, metadata, in_file) = read_cue(args)?;
+        CueDisc::new(tracks, metadata, in_file, args)
+    }
+
+    /// Parses the CUE sheet `cue_sheet` rather than the file of `args`, the BIN file of
+    /// `args` standing in for its first FILE
+    pub(crate) fn from_sheet(cue_sheet: &str, args: &mut ConvertOptions) -> io::Result<CueDisc> {
+        let (mut tracks, metadata, files) = parse_sheet(cue_sheet, args)?;
+        let in_file = open_sources(args, &files, &mut tracks)?;
+        lay_out(&mut tracks, metadata.lead_out, in_file.len(), args)?;
+        CueDisc::new(tracks, metadata, in_file, args)
+    }
+
+    /// Parses the CUE sheet `cue_sheet`, reading the sectors of its only FILE from `bin`
+    pub fn from_reader<R: Read + Seek + 'static>(
+        cue_sheet: &str,
+        bin: R,
+        args: &mut ConvertOptions,
+    ) -> io::Result<CueDisc> {
+        let (mut tracks, metadata, files) = parse_sheet(cue_sheet, args)?;
+        let name = match files.as_slice() {
+            [] => String::from("BIN data"),
+            [(name, FileType::Binary, _)] => name.clone(),
+            [(name, _, _)] => {
+                return Err(ErrorCode::Unsupported.error(format!(
+                    "{} can only be read from a file, it isn't BINARY",
+                    name
+                )))
+            }
+            _ => {
+                return Err(ErrorCode::Unsupported
+                    .error("A CUE sheet with more than one FILE can't be read from one reader"))
+            }
+        };
+        if args.bin_file.is_empty() {
+            args.bin_file = name.clone();
+        }
+
+        let mut in_file = Sources::new(args.read_buffer_size());
+        if let Err(e) = in_file.push_reader(Box::new(bin), &name, SECTOR_SIZE) {
+            return Err(ErrorCode::ReadFailed.wrap(&e, format!("Could not read {}: {}", name, e)));
+        }
+        detect_sector_size(&mut in_file, 0, &mut tracks, args)?;
+        lay_out(&mut tracks, metadata.lead_out, in_file.len(), args)?;
+        CueDisc::new(tracks, metadata, in_file, args)
+    }
+
+    fn new(
+        tracks: Vec<Track>,
+        mut metadata: DiscMetadata,
+        in_file: Sources,
+        args: &ConvertOptions,
+    ) -> io::Result<CueDisc> {
+        let file_size = in_file.len();
+        metadata.image_size = Some(file_size);
+        let mut disc = CueDisc {
+            tracks,
+            metadata,
+            surplus: None,
+            reader: BufReader::with_capacity(args.read_buffer_size(), in_file),
+            position: 0,
+            subcode: [0; SUBCODE_SIZE],
+            subcode_lba: None,
+        };
+
+        disc.check_modes(args)?;
+        disc.surplus = disc.find_surplus(file_size)?;
+        // Exported surplus is no longer part of the last track
+        if let (Some(surplus), Some(last), true) =
+            (&disc.surplus, disc.tracks.last_mut(), args.export_surplus)
+        {
+            last.stop_sector = Some(surplus.start_sector - 1);
+        }
+
+        Ok(disc)
+    }
+
+    /// Byte position and mode of a sector in the BIN file and whether subchannel follows it,
+    /// `None` for gaps that aren't stored in it
+    fn position(&self, lba: u64) -> Option<(u64, Mode, bool)> {
+        // Tracks may be shifted by offsets and gaps, so locate the ones around the sector
+        let next = self.tracks.partition_point(|t| t.start_sector <= lba);
+        if next > 0 {
+            let t = &self.tracks[next - 1];
+            let in_surplus = self.surplus.is_some_and(|s| lba >= s.start_sector);
+            if lba <= t.stop_sector() || (next == self.tracks.len() && in_surplus) {
+                let position = t.start + (lba - t.start_sector) * t.sector_size();
+                return Some((position, t.mode, t.subcode));
+            }
+            if lba <= t.stop_sector() + t.postgap {
+                return None;
+            }
+        }
+        match self.tracks.get(next) {
+            Some(n) if lba + n.pregap >= n.start_sector => None,
+            // Pregaps stored in the file, right before the start of the next track
+            Some(n) => {
+                let before = (n.start_sector - n.pregap - lba) * n.sector_size();
+                Some((n.start.checked_sub(before)?, n.mode, n.subcode))
+            }
+            None => {
+                let t = self.tracks.last()?;
+                let position = t.start + (lba - t.start_sector) * t.sector_size();
+                Some((position, t.mode, t.subcode))
+            }
+        }
+    }
+
+    /// Checks the mode of every raw track against its first sectors, which CUE sheets can be
+    /// wrong about, and with `ConvertOptions::trust_sector_modes` reads the track as their
+    /// mode instead
+    ///
+    /// Blank sectors are passed over, and tracks whose other sectors don't all have the same
+    /// mode are taken as the sheet says.
+    fn check_modes(&mut self, args: &ConvertOptions) -> io::Result<()> {
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        for n in 0..self.tracks.len() {
+            let t = &self.tracks[n];
+            let declared = t.mode;
+            if !matches!(
+                declared,
+                Mode::Audio | Mode::Mode1_2352 | Mode::Mode2_2352 | Mode::Cdi2352
+            ) {
+                continue;
+            }
+            let (number, first) = (t.number, t.start_sector);
+            let last = t.stop_sector().min(first + CHECKED_SECTORS - 1);
+            let mut modes = Vec::new();
+            for lba in first..=last {
+                self.read_sector(lba, &mut sector)?;
+                modes.extend(raw_mode(&sector));
+            }
+            let detected = match modes.first() {
+                Some(&mode) if modes.iter().all(|&m| m == mode) => mode,
+                _ => continue,
+            };
+            // CD-i sectors are mode 2 sectors
+            if detected == declared || (declared, detected) == (Mode::Cdi2352, Mode::Mode2_2352) {
+                continue;
+            }
+            if args.trust_sector_modes {
+                warning!(
+                    "Track {} is {}, not {} like the CUE sheet says, and is read as such",
+                    number,
+                    detected,
+                    declared
+                );
+                self.tracks[n].mode = detected;
+            } else {
+                warning!(
+                    "Track {} looks like {}, not {} like the CUE sheet says",
+                    number,
+                    detected,
+                    declared
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn find_surplus(&mut self, file_size: u64) -> io::Result<Option<Surplus>> {
+        let last = match self.tracks.last() {
+            Some(last) => last.clone(),
+            None => return Ok(None),
+        };
+        // Surplus is exported in raw sectors, which a cooked track can't provide
+        if last.sector_size() != SECTOR_SIZE {
+            return Ok(None);
+        }
+
+        /*
+         * Most CUE sheets don't say where the last track ends, so unless they give the
+         * lead-out the best hints we have are the volume size of a trailing data track and
+         * bytes that don't fill a whole sector
+         */
+        let mut end = file_size - (file_size - last.start) % SECTOR_SIZE;
+        if self.metadata.lead_out.is_some() {
+            let sectors = last.stop_sector() + 1 - last.start_sector;
+            end = end.min(last.start + sectors * SECTOR_SIZE);
+        } else if let Some(blocks) = volume_blocks(self, &last)? {
+            end = end.min(last.start + blocks * SECTOR_SIZE);
+        }
+
+        if end <= last.start || end >= file_size {
+            return Ok(None);
+        }
+        Ok(Some(Surplus {
+            start_sector: last.start_sector + (end - last.start) / SECTOR_SIZE,
+            length: file_size - end,
+        }))
+    }
+}
+
+impl Disc for CueDisc {
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn read_sector(&mut self, lba: u64, sector: &mut [u8]) -> io::Result<()> {
+        let (position, mode, subcode) = match self.position(lba) {
+            Some(position) => position,
+            None => {
+                sector.fill(0);
+                return Ok(());
+            }
+        };
+
+        // Seeking drops the read buffer, so avoid it for sequential reads
+        if position != self.position {
+            self.reader.seek(SeekFrom::Start(position))?;
+        }
+        if mode.stored_range().len() == SECTOR_SIZE as usize {
+            fill_sector(&mut self.reader, sector)?;
+        } else {
+            // Cooked sectors only hold the user data, the rest of the raw sector is rebuilt
+            sector.fill(0);
+            fill_sector(&mut self.reader, &mut sector[mode.stored_range()])?;
+            add_header(sector, lba, mode);
+        }
+        if mode == Mode::Cdg || subcode {
+            fill_sector(&mut self.reader, &mut self.subcode)?;
+            self.subcode_lba = Some(lba);
+        }
+        self.position = position + mode.sector_size();
+        if subcode {
+            self.position += SUBCODE_SIZE as u64;
+        }
+        Ok(())
+    }
+
+    fn read_subcode(&mut self, lba: u64, subcode: &mut [u8]) -> io::Result<()> {
+        if self.subcode_lba != Some(lba) {
+            let mut sector = [0u8; SECTOR_SIZE as usize];
+            self.read_sector(lba, &mut sector)?;
+        }
+        match self.subcode_lba == Some(lba) {
+            true => subcode.copy_from_slice(&self.subcode),
+            false => subcode.fill(0),
+        }
+        Ok(())
+    }
+
+    fn copy_sectors(&mut self, lbas: Range<u64>, output: &mut fs::File) -> io::Result<u64> {
+        let next = self
+            .tracks
+            .partition_point(|t| t.start_sector <= lbas.start);
+        let t = match next.checked_sub(1).map(|i| &self.tracks[i]) {
+            Some(t) if lbas.start <= t.stop_sector() => t,
+            // Gaps, which may not be stored
+            _ => return Ok(0),
+        };
+        if t.sector_size() != SECTOR_SIZE || t.mode.stored_range().len() != SECTOR_SIZE as usize {
+            return Ok(0);
+        }
+        let count = lbas.end.min(t.stop_sector() + 1).saturating_sub(lbas.start);
+        let position = t.start + (lbas.start - t.start_sector) * SECTOR_SIZE;
+        let copied = self
+            .reader
+            .get_mut()
+            .copy_to(position, count * SECTOR_SIZE, output)?;
+        // Whatever is left in the read buffer is from before the copy
+        self.position = u64::MAX;
+        Ok(if copied { count } else { 0 })
+    }
+
+    fn metadata(&self) -> &DiscMetadata {
+        &self.metadata
+    }
+
+    fn surplus(&self) -> Option<&Surplus> {
+        self.surplus.as_ref()
+    }
+}
+
+/// Reads a whole sector, padding it with zeroes if the file ends first
+pub(crate) fn fill_sector(reader: &mut impl Read, sector: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < sector.len() {
+        match reader.read(&mut sector[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    sector[filled..].fill(0);
+    Ok(())
+}
+
+/// Reads the CUE sheet at `path` along with the files it names, without converting anything
+///
+/// The disc lists the tracks with their modes, indexes, gaps and CD-TEXT, and reads their
+/// sectors like any other `Disc`. FILE entries are looked up like `convert` does, relative
+/// to the CUE sheet unless they exist as they are.
+pub fn parse_cue(path: &str) -> io::Result<CueDisc> {
+    let mut args = ConvertOptions {
+        cue_file: String::from(path),
+        ..Default::default()
+    };
+    CueDisc::open(&mut args)
+}
+
+/// Reads the CUE sheet `cue_sheet`, with the files it names looked up in `dir`
+pub fn parse_cue_str(cue_sheet: &str, dir: &str) -> io::Result<CueDisc> {
+    let mut args = ConvertOptions {
+        // FILE entries are found relative to a sheet stored in `dir`
+        cue_file: Path::new(dir)
+            .join("image.cue")
+            .to_string_lossy()
+            .into_owned(),
+        ..Default::default()
+    };
+    let (mut tracks, metadata, files) = parse_sheet(cue_sheet, &args)?;
+    if let Some((name, _, _)) = files.first() {
+        args.bin_file = pairing::file_for_cue(&args.cue_file, name).unwrap_or_else(|| name.clone());
+    }
+    let in_file = open_sources(&args, &files, &mut tracks)?;
+    lay_out(&mut tracks, metadata.lead_out, in_file.len(), &args)?;
+    CueDisc::new(tracks, metadata, in_file, &args)
+}
+
+/// Tracks, metadata and FILE entries of a parsed CUE sheet
+type CueSheet = (Vec<Track>, DiscMetadata, Vec<(String, FileType, usize)>);
+
+fn missing_index1(t: &Track) -> io::Error {
+    ErrorCode::CueMalformed.error(format!("Track {} has no INDEX 01", t.number))
+}
+
+/// Checks that `INDEX index` at sector `frames` follows the indexes `t` already has in order
+fn check_index(t: &Track, index: u32, frames: u64, has_index1: bool) -> io::Result<()> {
+    let problem = match index {
+        0 if t.index0.is_some() => "more than one INDEX 00",
+        0 if has_index1 => "INDEX 00 after INDEX 01",
+        1 if has_index1 => "more than one INDEX 01",
+        1 if t.index0.is_some_and(|index0| frames < index0) => "INDEX 01 before INDEX 00",
+        0 | 1 => return Ok(()),
+        _ if !has_index1 => "an index before INDEX 01",
+        _ if frames <= *t.indexes.last().unwrap_or(&t.start_sector) => "indexes out of order",
+        _ => return Ok(()),
+    };
+    Err(ErrorCode::CueMalformed.error(format!("Track {} has {}", t.number, problem)))
+}
+
+/// Tracks and metadata of the CUE sheet `cue`, with the name and type of every FILE and the
+/// first track in it
+fn parse_sheet(cue: &str, args: &ConvertOptions) -> io::Result<CueSheet> {
+    let mut tracks: Vec<Track> = Vec::with_capacity(32);
+    let mut metadata = DiscMetadata {
+        format: CueDisc::FORMAT,
+        ..Default::default()
+    };
+    // Offset from a REM OFFSET line following FILE, and the first track of that FILE
+    let mut file_offset: Option<i64> = None;
+    let mut file_first_track = 0;
+    let mut files: Vec<(String, FileType, usize)> = Vec::new();
+    let mut has_index1 = false;
+    // Session of the tracks read, and the lead-out and lead-in declared before the next one
+    let mut session = 0;
+    let mut lead_out: Option<u64> = None;
+    let mut lead_in: Option<u64> = None;
+    // Line of the track being read in verbose listings, logged once the next one starts
+    let mut listing = String::new();
+
+    // Sheets handed over as text may still start with the byte order mark of their file
+    let cue = cue.strip_prefix('\u{feff}').unwrap_or(cue);
+    for s in cue.lines() {
+        let words = tokenize(s);
+        let mut w = words.iter().map(String::as_str);
+        match w.next() {
+            Some("TRACK") => {
+                if let (Some(t), false) = (tracks.last(), has_index1) {
+                    return Err(missing_index1(t));
+                }
+                has_index1 = false;
+                log_listing(&mut listing);
+                let number = match w.next().map(str::parse) {
+                    Some(Ok(number)) => number,
+                    Some(Err(e)) => {
+                        return Err(ErrorCode::CueMalformed
+                            .error(format!("Error parsing track number! {}", e)))
+                    }
+                    None => return Err(ErrorCode::CueMalformed.error("Missing track number")),
+                };
+                // Modes of 2448 byte sectors are stored raw, followed by their subchannel
+                let (mode, subcode): (Mode, bool) = match w.next() {
+                    Some("AUDIO/2448") => (Mode::Audio, true),
+                    Some(mode) => match mode.strip_suffix("/2448") {
+                        Some(mode) => (format!("{}/2352", mode).as_str().into(), true),
+                        None => (mode.into(), false),
+                    },
+                    None => {
+                        return Err(
+                            ErrorCode::CueMalformed.error(format!("Track {} has no mode", number))
+                        )
+                    }
+                };
+                if args.verbose {
+                    listing = format!("Track {:>2}: {:12}", number, mode);
+                }
+                let mut track = Track::default();
+                track.number = number;
+                track.mode = mode;
+                track.subcode = subcode;
+                if let Some(size) = args.sector_size {
+                    if !track.set_sector_size(size) {
+                        return Err(ErrorCode::InvalidArguments.error(format!(
+                            "Track {} is {}, which has no sectors of {} bytes",
+                            number, mode, size
+                        )));
+                    }
+                }
+                track.byte_offset = file_offset;
+                track.session = session;
+                // Neither the lead-out nor the lead-in between sessions is in the image
+                if track.starts_session(tracks.last()) {
+                    let closed = tracks.last().map_or(1, Track::session);
+                    let lead_out = lead_out.take().unwrap_or(match closed {
+                        1 => FIRST_LEAD_OUT,
+                        _ => LEAD_OUT,
+                    });
+                    track.pregap = lead_out + lead_in.take().unwrap_or(LEAD_IN);
+                    if args.verbose {
+                        listing += &format!("session {} ", session);
+                    }
+                }
+                tracks.push(track);
+            }
+            Some("INDEX") => {
+                let index: u32 = match w.next() {
+                    Some(index_s) => {
+                        if args.verbose {
+                            listing += &format!("{} ", index_s);
+                        }
+                        match index_s.parse() {
+                            Ok(index) => index,
+                            Err(e) => {
+                                return Err(ErrorCode::CueMalformed
+                                    .error(format!("Error parsing index number! {}", e)))
+                            }
+                        }
+                    }
+                    None => return Err(ErrorCode::CueMalformed.error("Missing index number")),
+                };
+                let frames = match w.next() {
+                    Some(time) => {
+                        if args.verbose {
+                            listing += &format!("{} ", time);
+                        }
+                        time_to_frames(time)?
+                    }
+                    None => return Err(ErrorCode::CueMalformed.error("Missing INDEX time")),
+                };
+                let n = tracks.len();
+                let t = match tracks.last() {
+                    Some(t) => t,
+                    None => {
+                        return Err(ErrorCode::CueMalformed
+                            .error(format!("INDEX {:0>2} outside of a TRACK", index)))
+                    }
+                };
+                check_index(t, index, frames, has_index1)?;
+                // Tracks of a file may have different sector sizes, so count from the previous one
+                let previous = match n >= file_first_track + 2 {
+                    true => Some(&tracks[n - 2]),
+                    false => None,
+                };
+                let first_index = index == 0 || (index == 1 && t.index0.is_none());
+                if let (Some(p), true) = (previous, first_index) {
+                    if frames <= p.start_sector {
+                        return Err(ErrorCode::CueMalformed.error(format!(
+                            "Track {} doesn't start after track {} of the same file",
+                            t.number, p.number
+                        )));
+                    }
+                }
+                let position = |frames: u64, size: u64| match previous {
+                    Some(p) => p.start + (frames - p.start_sector) * p.sector_size(),
+                    None => frames * size,
+                };
+                let start = match t.index0 {
+                    Some(index0) => {
+                        position(index0, t.sector_size()) + (frames - index0) * t.sector_size()
+                    }
+                    None => position(frames, t.sector_size()),
+                };
+                let ends_previous = previous.is_some_and(|p| p.stop_sector.is_none());
+
+                let track = tracks.index_mut(n - 1);
+                match index {
+                    0 => track.index0 = Some(frames),
+                    1 => {
+                        track.start_sector = frames;
+                        track.start = start;
+                        has_index1 = true;
+                    }
+                    // Later indexes only subdivide the track
+                    _ => {
+                        track.indexes.push(frames);
+                        continue;
+                    }
+                }
+                // The previous track of the file ends where the pregap of this one begins
+                if ends_previous {
+                    tracks.index_mut(n - 2).stop_sector = Some(frames - 1);
+                }
+            }
+            Some("FILE") => match w.next() {
+                Some(filename) => {
+                    file_offset = None;
+                    file_first_track = tracks.len();
+                    files.push((
+                        String::from(filename),
+                        FileType::parse(w.next()),
+                        tracks.len(),
+                    ));
+                }
+                None => return Err(ErrorCode::CueMalformed.error("Error reading FILE row")),
+            },
+            Some(command @ ("TITLE" | "PERFORMER" | "SONGWRITER")) => {
+                let value = match w.next() {
+                    Some(value) => String::from(value),
+                    None => {
+                        return Err(
+                            ErrorCode::CueMalformed.error(format!("Missing {} text", command))
+                        )
+                    }
+                };
+                if args.verbose {
+                    listing += &format!("{} \"{}\" ", command.to_ascii_lowercase(), value);
+                }
+                // Entries before the first TRACK describe the whole disc
+                match tracks.last_mut() {
+                    Some(t) => t.cd_text.set(command, value),
+                    None => metadata.cd_text.set(command, value),
+                };
+            }
+            Some("CATALOG") => match w.next() {
+                Some(catalog) => {
+                    if args.verbose {
+                        listing += &format!("catalog {} ", catalog);
+                    }
+                    metadata.catalog = Some(String::from(catalog));
+                }
+                None => return Err(ErrorCode::CueMalformed.error("Missing CATALOG number")),
+            },
+            Some("ISRC") => {
+                let (t, isrc) = match (tracks.last_mut(), w.next()) {
+                    (Some(t), Some(isrc)) => (t, isrc),
+                    (None, _) => {
+                        return Err(ErrorCode::CueMalformed.error("ISRC outside of a TRACK"))
+                    }
+                    (_, None) => return Err(ErrorCode::CueMalformed.error("Missing ISRC code")),
+                };
+                if args.verbose {
+                    listing += &format!("isrc {} ", isrc);
+                }
+                t.isrc = Some(String::from(isrc));
+            }
+            Some("FLAGS") => {
+                let t = match tracks.last_mut() {
+                    Some(t) => t,
+                    None => return Err(ErrorCode::CueMalformed.error("FLAGS outside of a TRACK")),
+                };
+                for flag in w {
+                    if !t.flags.set(flag) && args.verbose {
+                        warning!("Ignoring unknown flag {} of track {}", flag, t.number);
+                    }
+                }
+                if args.verbose {
+                    listing += &format!("flags {} ", t.flags);
+                }
+            }
+            Some(gap @ ("PREGAP" | "POSTGAP")) => {
+                let sectors = match w.next().map(time_to_frames) {
+                    Some(Ok(sectors)) => sectors,
+                    Some(Err(e)) => return Err(e),
+                    None => {
+                        return Err(ErrorCode::CueMalformed.error(format!("Missing {} time", gap)))
+                    }
+                };
+                let t = match tracks.last_mut() {
+                    Some(t) => t,
+                    None => {
+                        return Err(
+                            ErrorCode::CueMalformed.error(format!("{} outside of a TRACK", gap))
+                        )
+                    }
+                };
+                // A track opening a session gets it after the gap between the sessions
+                if gap == "PREGAP" {
+                    t.pregap += sectors;
+                } else {
+                    t.postgap = sectors;
+                }
+                if args.verbose {
+                    listing += &format!("{} {} ", gap.to_ascii_lowercase(), sectors);
+                }
+            }
+            Some("SESSION") => session = session_number(w.next(), session)?,
+            Some("REM") => match w.next() {
+                Some("SESSION") => session = session_number(w.next(), session)?,
+                Some(key @ ("LEAD-OUT" | "LEAD-IN")) => {
+                    let sectors = match w.next().map(time_to_frames) {
+                        Some(Ok(sectors)) => sectors,
+                        Some(Err(e)) => return Err(e),
+                        None => {
+                            return Err(
+                                ErrorCode::CueMalformed.error(format!("Missing REM {} time", key))
+                            )
+                        }
+                    };
+                    // Before the first track it's where the lead-out of the disc starts
+                    match key {
+                        "LEAD-OUT" if tracks.is_empty() => metadata.lead_out = Some(sectors),
+                        "LEAD-OUT" => lead_out = Some(sectors),
+                        _ => lead_in = Some(sectors),
+                    }
+                }
+                Some("OFFSET") => {
+                    let offset = match w.next().map(str::parse::<i64>) {
+                        Some(Ok(offset)) => offset,
+                        _ => {
+                            return Err(
+                                ErrorCode::CueMalformed.error("Error parsing REM OFFSET bytes")
+                            )
+                        }
+                    };
+                    if args.verbose {
+                        listing += &format!("offset {} ", offset);
+                    }
+                    // An offset right after FILE applies to all of its tracks
+                    let in_file = tracks.len() > file_first_track;
+                    match (tracks.last_mut(), in_file) {
+                        (Some(t), true) => t.byte_offset = Some(offset),
+                        _ => file_offset = Some(offset),
+                    }
+                }
+                Some(key) => {
+                    let value = w.collect::<Vec<_>>().join(" ");
+                    if args.verbose {
+                        listing += &format!("{} {} ", key.to_ascii_lowercase(), value);
+                    }
+                    match tracks.last_mut() {
+                        Some(t) => t.rem.set(key, value),
+                        None => metadata.rem.set(key, value),
+                    }
+                }
+                None => {}
+            },
+            _ => {}
+        }
+    }
+    log_listing(&mut listing);
+    match tracks.last() {
+        Some(t) if !has_index1 => return Err(missing_index1(t)),
+        Some(_) => {}
+        None => return Err(ErrorCode::CueMalformed.error("No valid CUE data found")),
+    }
+    Ok((tracks, metadata, files))
+}
+
+/// Number of the session a `SESSION` or `REM SESSION` line starts after `current`
+fn session_number(number: Option<&str>, current: u32) -> io::Result<u32> {
+    let session = match number.map(str::parse::<u32>) {
+        Some(Ok(session)) if session > 0 => session,
+        Some(Ok(_)) => return Err(ErrorCode::CueMalformed.error("Sessions count from 1")),
+        Some(Err(e)) => {
+            return Err(
+                ErrorCode::CueMalformed.error(format!("Error parsing session number! {}", e))
+            )
+        }
+        None => return Err(ErrorCode::CueMalformed.error("Missing session number")),
+    };
+    if session < current {
+        return Err(ErrorCode::CueMalformed
+            .error(format!("Session {} follows session {}", session, current)));
+    }
+    Ok(session)
+}
+
+/// Parses the CUE sheet of `args` and opens the files it names
+fn read_cue(args: &mut ConvertOptions) -> io::Result<(Vec<Track>, DiscMetadata, Sources)> {
+    let (cue, charset) = match fs::read(long_path(&args.cue_file)) {
+        Ok(f) => charset::decode(&f),
+        Err(e) => {
+            return Err(ErrorCode::ReadFailed.wrap(&e, format!("Could not open CUE file: {}", e)))
+        }
+    };
+    if charset != Charset::Utf8 && args.verbose {
+        info!("CUE file is {}", charset);
+    }
+    let (mut tracks, metadata, files) = parse_sheet(&cue, args)?;
+
+    // Only the first file can be supplied in place of the one in the CUE sheet
+    let mut bin_from_cue = false;
+    if let Some((filename, _, _)) = files.first() {
+        if args.bin_file.is_empty() {
+            args.bin_file = filename.clone();
+            bin_from_cue = true;
+            if args.verbose {
+                warning!("BIN file not supplied. Reading BIN file from CUE file");
+            }
+        } else if pairing::entry_path(filename).file_name() != Path::new(&args.bin_file).file_name()
+            && args.verbose
+        {
+            warning!("Filename in CUE file doesn't match filename provided")
+        }
+    }
+    if bin_from_cue && !long_path(&args.bin_file).is_file() {
+        if let Some(bin) = pairing::bin_for_cue(&args.cue_file, &args.bin_file) {
+            if args.verbose {
+                warning!("BIN file {} not found, using {}", args.bin_file, bin);
+            }
+            args.bin_file = bin;
+        }
+    }
+    let sources = open_sources(args, &files, &mut tracks)?;
+    lay_out(&mut tracks, metadata.lead_out, sources.len(), args)?;
+    Ok((tracks, metadata, sources))
+}
+
+/// Moves the tracks of a CUE sheet to where they are on the disc and in the `bin_file_size`
+/// bytes of its files, ending the last one before the `lead_out` the sheet gives
+fn lay_out(
+    tracks: &mut [Track],
+    lead_out: Option<u64>,
+    bin_file_size: u64,
+    args: &ConvertOptions,
+) -> io::Result<()> {
+    // Shift tracks whose payload doesn't start on a sector boundary of the BIN file
+    for t in tracks.iter_mut() {
+        let offset = t
+            .byte_offset
+            .unwrap_or(if t.is_audio() { args.audio_offset } else { 0 });
+        t.start = match (t.start as i64).checked_add(offset) {
+            Some(start) if start >= 0 => start as u64,
+            _ => {
+                return Err(ErrorCode::CueMalformed.error(format!(
+                    "Offset {} moves track {} before the start of the BIN file",
+                    offset, t.number
+                )))
+            }
+        };
+    }
+
+    let last = match tracks.last_mut() {
+        Some(last) => last,
+        None => return Err(ErrorCode::CueMalformed.error("No valid CUE data found")),
+    };
+    if last.start >= bin_file_size {
+        return Err(ErrorCode::CueMalformed.error(format!(
+            "Track {} starts beyond the end of the BIN file",
+            last.number
+        )));
+    }
+    last.stop_sector = Some(stop_sector(last, bin_file_size, args.partial_sector)?);
+
+    // Gaps that aren't stored in the BIN file still move every following track on the disc
+    let mut shift = 0;
+    for t in tracks.iter_mut() {
+        // Stored pregaps come before the ones that aren't stored
+        t.index0 = t.index0.map(|index0| index0 + shift);
+        shift += t.pregap;
+        t.start_sector += shift;
+        t.stop_sector = t.stop_sector.map(|stop| stop + shift);
+        for index in t.indexes.iter_mut() {
+            *index += shift;
+        }
+        shift += t.postgap;
+    }
+
+    // The table of contents knows better where the disc ends than the size of the BIN file
+    let (last, lead_out) = match (tracks.last_mut(), lead_out) {
+        (Some(last), Some(lead_out)) => (last, lead_out),
+        _ => return Ok(()),
+    };
+    let stop = match lead_out.checked_sub(last.postgap + 1) {
+        Some(stop) if stop >= last.start_sector => stop,
+        _ => {
+            return Err(ErrorCode::CueMalformed.error(format!(
+                "The lead-out at {} doesn't come after the start of track {}",
+                frames_to_time(lead_out),
+                last.number
+            )))
+        }
+    };
+    // What was dumped past the lead-out is left to the surplus
+    if stop > last.stop_sector() {
+        warning!(
+            "The BIN file ends {} sectors before the lead-out, track {} may be cut short",
+            stop - last.stop_sector(),
+            last.number
+        );
+    } else {
+        last.stop_sector = Some(stop);
+    }
+    Ok(())
+}
+
+/// Last sector of the track `t` running up to the byte `end` of its file, which may be
+/// partway into a sector
+fn stop_sector(t: &Track, end: u64, policy: PartialSector) -> io::Result<u64> {
+    let sectors = (end - t.start) / t.sector_size();
+    let leftover = (end - t.start) % t.sector_size();
+    if leftover == 0 {
+        return Ok(t.start_sector + sectors - 1);
+    }
+    match policy {
+        PartialSector::Pad => Ok(t.start_sector + sectors),
+        PartialSector::Truncate if sectors > 0 => Ok(t.start_sector + sectors - 1),
+        PartialSector::Truncate => Err(ErrorCode::CorruptSectors.error(format!(
+            "Track {} doesn't have a single whole sector",
+            t.number
+        ))),
+        PartialSector::Error => Err(ErrorCode::CorruptSectors.error(format!(
+            "Track {} ends {} bytes into a sector, the file may be cut short",
+            t.number, leftover
+        ))),
+    }
+}
+
+/// Moves the `tracks` of the file at `start` of `sources` to the sector size it was really
+/// dumped with, where the CUE sheet says otherwise
+///
+/// The first data track tells: the sync pattern of raw sectors repeats every 2352 bytes, or
+/// every 2448 with subchannel after them, while cooked sectors of 2048 bytes have no sync
+/// but the ISO9660 volume descriptor at sector 16, and MODE2 sectors of 2336 bytes have it
+/// after the subheader of sector 16. Audio has nothing to tell by, so a file without a data
+/// track is taken as the sheet says.
+fn detect_sector_size(
+    sources: &mut Sources,
+    start: u64,
+    tracks: &mut [Track],
+    args: &ConvertOptions,
+) -> io::Result<()> {
+    let declared = match tracks.first() {
+        Some(t) if args.sector_size.is_none() => t.sector_size(),
+        _ => return Ok(()),
+    };
+    // Positions were counted in sectors of the sheet, which all tracks have to share
+    if tracks.iter().any(|t| t.sector_size() != declared) {
+        return Ok(());
+    }
+    let data = match tracks
+        .iter()
+        .find(|t| !t.is_audio() && t.mode != Mode::Unknown)
+    {
+        Some(data) => data.start / declared,
+        None => return Ok(()),
+    };
+    let mut holds = |position: u64, pattern: &[u8]| -> io::Result<bool> {
+        let mut bytes = [0u8; SYNC.len()];
+        sources.seek(SeekFrom::Start(start + position))?;
+        fill_sector(sources, &mut bytes[..pattern.len()])?;
+        Ok(bytes[..pattern.len()] == *pattern)
+    };
+    let mut detected = None;
+    for size in [SECTOR_SIZE, SECTOR_SIZE + SUBCODE_SIZE as u64] {
+        if holds(data * size, &SYNC)? && holds((data + 1) * size, &SYNC)? {
+            detected = Some(size);
+            break;
+        }
+    }
+    if detected.is_none() && holds((data + 16) * 2048, b"\x01CD001")? {
+        detected = Some(2048);
+    }
+    if detected.is_none() && holds((data + 16) * 2336 + 8, b"\x01CD001")? {
+        detected = Some(2336);
+    }
+    // Sectors are read from where the last one ended, which has to be the start again
+    sources.seek(SeekFrom::Start(0))?;
+
+    let size = match detected {
+        Some(size) if size != declared => size,
+        _ => return Ok(()),
+    };
+    if !tracks.iter().all(|t| t.clone().set_sector_size(size)) {
+        return Ok(());
+    }
+    if args.verbose {
+        info!(
+            "Sectors are {} bytes, not {} like the CUE sheet says",
+            size, declared
+        );
+    }
+    for t in tracks.iter_mut() {
+        t.start = t.start / declared * size;
+        t.set_sector_size(size);
+    }
+    Ok(())
+}
+
+/// Opens every FILE in order, moving the tracks of each behind those of the files before it
+///
+/// Times and positions in a CUE sheet count from the start of the FILE they follow, and
+/// tracks only end where the next one starts within the same file.
+fn open_sources(
+    args: &ConvertOptions,
+    files: &[(String, FileType, usize)],
+    tracks: &mut [Track],
+) -> io::Result<Sources> {
+    let mut sources = Sources::new(args.read_buffer_size());
+    // Sheets without a FILE entry only work with a BIN file supplied for them
+    let first = [(args.bin_file.clone(), FileType::Binary, 0)];
+    let files = match files.is_empty() {
+        true => &first[..],
+        false => files,
+    };
+    for (i, (name, file_type, first_track)) in files.iter().enumerate() {
+        let path = match i {
+            0 => args.bin_file.clone(),
+            _ if long_path(name).is_file() => name.clone(),
+            _ => pairing::file_for_cue(&args.cue_file, name).unwrap_or_else(|| name.clone()),
+        };
+        let sector_size = match first_track.checked_sub(1) {
+            Some(previous) => tracks[previous].sector_size(),
+            None => SECTOR_SIZE,
+        };
+        if let Err(e) = sources.push(&long_path(&path), *file_type, sector_size) {
+            return Err(ErrorCode::ReadFailed.wrap(&e, format!("Could not open BIN file\n{}", e)));
+        }
+        let end = files.get(i + 1).map_or(tracks.len(), |f| f.2);
+        let start = sources.starts()[i];
+        detect_sector_size(&mut sources, start, &mut tracks[*first_track..end], args)?;
+    }
+
+    let starts = sources.starts();
+    for (i, (_, _, first_track)) in files.iter().enumerate().skip(1) {
+        let end = files.get(i + 1).map_or(tracks.len(), |f| f.2);
+        if *first_track == 0 || *first_track == end {
+            continue;
+        }
+        let previous = &mut tracks[first_track - 1];
+        if previous.stop_sector.is_none() {
+            if previous.start >= starts[i] {
+                return Err(ErrorCode::CueMalformed.error(format!(
+                    "Track {} starts beyond the end of its file",
+                    previous.number
+                )));
+            }
+            previous.stop_sector = Some(stop_sector(previous, starts[i], args.partial_sector)?);
+        }
+        let sector = previous.stop_sector() + 1;
+        for t in tracks[*first_track..end].iter_mut() {
+            t.start += starts[i];
+            t.start_sector += sector;
+            t.index0 = t.index0.map(|index0| index0 + sector);
+            t.stop_sector = t.stop_sector.map(|stop| stop + sector);
+            for index in t.indexes.iter_mut() {
+                *index += sector;
+            }
+        }
+    }
+    Ok(sources)
+}
+
+/// Writes `<name>.cue` referencing the converted `tracks`, so emulators can load them as a disc
+pub(crate) fn write_converted(
+    metadata: &DiscMetadata,
+    tracks: &[(Track, OutputFile)],
+    a: &ConvertOptions,
+) -> io::Result<OutputFile> {
+    let filename = format!("{}.cue", a.output_name);
+    check_not_input(&filename, &a.cue_file)?;
+
+    let mut sheet = disc_header(metadata);
+    let sessions = tracks.last().map_or(1, |(t, _)| t.session());
+    for (i, (t, output)) in tracks.iter().enumerate() {
+        let (file_type, track_type) = match t.converted_cue_types(a) {
+            Some(types) => types,
+            None => {
+                return Err(ErrorCode::Unsupported.error(format!(
+                    "Track {} was converted to a layout CUE sheets can't describe",
+                    t.number
+                )))
+            }
+        };
+        /*
+         * Appended pregaps are at the end of the previous file, so the track starts
+         * there and only continues in its own file from INDEX 01 on
+         */
+        let previous = i.checked_sub(1).map(|i| &tracks[i].0);
+        let starts_session = t.starts_session(previous);
+        let appended_pregap = match previous {
+            Some(p) if a.gap_policy == GapPolicy::Append && p.is_audio() && t.is_audio() => {
+                t.pregap > 0 && !starts_session
+            }
+            _ => false,
+        };
+        // ECM outputs are referenced by the name `unecm` restores them to, which is found too
+        let name = file_name(&output.filename);
+        let name = match a.ecm {
+            true => name.strip_suffix(".ecm").unwrap_or(name),
+            false => name,
+        };
+        let file = format!("FILE \"{}\" {}\n", name, file_type);
+        // The parts of a track split at its indexes after the first start files of their own
+        if previous.is_some_and(|p| p.number == t.number) {
+            sheet += &file;
+            sheet += &format!("    INDEX {:0>2} 00:00:00\n", t.split_index);
+            if t.postgap > 0 && a.gap_policy == GapPolicy::Skip {
+                sheet += &format!("    POSTGAP {}\n", frames_to_time(t.postgap));
+            }
+            continue;
+        }
+        let (session, pregap) = session_header(t, previous, sessions);
+        sheet += &session;
+        if !appended_pregap {
+            sheet += &file;
+        }
+        sheet += &converted_header(t, track_type, a);
+        // Gaps left out of the outputs still have to be there when the disc is played back,
+        // like the pregap after the lead-in of a session, which is never written
+        let gaps_skipped = !t.is_audio() || a.gap_policy == GapPolicy::Skip || starts_session;
+        if pregap > 0 && gaps_skipped {
+            sheet += &format!("    PREGAP {}\n", frames_to_time(pregap));
+        }
+        // Pregap and silence written before the track stay its pregap
+        let lead_in = t.lead_in(a);
+        if let (Some(p), true) = (previous, appended_pregap) {
+            let index0 = p.output_sectors(a) - t.pregap;
+            sheet += &format!("    INDEX 00 {}\n{}", frames_to_time(index0), file);
+        } else if lead_in > 0 {
+            sheet += "    INDEX 00 00:00:00\n";
+        }
+        sheet += &converted_indexes(t, lead_in);
+        if t.postgap > 0 && gaps_skipped {
+            sheet += &format!("    POSTGAP {}\n", frames_to_time(t.postgap));
+        }
+    }
+
+    let output = write_file(filename, &sheet, a)?;
+    if a.verbose {
+        info!("cue: {}", output.filename);
+    }
+
+    Ok(output)
+}
+
+/// CUE sheet of the output `file` of audio track `t` alone, for encoders embedding it
+///
+/// The metadata of the disc comes first, and the indexes count from the start of the output
+/// like in the CUE sheet of `write_converted`.
+#[cfg(feature = "flac")]
+pub(crate) fn track_sheet(
+    t: &Track,
+    metadata: &DiscMetadata,
+    file: &str,
+    a: &ConvertOptions,
+) -> String {
+    let mut sheet = disc_header(metadata);
+    sheet += &format!("FILE \"{}\" WAVE\n", quote(file));
+    sheet += &converted_header(t, Mode::Audio.as_ref(), a);
+    let lead_in = t.lead_in(a);
+    if lead_in > 0 {
+        sheet += "    INDEX 00 00:00:00\n";
+    }
+    sheet + &converted_indexes(t, lead_in)
+}
+
+/// `TRACK` line of the converted `t` followed by its metadata
+fn converted_header(t: &Track, track_type: &str, a: &ConvertOptions) -> String {
+    // Players would undo pre-emphasis the output no longer has
+    if t.deemphasized(a) {
+        let mut t = t.clone();
+        t.flags.pre_emphasis = false;
+        return track_header(&t, track_type);
+    }
+    track_header(t, track_type)
+}
+
+/// `INDEX 01` onwards of the converted `t`, whose output has `lead_in` sectors before it
+fn converted_indexes(t: &Track, lead_in: u64) -> String {
+    let mut sheet = format!("    INDEX 01 {}\n", frames_to_time(lead_in));
+    for (n, index) in t.indexes.iter().enumerate() {
+        sheet += &format!(
+            "    INDEX {:0>2} {}\n",
+            n + 2,
+            frames_to_time(lead_in + index - t.start_sector)
+        );
+    }
+    sheet
+}
+
+/// Writes `<name>.cue` describing `bin_file`, a single BIN holding every track in raw sectors
+pub(crate) fn write_normalized(
+    metadata: &DiscMetadata,
+    tracks: &[Track],
+    bin_file: &str,
+    a: &ConvertOptions,
+) -> io::Result<OutputFile> {
+    let filename = format!("{}.cue", a.output_name);
+    check_not_input(&filename, &a.cue_file)?;
+
+    let raw = tracks
+        .iter()
+        .map(|t| {
+            let mut t = t.clone();
+            t.mode = t.mode.raw();
+            t
+        })
+        .collect::<Vec<_>>();
+    let sheet = disc_header(metadata) + &write(&raw, file_name(bin_file));
+    let output = write_file(filename, &sheet, a)?;
+    if a.verbose {
+        info!("cue: {}", output.filename);
+    }
+
+    Ok(output)
+}
+
+/// CUE sheet of `tracks` stored one after another in the single BIN file `bin_file`
+///
+/// Every track is stored in sectors of its mode, with their subchannel after them for tracks
+/// of 2448 byte images, from `INDEX 00` when it has one, so its
+/// pregap before that and its postgap become `PREGAP` and `POSTGAP` commands. Metadata of
+/// the tracks, like CD-TEXT and `FLAGS`, is written along with them.
+pub fn write(tracks: &[Track], bin_file: &str) -> String {
+    let mut sheet = format!("FILE \"{}\" BINARY\n", quote(bin_file));
+    let mut first = 0;
+    let sessions = tracks.last().map_or(1, Track::session);
+    for (i, t) in tracks.iter().enumerate() {
+        let mode = match t.subcode {
+            true => format!("{}/2448", t.mode.as_ref().trim_end_matches("/2352")),
+            false => t.mode.to_string(),
+        };
+        let (session, pregap) = session_header(t, i.checked_sub(1).map(|i| &tracks[i]), sessions);
+        sheet += &session;
+        sheet += &track_header(t, &mode);
+        if pregap > 0 {
+            sheet += &format!("    PREGAP {}\n", frames_to_time(pregap));
+        }
+        let mut start = first;
+        if let Some(index0) = t.index0 {
+            sheet += &format!("    INDEX 00 {}\n", frames_to_time(start));
+            start += t.start_sector - t.pregap - index0;
+        }
+        sheet += &format!("    INDEX 01 {}\n", frames_to_time(start));
+        for (n, index) in t.indexes.iter().enumerate() {
+            sheet += &format!(
+                "    INDEX {:0>2} {}\n",
+                n + 2,
+                frames_to_time(start + index - t.start_sector)
+            );
+        }
+        if t.postgap > 0 {
+            sheet += &format!("    POSTGAP {}\n", frames_to_time(t.postgap));
+        }
+        first = start + t.stop_sector() + 1 - t.start_sector;
+    }
+    sheet
+}
+
+/// `REM SESSION` line of `t` on a disc of `sessions`, if it opens one, and what's left of
+/// its pregap for `PREGAP`
+///
+/// The gap before a later session goes into `REM LEAD-OUT` and `REM LEAD-IN`, which give it
+/// back when the sheet is read again.
+fn session_header(t: &Track, previous: Option<&Track>, sessions: u32) -> (String, u64) {
+    if previous.is_none() && sessions > 1 {
+        return (format!("REM SESSION {:0>2}\n", t.session()), t.pregap);
+    }
+    let previous = match previous {
+        Some(p) if t.starts_session(Some(p)) => p,
+        _ => return (String::new(), t.pregap),
+    };
+    let lead_in = LEAD_IN.min(t.pregap);
+    let lead_out = match previous.session() {
+        1 => FIRST_LEAD_OUT,
+        _ => LEAD_OUT,
+    }
+    .min(t.pregap - lead_in);
+    let lines = format!(
+        "REM LEAD-OUT {}\nREM SESSION {:0>2}\nREM LEAD-IN {}\n",
+        frames_to_time(lead_out),
+        t.session(),
+        frames_to_time(lead_in)
+    );
+    (lines, t.pregap - lead_in - lead_out)
+}
+
+/// Metadata of the whole disc, written before the first `FILE`
+fn disc_header(metadata: &DiscMetadata) -> String {
+    let mut sheet = String::new();
+    for (key, value) in metadata.rem.entries() {
+        sheet += &format!("REM {} {}\n", key, rem_value(value));
+    }
+    if let Some(catalog) = &metadata.catalog {
+        sheet += &format!("CATALOG {}\n", catalog);
+    }
+    for (command, value) in metadata.cd_text.entries() {
+        sheet += &format!("{} \"{}\"\n", command, quote(value));
+    }
+    sheet
+}
+
+/// `TRACK` line of `t` followed by its metadata
+fn track_header(t: &Track, track_type: &str) -> String {
+    let mut sheet = format!("  TRACK {:0>2} {}\n", t.number, track_type);
+    for (command, value) in t.cd_text.entries() {
+        sheet += &format!("    {} \"{}\"\n", command, quote(value));
+    }
+    for (key, value) in t.rem.entries() {
+        sheet += &format!("    REM {} {}\n", key, rem_value(value));
+    }
+    if let Some(isrc) = &t.isrc {
+        sheet += &format!("    ISRC {}\n", isrc);
+    }
+    if !t.flags.is_empty() {
+        sheet += &format!("    FLAGS {}\n", t.flags);
+    }
+    sheet
+}
+
+/// Splits a CUE sheet line into words, keeping quoted strings like `"Game (Disc 1).bin"` whole
+fn tokenize(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (word, next) = match rest.strip_prefix('"') {
+            /*
+             * Quotes can't be escaped, so a string ends at the last quote followed by
+             * whitespace, which keeps titles like "Best "Of"" in one piece. An unterminated
+             * string runs to the end of the line.
+             */
+            Some(quoted) => match quoted
+                .rmatch_indices('"')
+                .find(|(i, _)| !quoted[i + 1..].starts_with(|c: char| !c.is_whitespace()))
+            {
+                Some((end, _)) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len())),
+        };
+        words.push(String::from(word));
+        rest = next.trim_start();
+    }
+    words
+}
+
+// CUE sheets have no way of escaping quotes inside strings
+fn quote(s: &str) -> String {
+    s.replace('"', "'")
+}
+
+// REM values are only quoted when they wouldn't survive as a single word
+fn rem_value(s: &str) -> String {
+    match s.contains(char::is_whitespace) {
+        true => format!("\"{}\"", quote(s)),
+        false => String::from(s),
+    }
+}
+
+/// Logs what verbose listings gathered about a track, or about the disc before the first
+fn log_listing(listing: &mut String) {
+    if !listing.is_empty() {
+        info!("{}", listing.trim_end());
+        listing.clear();
+    }
+}
+
+/// Line of a track in verbose listings, with its mode and where its indexes start
+pub(crate) fn describe_track(track: &Track) -> String {
+    let mut line = format!("Track {:>2}: {:12}", track.number, track.mode);
+    if let Some(index0) = track.index0 {
+        line += &format!("00 {} ", frames_to_time(index0));
+    }
+    line + &format!("01 {}", frames_to_time(track.start_sector))
+}
+
+pub(crate) fn frames_to_time(frames: u64) -> String {
+    format!(
+        "{:0>2}:{:0>2}:{:0>2}",
+        frames / 75 / 60,
+        frames / 75 % 60,
+        frames % 75
+    )
+}
+
+fn time_to_frames(s: &str) -> io::Result<u64> {
+    let invalid =
+        || ErrorCode::CueMalformed.error(format!("Invalid time {}, expected mm:ss:ff", s));
+    let mut duration = [0u64; 3]; // minutes,seconds,frames
+
+    let mut parts = s.split(':');
+    for t in duration.iter_mut() {
+        *t = match parts.next().map(str::parse) {
+            Some(Ok(t)) => t,
+            _ => return Err(invalid()),
+        };
+    }
+    if parts.next().is_some() || duration[1] >= 60 || duration[2] >= 75 {
+        return Err(invalid());
+    }
+    // Far beyond any disc, but keeps byte positions computed from it from overflowing
+    let frames = duration[0]
+        .checked_mul(60 * 75)
+        .and_then(|frames| frames.checked_add(duration[1] * 75 + duration[2]));
+    match frames {
+        Some(frames) if frames <= u32::MAX as u64 => Ok(frames),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tracks of `cue` laid out over a BIN file of `sectors` raw sectors
+    fn lay_out_sheet(cue: &str, sectors: u64) -> io::Result<Vec<Track>> {
+        let args = ConvertOptions::default();
+        let (mut tracks, metadata, _) = parse_sheet(cue, &args)?;
+        lay_out(&mut tracks, metadata.lead_out, sectors * SECTOR_SIZE, &args)?;
+        Ok(tracks)
+    }
+
+    fn malformed(cue: &str) -> bool {
+        parse_sheet(cue, &ConvertOptions::default())
+            .is_err_and(|e| ErrorCode::of(&e) == ErrorCode::CueMalformed)
+    }
+
+    #[test]
+    fn times_are_in_frames() {
+        assert_eq!(time_to_frames("00:00:00").unwrap(), 0);
+        assert_eq!(time_to_frames("01:02:03").unwrap(), 60 * 75 + 2 * 75 + 3);
+        assert!(time_to_frames("00:60:00").is_err());
+        assert!(time_to_frames("00:00:75").is_err());
+        assert!(time_to_frames("00:00").is_err());
+        assert_eq!(frames_to_time(4653), "01:02:03");
+    }
+
+    #[test]
+    fn quoted_words_keep_their_quotes_and_spaces() {
+        assert_eq!(
+            tokenize(r#"  TITLE "Best "Of" Hits"  "#),
+            ["TITLE", r#"Best "Of" Hits"#]
+        );
+        assert_eq!(
+            tokenize(r#"FILE "a b.bin" BINARY"#),
+            ["FILE", "a b.bin", "BINARY"]
+        );
+        assert_eq!(
+            tokenize(r#"TITLE "unterminated"#),
+            ["TITLE", "unterminated"]
+        );
+    }
+
+    #[test]
+    fn gaps_move_the_tracks_after_them() {
+        let cue = "FILE \"a.bin\" BINARY
+  TRACK 01 MODE1/2352
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    PREGAP 00:02:00
+    INDEX 00 00:10:00
+    INDEX 01 00:12:00
+";
+        let tracks = lay_out_sheet(cue, 1000).unwrap();
+        assert_eq!((tracks[0].start_sector, tracks[0].stop_sector()), (0, 749));
+        // The stored pregap from INDEX 00 comes first, then the 150 sectors of PREGAP
+        assert_eq!(tracks[1].index0, Some(750));
+        assert_eq!(tracks[1].pregap, 150);
+        assert_eq!(
+            (tracks[1].start_sector, tracks[1].stop_sector()),
+            (1050, 1149)
+        );
+        assert_eq!(tracks[1].start, 900 * SECTOR_SIZE);
+    }
+
+    #[test]
+    fn later_sessions_start_after_the_lead_out_and_lead_in() {
+        let cue = "FILE \"a.bin\" BINARY
+  TRACK 01 AUDIO
+    INDEX 01 00:00:00
+REM SESSION 02
+  TRACK 02 MODE2/2352
+    INDEX 01 00:10:00
+";
+        let tracks = lay_out_sheet(cue, 1000).unwrap();
+        assert_eq!(tracks[1].session(), 2);
+        assert_eq!(tracks[1].pregap, FIRST_LEAD_OUT + LEAD_IN);
+        assert_eq!(tracks[1].start_sector, 750 + FIRST_LEAD_OUT + LEAD_IN);
+    }
+
+    #[test]
+    fn rejects_misplaced_indexes() {
+        let track = "FILE \"a.bin\" BINARY\n  TRACK 01 AUDIO\n";
+        assert!(malformed(&format!(
+            "{}    INDEX 01 00:02:00\n    INDEX 00 00:00:00\n",
+            track
+        )));
+        assert!(malformed(&format!(
+            "{}    INDEX 00 00:02:00\n    INDEX 01 00:00:00\n",
+            track
+        )));
+        assert!(malformed(&format!("{}    INDEX 02 00:02:00\n", track)));
+        assert!(malformed(&format!(
+            "{}  TRACK 02 AUDIO\n    INDEX 01 00:00:00\n",
+            track
+        )));
+        assert!(malformed("    INDEX 01 00:00:00\n"));
+    }
+}
@@ -0,0 +1,42 @@
+//! Logiqx dat fragments describing the files a conversion produced
+
+use std::io;
+
+use crate::log::info;
+use crate::longpath::file_name;
+use crate::output::{write_file, OutputFile};
+use crate::toc::xml_escape;
+use crate::ConvertOptions;
+
+/// Writes a `<game>` element listing `outputs` to `<name>.game.xml`, ready to paste into a dat
+pub(crate) fn write_fragment(outputs: &[OutputFile], a: &ConvertOptions) -> io::Result<OutputFile> {
+    let name = file_name(&a.output_name);
+    let mut fragment = format!(
+        "<game name=\"{}\">\n\t<description>{}</description>\n",
+        xml_escape(name),
+        xml_escape(name)
+    );
+
+    for output in outputs {
+        let rom = xml_escape(file_name(&output.filename));
+        fragment += &match &output.hashes {
+            Some(hashes) => format!(
+                "\t<rom name=\"{}\" size=\"{}\" crc=\"{}\" md5=\"{}\" sha1=\"{}\"/>\n",
+                rom,
+                output.size,
+                hashes.crc32_hex(),
+                hashes.md5_hex(),
+                hashes.sha1_hex()
+            ),
+            None => format!("\t<rom name=\"{}\" size=\"{}\"/>\n", rom, output.size),
+        };
+    }
+    fragment += "</game>\n";
+
+    let output = write_file(format!("{}.game.xml", a.output_name), &fragment, a)?;
+    if a.verbose {
+        info!("dat: {}", output.filename);
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,225 @@
+use std::fs;
+use std::io;
+use std::ops::Range;
+
+use crate::error::ErrorCode;
+use crate::log::info;
+use crate::output::{OutputFile, OutputWriter};
+use crate::track::Track;
+use crate::{ConvertOptions, SECTOR_SIZE};
+
+/// A disc image tracks can be extracted from, regardless of the format it's stored in
+pub trait Disc {
+    /// Tracks of the disc in the order they appear on the disc
+    fn tracks(&self) -> &[Track];
+
+    /// Reads the raw sector at `lba` into `sector`, zero padding whatever the image lacks
+    fn read_sector(&mut self, lba: u64, sector: &mut [u8]) -> io::Result<()>;
+
+    /// Reads the subcode stored with the sector at `lba` into `subcode`, zero filling it
+    /// for sectors and formats without any
+    fn read_subcode(&mut self, _lba: u64, subcode: &mut [u8]) -> io::Result<()> {
+        subcode.fill(0);
+        Ok(())
+    }
+
+    /// Copies the raw sectors `lbas` straight from the image into `output`, for images storing
+    /// them back to back in a plain file, returning how many it copied
+    ///
+    /// Copying stops short where sectors have to be read one by one instead, e.g. at the end
+    /// of a track, and the default copies none.
+    fn copy_sectors(&mut self, _lbas: Range<u64>, _output: &mut fs::File) -> io::Result<u64> {
+        Ok(0)
+    }
+
+    fn metadata(&self) -> &DiscMetadata;
+
+    /// Data stored in the image past the end of the disc layout
+    fn surplus(&self) -> Option<&Surplus> {
+        None
+    }
+}
+
+/// A disc with tracks and metadata worked out beyond what its image says, like the titles
+/// looked up on MusicBrainz
+pub(crate) struct Retagged {
+    pub(crate) disc: Box<dyn Disc>,
+    pub(crate) tracks: Vec<Track>,
+    pub(crate) metadata: DiscMetadata,
+}
+
+impl Disc for Retagged {
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn read_sector(&mut self, lba: u64, sector: &mut [u8]) -> io::Result<()> {
+        self.disc.read_sector(lba, sector)
+    }
+
+    fn read_subcode(&mut self, lba: u64, subcode: &mut [u8]) -> io::Result<()> {
+        self.disc.read_subcode(lba, subcode)
+    }
+
+    fn copy_sectors(&mut self, lbas: Range<u64>, output: &mut fs::File) -> io::Result<u64> {
+        self.disc.copy_sectors(lbas, output)
+    }
+
+    fn metadata(&self) -> &DiscMetadata {
+        &self.metadata
+    }
+
+    fn surplus(&self) -> Option<&Surplus> {
+        self.disc.surplus()
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct DiscMetadata {
+    /// Name of the format the disc was read from
+    pub format: &'static str,
+    /// Size of the image in bytes, for formats storing it in a single file
+    pub image_size: Option<u64>,
+    /// CD-TEXT of the whole disc, per track CD-TEXT is on the tracks
+    pub cd_text: CdText,
+    /// Metadata from the `REM` lines of the whole disc
+    pub rem: RemMetadata,
+    /// UPC/EAN of the disc from the `CATALOG` command
+    pub catalog: Option<String>,
+    /// Serial of the game on a PlayStation disc, like `SLUS-01234`
+    pub serial: Option<String>,
+    /// Sector the lead-out starts at, for CUE sheets giving it with `REM LEAD-OUT` before
+    /// their first track
+    pub lead_out: Option<u64>,
+}
+
+/// Metadata rippers like EAC and XLD store in `REM` lines of CUE sheets
+#[derive(Default, Clone, Debug)]
+pub struct RemMetadata {
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    /// FreeDB disc ID
+    pub disc_id: Option<String>,
+    pub comment: Option<String>,
+    /// Every other `REM` line as key and value, e.g. `REPLAYGAIN_ALBUM_GAIN` and `-7.5 dB`
+    pub other: Vec<(String, String)>,
+}
+
+impl RemMetadata {
+    pub(crate) fn set(&mut self, key: &str, value: String) {
+        match key {
+            "GENRE" => self.genre = Some(value),
+            "DATE" => self.date = Some(value),
+            "DISCID" => self.disc_id = Some(value),
+            "COMMENT" => self.comment = Some(value),
+            _ => self.other.push((String::from(key), value)),
+        }
+    }
+
+    /// Entries as `REM` keys and their values, in the syntax they were read with
+    pub(crate) fn entries(&self) -> Vec<(&str, &str)> {
+        let known = [
+            ("GENRE", &self.genre),
+            ("DATE", &self.date),
+            ("DISCID", &self.disc_id),
+            ("COMMENT", &self.comment),
+        ];
+        known
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value.as_deref()?)))
+            .chain(self.other.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .collect()
+    }
+}
+
+/// CD-TEXT entries of a disc or track, from the CUE sheet `TITLE`, `PERFORMER` and `SONGWRITER`
+#[derive(Default, Clone, Debug)]
+pub struct CdText {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub songwriter: Option<String>,
+}
+
+impl CdText {
+    /// Sets the entry of a CUE sheet command, returning false for commands that aren't CD-TEXT
+    pub(crate) fn set(&mut self, command: &str, value: String) -> bool {
+        match command {
+            "TITLE" => self.title = Some(value),
+            "PERFORMER" => self.performer = Some(value),
+            "SONGWRITER" => self.songwriter = Some(value),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Entries as CUE sheet commands and their values
+    pub(crate) fn entries(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("TITLE", &self.title),
+            ("PERFORMER", &self.performer),
+            ("SONGWRITER", &self.songwriter),
+        ]
+        .into_iter()
+        .filter_map(|(command, value)| Some((command, value.as_deref()?)))
+        .collect()
+    }
+}
+
+/// Data left in an image after the end of the layout described by its table of contents
+#[derive(Clone, Copy)]
+pub struct Surplus {
+    /// Sector the surplus starts at
+    pub start_sector: u64,
+    /// Length of the surplus in bytes
+    pub length: u64,
+}
+
+impl Surplus {
+    pub(crate) fn write_to_file(
+        &self,
+        disc: &mut dyn Disc,
+        a: &ConvertOptions,
+    ) -> io::Result<OutputFile> {
+        let filename = format!("{}-surplus.bin", a.output_name);
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+
+        let mut writer = match OutputWriter::create(filename, a) {
+            Ok(t_writer) => t_writer,
+            Err(e) => {
+                return Err(
+                    ErrorCode::WriteFailed.wrap(&e, format!("Could not write surplus: {}", e))
+                )
+            }
+        };
+
+        let mut remaining = self.length;
+        let mut lba = self.start_sector;
+        while remaining > 0 {
+            if let Err(e) = disc.read_sector(lba, &mut sector) {
+                return Err(ErrorCode::ReadFailed.wrap(&e, format!("Could not read surplus {}", e)));
+            }
+            let length = remaining.min(SECTOR_SIZE);
+            if let Err(e) = writer.write_all(&sector[..length as usize]) {
+                return Err(
+                    ErrorCode::WriteFailed.wrap(&e, format!("Could not write surplus {}", e))
+                );
+            }
+            remaining -= length;
+            lba += 1;
+        }
+
+        let output = match writer.finish() {
+            Ok(output) => output,
+            Err(e) => {
+                return Err(
+                    ErrorCode::WriteFailed.wrap(&e, format!("Could not write surplus {}", e))
+                )
+            }
+        };
+        if a.verbose {
+            info!("surplus: {}", output.summary());
+        }
+
+        Ok(output)
+    }
+}
@@ -0,0 +1,168 @@
+//! IDs of audio CDs in the FreeDB and MusicBrainz databases, worked out from their TOC
+//!
+//! Both are taken from where the tracks start and the disc ends, in frames from the start of
+//! the lead-in 150 frames before LBA 0, so they only need the layout and not a sector.
+
+use crate::hash::Sha1;
+use crate::track::Track;
+
+/// Frames of the lead-in before the first sector of the image
+const LEAD_IN: u64 = 150;
+
+/// IDs a disc is looked up by in metadata databases
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscIds {
+    /// CDDB ID of FreeDB and GnuDB, eight hex digits like `"b10e5d0d"`
+    pub freedb: String,
+    /// MusicBrainz disc ID, 28 characters like `"49HHV7Eb8UKF3aQiNmu1GR8vKTY-"`
+    pub musicbrainz: String,
+}
+
+/// IDs of the disc of `tracks`, `None` if it has no audio tracks to look up
+///
+/// The data track at the end of an Enhanced CD is in a session of its own, which
+/// MusicBrainz leaves out like a CD player does. FreeDB counts it along with the gap between
+/// the sessions, which only images telling their sessions store, like CUE sheets with
+/// `REM SESSION` lines, so other images of such discs may get a FreeDB ID other than the
+/// pressed one.
+pub fn disc_ids(tracks: &[Track]) -> Option<DiscIds> {
+    if !tracks.iter().any(Track::is_audio) {
+        return None;
+    }
+    Some(DiscIds {
+        freedb: format!("{:08x}", freedb_id(tracks)),
+        musicbrainz: musicbrainz_id(session(tracks)),
+    })
+}
+
+/// First frame after the last of `tracks`
+fn lead_out(tracks: &[Track]) -> u64 {
+    let last = &tracks[tracks.len() - 1];
+    last.stop_sector() + 1 + last.postgap() + LEAD_IN
+}
+
+/// Tracks of the first session, or all but a data track after the audio ones for images
+/// that don't tell their sessions
+fn session(tracks: &[Track]) -> &[Track] {
+    let first = tracks.iter().take_while(|t| t.session() == 1).count();
+    if first > 0 && first < tracks.len() {
+        return &tracks[..first];
+    }
+    match tracks {
+        [audio @ .., last] if !last.is_audio() && audio.iter().any(Track::is_audio) => audio,
+        _ => tracks,
+    }
+}
+
+fn freedb_id(tracks: &[Track]) -> u32 {
+    let seconds = |frame: u64| frame / 75;
+    let mut digits = 0;
+    for t in tracks {
+        let mut n = seconds(t.start_sector() + LEAD_IN);
+        while n > 0 {
+            digits += n % 10;
+            n /= 10;
+        }
+    }
+    let length = seconds(lead_out(tracks)) - seconds(tracks[0].start_sector() + LEAD_IN);
+    ((digits % 255) << 24 | length << 8 | tracks.len() as u64) as u32
+}
+
+fn musicbrainz_id(tracks: &[Track]) -> String {
+    let first = tracks[0].number();
+    let last = tracks[tracks.len() - 1].number();
+    // The lead-out and then the start of tracks 1 to 99, 0 for those the disc doesn't have
+    let mut offsets = [0; 100];
+    offsets[0] = lead_out(tracks);
+    for t in tracks.iter().filter(|t| (1..=99).contains(&t.number())) {
+        offsets[t.number() as usize] = t.start_sector() + LEAD_IN;
+    }
+
+    let mut toc = format!("{:02X}{:02X}", first, last);
+    for offset in offsets {
+        toc += &format!("{:08X}", offset);
+    }
+    let mut sha1 = Sha1::new();
+    sha1.update(toc.as_bytes());
+    base64(&sha1.finish())
+}
+
+/// Base64 with the `.`, `_` and `-` MusicBrainz uses instead of `+`, `/` and `=`, which
+/// don't go well in URLs
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789._";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for n in 0..4 {
+            match n <= chunk.len() {
+                true => encoded.push(ALPHABET[(bits >> (18 - 6 * n) & 63) as usize] as char),
+                false => encoded.push('-'),
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::track::Mode;
+
+    /// Tracks starting at the LBAs of `starts`, in `sessions` if it isn't empty, with a disc
+    /// ending before `end`
+    fn disc(modes: &[Mode], starts: &[u64], sessions: &[u32], end: u64) -> Vec<Track> {
+        let mut tracks = Vec::new();
+        for (i, (&mode, &start)) in modes.iter().zip(starts).enumerate() {
+            let stop = starts.get(i + 1).copied().unwrap_or(end) - 1;
+            let mut track = Track::new(i as u32 + 1, mode, start, stop);
+            track.session = sessions.get(i).copied().unwrap_or_default();
+            tracks.push(track);
+        }
+        tracks
+    }
+
+    #[test]
+    fn musicbrainz_documentation_example() {
+        // The TOC of the MusicBrainz disc ID calculation docs, in LBAs rather than frames
+        let starts = [0, 15213, 32164, 46442, 63264, 80339];
+        let tracks = disc(&[Mode::Audio; 6], &starts, &[], 95312);
+        let ids = disc_ids(&tracks).unwrap();
+        assert_eq!(ids.musicbrainz, "49HHV7Eb8UKF3aQiNmu1GR8vKTY-");
+        assert_eq!(ids.freedb, "3404f606");
+    }
+
+    #[test]
+    fn enhanced_cd_leaves_the_data_session_to_freedb() {
+        // Three audio tracks, then a data track after the 11400 sectors between sessions
+        let modes = [Mode::Audio, Mode::Audio, Mode::Audio, Mode::Mode1_2352];
+        let starts = [0, 20000, 45000, 81400];
+        let mut tracks = disc(&modes, &starts, &[1, 1, 1, 2], 90000);
+        tracks[2].stop_sector = Some(69999);
+        let ids = disc_ids(&tracks).unwrap();
+        assert_eq!(ids.musicbrainz, "5GC2xPukEmCEOLJDB.a3MaCV0.Y-");
+        assert_eq!(ids.freedb, "2a04b004");
+
+        // Without sessions the data track after the audio is still left out of MusicBrainz
+        for t in tracks.iter_mut() {
+            t.session = 0;
+        }
+        assert_eq!(disc_ids(&tracks).unwrap().musicbrainz, ids.musicbrainz);
+    }
+
+    #[test]
+    fn data_discs_have_no_ids() {
+        let tracks = disc(&[Mode::Mode1_2352], &[0], &[], 1000);
+        assert_eq!(disc_ids(&tracks), None);
+    }
+
+    #[test]
+    fn base64_pads_with_dashes() {
+        assert_eq!(base64(b"rbc"), "cmJj");
+        assert_eq!(base64(b"rb"), "cmI-");
+        assert_eq!(base64(b"r"), "cg--");
+        assert_eq!(base64(&[0xfb, 0xff]), "._8-");
+    }
+}
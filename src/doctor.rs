@@ -0,0 +1,361 @@
+//! Diagnostics for the usual problems of bad dumps
+
+use std::fmt::Display;
+use std::io;
+
+use crate::disc::Disc;
+use crate::sector::{data_mode, has_sync, volume_blocks};
+use crate::track::{Mode, Track};
+use crate::{open_disc, ConvertOptions, SECTOR_SIZE};
+
+// Red Book: the first audio track after a data track needs a 2 second pregap
+const DATA_TO_AUDIO_PREGAP: u64 = 150;
+// Number of sectors sampled from a track when looking at its contents
+const SAMPLED_SECTORS: u64 = 64;
+// Red Book and Yellow Book: no track is shorter than 4 seconds
+const MIN_TRACK_SECTORS: u64 = 300;
+
+/// A problem found in an image, with a suggestion on how to deal with it
+pub struct Diagnostic {
+    /// Track the problem was found in, if it is about a single track
+    pub track: Option<u32>,
+    pub problem: String,
+    pub suggestion: Option<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(track) = self.track {
+            write!(f, "Track {:>2}: ", track)?;
+        }
+        write!(f, "{}", self.problem)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n          {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl Diagnostic {
+    /// The problem and its suggestion on a single line, as log messages are
+    pub(crate) fn summary(&self) -> String {
+        let mut summary = match self.track {
+            Some(track) => format!("Track {}: {}", track, self.problem),
+            None => self.problem.clone(),
+        };
+        if let Some(suggestion) = &self.suggestion {
+            summary += &format!(" ({})", suggestion);
+        }
+        summary
+    }
+}
+
+fn diagnostic(track: Option<&Track>, problem: String, suggestion: Option<&str>) -> Diagnostic {
+    Diagnostic {
+        track: track.map(Track::number),
+        problem,
+        suggestion: suggestion.map(String::from),
+    }
+}
+
+/// LBAs of up to `SAMPLED_SECTORS` sectors spread evenly over a track
+fn sampled_sectors(track: &Track) -> impl Iterator<Item = u64> {
+    let length = track.stop_sector() - track.start_sector() + 1;
+    let step = length.div_ceil(SAMPLED_SECTORS);
+    (track.start_sector()..=track.stop_sector()).step_by(step as usize)
+}
+
+/// Sum of the differences between consecutive samples of the same channel
+fn roughness(sector: &[u8], swapped: bool) -> u64 {
+    let samples: Vec<i32> = sector
+        .chunks_exact(2)
+        .map(|b| match swapped {
+            false => i16::from_le_bytes([b[0], b[1]]) as i32,
+            true => i16::from_be_bytes([b[0], b[1]]) as i32,
+        })
+        .collect();
+    samples
+        .windows(3)
+        .map(|w| (w[2] - w[0]).unsigned_abs() as u64)
+        .sum()
+}
+
+fn check_audio(disc: &mut dyn Disc, track: &Track, found: &mut Vec<Diagnostic>) -> io::Result<()> {
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    let (mut native, mut swapped, mut data_sectors) = (0, 0, 0);
+
+    for lba in sampled_sectors(track) {
+        disc.read_sector(lba, &mut sector)?;
+        if has_sync(&sector) {
+            data_sectors += 1;
+            continue;
+        }
+        native += roughness(&sector, false);
+        swapped += roughness(&sector, true);
+    }
+
+    if data_sectors > 0 {
+        found.push(diagnostic(
+            Some(track),
+            format!(
+                "declared as {} but {} sampled sectors carry a data sync pattern",
+                track.mode(),
+                data_sectors
+            ),
+            Some("check the TRACK mode in the CUE sheet"),
+        ));
+    }
+    // Music is far smoother than the noise byte swapped samples turn into
+    if native > swapped * 2 && native > 0 {
+        found.push(diagnostic(
+            Some(track),
+            String::from("audio looks byte swapped"),
+            Some("re-run with -s"),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a cooked track doesn't actually hold raw sectors
+fn check_cooked(disc: &mut dyn Disc, track: &Track, found: &mut Vec<Diagnostic>) -> io::Result<()> {
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    let stored = track.stored_range();
+    let mut raw = 0;
+
+    for lba in sampled_sectors(track) {
+        disc.read_sector(lba, &mut sector)?;
+        if has_sync(&sector[stored.clone()]) {
+            raw += 1;
+        }
+    }
+
+    if raw > 0 {
+        found.push(diagnostic(
+            Some(track),
+            format!(
+                "{} sampled sectors start with the sync pattern of raw sectors although declared {}",
+                raw,
+                track.mode()
+            ),
+            Some("the image is probably raw, try MODE1/2352 or MODE2/2352 as TRACK mode"),
+        ));
+    }
+    Ok(())
+}
+
+fn check_data(disc: &mut dyn Disc, track: &Track, found: &mut Vec<Diagnostic>) -> io::Result<()> {
+    if track.sector_size() != SECTOR_SIZE {
+        return check_cooked(disc, track, found);
+    }
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    let expected = match track.mode() {
+        Mode::Mode1_2352 => 1,
+        _ => 2,
+    };
+    let (mut missing_sync, mut wrong_mode) = (0, 0);
+
+    for lba in sampled_sectors(track) {
+        disc.read_sector(lba, &mut sector)?;
+        match data_mode(&sector) {
+            None => missing_sync += 1,
+            Some(mode) if mode != expected => wrong_mode += 1,
+            Some(_) => {}
+        }
+    }
+
+    if missing_sync > 0 {
+        found.push(diagnostic(
+            Some(track),
+            format!(
+                "{} sampled sectors lack the sync pattern of a raw {} sector",
+                missing_sync,
+                track.mode()
+            ),
+            Some("the image may be cooked (2048 bytes per sector) or the track mode is wrong"),
+        ));
+    }
+    if wrong_mode > 0 {
+        found.push(diagnostic(
+            Some(track),
+            format!(
+                "{} sampled sectors aren't mode {} sectors as declared by {}",
+                wrong_mode,
+                expected,
+                track.mode()
+            ),
+            Some("fix the TRACK mode in the CUE sheet"),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the pregap the Red Book requires between a data track and the following audio
+fn check_pregap(
+    disc: &mut dyn Disc,
+    previous: &Track,
+    track: &Track,
+    found: &mut Vec<Diagnostic>,
+) -> io::Result<()> {
+    if previous.stop_sector() + 1 < track.start_sector() {
+        return Ok(());
+    }
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    let mut silent = 0;
+    for lba in track.start_sector()..track.start_sector() + DATA_TO_AUDIO_PREGAP {
+        disc.read_sector(lba, &mut sector)?;
+        if sector.iter().any(|&b| b != 0) {
+            break;
+        }
+        silent += 1;
+    }
+
+    found.push(if silent == DATA_TO_AUDIO_PREGAP {
+        diagnostic(
+            Some(track),
+            String::from("starts with a 2 second silent pregap the CUE sheet doesn't declare"),
+            Some("add an INDEX 00 at the current start and move INDEX 01 2 seconds later"),
+        )
+    } else {
+        diagnostic(
+            Some(track),
+            String::from("audio track follows a data track without a pregap"),
+            Some("the image may be missing its pregap, compare the TOC against the disc"),
+        )
+    });
+    Ok(())
+}
+
+/// Checks the layout of the tracks of `disc` against each other and the size of the image,
+/// which conversions do before writing anything too
+///
+/// Tracks out of order or overlapping, too short, with indexes beyond their end or with a
+/// filesystem running past it are found without reading more than the volume descriptor of
+/// each data track.
+pub(crate) fn check_consistency(disc: &mut dyn Disc) -> io::Result<Vec<Diagnostic>> {
+    let mut found = Vec::new();
+    let tracks = disc.tracks().to_vec();
+
+    if let Some(size) = disc.metadata().image_size {
+        let raw = tracks.iter().all(|t| t.sector_size() == SECTOR_SIZE);
+        if raw && size % SECTOR_SIZE != 0 {
+            found.push(diagnostic(
+                None,
+                format!(
+                    "image size {} is not a multiple of the {} byte sector size",
+                    size, SECTOR_SIZE
+                ),
+                Some("the last sector is incomplete, the dump may be truncated"),
+            ));
+        }
+    }
+
+    for (i, track) in tracks.iter().enumerate() {
+        if let Some(previous) = i.checked_sub(1).map(|i| &tracks[i]) {
+            if track.number() <= previous.number() {
+                found.push(diagnostic(
+                    Some(track),
+                    format!("follows track {}, out of order", previous.number()),
+                    Some("number the tracks of the CUE sheet in the order they're on the disc"),
+                ));
+            }
+            if track.start_sector() <= previous.stop_sector() {
+                found.push(diagnostic(
+                    Some(track),
+                    format!(
+                        "starts at sector {}, within track {} ending at {}",
+                        track.start_sector(),
+                        previous.number(),
+                        previous.stop_sector()
+                    ),
+                    Some("fix the INDEX times of the tracks in the CUE sheet"),
+                ));
+            }
+        }
+
+        let length = (track.stop_sector() + 1).saturating_sub(track.start_sector());
+        if length == 0 {
+            found.push(diagnostic(
+                Some(track),
+                String::from("holds no sectors"),
+                Some("fix its INDEX 01 or remove the track from the CUE sheet"),
+            ));
+        } else if length < MIN_TRACK_SECTORS {
+            found.push(diagnostic(
+                Some(track),
+                format!(
+                    "is {} sectors long, shorter than the 4 seconds every track of a CD lasts",
+                    length
+                ),
+                Some("the image may be cut short or the INDEX times may be off"),
+            ));
+        }
+        for (n, index) in track.indexes().iter().enumerate() {
+            if *index > track.stop_sector() {
+                found.push(diagnostic(
+                    Some(track),
+                    format!(
+                        "INDEX {:0>2} at sector {} lies beyond the end of the track at {}",
+                        n + 2,
+                        index,
+                        track.stop_sector()
+                    ),
+                    Some("the BIN file is probably truncated, or the CUE sheet is of another dump"),
+                ));
+            }
+        }
+
+        if let Some(blocks) = volume_blocks(disc, track)? {
+            if blocks > length {
+                found.push(diagnostic(
+                    Some(track),
+                    format!(
+                        "track holds {} sectors but its filesystem spans {}",
+                        length, blocks
+                    ),
+                    Some("the dump is probably truncated, re-dump the disc"),
+                ));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Runs every check on an already opened disc
+pub fn diagnose(disc: &mut dyn Disc) -> io::Result<Vec<Diagnostic>> {
+    let mut found = check_consistency(disc)?;
+    let tracks = disc.tracks().to_vec();
+    if let Some(surplus) = disc.surplus() {
+        found.push(diagnostic(
+            None,
+            format!(
+                "{} bytes of data beyond the end of the disc layout",
+                surplus.length
+            ),
+            Some("re-run with -e to export them separately"),
+        ));
+    }
+
+    for (i, track) in tracks.iter().enumerate() {
+        match track.mode() {
+            Mode::Audio | Mode::Cdg => check_audio(disc, track, &mut found)?,
+            Mode::Unknown => found.push(diagnostic(
+                Some(track),
+                String::from("unknown track mode"),
+                Some("check the TRACK mode in the CUE sheet"),
+            )),
+            _ => check_data(disc, track, &mut found)?,
+        }
+        if i > 0 && track.is_audio() && !tracks[i - 1].is_audio() {
+            check_pregap(disc, &tracks[i - 1], track, &mut found)?;
+        }
+    }
+
+    Ok(found)
+}
+
+/// Opens the image described by `options` and runs every check on it
+pub fn doctor(options: impl Into<ConvertOptions>) -> io::Result<Vec<Diagnostic>> {
+    let mut args = ConvertOptions::new(options.into());
+    let mut disc = open_disc(&mut args)?;
+    diagnose(disc.as_mut())
+}
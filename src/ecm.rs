@@ -0,0 +1,620 @@
+//! BIN files compressed with ECM, which strips the sync, EDC/ECC and other data that can be
+//! recomputed
+//!
+//! An ECM file is a series of records, each either a run of bytes copied as is or a run of
+//! data sectors of one layout with only the parts that can't be recomputed stored. The
+//! records are indexed when the file is opened, so sectors can be decoded in any order.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::ErrorCode;
+use crate::sector::{add_edc_ecc, data_mode, edc, Layout, FORM2, SYNC};
+use crate::source::ReadSeek;
+use crate::SECTOR_SIZE;
+
+const MAGIC: [u8; 4] = *b"ECM\0";
+// Sector count marking the end of the records
+const END: u64 = 0xffff_ffff;
+
+/// Run of bytes with the same encoding
+struct Record {
+    /// Position of the first decoded byte
+    decoded: u64,
+    /// Position of the first encoded byte in the ECM file
+    encoded: u64,
+    kind: Kind,
+    /// Bytes for `Kind::Raw`, sectors for the others
+    count: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Raw,
+    /// Mode 1 sectors stored as address and user data
+    Mode1,
+    /// Mode 2 sectors without sync and header, stored as subheader and user data
+    Mode2Form1,
+    Mode2Form2,
+}
+
+impl Kind {
+    fn from_type(t: u8) -> Kind {
+        match t {
+            1 => Kind::Mode1,
+            2 => Kind::Mode2Form1,
+            3 => Kind::Mode2Form2,
+            _ => Kind::Raw,
+        }
+    }
+
+    /// Bytes a sector takes in the ECM file
+    fn encoded_size(self) -> u64 {
+        match self {
+            Kind::Raw => 1,
+            Kind::Mode1 => 3 + 2048,
+            Kind::Mode2Form1 => 4 + 2048,
+            Kind::Mode2Form2 => 4 + 2324,
+        }
+    }
+
+    /// Bytes a sector takes once decoded
+    fn decoded_size(self) -> u64 {
+        match self {
+            Kind::Raw => 1,
+            Kind::Mode1 => SECTOR_SIZE,
+            Kind::Mode2Form1 | Kind::Mode2Form2 => SECTOR_SIZE - 16,
+        }
+    }
+}
+
+/// Reader of the BIN file an ECM file decodes to
+pub(crate) struct EcmReader {
+    inner: BufReader<Box<dyn ReadSeek>>,
+    /// Position of `inner` in the ECM file
+    encoded: u64,
+    records: Vec<Record>,
+    size: u64,
+    position: u64,
+    /// Last decoded sector, starting at byte 16 for the Mode 2 kinds that lack sync and header
+    sector: [u8; SECTOR_SIZE as usize],
+    /// Decoded position and size of the sector in `sector`
+    decoded: Option<(u64, usize)>,
+}
+
+impl EcmReader {
+    fn open(reader: Box<dyn ReadSeek>, name: &str, buffer_size: usize) -> io::Result<EcmReader> {
+        let mut inner = BufReader::with_capacity(buffer_size, reader);
+        let (records, encoded) = match index(&mut inner) {
+            Ok(records) => records,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(ErrorCode::ReadFailed
+                    .error(format!("ECM file {} ends before its last record", name)))
+            }
+            Err(e) => return Err(e),
+        };
+        let size = records
+            .last()
+            .map_or(0, |r| r.decoded + r.count * r.kind.decoded_size());
+        Ok(EcmReader {
+            inner,
+            encoded,
+            records,
+            size,
+            position: 0,
+            sector: [0; SECTOR_SIZE as usize],
+            decoded: None,
+        })
+    }
+
+    /// Moves `inner` to `position` of the ECM file, keeping what it buffered if it can
+    fn seek_inner(&mut self, position: u64) -> io::Result<()> {
+        if position != self.encoded {
+            self.inner
+                .seek_relative(position as i64 - self.encoded as i64)?;
+            self.encoded = position;
+        }
+        Ok(())
+    }
+
+    /// Decodes the sector `n` of a sector record into `self.sector`
+    fn decode(&mut self, record: usize, n: u64) -> io::Result<()> {
+        let r = &self.records[record];
+        let (kind, decoded) = (r.kind, r.decoded + n * r.kind.decoded_size());
+        self.seek_inner(r.encoded + n * kind.encoded_size())?;
+
+        let sector = &mut self.sector;
+        sector.fill(0);
+        sector[..SYNC.len()].copy_from_slice(&SYNC);
+        let layout = match kind {
+            Kind::Mode1 => {
+                self.inner.read_exact(&mut sector[12..15])?;
+                sector[15] = 1;
+                self.inner.read_exact(&mut sector[16..16 + 2048])?;
+                Layout::Mode1
+            }
+            _ => {
+                // Only one copy of the subheader is stored
+                sector[15] = 2;
+                let length = kind.encoded_size() as usize;
+                self.inner.read_exact(&mut sector[20..20 + length])?;
+                sector.copy_within(20..24, 16);
+                match kind {
+                    Kind::Mode2Form1 => Layout::Mode2Form1,
+                    _ => Layout::Mode2Form2,
+                }
+            }
+        };
+        self.encoded += kind.encoded_size();
+        add_edc_ecc(sector, layout);
+        self.decoded = Some((decoded, kind.decoded_size() as usize));
+        Ok(())
+    }
+}
+
+/// Reads the record headers of an ECM file, skipping over their data, and returns them with
+/// the position it stopped at
+fn index(reader: &mut BufReader<Box<dyn ReadSeek>>) -> io::Result<(Vec<Record>, u64)> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(ErrorCode::Unsupported.error("Not an ECM file"));
+    }
+
+    let mut records = Vec::new();
+    let mut decoded = 0;
+    let mut encoded = MAGIC.len() as u64;
+    loop {
+        // Type in the lowest 2 bits, then the count minus one in 5 bits and 7 per extra byte
+        let mut byte = read_byte(reader)?;
+        encoded += 1;
+        let kind = Kind::from_type(byte & 3);
+        let mut count = (byte as u64 >> 2) & 0x1f;
+        let mut bits = 5;
+        while byte & 0x80 != 0 {
+            if bits > 32 {
+                return Err(corrupt());
+            }
+            byte = read_byte(reader)?;
+            encoded += 1;
+            count |= ((byte & 0x7f) as u64) << bits;
+            bits += 7;
+        }
+        if count == END {
+            break;
+        }
+        let count = count + 1;
+        if count >= 0x8000_0000 {
+            return Err(corrupt());
+        }
+
+        records.push(Record {
+            decoded,
+            encoded,
+            kind,
+            count,
+        });
+        decoded += count * kind.decoded_size();
+        let length = count * kind.encoded_size();
+        reader.seek_relative(length as i64)?;
+        encoded += length;
+    }
+    Ok((records, encoded))
+}
+
+fn read_byte(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn corrupt() -> io::Error {
+    ErrorCode::ReadFailed.error("ECM file has a corrupt record header")
+}
+
+impl Read for EcmReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let record = self.records.partition_point(|r| r.decoded <= self.position) - 1;
+        let r = &self.records[record];
+        let offset = self.position - r.decoded;
+
+        let read = if r.kind == Kind::Raw {
+            let length = buf.len().min((r.count - offset) as usize);
+            self.seek_inner(r.encoded + offset)?;
+            self.inner.read_exact(&mut buf[..length])?;
+            self.encoded += length as u64;
+            length
+        } else {
+            let n = offset / r.kind.decoded_size();
+            let start = r.decoded + n * r.kind.decoded_size();
+            if self.decoded.map(|(position, _)| position) != Some(start) {
+                self.decode(record, n)?;
+            }
+            let (_, size) = self.decoded.unwrap();
+            // Mode 2 sectors are decoded without the sync and header in front of them
+            let skip = SECTOR_SIZE as usize - size;
+            let from = skip + (self.position - start) as usize;
+            let length = buf.len().min(SECTOR_SIZE as usize - from);
+            buf[..length].copy_from_slice(&self.sector[from..from + length]);
+            length
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for EcmReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+/// BIN file read by a CUE disc, decoded on the fly if it's compressed with ECM
+pub(crate) enum BinFile {
+    Plain(File),
+    /// Data read from somewhere other than a file, e.g. memory
+    Reader(Box<dyn ReadSeek>),
+    Ecm(Box<EcmReader>),
+}
+
+impl BinFile {
+    /// Opens `path`, telling ECM files from plain ones by their contents
+    pub(crate) fn open(path: &Path, buffer_size: usize) -> io::Result<BinFile> {
+        let mut file = File::open(path)?;
+        match is_ecm(&mut file)? {
+            true => {
+                let name = path.display().to_string();
+                Ok(BinFile::Ecm(Box::new(EcmReader::open(
+                    Box::new(file),
+                    &name,
+                    buffer_size,
+                )?)))
+            }
+            false => Ok(BinFile::Plain(file)),
+        }
+    }
+
+    /// Reads the BIN file `name` from `reader`, which may be compressed with ECM as well
+    pub(crate) fn from_reader(
+        mut reader: Box<dyn ReadSeek>,
+        name: &str,
+        buffer_size: usize,
+    ) -> io::Result<BinFile> {
+        match is_ecm(&mut reader)? {
+            true => Ok(BinFile::Ecm(Box::new(EcmReader::open(
+                reader,
+                name,
+                buffer_size,
+            )?))),
+            false => Ok(BinFile::Reader(reader)),
+        }
+    }
+
+    /// Size of the BIN file, once decoded
+    pub(crate) fn len(&mut self) -> io::Result<u64> {
+        match self {
+            BinFile::Plain(file) => Ok(file.metadata()?.len()),
+            BinFile::Reader(reader) => {
+                let len = reader.seek(SeekFrom::End(0))?;
+                reader.rewind()?;
+                Ok(len)
+            }
+            BinFile::Ecm(reader) => Ok(reader.size),
+        }
+    }
+}
+
+/// Whether the data of `reader` starts like an ECM file, leaving it at the start again
+fn is_ecm(reader: &mut impl ReadSeek) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    let is_ecm = match reader.read_exact(&mut magic) {
+        Ok(()) => magic == MAGIC,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+    reader.rewind()?;
+    Ok(is_ecm)
+}
+
+impl Read for BinFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BinFile::Plain(file) => file.read(buf),
+            BinFile::Reader(reader) => reader.read(buf),
+            BinFile::Ecm(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for BinFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            BinFile::Plain(file) => file.seek(pos),
+            BinFile::Reader(reader) => reader.seek(pos),
+            BinFile::Ecm(reader) => reader.seek(pos),
+        }
+    }
+}
+
+// Bytes of data a record holds before it's written out, to bound the memory held
+const RECORD_LIMIT: usize = 1024 * 1024;
+
+/// Writer compressing what it's given to ECM, stripping whatever it can recompute
+///
+/// Data sectors are recognized wherever they start, raw with sync and header or as Mode 2
+/// sectors without them, and only kept if their EDC/ECC is exactly what would be recomputed.
+pub(crate) struct EcmWriter<W: Write> {
+    inner: W,
+    /// Data not encoded yet, held back until there's enough to recognize a sector in it
+    pending: Vec<u8>,
+    /// Kind, count and stored data of the record being built
+    record: Option<(Kind, u64, Vec<u8>)>,
+    /// EDC of everything written, which ends the file so decoders can verify it
+    edc: u32,
+}
+
+impl<W: Write> EcmWriter<W> {
+    pub(crate) fn new(mut inner: W) -> io::Result<EcmWriter<W>> {
+        inner.write_all(&MAGIC)?;
+        Ok(EcmWriter {
+            inner,
+            pending: Vec::with_capacity(SECTOR_SIZE as usize * 2),
+            record: None,
+            edc: 0,
+        })
+    }
+
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.edc = edc(self.edc, data);
+        self.pending.extend_from_slice(data);
+        self.encode(false)
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Encodes the rest and ends the file
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        self.encode(true)?;
+        self.end_record()?;
+        write_header(&mut self.inner, Kind::Raw, END)?;
+        self.inner.write_all(&self.edc.to_le_bytes())?;
+        self.inner.flush()
+    }
+
+    /// Encodes pending data as long as a whole sector is left, or all of it at the `end`
+    fn encode(&mut self, end: bool) -> io::Result<()> {
+        let mut pending = std::mem::take(&mut self.pending);
+        let mut position = 0;
+        while position < pending.len() {
+            let rest = &pending[position..];
+            if !end && rest.len() < SECTOR_SIZE as usize {
+                break;
+            }
+            let kind = recognize(rest);
+            let stored = match kind {
+                Kind::Raw => &rest[..1],
+                Kind::Mode1 => &rest[..2064],
+                Kind::Mode2Form1 | Kind::Mode2Form2 => &rest[4..4 + kind.encoded_size() as usize],
+            };
+            self.add(kind, stored)?;
+            position += kind.decoded_size() as usize;
+        }
+        pending.drain(..position);
+        self.pending = pending;
+        Ok(())
+    }
+
+    fn add(&mut self, kind: Kind, stored: &[u8]) -> io::Result<()> {
+        if self
+            .record
+            .as_ref()
+            .is_some_and(|(k, _, data)| *k != kind || data.len() >= RECORD_LIMIT)
+        {
+            self.end_record()?;
+        }
+        let (_, count, data) = self.record.get_or_insert((kind, 0, Vec::new()));
+        *count += 1;
+        match kind {
+            // Address and user data, without the mode byte in between
+            Kind::Mode1 => {
+                data.extend_from_slice(&stored[12..15]);
+                data.extend_from_slice(&stored[16..]);
+            }
+            _ => data.extend_from_slice(stored),
+        }
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> io::Result<()> {
+        if let Some((kind, count, data)) = self.record.take() {
+            write_header(&mut self.inner, kind, count - 1)?;
+            self.inner.write_all(&data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the type of a record and its count minus one, or `END`
+fn write_header(writer: &mut impl Write, kind: Kind, mut n: u64) -> io::Result<()> {
+    let t = match kind {
+        Kind::Raw => 0,
+        Kind::Mode1 => 1,
+        Kind::Mode2Form1 => 2,
+        Kind::Mode2Form2 => 3,
+    };
+    let mut header = Vec::with_capacity(5);
+    let mut byte = t | ((n & 0x1f) << 2) as u8;
+    n >>= 5;
+    while n != 0 {
+        header.push(byte | 0x80);
+        byte = (n & 0x7f) as u8;
+        n >>= 7;
+    }
+    header.push(byte);
+    writer.write_all(&header)
+}
+
+/// Kind of the sector `data` starts with, `Kind::Raw` if it doesn't start with one
+fn recognize(data: &[u8]) -> Kind {
+    if data.len() >= SECTOR_SIZE as usize && data_mode(data) == Some(1) {
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        sector.copy_from_slice(&data[..SECTOR_SIZE as usize]);
+        add_edc_ecc(&mut sector, Layout::Mode1);
+        if sector[..] == data[..SECTOR_SIZE as usize] {
+            return Kind::Mode1;
+        }
+    }
+
+    // Mode 2 sectors are recognized without sync and header, from their subheader copies
+    let length = SECTOR_SIZE as usize - 16;
+    if data.len() >= length && data[..4] == data[4..8] {
+        let (kind, layout) = match data[2] & FORM2 {
+            0 => (Kind::Mode2Form1, Layout::Mode2Form1),
+            _ => (Kind::Mode2Form2, Layout::Mode2Form2),
+        };
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        sector[16..].copy_from_slice(&data[..length]);
+        add_edc_ecc(&mut sector, layout);
+        if sector[16..] == data[..length] {
+            return kind;
+        }
+    }
+    Kind::Raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sector::add_header;
+    use crate::track::Mode;
+    use std::io::Cursor;
+
+    /// Raw sector of `layout` at `lba` with user data counting up from `seed`, and the bytes
+    /// ECM keeps of it
+    fn sector(layout: Layout, lba: u64, seed: u8) -> (Vec<u8>, Vec<u8>) {
+        let mut sector = vec![0u8; SECTOR_SIZE as usize];
+        let (mode, data, length) = match layout {
+            Layout::Mode1 => (Mode::Mode1_2048, 16, 2048),
+            Layout::Mode2Form1 => (Mode::Mode2_2336, 24, 2048),
+            Layout::Mode2Form2 => (Mode::Mode2_2336, 24, 2324),
+        };
+        add_header(&mut sector, lba, mode);
+        if layout != Layout::Mode1 {
+            let submode = if layout == Layout::Mode2Form2 {
+                FORM2
+            } else {
+                0
+            };
+            let subheader = [1, 2, submode | 0x08, 0];
+            sector[16..20].copy_from_slice(&subheader);
+            sector[20..24].copy_from_slice(&subheader);
+        }
+        for (i, b) in sector[data..data + length].iter_mut().enumerate() {
+            *b = seed.wrapping_add(i as u8).wrapping_mul(7);
+        }
+        add_edc_ecc(&mut sector, layout);
+        // Mode 1 keeps the address and user data, Mode 2 one subheader and the user data
+        let stored = match layout {
+            Layout::Mode1 => [&sector[12..15], &sector[16..16 + 2048]].concat(),
+            _ => sector[20..data + length].to_vec(),
+        };
+        (sector, stored)
+    }
+
+    fn decode(ecm: Vec<u8>) -> EcmReader {
+        EcmReader::open(Box::new(Cursor::new(ecm)), "test.ecm", 4096).unwrap()
+    }
+
+    /// ECM file of a few raw bytes, then one record of each sector kind
+    fn encoded() -> (Vec<u8>, Vec<u8>) {
+        let (mode1, mode1_stored) = sector(Layout::Mode1, 16, 1);
+        let (form1, form1_stored) = sector(Layout::Mode2Form1, 17, 2);
+        let (form2, form2_stored) = sector(Layout::Mode2Form2, 18, 3);
+        let mut ecm = MAGIC.to_vec();
+        // Type in the low 2 bits, count minus one above them
+        ecm.extend_from_slice(&[2 << 2, b'a', b'b', b'c']);
+        ecm.push(1);
+        ecm.extend_from_slice(&mode1_stored);
+        ecm.push(2);
+        ecm.extend_from_slice(&form1_stored);
+        ecm.push(3);
+        ecm.extend_from_slice(&form2_stored);
+        ecm.extend_from_slice(&[0xfc, 0xff, 0xff, 0xff, 0x3f]);
+
+        // Mode 2 sectors are stored without their sync and header
+        let bin = [b"abc", &mode1[..], &form1[16..], &form2[16..]].concat();
+        ecm.extend_from_slice(&edc(0, &bin).to_le_bytes());
+        (ecm, bin)
+    }
+
+    #[test]
+    fn record_sizes() {
+        assert_eq!(Kind::Mode1.encoded_size(), 2051);
+        assert_eq!(Kind::Mode2Form1.encoded_size(), 2052);
+        assert_eq!(Kind::Mode2Form2.encoded_size(), 2328);
+        assert_eq!(Kind::Mode1.decoded_size(), 2352);
+        assert_eq!(Kind::Mode2Form1.decoded_size(), 2336);
+        assert_eq!(Kind::Mode2Form2.decoded_size(), 2336);
+    }
+
+    #[test]
+    fn decodes_every_sector_kind() {
+        let (ecm, bin) = encoded();
+        let mut reader = decode(ecm);
+        assert_eq!(reader.size, bin.len() as u64);
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded).unwrap();
+        assert!(decoded == bin);
+    }
+
+    #[test]
+    fn decodes_sectors_in_any_order() {
+        let (ecm, bin) = encoded();
+        let mut reader = decode(ecm);
+        // The Form 2 sector, then the Form 1 one before it, from the middle of their data
+        for start in [3 + 2352 + 2336 + 100, 3 + 2352 + 20] {
+            let mut decoded = [0u8; 32];
+            reader.seek(SeekFrom::Start(start as u64)).unwrap();
+            reader.read_exact(&mut decoded).unwrap();
+            assert_eq!(decoded[..], bin[start..start + 32]);
+        }
+    }
+
+    #[test]
+    fn writer_strips_what_the_reader_rebuilds() {
+        let (ecm, bin) = encoded();
+        let mut writer = EcmWriter::new(Vec::new()).unwrap();
+        writer.write_all(&bin).unwrap();
+        writer.finish().unwrap();
+        assert!(writer.inner == ecm);
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let (mut ecm, _) = encoded();
+        ecm.truncate(MAGIC.len() + 4 + 1 + 1000);
+        let open = EcmReader::open(Box::new(Cursor::new(ecm)), "test.ecm", 4096);
+        assert!(open.is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! El Torito boot images of bootable data tracks, found through the boot catalog their boot
+//! record points at
+//!
+//! The boot record is the volume descriptor after the primary one. Its catalog lists the
+//! default image and those of further sections, each emulating a floppy disk, a hard disk or
+//! nothing at all, the way a PC BIOS loads it.
+
+use std::fmt::Display;
+use std::io;
+
+use crate::disc::Disc;
+use crate::error::ErrorCode;
+use crate::iso9660::{le32, read_block, read_extent, BLOCK_SIZE};
+use crate::log::info;
+use crate::output::{self, OutputFile, OutputWriter};
+use crate::track::Track;
+use crate::{open_disc, validate, ConvertOptions};
+
+/// Block of a track the boot record follows the primary volume descriptor in
+const BOOT_RECORD_BLOCK: u64 = 17;
+
+const BOOT_SYSTEM: &[u8] = b"EL TORITO SPECIFICATION";
+
+/// Bytes of the virtual sectors boot images are counted and loaded in
+const VIRTUAL_SECTOR: u64 = 512;
+
+/// Segment images are loaded at when their entry says 0, where a BIOS loads boot sectors
+const DEFAULT_SEGMENT: u16 = 0x07c0;
+
+/// What the BIOS makes of a boot image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Emulation {
+    /// Loaded into memory as it is, like the boot loaders of most installation discs
+    NoEmulation,
+    Floppy1200,
+    Floppy1440,
+    Floppy2880,
+    HardDisk,
+}
+
+impl Emulation {
+    fn from_media(media: u8) -> Option<Emulation> {
+        match media & 0x0f {
+            0 => Some(Emulation::NoEmulation),
+            1 => Some(Emulation::Floppy1200),
+            2 => Some(Emulation::Floppy1440),
+            3 => Some(Emulation::Floppy2880),
+            4 => Some(Emulation::HardDisk),
+            _ => None,
+        }
+    }
+
+    /// Bytes of the emulated floppy disk, `None` for the other emulations
+    fn floppy_size(&self) -> Option<u64> {
+        match self {
+            Emulation::Floppy1200 => Some(1_228_800),
+            Emulation::Floppy1440 => Some(1_474_560),
+            Emulation::Floppy2880 => Some(2_949_120),
+            Emulation::NoEmulation | Emulation::HardDisk => None,
+        }
+    }
+}
+
+impl AsRef<str> for Emulation {
+    fn as_ref(&self) -> &'static str {
+        match self {
+            Emulation::NoEmulation => "no emulation",
+            Emulation::Floppy1200 => "1.2 MB floppy",
+            Emulation::Floppy1440 => "1.44 MB floppy",
+            Emulation::Floppy2880 => "2.88 MB floppy",
+            Emulation::HardDisk => "hard disk",
+        }
+    }
+}
+
+impl Display for Emulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(self.as_ref())
+    }
+}
+
+/// An image of the boot catalog of a data track
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BootImage {
+    /// Number of the track holding the image
+    pub track: u32,
+    /// Whether the BIOS boots the image, or only lists it
+    pub bootable: bool,
+    /// Platform ID of the section, 0 for x86 PCs and 0xef for EFI
+    pub platform: u8,
+    pub emulation: Emulation,
+    /// Segment the image is loaded at, like `0x07c0`
+    pub load_segment: u16,
+    /// 512 byte sectors loaded at boot
+    pub sectors: u16,
+    /// Block of the track the image starts at
+    pub extent: u64,
+    /// Bytes of the image, the emulated disk or the sectors loaded without emulation
+    pub size: u64,
+}
+
+impl Display for BootImage {
+    /// One line per image like `Track  1: boot image, no emulation, load segment 0x07c0, ...`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Track {:>2}: {}boot image, {}, load segment {:#06x}, {} sectors at block {}",
+            self.track,
+            if self.bootable { "" } else { "non-bootable " },
+            self.emulation,
+            self.load_segment,
+            self.sectors,
+            self.extent
+        )
+    }
+}
+
+/// Bytes of the emulated hard disk of an image starting with `mbr`, up to the end of its
+/// last partition
+fn hard_disk_size(mbr: &[u8]) -> Option<u64> {
+    if mbr[510..512] != [0x55, 0xaa] {
+        return None;
+    }
+    (0..4)
+        .map(|n| &mbr[446 + 16 * n..462 + 16 * n])
+        .map(|partition| le32(&partition[8..]) + le32(&partition[12..]))
+        .max()
+        .filter(|&end| end > 0)
+        .map(|end| end * VIRTUAL_SECTOR)
+}
+
+/// Boot image of the catalog `entry` of the section for `platform`, `None` for unused ones
+fn boot_image(
+    disc: &mut dyn Disc,
+    track: &Track,
+    entry: &[u8],
+    platform: u8,
+) -> io::Result<Option<BootImage>> {
+    let bootable = match entry[0] {
+        0x88 => true,
+        0x00 => false,
+        _ => return Ok(None),
+    };
+    let emulation = match Emulation::from_media(entry[1]) {
+        Some(emulation) => emulation,
+        None => return Ok(None),
+    };
+    let extent = le32(&entry[8..]);
+    if extent == 0 {
+        return Ok(None);
+    }
+    let load_segment = match u16::from_le_bytes([entry[2], entry[3]]) {
+        0 => DEFAULT_SEGMENT,
+        segment => segment,
+    };
+    let sectors = u16::from_le_bytes([entry[6], entry[7]]);
+    let mut size = emulation.floppy_size();
+    if emulation == Emulation::HardDisk {
+        let mut mbr = [0u8; BLOCK_SIZE];
+        if read_block(disc, track, extent, &mut mbr)? {
+            size = hard_disk_size(&mbr);
+        }
+    }
+    Ok(Some(BootImage {
+        track: track.number(),
+        bootable,
+        platform,
+        emulation,
+        load_segment,
+        sectors,
+        extent,
+        size: size.unwrap_or(sectors.max(1) as u64 * VIRTUAL_SECTOR),
+    }))
+}
+
+/// Images in the boot catalog of the data `track`, none if it isn't bootable
+pub(crate) fn boot_images(disc: &mut dyn Disc, track: &Track) -> io::Result<Vec<BootImage>> {
+    let mut record = [0u8; BLOCK_SIZE];
+    if !read_block(disc, track, BOOT_RECORD_BLOCK, &mut record)? {
+        return Ok(Vec::new());
+    }
+    if record[0] != 0 || &record[1..6] != b"CD001" || !record[7..].starts_with(BOOT_SYSTEM) {
+        return Ok(Vec::new());
+    }
+    let mut catalog = [0u8; BLOCK_SIZE];
+    if !read_block(disc, track, le32(&record[0x47..]), &mut catalog)? {
+        return Ok(Vec::new());
+    }
+    // The validation entry starts the catalog and ends with its key
+    if catalog[0] != 1 || catalog[30..32] != [0x55, 0xaa] {
+        return Ok(Vec::new());
+    }
+
+    let mut images = Vec::new();
+    images.extend(boot_image(disc, track, &catalog[32..64], catalog[1])?);
+    // Section headers follow the default entry, each with the entries of a platform
+    let mut entries = catalog[64..].chunks_exact(32);
+    while let Some(header) = entries.next() {
+        if header[0] != 0x90 && header[0] != 0x91 {
+            break;
+        }
+        let count = u16::from_le_bytes([header[2], header[3]]);
+        for entry in entries.by_ref().take(count as usize) {
+            images.extend(boot_image(disc, track, entry, header[1])?);
+        }
+        // 0x91 marks the last header
+        if header[0] == 0x91 {
+            break;
+        }
+    }
+    Ok(images)
+}
+
+/// Writes every boot image of the data tracks of an already opened disc to
+/// `<name>NN-boot1.img` onwards, numbered in the order of the catalog of track NN
+///
+/// Images without emulation are written as long as the sectors they have loaded at boot,
+/// which the boot loader may read more of its file after. If writing fails the images
+/// already written are removed again, unless `ConvertOptions::keep_partial` is set.
+pub fn extract_disc_boot_images(
+    disc: &mut dyn Disc,
+    a: &ConvertOptions,
+) -> io::Result<Vec<OutputFile>> {
+    let mut written = Vec::new();
+    if let Err(e) = write_boot_images(disc, &mut written, a) {
+        output::roll_back(&written, a);
+        return Err(e);
+    }
+    Ok(written)
+}
+
+fn write_boot_images(
+    disc: &mut dyn Disc,
+    written: &mut Vec<OutputFile>,
+    a: &ConvertOptions,
+) -> io::Result<()> {
+    let tracks = disc.tracks().to_vec();
+    for t in tracks.iter().filter(|t| !t.is_audio()) {
+        for (n, image) in boot_images(disc, t)?.into_iter().enumerate() {
+            a.check_cancelled()?;
+            let filename = format!("{}{:0>2}-boot{}.img", a.output_name, t.number(), n + 1);
+            let mut writer = match OutputWriter::create(filename, a) {
+                Ok(writer) => writer,
+                Err(e) => {
+                    return Err(ErrorCode::WriteFailed
+                        .wrap(&e, format!("Could not write boot image: {}", e)))
+                }
+            };
+            let read = read_extent(disc, t, image.extent, image.size, |data| {
+                writer.write_all(data)
+            })?;
+            if read < image.size {
+                return Err(ErrorCode::CorruptSectors.error(format!(
+                    "Boot image {} of track {} runs past the end of the track",
+                    n + 1,
+                    t.number()
+                )));
+            }
+            let output = writer.finish()?;
+            if a.verbose {
+                info!("{}", output.filename);
+            }
+            written.push(output);
+        }
+    }
+    Ok(())
+}
+
+/// Opens the image described by `options` and writes its boot images like
+/// `extract_disc_boot_images`
+pub fn extract_boot_images(options: impl Into<ConvertOptions>) -> io::Result<Vec<OutputFile>> {
+    let mut args = ConvertOptions::new(options.into());
+    validate(&args)?;
+    let mut disc = open_disc(&mut args)?;
+    extract_disc_boot_images(disc.as_mut(), &args)
+}
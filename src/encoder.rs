@@ -0,0 +1,244 @@
+//! Audio tracks compressed by external command line encoders fed the PCM of the track, and
+//! disc images by `gzip` and `zstd` fed their sectors
+//!
+//! Nothing is linked in, so an encoder only has to be installed when its format is asked for.
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use std::fs;
+use std::io;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use crate::error::ErrorCode;
+#[cfg(feature = "flac")]
+use crate::temp::TemporaryDirectory;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use crate::ConvertOptions;
+use crate::SECTOR_SIZE;
+
+/// Encoder process writing the output file itself from what it reads on stdin
+pub(crate) struct Encoder {
+    name: String,
+    child: Child,
+    // Closed when the track is done, which tells the encoder to finish the file
+    stdin: Option<BufWriter<ChildStdin>>,
+    /// Files the encoder reads besides its stdin, kept until it's done
+    #[cfg(feature = "flac")]
+    _scratch: Option<TemporaryDirectory>,
+}
+
+impl Encoder {
+    #[cfg(any(feature = "ogg", feature = "mp3", feature = "flac"))]
+    pub(crate) fn spawn(command: Command) -> io::Result<Encoder> {
+        Encoder::spawn_with(command, Stdio::null())
+    }
+
+    /// Encoder whose stdout is the file `path`, for compressors that only write there
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    pub(crate) fn spawn_writing(command: Command, path: &std::path::Path) -> io::Result<Encoder> {
+        let result = Encoder::spawn_with(command, Stdio::from(fs::File::create(path)?));
+        if result.is_err() {
+            let _ = fs::remove_file(path);
+        }
+        result
+    }
+
+    fn spawn_with(mut command: Command, stdout: Stdio) -> io::Result<Encoder> {
+        let name = command.get_program().to_string_lossy().into_owned();
+        let mut child = match command.stdin(Stdio::piped()).stdout(stdout).spawn() {
+            Ok(child) => child,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(ErrorCode::Unsupported.error(format!(
+                    "{} is needed for this output format but could not be found",
+                    name
+                )))
+            }
+            Err(e) => return Err(e),
+        };
+        let stdin = child
+            .stdin
+            .take()
+            .map(|stdin| BufWriter::with_capacity(SECTOR_SIZE as usize * 16, stdin));
+        Ok(Encoder {
+            name,
+            child,
+            stdin,
+            #[cfg(feature = "flac")]
+            _scratch: None,
+        })
+    }
+
+    /// Keeps `scratch` around for as long as the encoder runs
+    #[cfg(feature = "flac")]
+    pub(crate) fn keeping(mut self, scratch: Option<TemporaryDirectory>) -> Encoder {
+        self._scratch = scratch;
+        self
+    }
+
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stdin.as_mut().unwrap().write_all(data)
+    }
+
+    /// Waits for the encoder to write the rest of the file
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut stdin) = self.stdin.take() {
+            stdin.flush()?;
+        }
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(
+                ErrorCode::WriteFailed.error(format!("{} failed with {}", self.name, status))
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        // Still being fed, so the conversion failed and the half written file is of no use
+        if self.stdin.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// `oggenc` command encoding the 16 bit stereo audio of `rate` Hz on its stdin to the Ogg
+/// Vorbis file `filename`, tagged with the comments `tags`
+#[cfg(feature = "ogg")]
+pub(crate) fn vorbis(
+    filename: &std::path::Path,
+    quality: f32,
+    rate: u32,
+    big_endian: bool,
+    tags: &[(String, String)],
+) -> Command {
+    let mut command = Command::new("oggenc");
+    command
+        .args(["--quiet", "--raw", "--raw-bits=16", "--raw-chan=2"])
+        .arg(format!("--raw-rate={}", rate))
+        .arg(format!("--raw-endianness={}", big_endian as u8))
+        .arg(format!("--quality={}", quality));
+    for (name, value) in tags {
+        command.arg("--comment").arg(format!("{}={}", name, value));
+    }
+    command.arg("--output").arg(filename).arg("-");
+    command
+}
+
+/// `lame` command encoding the 16 bit stereo audio of `rate` Hz on its stdin to a constant
+/// `bitrate` kbps MP3 `filename`, with an ID3v2 tag of the Vorbis comments `tags`
+///
+/// Tags without an ID3v2 frame of their own, like `DISCID`, go in user defined `TXXX` ones.
+#[cfg(feature = "mp3")]
+pub(crate) fn mp3(
+    filename: &std::path::Path,
+    bitrate: u32,
+    rate: u32,
+    big_endian: bool,
+    tags: &[(String, String)],
+) -> Command {
+    let mut command = Command::new("lame");
+    command
+        .args(["--quiet", "-r", "-s"])
+        // In kHz, like 44.1
+        .arg((rate as f64 / 1000.0).to_string())
+        .args(["--bitwidth", "16", "--signed"])
+        .arg(match big_endian {
+            true => "--big-endian",
+            false => "--little-endian",
+        })
+        .args(["--cbr", "-b"])
+        .arg(bitrate.to_string());
+    if !tags.is_empty() {
+        // CD-TEXT decoded from Shift-JIS doesn't fit into the Latin-1 of the default
+        command.args(["--id3v2-only", "--id3v2-utf16"]);
+    }
+    for (name, value) in tags {
+        let option = match name.as_str() {
+            "TITLE" => "--tt",
+            "ARTIST" => "--ta",
+            "ALBUM" => "--tl",
+            "DATE" => "--ty",
+            "TRACKNUMBER" => "--tn",
+            "GENRE" => "--tg",
+            "COMMENT" => "--tc",
+            _ => {
+                let frame = match name.as_str() {
+                    "ALBUMARTIST" => String::from("TPE2"),
+                    "COMPOSER" => String::from("TCOM"),
+                    "ISRC" => String::from("TSRC"),
+                    _ => format!("TXXX={}", name),
+                };
+                command.arg("--tv").arg(format!("{}={}", frame, value));
+                continue;
+            }
+        };
+        command.arg(option).arg(value);
+    }
+    command.arg("-").arg(filename);
+    command
+}
+
+/// `flac` command encoding the 16 bit stereo audio of `rate` Hz on its stdin to the FLAC file
+/// `filename` at compression `level`, tagged with the Vorbis comments `tags` and embedding the
+/// CUE sheet in the file `cuesheet` as its CUESHEET block
+#[cfg(feature = "flac")]
+pub(crate) fn flac(
+    filename: &std::path::Path,
+    level: u32,
+    rate: u32,
+    big_endian: bool,
+    tags: &[(String, String)],
+    cuesheet: Option<&std::path::Path>,
+) -> Command {
+    let mut command = Command::new("flac");
+    command
+        .args([
+            "--silent",
+            // Outputs that were there were checked up front
+            "--force",
+            "--force-raw-format",
+            "--sign=signed",
+            "--channels=2",
+            "--bps=16",
+        ])
+        .arg(format!("--sample-rate={}", rate))
+        .arg(match big_endian {
+            true => "--endian=big",
+            false => "--endian=little",
+        })
+        .arg(format!("-{}", level));
+    for (name, value) in tags {
+        command.arg("-T").arg(format!("{}={}", name, value));
+    }
+    if let Some(cuesheet) = cuesheet {
+        let mut arg = std::ffi::OsString::from("--cuesheet=");
+        arg.push(cuesheet);
+        command.arg(arg);
+    }
+    command.arg("-o").arg(filename).arg("-");
+    command
+}
+
+/// `zstd` or `gzip` command compressing its stdin to its stdout at the level `a` picks, `None`
+/// if it picks neither
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub(crate) fn compressor(a: &ConvertOptions) -> Option<Command> {
+    #[cfg(feature = "zstd")]
+    if let Some(level) = a.zstd_level {
+        let mut command = Command::new("zstd");
+        command
+            .args(["--quiet", "--stdout"])
+            .arg(format!("-{}", level));
+        return Some(command);
+    }
+    #[cfg(feature = "gzip")]
+    if let Some(level) = a.gzip_level {
+        let mut command = Command::new("gzip");
+        command.arg("--stdout").arg(format!("-{}", level));
+        return Some(command);
+    }
+    None
+}
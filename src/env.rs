@@ -0,0 +1,120 @@
+//! Defaults for `ConvertOptions` taken from `RBCHUNK_*` environment variables
+
+use std::env;
+use std::io;
+
+use crate::error::ErrorCode;
+use crate::{ConvertOptions, DataTracks, GapPolicy};
+
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn flag(name: &str) -> io::Result<bool> {
+    match var(name).map(|v| v.to_ascii_lowercase()).as_deref() {
+        None | Some("0" | "false" | "no" | "off") => Ok(false),
+        Some("1" | "true" | "yes" | "on") => Ok(true),
+        Some(value) => Err(ErrorCode::InvalidArguments.error(format!(
+            "Invalid value {} for {}, expected a boolean",
+            value, name
+        ))),
+    }
+}
+
+fn number<T: std::str::FromStr>(name: &str) -> io::Result<Option<T>> {
+    match var(name) {
+        Some(value) => match value.parse() {
+            Ok(number) => Ok(Some(number)),
+            Err(_) => Err(ErrorCode::InvalidArguments.error(format!(
+                "Invalid value {} for {}, expected a number",
+                value, name
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+impl ConvertOptions {
+    /// Options defaulted from the environment, meant to be overridden by command line flags
+    ///
+    /// - `RBCHUNK_OUTPUT_DIR`: directory outputs are written to
+    /// - `RBCHUNK_NAME_TEMPLATE`: track output names, see `ConvertOptions::name_template`
+    /// - `RBCHUNK_AUDIO_FORMAT`: `wav`, `cdr`, `ogg`, `mp3` or `flac`
+    /// - `RBCHUNK_OGG_QUALITY`: quality of Ogg Vorbis audio, 3 by default like `oggenc`
+    /// - `RBCHUNK_MP3_BITRATE`: bitrate of MP3 audio in kbps, 128 by default like `lame`
+    /// - `RBCHUNK_WAV_RATE`, `RBCHUNK_WAV_CHANNELS`, `RBCHUNK_WAV_BITS`: WAV header overrides
+    /// - `RBCHUNK_AUDIO_OFFSET`: byte offset for audio tracks
+    /// - `RBCHUNK_THREADS`: tracks written at the same time, see `ConvertOptions::threads`
+    /// - `RBCHUNK_GAPS`: `skip`, `silence` or `append`, see `GapPolicy`
+    /// - `RBCHUNK_DATA_TRACKS`: `separate`, `merge` or `first`, see `DataTracks`
+    /// - `RBCHUNK_VERBOSE`, `RBCHUNK_RAW`, `RBCHUNK_PSX`, `RBCHUNK_SWAP_AUDIO`: booleans
+    /// - `RBCHUNK_KEEP_PARTIAL`: boolean, keep the outputs of failed conversions
+    pub fn from_env() -> io::Result<ConvertOptions> {
+        let mut options = ConvertOptions {
+            output_dir: var("RBCHUNK_OUTPUT_DIR").unwrap_or_default(),
+            name_template: var("RBCHUNK_NAME_TEMPLATE"),
+            audio_offset: number("RBCHUNK_AUDIO_OFFSET")?.unwrap_or_default(),
+            ogg_quality: number("RBCHUNK_OGG_QUALITY")?,
+            mp3_bitrate: number("RBCHUNK_MP3_BITRATE")?,
+            wav_sample_rate: number("RBCHUNK_WAV_RATE")?,
+            wav_channels: number("RBCHUNK_WAV_CHANNELS")?,
+            wav_bits_per_sample: number("RBCHUNK_WAV_BITS")?,
+            threads: number("RBCHUNK_THREADS")?.unwrap_or_default(),
+            verbose: flag("RBCHUNK_VERBOSE")?,
+            raw: flag("RBCHUNK_RAW")?,
+            psx_truncate: flag("RBCHUNK_PSX")?,
+            swap_audo_bytes: flag("RBCHUNK_SWAP_AUDIO")?,
+            keep_partial: flag("RBCHUNK_KEEP_PARTIAL")?,
+            ..Default::default()
+        };
+
+        match var("RBCHUNK_AUDIO_FORMAT")
+            .map(|v| v.to_ascii_lowercase())
+            .as_deref()
+        {
+            None | Some("cdr") => {}
+            Some("wav") => options.to_wav = true,
+            Some("ogg") => {
+                options.ogg_quality.get_or_insert(3.0);
+            }
+            Some("mp3") => {
+                options.mp3_bitrate.get_or_insert(128);
+            }
+            Some("flac") => {
+                options.flac_compression.get_or_insert(5);
+            }
+            Some(format) => {
+                return Err(ErrorCode::InvalidArguments
+                    .error(format!("Unsupported RBCHUNK_AUDIO_FORMAT {}", format)))
+            }
+        }
+
+        options.gap_policy = match var("RBCHUNK_GAPS")
+            .map(|v| v.to_ascii_lowercase())
+            .as_deref()
+        {
+            None | Some("skip") => GapPolicy::Skip,
+            Some("silence") => GapPolicy::Silence,
+            Some("append") => GapPolicy::Append,
+            Some(policy) => {
+                return Err(ErrorCode::InvalidArguments
+                    .error(format!("Unsupported RBCHUNK_GAPS {}", policy)))
+            }
+        };
+
+        options.data_tracks = match var("RBCHUNK_DATA_TRACKS")
+            .map(|v| v.to_ascii_lowercase())
+            .as_deref()
+        {
+            None | Some("separate") => DataTracks::Separate,
+            Some("merge") => DataTracks::Merge,
+            Some("first") => DataTracks::First,
+            Some(policy) => {
+                return Err(ErrorCode::InvalidArguments
+                    .error(format!("Unsupported RBCHUNK_DATA_TRACKS {}", policy)))
+            }
+        };
+
+        Ok(options)
+    }
+}
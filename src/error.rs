@@ -0,0 +1,136 @@
+//! Stable error codes attached to the `io::Error`s returned by the library
+
+use std::fmt::Display;
+use std::io;
+
+/// What went wrong, as a code that stays the same between releases
+///
+/// The numbers double as process exit statuses of the command line tool and the
+/// strings are used in machine readable output, neither is ever reused or changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// Any I/O error without a more specific code
+    Io,
+    InvalidArguments,
+    /// An input file doesn't exist
+    InputNotFound,
+    CueMalformed,
+    /// The input or requested output isn't supported by this build
+    Unsupported,
+    ReadFailed,
+    WriteFailed,
+    DiskFull,
+    PermissionDenied,
+    /// Data sectors of the image don't match their EDC or ECC, or aren't where they should be
+    CorruptSectors,
+    /// The conversion was stopped through `ConvertOptions::cancel`
+    Cancelled,
+    /// An output is already there and `ConvertOptions::existing` doesn't allow replacing it
+    OutputExists,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Io => "io",
+            ErrorCode::InvalidArguments => "invalid-arguments",
+            ErrorCode::InputNotFound => "input-not-found",
+            ErrorCode::CueMalformed => "cue-malformed",
+            ErrorCode::Unsupported => "unsupported",
+            ErrorCode::ReadFailed => "read-failed",
+            ErrorCode::WriteFailed => "write-failed",
+            ErrorCode::DiskFull => "disk-full",
+            ErrorCode::PermissionDenied => "permission-denied",
+            ErrorCode::CorruptSectors => "corrupt-sectors",
+            ErrorCode::Cancelled => "cancelled",
+            ErrorCode::OutputExists => "output-exists",
+        }
+    }
+
+    pub fn number(self) -> i32 {
+        match self {
+            ErrorCode::Io => 1,
+            ErrorCode::InvalidArguments => 2,
+            ErrorCode::InputNotFound => 3,
+            ErrorCode::CueMalformed => 4,
+            ErrorCode::Unsupported => 5,
+            ErrorCode::ReadFailed => 6,
+            ErrorCode::WriteFailed => 7,
+            ErrorCode::DiskFull => 8,
+            ErrorCode::PermissionDenied => 9,
+            ErrorCode::CorruptSectors => 10,
+            ErrorCode::Cancelled => 11,
+            ErrorCode::OutputExists => 12,
+        }
+    }
+
+    fn kind(self) -> io::ErrorKind {
+        match self {
+            ErrorCode::InvalidArguments => io::ErrorKind::InvalidInput,
+            ErrorCode::InputNotFound => io::ErrorKind::NotFound,
+            ErrorCode::CueMalformed | ErrorCode::CorruptSectors => io::ErrorKind::InvalidData,
+            ErrorCode::Unsupported => io::ErrorKind::Unsupported,
+            ErrorCode::PermissionDenied => io::ErrorKind::PermissionDenied,
+            ErrorCode::Cancelled => io::ErrorKind::Interrupted,
+            ErrorCode::OutputExists => io::ErrorKind::AlreadyExists,
+            _ => io::ErrorKind::Other,
+        }
+    }
+
+    /// Code of an error returned by the library
+    pub fn of(e: &io::Error) -> ErrorCode {
+        if let Some(coded) = e.get_ref().and_then(|e| e.downcast_ref::<CodedError>()) {
+            return coded.code;
+        }
+        // ENOSPC on unix, ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL on Windows
+        match (e.raw_os_error(), e.kind()) {
+            (Some(28), _) if cfg!(unix) => ErrorCode::DiskFull,
+            (Some(39 | 112), _) if cfg!(windows) => ErrorCode::DiskFull,
+            (_, io::ErrorKind::NotFound) => ErrorCode::InputNotFound,
+            (_, io::ErrorKind::PermissionDenied) => ErrorCode::PermissionDenied,
+            (_, io::ErrorKind::Unsupported) => ErrorCode::Unsupported,
+            _ => ErrorCode::Io,
+        }
+    }
+
+    /// Error of this code, e.g. for `DiscFormat`s and `OutputSink`s of other crates
+    pub fn error(self, message: impl Into<String>) -> io::Error {
+        io::Error::new(
+            self.kind(),
+            CodedError {
+                code: self,
+                message: message.into(),
+            },
+        )
+    }
+
+    /// Error caused by `e`, keeping its code when it is more specific than this one
+    pub(crate) fn wrap(self, e: &io::Error, message: impl Into<String>) -> io::Error {
+        match ErrorCode::of(e) {
+            ErrorCode::Io => self,
+            code => code,
+        }
+        .error(message)
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct CodedError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl Display for CodedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
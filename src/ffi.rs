@@ -0,0 +1,153 @@
+//! C ABI for launchers and tools that aren't written in Rust, declared in `include/rbchunk.h`
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::io;
+use std::mem::{self, MaybeUninit};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::{ptr, slice};
+
+use crate::error::ErrorCode;
+use crate::{convert, ConvertOptions, ExistingOutputs, Progress};
+
+/// Called with the track, sectors done and sectors total, and the `user_data` of the options
+pub type RbchunkProgress = extern "C" fn(u32, u64, u64, *mut c_void);
+
+/// `rbchunk_options` of the header, of which only the first `struct_size` bytes are read
+#[repr(C)]
+pub struct RbchunkOptions {
+    pub struct_size: usize,
+    pub bin_file: *const c_char,
+    pub cue_file: *const c_char,
+    pub output_name: *const c_char,
+    pub output_dir: *const c_char,
+    pub verbose: c_int,
+    pub psx_truncate: c_int,
+    pub raw: c_int,
+    pub swap_audio_bytes: c_int,
+    pub to_wav: c_int,
+    pub threads: u32,
+    pub progress: Option<RbchunkProgress>,
+    pub user_data: *mut c_void,
+    /// Added in version 2 of the options, so 0 for callers whose `struct_size` ends before it
+    pub existing: c_int,
+}
+
+/// The C side promises its callback may be called from any thread with its `user_data`
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+/// UTF-8 string behind `s`, empty for NULL
+unsafe fn string(s: *const c_char, name: &str) -> io::Result<String> {
+    if s.is_null() {
+        return Ok(String::new());
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(s.to_string()),
+        Err(_) => Err(ErrorCode::InvalidArguments.error(format!("{} is not valid UTF-8", name))),
+    }
+}
+
+unsafe fn convert_options(options: &RbchunkOptions) -> io::Result<ConvertOptions> {
+    let mut builder = ConvertOptions::builder()
+        .bin_file(string(options.bin_file, "bin_file")?)
+        .cue_file(string(options.cue_file, "cue_file")?)
+        .output_name(string(options.output_name, "output_name")?)
+        .output_dir(string(options.output_dir, "output_dir")?)
+        .verbose(options.verbose != 0)
+        .psx_truncate(options.psx_truncate != 0)
+        .raw(options.raw != 0)
+        .swap_audio_bytes(options.swap_audio_bytes != 0)
+        .to_wav(options.to_wav != 0)
+        .threads(options.threads as usize)
+        .existing(match options.existing {
+            1 => ExistingOutputs::Overwrite,
+            2 => ExistingOutputs::Skip,
+            _ => ExistingOutputs::Fail,
+        });
+    if let Some(callback) = options.progress {
+        let user_data = UserData(options.user_data);
+        builder = builder.progress(Arc::new(move |progress: &Progress| {
+            // The whole `UserData` is captured, not just the pointer it wraps
+            let user_data = &user_data;
+            callback(
+                progress.track,
+                progress.sectors_done,
+                progress.sectors_total,
+                user_data.0,
+            )
+        }));
+    }
+    builder.build()
+}
+
+/// The part of `options` within its `struct_size`, the fields after it being zeroed
+unsafe fn read_options(options: *const RbchunkOptions) -> io::Result<RbchunkOptions> {
+    let size = (*options).struct_size;
+    if size < mem::size_of::<usize>() {
+        return Err(ErrorCode::InvalidArguments.error(format!(
+            "struct_size is {}, it must be sizeof(rbchunk_options)",
+            size
+        )));
+    }
+    // Zeroes are NULL pointers, a NULL callback and the defaults of every other field
+    let mut copy = MaybeUninit::<RbchunkOptions>::zeroed();
+    let size = size.min(mem::size_of::<RbchunkOptions>());
+    ptr::copy_nonoverlapping(options as *const u8, copy.as_mut_ptr() as *mut u8, size);
+    Ok(copy.assume_init())
+}
+
+/// Copies as much of `message` as fits into `buf`, always NUL terminated
+unsafe fn write_error(buf: *mut c_char, len: usize, message: &str) {
+    if buf.is_null() || len == 0 {
+        return;
+    }
+    let buf = slice::from_raw_parts_mut(buf as *mut u8, len);
+    let mut length = message.len().min(len - 1);
+    // Cut between characters, not in the middle of one
+    while !message.is_char_boundary(length) {
+        length -= 1;
+    }
+    buf[..length].copy_from_slice(&message.as_bytes()[..length]);
+    buf[length] = 0;
+}
+
+/// Converts like `rbchunk::convert`, returning 0 or the number of the `ErrorCode`
+///
+/// # Safety
+///
+/// `options` must point to a valid `rbchunk_options` of `struct_size` bytes whose strings
+/// are NULL or NUL terminated, and `error_buf` must be NULL or hold `error_buf_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rbchunk_convert(
+    options: *const RbchunkOptions,
+    error_buf: *mut c_char,
+    error_buf_len: usize,
+) -> c_int {
+    if options.is_null() {
+        write_error(error_buf, error_buf_len, "options is NULL");
+        return ErrorCode::InvalidArguments.number();
+    }
+    // Panics mustn't unwind into C
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        convert(convert_options(&read_options(options)?)?)
+    }));
+    let e = match result {
+        Ok(Ok(())) => {
+            write_error(error_buf, error_buf_len, "");
+            return 0;
+        }
+        Ok(Err(e)) => e,
+        Err(_) => ErrorCode::Io.error("The conversion panicked"),
+    };
+    write_error(error_buf, error_buf_len, &e.to_string());
+    ErrorCode::of(&e).number()
+}
+
+/// Version of the library, e.g. "2.1.0"
+#[no_mangle]
+pub extern "C" fn rbchunk_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
@@ -0,0 +1,111 @@
+//! Single file images storing the sectors of every track at an offset and stride of its own
+
+use std::fs;
+use std::io;
+use std::io::{BufReader, Seek, SeekFrom};
+
+use crate::cue::fill_sector;
+use crate::sector::{add_header, SUBCODE_SIZE};
+use crate::track::Track;
+use crate::{ConvertOptions, SECTOR_SIZE};
+
+/// Reader of the sectors of tracks, each starting at `Track::start` in the file
+///
+/// A track may store its pregap right before its start, from `Track::index0`, and 96 bytes
+/// of subcode after every sector when its stride leaves room for them.
+pub(crate) struct ImageFile {
+    reader: BufReader<fs::File>,
+    /// Bytes every sector of the track at the same index takes in the file
+    strides: Vec<u64>,
+    position: u64,
+    /// Subcode stored after the last sector read, and that sector
+    subcode: [u8; SUBCODE_SIZE],
+    subcode_lba: Option<u64>,
+}
+
+impl ImageFile {
+    pub(crate) fn new(file: fs::File, strides: Vec<u64>, args: &ConvertOptions) -> ImageFile {
+        ImageFile {
+            reader: BufReader::with_capacity(args.read_buffer_size(), file),
+            strides,
+            position: 0,
+            subcode: [0; SUBCODE_SIZE],
+            subcode_lba: None,
+        }
+    }
+
+    /// Whether sectors of every track take up exactly what their mode stores, nothing more
+    pub(crate) fn is_plain(&self, tracks: &[Track]) -> bool {
+        tracks
+            .iter()
+            .zip(&self.strides)
+            .all(|(t, s)| t.sector_size() == *s)
+    }
+
+    /// Index of the track holding the sector `lba` and its position in the file
+    fn position(&self, tracks: &[Track], lba: u64) -> Option<(usize, u64)> {
+        let first = |t: &Track| t.index0.unwrap_or(t.start_sector);
+        let i = tracks.partition_point(|t| first(t) <= lba).checked_sub(1)?;
+        let t = &tracks[i];
+        let stride = self.strides[i];
+        match lba <= t.stop_sector() {
+            // Stored pregaps come right before the start of the track
+            true => Some((i, t.start + lba * stride - t.start_sector * stride)),
+            false => None,
+        }
+    }
+
+    pub(crate) fn read_sector(
+        &mut self,
+        tracks: &[Track],
+        lba: u64,
+        sector: &mut [u8],
+    ) -> io::Result<()> {
+        let (i, position) = match self.position(tracks, lba) {
+            Some(position) => position,
+            None => {
+                sector.fill(0);
+                return Ok(());
+            }
+        };
+        let mode = tracks[i].mode;
+
+        // Seeking drops the read buffer, so avoid it for sequential reads
+        if position != self.position {
+            self.reader.seek(SeekFrom::Start(position))?;
+        }
+        if mode.stored_range().len() == SECTOR_SIZE as usize {
+            fill_sector(&mut self.reader, sector)?;
+        } else {
+            // Cooked sectors only hold the user data, the rest of the raw sector is rebuilt
+            sector.fill(0);
+            fill_sector(&mut self.reader, &mut sector[mode.stored_range()])?;
+            add_header(sector, lba, mode);
+        }
+        let mut stored = mode.stored_range().len() as u64;
+        if self.strides[i] - stored == SUBCODE_SIZE as u64 {
+            fill_sector(&mut self.reader, &mut self.subcode)?;
+            self.subcode_lba = Some(lba);
+            stored += SUBCODE_SIZE as u64;
+        }
+        self.position = position + stored;
+        Ok(())
+    }
+
+    pub(crate) fn read_subcode(
+        &mut self,
+        tracks: &[Track],
+        lba: u64,
+        subcode: &mut [u8],
+    ) -> io::Result<()> {
+        if self.subcode_lba != Some(lba) {
+            let mut sector = [0u8; SECTOR_SIZE as usize];
+            self.read_sector(tracks, lba, &mut sector)?;
+        }
+        match self.subcode_lba == Some(lba) {
+            true => subcode.copy_from_slice(&self.subcode),
+            false => subcode.fill(0),
+        }
+        Ok(())
+    }
+}
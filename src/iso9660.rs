@@ -0,0 +1,554 @@
+//! The ISO9660 file systems of data tracks, their volume and the files in them
+//!
+//! The primary volume descriptor is the 17th block of a track, so inspecting a disc reads
+//! one sector per data track, and two more for the boot catalog of bootable ones. Files
+//! are read block by block from the sectors of the track, without writing the track out
+//! as an ISO first.
+
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::disc::Disc;
+use crate::eltorito::{boot_images, BootImage};
+use crate::error::ErrorCode;
+use crate::log::info;
+use crate::longpath::long_path;
+use crate::output::{self, OutputFile, OutputWriter};
+use crate::template::sanitize;
+use crate::track::{Mode, Track};
+use crate::{open_disc, validate, ConvertOptions, SECTOR_SIZE};
+
+/// Bytes of user data in a MODE1 or Form 1 sector, the logical blocks of the file system
+pub(crate) const BLOCK_SIZE: usize = 2048;
+
+/// Block of a track the primary volume descriptor is in, after the system area
+const DESCRIPTOR_BLOCK: u64 = 16;
+
+/// Levels of directories ISO9660 allows below the root
+const MAX_DEPTH: usize = 8;
+
+/// Directory bit of the flags of a directory record
+const DIRECTORY: u8 = 0x02;
+
+/// What the primary volume descriptor of a data track says about its volume
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Volume {
+    /// Number of the track holding the volume
+    pub track: u32,
+    /// Volume identifier, the label operating systems show for the disc
+    pub label: Option<String>,
+    /// Publisher identifier, or the name of the file holding it when prefixed with `_`
+    pub publisher: Option<String>,
+    /// Creation date and time, like `1999-07-23 12:34:56`
+    pub created: Option<String>,
+    /// Logical blocks the volume spans, counting from the start of the track
+    pub blocks: u64,
+    /// El Torito boot images of the volume, none unless the disc is bootable
+    pub boot_images: Vec<BootImage>,
+}
+
+impl Display for Volume {
+    /// One line per volume like `Track  1: SLUS_01234, 216728 blocks, created ...`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Track {:>2}: {}, {} blocks",
+            self.track,
+            self.label.as_deref().unwrap_or("(no label)"),
+            self.blocks
+        )?;
+        if let Some(publisher) = &self.publisher {
+            write!(f, ", published by {}", publisher)?;
+        }
+        if let Some(created) = &self.created {
+            write!(f, ", created {}", created)?;
+        }
+        if !self.boot_images.is_empty() {
+            write!(f, ", bootable")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the user data of `block` of the data `track`, returning false where it has none
+pub(crate) fn read_block(
+    disc: &mut dyn Disc,
+    track: &Track,
+    block: u64,
+    data: &mut [u8; BLOCK_SIZE],
+) -> io::Result<bool> {
+    let offset = match track.mode() {
+        Mode::Mode1_2352 | Mode::Mode1_2048 => 16,
+        Mode::Mode2_2352 | Mode::Mode2_2336 | Mode::Cdi2352 | Mode::Cdi2336 => 24,
+        _ => return Ok(false),
+    };
+    let lba = track.start_sector() + block;
+    if lba > track.stop_sector() {
+        return Ok(false);
+    }
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    disc.read_sector(lba, &mut sector)?;
+    data.copy_from_slice(&sector[offset..offset + BLOCK_SIZE]);
+    Ok(true)
+}
+
+/// Little endian half of the both-endian numbers of ISO9660
+pub(crate) fn le32(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64
+}
+
+/// Identifier padded with spaces, `None` if it's blank
+fn identifier(bytes: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim_end_matches([' ', '\0']).trim_start();
+    (!text.is_empty()).then(|| String::from(text))
+}
+
+/// Date and time of the 17 byte `YYYYMMDDhhmmsscc` form, `None` for the zeroes of no date
+fn date(bytes: &[u8]) -> Option<String> {
+    let digits = bytes.get(..14)?;
+    if !digits.iter().all(u8::is_ascii_digit) || digits.iter().all(|&b| b == b'0') {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| String::from_utf8_lossy(&digits[range]);
+    Some(format!(
+        "{}-{}-{} {}:{}:{}",
+        field(0..4),
+        field(4..6),
+        field(6..8),
+        field(8..10),
+        field(10..12),
+        field(12..14)
+    ))
+}
+
+/// Primary volume descriptor of the data `track`, `None` if it has no ISO9660 file system
+fn descriptor(disc: &mut dyn Disc, track: &Track) -> io::Result<Option<[u8; BLOCK_SIZE]>> {
+    let mut descriptor = [0u8; BLOCK_SIZE];
+    if !read_block(disc, track, DESCRIPTOR_BLOCK, &mut descriptor)? {
+        return Ok(None);
+    }
+    if descriptor[0] != 1 || &descriptor[1..6] != b"CD001" {
+        return Ok(None);
+    }
+    Ok(Some(descriptor))
+}
+
+/// Volume of the data `track`, `None` if it has no ISO9660 file system
+pub(crate) fn volume(disc: &mut dyn Disc, track: &Track) -> io::Result<Option<Volume>> {
+    let descriptor = match descriptor(disc, track)? {
+        Some(descriptor) => descriptor,
+        None => return Ok(None),
+    };
+    Ok(Some(Volume {
+        track: track.number(),
+        label: identifier(&descriptor[40..72]),
+        publisher: identifier(&descriptor[318..446]),
+        created: date(&descriptor[813..830]),
+        blocks: le32(&descriptor[80..]),
+        boot_images: boot_images(disc, track)?,
+    }))
+}
+
+/// Volumes of every data track of an already opened disc
+pub fn inspect_disc(disc: &mut dyn Disc) -> io::Result<Vec<Volume>> {
+    let tracks = disc.tracks().to_vec();
+    let mut volumes = Vec::new();
+    for t in tracks.iter().filter(|t| !t.is_audio()) {
+        volumes.extend(volume(disc, t)?);
+    }
+    Ok(volumes)
+}
+
+/// Opens the image described by `options` and reads the volume of every data track
+pub fn inspect(options: impl Into<ConvertOptions>) -> io::Result<Vec<Volume>> {
+    let mut args = ConvertOptions::new(options.into());
+    let mut disc = open_disc(&mut args)?;
+    inspect_disc(disc.as_mut())
+}
+
+/// A file or directory in the ISO9660 file system of a data track
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VolumeFile {
+    /// Number of the track holding the file system
+    pub track: u32,
+    /// Path from the root of the volume like `/DATA/MOVIE.STR`, without the `;1` version
+    pub path: String,
+    /// Block of the track the file starts at
+    pub extent: u64,
+    /// Bytes of the file, or of the records of a directory
+    pub size: u64,
+    pub directory: bool,
+}
+
+/// Names, extents, sizes and whether they're directories of the records of the directory at
+/// block `extent` of `track`, `size` bytes long, leaving out `.` and `..`
+pub(crate) fn directory(
+    disc: &mut dyn Disc,
+    track: &Track,
+    extent: u64,
+    size: u64,
+) -> io::Result<Vec<(String, u64, u64, bool)>> {
+    let mut records = Vec::new();
+    let mut block = [0u8; BLOCK_SIZE];
+    for n in extent..extent + size.div_ceil(BLOCK_SIZE as u64) {
+        if !read_block(disc, track, n, &mut block)? {
+            break;
+        }
+        // Records don't cross blocks, the rest of one too short for another is zeroed
+        let mut rest = &block[..];
+        while rest.len() > 33 && rest[0] as usize >= 33 && rest[0] as usize <= rest.len() {
+            let (record, after) = rest.split_at(rest[0] as usize);
+            rest = after;
+            let name = &record[33..(33 + record[32] as usize).min(record.len())];
+            if name == [0] || name == [1] {
+                continue;
+            }
+            let name = name.split(|&b| b == b';').next().unwrap_or_default();
+            records.push((
+                String::from_utf8_lossy(name).into_owned(),
+                le32(&record[2..]),
+                le32(&record[10..]),
+                record[25] & DIRECTORY != 0,
+            ));
+        }
+    }
+    Ok(records)
+}
+
+/// Hands the `size` bytes of the file at block `extent` of `track` to `write`, a block at
+/// a time
+///
+/// Returns how many bytes the track held of them, fewer for files past its end.
+pub(crate) fn read_extent(
+    disc: &mut dyn Disc,
+    track: &Track,
+    extent: u64,
+    size: u64,
+    mut write: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<u64> {
+    let mut block = [0u8; BLOCK_SIZE];
+    let mut read = 0;
+    let mut n = extent;
+    while read < size {
+        if !read_block(disc, track, n, &mut block)? {
+            break;
+        }
+        let length = (size - read).min(BLOCK_SIZE as u64);
+        write(&block[..length as usize])?;
+        read += length;
+        n += 1;
+    }
+    Ok(read)
+}
+
+/// Every file and directory of the file system of `track`, parents before what they hold
+fn track_files(disc: &mut dyn Disc, track: &Track) -> io::Result<Vec<VolumeFile>> {
+    let descriptor = match descriptor(disc, track)? {
+        Some(descriptor) => descriptor,
+        None => return Ok(Vec::new()),
+    };
+    // The record of the root directory is part of the volume descriptor
+    let root = &descriptor[156..];
+    let mut pending = vec![(String::new(), le32(&root[2..]), le32(&root[10..]), 0)];
+    let mut visited = Vec::new();
+    let mut files = Vec::new();
+    while let Some((parent, extent, size, depth)) = pending.pop() {
+        // Broken images may point a directory at one of its parents
+        if visited.contains(&extent) {
+            continue;
+        }
+        visited.push(extent);
+        let mut children = Vec::new();
+        for (name, extent, size, is_directory) in directory(disc, track, extent, size)? {
+            let file = VolumeFile {
+                track: track.number(),
+                path: format!("{}/{}", parent, name),
+                extent,
+                size,
+                directory: is_directory,
+            };
+            if is_directory && depth < MAX_DEPTH {
+                children.push((file.path.clone(), extent, size, depth + 1));
+            }
+            files.push(file);
+        }
+        // Popped from the end, so the first directory is walked first
+        pending.extend(children.into_iter().rev());
+    }
+    Ok(files)
+}
+
+/// Files and directories in the data tracks of an already opened disc
+pub fn list_disc_files(disc: &mut dyn Disc) -> io::Result<Vec<VolumeFile>> {
+    let tracks = disc.tracks().to_vec();
+    let mut files = Vec::new();
+    for t in tracks.iter().filter(|t| !t.is_audio()) {
+        files.extend(track_files(disc, t)?);
+    }
+    Ok(files)
+}
+
+/// Opens the image described by `options` and lists the files in its data tracks
+pub fn list_files(options: impl Into<ConvertOptions>) -> io::Result<Vec<VolumeFile>> {
+    let mut args = ConvertOptions::new(options.into());
+    let mut disc = open_disc(&mut args)?;
+    list_disc_files(disc.as_mut())
+}
+
+/// Whether `file` is one of `paths` or in one of them, all of them if there are none
+///
+/// Paths are matched without regard to case, as ISO9660 names are upper case.
+fn selected(file: &VolumeFile, paths: &[String]) -> bool {
+    paths.is_empty()
+        || paths.iter().any(|path| {
+            let path = format!("/{}", path.trim_matches('/')).to_ascii_uppercase();
+            let name = file.path.to_ascii_uppercase();
+            name == path || path == "/" || name.starts_with(&format!("{}/", path))
+        })
+}
+
+/// Writes the files of the data tracks of an already opened disc to a directory per track,
+/// like `foo01/DATA/MOVIE.STR`, keeping the tree they're in
+///
+/// Only the files at and under `paths` are written, every one for no `paths`. Names from
+/// the image have characters file systems reject replaced, so they can't point elsewhere.
+///
+/// If writing fails the files already written are removed again, unless
+/// `ConvertOptions::keep_partial` is set.
+pub fn extract_disc_files(
+    disc: &mut dyn Disc,
+    paths: &[String],
+    a: &ConvertOptions,
+) -> io::Result<Vec<OutputFile>> {
+    let mut written = Vec::new();
+    if let Err(e) = write_files(disc, paths, &mut written, a) {
+        output::roll_back(&written, a);
+        return Err(e);
+    }
+    Ok(written)
+}
+
+fn write_files(
+    disc: &mut dyn Disc,
+    paths: &[String],
+    written: &mut Vec<OutputFile>,
+    a: &ConvertOptions,
+) -> io::Result<()> {
+    let tracks = disc.tracks().to_vec();
+    for t in tracks.iter().filter(|t| !t.is_audio()) {
+        let directory = format!("{}{:0>2}", a.output_name, t.number());
+        for file in track_files(disc, t)? {
+            if file.directory || !selected(&file, paths) {
+                continue;
+            }
+            a.check_cancelled()?;
+            let mut filename = directory.clone();
+            for component in file.path.split('/').filter(|c| !c.is_empty()) {
+                match sanitize(component) {
+                    component if component.is_empty() => filename += "/_",
+                    component => filename += &format!("/{}", component),
+                }
+            }
+            if a.output_sink.is_none() {
+                if let Some(parent) = Path::new(&filename).parent() {
+                    fs::create_dir_all(long_path(parent))?;
+                }
+            }
+            let mut writer = match OutputWriter::create(filename, a) {
+                Ok(writer) => writer,
+                Err(e) => {
+                    return Err(ErrorCode::WriteFailed
+                        .wrap(&e, format!("Could not write {}: {}", file.path, e)))
+                }
+            };
+            let read = read_extent(disc, t, file.extent, file.size, |data| {
+                writer.write_all(data)
+            })?;
+            if read < file.size {
+                return Err(ErrorCode::CorruptSectors.error(format!(
+                    "{} of track {} runs past the end of the track",
+                    file.path,
+                    t.number()
+                )));
+            }
+            let output = writer.finish()?;
+            if a.verbose {
+                info!("{}: {}", file.path, output.filename);
+            }
+            written.push(output);
+        }
+    }
+    Ok(())
+}
+
+/// Opens the image described by `options` and writes the files of its data tracks like
+/// `extract_disc_files`
+pub fn extract_files(
+    options: impl Into<ConvertOptions>,
+    paths: &[String],
+) -> io::Result<Vec<OutputFile>> {
+    let mut args = ConvertOptions::new(options.into());
+    validate(&args)?;
+    let mut disc = open_disc(&mut args)?;
+    extract_disc_files(disc.as_mut(), paths, &args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disc::DiscMetadata;
+
+    /// Single MODE1 track of cooked blocks, read back at the offset of raw sectors
+    struct Blocks {
+        tracks: Vec<Track>,
+        blocks: Vec<[u8; BLOCK_SIZE]>,
+        metadata: DiscMetadata,
+    }
+
+    impl Disc for Blocks {
+        fn tracks(&self) -> &[Track] {
+            &self.tracks
+        }
+
+        fn read_sector(&mut self, lba: u64, sector: &mut [u8]) -> io::Result<()> {
+            sector.fill(0);
+            sector[16..16 + BLOCK_SIZE].copy_from_slice(&self.blocks[lba as usize]);
+            Ok(())
+        }
+
+        fn metadata(&self) -> &DiscMetadata {
+            &self.metadata
+        }
+    }
+
+    /// Directory record of `name` at block `extent`, `size` bytes long
+    fn record(name: &[u8], extent: u32, size: u32, directory: bool) -> Vec<u8> {
+        let length = 33 + name.len() + (name.len() + 1) % 2;
+        let mut record = vec![0u8; length];
+        record[0] = length as u8;
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[6..10].copy_from_slice(&extent.to_be_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[14..18].copy_from_slice(&size.to_be_bytes());
+        record[25] = if directory { DIRECTORY } else { 0 };
+        record[32] = name.len() as u8;
+        record[33..33 + name.len()].copy_from_slice(name);
+        record
+    }
+
+    fn directory_block(records: &[Vec<u8>]) -> [u8; BLOCK_SIZE] {
+        let mut block = [0u8; BLOCK_SIZE];
+        let records = records.concat();
+        block[..records.len()].copy_from_slice(&records);
+        block
+    }
+
+    /// Volume of `/SYSTEM.CNF` and `/DATA/MOVIE.STR`, whose `DATA` also points back at the root
+    fn disc() -> Blocks {
+        let mut blocks = vec![[0u8; BLOCK_SIZE]; 24];
+        let pvd = &mut blocks[DESCRIPTOR_BLOCK as usize];
+        pvd[..6].copy_from_slice(b"\x01CD001");
+        pvd[40..72].copy_from_slice(&[b' '; 32]);
+        pvd[40..49].copy_from_slice(b"SLUS_0123");
+        pvd[80..84].copy_from_slice(&24u32.to_le_bytes());
+        pvd[318..446].copy_from_slice(&[b' '; 128]);
+        pvd[813..830].copy_from_slice(b"1999072312345600\0");
+        let root = record(&[0], 18, 2048, true);
+        pvd[156..156 + root.len()].copy_from_slice(&root);
+
+        blocks[18] = directory_block(&[
+            record(&[0], 18, 2048, true),
+            record(&[1], 18, 2048, true),
+            record(b"DATA", 19, 2048, true),
+            record(b"SYSTEM.CNF;1", 20, 30, false),
+        ]);
+        blocks[19] = directory_block(&[
+            record(&[0], 19, 2048, true),
+            record(&[1], 18, 2048, true),
+            record(b"LOOP", 18, 2048, true),
+            record(b"MOVIE.STR;1", 21, 3000, false),
+        ]);
+        blocks[21].fill(0x21);
+        blocks[22].fill(0x22);
+        Blocks {
+            tracks: vec![Track::new(1, Mode::Mode1_2048, 0, 23)],
+            blocks,
+            metadata: DiscMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn reads_the_primary_volume_descriptor() {
+        let volumes = inspect_disc(&mut disc()).unwrap();
+        assert_eq!(
+            volumes,
+            [Volume {
+                track: 1,
+                label: Some(String::from("SLUS_0123")),
+                publisher: None,
+                created: Some(String::from("1999-07-23 12:34:56")),
+                blocks: 24,
+                boot_images: Vec::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn lists_files_parents_first_without_looping() {
+        let files = list_disc_files(&mut disc()).unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["/DATA", "/SYSTEM.CNF", "/DATA/LOOP", "/DATA/MOVIE.STR"]
+        );
+        assert_eq!(
+            (files[3].extent, files[3].size, files[3].directory),
+            (21, 3000, false)
+        );
+    }
+
+    #[test]
+    fn reads_extents_up_to_their_size() {
+        let mut disc = disc();
+        let track = disc.tracks[0].clone();
+        let mut data = Vec::new();
+        let read = read_extent(&mut disc, &track, 21, 3000, |block| {
+            data.extend_from_slice(block);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(read, 3000);
+        assert!(data[..2048].iter().all(|&b| b == 0x21) && data[2048..].iter().all(|&b| b == 0x22));
+        // Files running past the end of the track stop where it does
+        assert_eq!(
+            read_extent(&mut disc, &track, 23, 4096, |_| Ok(())).unwrap(),
+            2048
+        );
+    }
+
+    #[test]
+    fn tracks_without_a_descriptor_have_no_volume() {
+        let mut disc = disc();
+        disc.blocks[DESCRIPTOR_BLOCK as usize][1] = b'X';
+        assert!(inspect_disc(&mut disc).unwrap().is_empty());
+        assert!(list_disc_files(&mut disc).unwrap().is_empty());
+    }
+
+    #[test]
+    fn selects_files_under_paths_regardless_of_case() {
+        let file = VolumeFile {
+            track: 1,
+            path: String::from("/DATA/MOVIE.STR"),
+            extent: 21,
+            size: 3000,
+            directory: false,
+        };
+        assert!(selected(&file, &[]));
+        assert!(selected(&file, &[String::from("data/")]));
+        assert!(selected(&file, &[String::from("/DATA/movie.str")]));
+        assert!(!selected(&file, &[String::from("/DAT")]));
+    }
+}
@@ -0,0 +1,1044 @@
+//! The track model written as JSON and read back, for frontends persisting parsed layouts
+//!
+//! This is what a serde feature would give, without pulling in a dependency: `ToJson` and
+//! `FromJson` are implemented for tracks, their modes and metadata, plans, conversion
+//! reports and their stats, outputs and diagnostics, and `Json` prints and parses the
+//! documents.
+
+use std::fmt::Display;
+use std::io;
+
+use crate::disc::{CdText, DiscMetadata, RemMetadata};
+use crate::discid::DiscIds;
+use crate::doctor::Diagnostic;
+use crate::eltorito::{BootImage, Emulation};
+use crate::error::ErrorCode;
+use crate::hash::Hashes;
+use crate::iso9660::{Volume, VolumeFile};
+use crate::output::OutputFile;
+use crate::plan::{Plan, PlannedOutput, PlannedTrack};
+use crate::registry::formats;
+use crate::stats::{Stats, TrackStats};
+use crate::track::{Mode, Track, TrackFlags};
+use crate::Report;
+
+/// Arrays and objects nested deeper than this aren't parsed, rather than overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+/// A JSON value, objects keep their members in order
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Values written as JSON
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+/// Values read back from the JSON `ToJson` writes
+pub trait FromJson: Sized {
+    fn from_json(json: &Json) -> io::Result<Self>;
+}
+
+fn invalid(problem: impl Display) -> io::Error {
+    ErrorCode::InvalidArguments.error(format!("Invalid JSON: {}", problem))
+}
+
+impl Json {
+    /// Parses a whole JSON document
+    pub fn parse(text: &str) -> io::Result<Json> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            at: 0,
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        match parser.at == text.len() {
+            true => Ok(value),
+            false => Err(parser.error("trailing characters")),
+        }
+    }
+
+    /// Object of `members`
+    pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (String::from(key), value))
+                .collect(),
+        )
+    }
+
+    /// Member `key` of an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Member `key` of an object read as a `T`, missing members read like `null`
+    pub fn member<T: FromJson>(&self, key: &str) -> io::Result<T> {
+        if !matches!(self, Json::Object(_)) {
+            return Err(invalid(format!("expected an object with {}", key)));
+        }
+        match T::from_json(self.get(key).unwrap_or(&Json::Null)) {
+            Ok(value) => Ok(value),
+            Err(e) => Err(ErrorCode::InvalidArguments.wrap(&e, format!("{} in {}", e, key))),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Number(_) => "a number",
+            Json::String(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+
+    fn expected(&self, what: &str) -> io::Error {
+        invalid(format!("expected {}, found {}", what, self.kind()))
+    }
+
+    fn write(&self, f: &mut std::fmt::Formatter, indent: usize) -> std::fmt::Result {
+        // `{:#}` prints a member or element per line
+        let (pretty, inner) = (f.alternate(), indent + 2);
+        let newline = |f: &mut std::fmt::Formatter, indent: usize| match pretty {
+            true => write!(f, "\n{:indent$}", ""),
+            false => Ok(()),
+        };
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if !n.is_finite() => f.write_str("null"),
+            // Sector counts and sizes are printed as integers, not as 1e15
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(elements) if elements.is_empty() => f.write_str("[]"),
+            Json::Array(elements) => {
+                f.write_str("[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    newline(f, inner)?;
+                    element.write(f, inner)?;
+                }
+                newline(f, indent)?;
+                f.write_str("]")
+            }
+            Json::Object(members) if members.is_empty() => f.write_str("{}"),
+            Json::Object(members) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    newline(f, inner)?;
+                    write_string(f, key)?;
+                    f.write_str(if pretty { ": " } else { ":" })?;
+                    value.write(f, inner)?;
+                }
+                newline(f, indent)?;
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// The value as a JSON document, indented with `{:#}`
+impl Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter, s: &str) -> std::fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, problem: &str) -> io::Error {
+        invalid(format!("{} at byte {}", problem, self.at))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.text.get(self.at), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.at += 1;
+        }
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.text.get(self.at).copied();
+        self.at += 1;
+        byte
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> io::Result<Json> {
+        match self.text[self.at..].starts_with(literal.as_bytes()) {
+            true => {
+                self.at += literal.len();
+                Ok(value)
+            }
+            false => Err(self.error("unknown literal")),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> io::Result<Json> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("too deeply nested"));
+        }
+        self.skip_whitespace();
+        match self.text.get(self.at) {
+            None => Err(self.error("unexpected end")),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'[') => {
+                self.at += 1;
+                let mut elements = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.at) == Some(&b']') {
+                    self.at += 1;
+                    return Ok(Json::Array(elements));
+                }
+                loop {
+                    elements.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(b',') => {}
+                        Some(b']') => return Ok(Json::Array(elements)),
+                        _ => return Err(self.error("expected , or ]")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.at += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.at) == Some(&b'}') {
+                    self.at += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.text.get(self.at) != Some(&b'"') {
+                        return Err(self.error("expected a member name"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if self.next() != Some(b':') {
+                        return Err(self.error("expected :"));
+                    }
+                    members.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(b',') => {}
+                        Some(b'}') => return Ok(Json::Object(members)),
+                        _ => return Err(self.error("expected , or }")),
+                    }
+                }
+            }
+            Some(_) => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> io::Result<Json> {
+        let start = self.at;
+        while matches!(
+            self.text.get(self.at),
+            Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')
+        ) {
+            self.at += 1;
+        }
+        // Only ASCII was skipped, so this is still UTF-8
+        let number = std::str::from_utf8(&self.text[start..self.at]).unwrap_or_default();
+        match number.parse() {
+            Ok(n) if !number.starts_with(['+', '.']) => Ok(Json::Number(n)),
+            _ => {
+                self.at = start;
+                Err(self.error("expected a value"))
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> io::Result<u32> {
+        let digits = self.text.get(self.at..self.at + 4);
+        let code = digits
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok());
+        match code {
+            Some(code) => {
+                self.at += 4;
+                Ok(code)
+            }
+            None => Err(self.error("invalid \\u escape")),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        // Skips the opening quote
+        self.at += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let c = match self.next() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            // Characters outside the BMP are escaped as surrogate pairs
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.text[self.at..].starts_with(b"\\u")
+                            {
+                                self.at += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid \\u escape"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => c,
+                                None => return Err(self.error("invalid \\u escape")),
+                            }
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(byte) => bytes.push(byte),
+            }
+        }
+        // The text was a str and escapes are pushed as whole characters
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+}
+
+impl ToJson for bool {
+    fn to_json(&self) -> Json {
+        Json::Bool(*self)
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match json {
+            Json::Bool(b) => Ok(*b),
+            json => Err(json.expected("a boolean")),
+        }
+    }
+}
+
+impl ToJson for u64 {
+    fn to_json(&self) -> Json {
+        Json::Number(*self as f64)
+    }
+}
+
+impl FromJson for u64 {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match json {
+            Json::Number(n) if n.fract() == 0.0 && *n >= 0.0 && *n < u64::MAX as f64 => {
+                Ok(*n as u64)
+            }
+            json => Err(json.expected("a whole number")),
+        }
+    }
+}
+
+impl ToJson for u32 {
+    fn to_json(&self) -> Json {
+        Json::Number(*self as f64)
+    }
+}
+
+impl FromJson for u32 {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match u32::try_from(u64::from_json(json)?) {
+            Ok(n) => Ok(n),
+            Err(_) => Err(invalid("number out of range")),
+        }
+    }
+}
+
+impl ToJson for u16 {
+    fn to_json(&self) -> Json {
+        Json::Number(*self as f64)
+    }
+}
+
+impl FromJson for u16 {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match u16::try_from(u64::from_json(json)?) {
+            Ok(n) => Ok(n),
+            Err(_) => Err(invalid("number out of range")),
+        }
+    }
+}
+
+impl ToJson for u8 {
+    fn to_json(&self) -> Json {
+        Json::Number(*self as f64)
+    }
+}
+
+impl FromJson for u8 {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match u8::try_from(u64::from_json(json)?) {
+            Ok(n) => Ok(n),
+            Err(_) => Err(invalid("number out of range")),
+        }
+    }
+}
+
+impl ToJson for i64 {
+    fn to_json(&self) -> Json {
+        Json::Number(*self as f64)
+    }
+}
+
+impl FromJson for i64 {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match json {
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Ok(*n as i64),
+            json => Err(json.expected("a whole number")),
+        }
+    }
+}
+
+impl ToJson for f64 {
+    fn to_json(&self) -> Json {
+        Json::Number(*self)
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match json {
+            Json::Number(n) => Ok(*n),
+            json => Err(json.expected("a number")),
+        }
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self) -> Json {
+        Json::String(String::from(self))
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> Json {
+        Json::String(self.clone())
+    }
+}
+
+impl FromJson for String {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match json {
+            Json::String(s) => Ok(s.clone()),
+            json => Err(json.expected("a string")),
+        }
+    }
+}
+
+/// `None` is written as `null`
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        match self {
+            Some(value) => value.to_json(),
+            None => Json::Null,
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match json {
+            Json::Null => Ok(None),
+            json => Ok(Some(T::from_json(json)?)),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        self.as_slice().to_json()
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        match json {
+            Json::Array(elements) => elements.iter().map(T::from_json).collect(),
+            json => Err(json.expected("an array")),
+        }
+    }
+}
+
+/// Modes are written the way CUE sheets name them, e.g. `MODE1/2352`
+impl ToJson for Mode {
+    fn to_json(&self) -> Json {
+        self.as_ref().to_json()
+    }
+}
+
+impl FromJson for Mode {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        let name = String::from_json(json)?;
+        match Mode::from(name.as_str()) {
+            Mode::Unknown if name != Mode::Unknown.as_ref() => {
+                Err(invalid(format!("unknown track mode {}", name)))
+            }
+            mode => Ok(mode),
+        }
+    }
+}
+
+impl ToJson for TrackFlags {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("digital_copy", self.digital_copy.to_json()),
+            ("four_channel", self.four_channel.to_json()),
+            ("pre_emphasis", self.pre_emphasis.to_json()),
+            ("scms", self.scms.to_json()),
+        ])
+    }
+}
+
+impl FromJson for TrackFlags {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(TrackFlags {
+            digital_copy: json.member("digital_copy")?,
+            four_channel: json.member("four_channel")?,
+            pre_emphasis: json.member("pre_emphasis")?,
+            scms: json.member("scms")?,
+        })
+    }
+}
+
+impl ToJson for CdText {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("title", self.title.to_json()),
+            ("performer", self.performer.to_json()),
+            ("songwriter", self.songwriter.to_json()),
+        ])
+    }
+}
+
+impl FromJson for CdText {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(CdText {
+            title: json.member("title")?,
+            performer: json.member("performer")?,
+            songwriter: json.member("songwriter")?,
+        })
+    }
+}
+
+/// Other `REM` lines are written as an object, in the order they were read
+impl ToJson for RemMetadata {
+    fn to_json(&self) -> Json {
+        let other = self
+            .other
+            .iter()
+            .map(|(key, value)| (key.clone(), value.to_json()))
+            .collect();
+        Json::object([
+            ("genre", self.genre.to_json()),
+            ("date", self.date.to_json()),
+            ("disc_id", self.disc_id.to_json()),
+            ("comment", self.comment.to_json()),
+            ("other", Json::Object(other)),
+        ])
+    }
+}
+
+impl FromJson for RemMetadata {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        let other = match json.get("other") {
+            None | Some(Json::Null) => Vec::new(),
+            Some(Json::Object(members)) => members
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), String::from_json(value)?)))
+                .collect::<io::Result<_>>()?,
+            Some(other) => return Err(other.expected("an object of other")),
+        };
+        Ok(RemMetadata {
+            genre: json.member("genre")?,
+            date: json.member("date")?,
+            disc_id: json.member("disc_id")?,
+            comment: json.member("comment")?,
+            other,
+        })
+    }
+}
+
+/// The layout of the track as it was read, outputs are worked out again when converting
+impl ToJson for Track {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("number", self.number.to_json()),
+            ("mode", self.mode.to_json()),
+            ("start_sector", self.start_sector.to_json()),
+            ("stop_sector", self.stop_sector().to_json()),
+            ("start", self.start.to_json()),
+            ("byte_offset", self.byte_offset.to_json()),
+            ("index0", self.index0.to_json()),
+            ("indexes", self.indexes.to_json()),
+            ("pregap", self.pregap.to_json()),
+            ("postgap", self.postgap.to_json()),
+            ("session", self.session().to_json()),
+            ("subcode", self.subcode.to_json()),
+            ("flags", self.flags.to_json()),
+            ("isrc", self.isrc.to_json()),
+            ("cd_text", self.cd_text.to_json()),
+            ("rem", self.rem.to_json()),
+        ])
+    }
+}
+
+impl FromJson for Track {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        let mut track = Track::new(
+            json.member("number")?,
+            json.member("mode")?,
+            json.member("start_sector")?,
+            json.member("stop_sector")?,
+        );
+        if let Some(start) = json.member("start")? {
+            track.start = start;
+        }
+        track.byte_offset = json.member("byte_offset")?;
+        track.index0 = json.member("index0")?;
+        track.indexes = json.member::<Option<_>>("indexes")?.unwrap_or_default();
+        track.pregap = json.member::<Option<_>>("pregap")?.unwrap_or_default();
+        track.postgap = json.member::<Option<_>>("postgap")?.unwrap_or_default();
+        track.session = json.member::<Option<_>>("session")?.unwrap_or_default();
+        track.subcode = json.member::<Option<_>>("subcode")?.unwrap_or_default();
+        track.flags = json.member::<Option<_>>("flags")?.unwrap_or_default();
+        track.isrc = json.member("isrc")?;
+        track.cd_text = json.member::<Option<_>>("cd_text")?.unwrap_or_default();
+        track.rem = json.member::<Option<_>>("rem")?.unwrap_or_default();
+        track.check_layout()?;
+        Ok(track)
+    }
+}
+
+impl ToJson for DiscMetadata {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("format", self.format.to_json()),
+            ("image_size", self.image_size.to_json()),
+            ("catalog", self.catalog.to_json()),
+            ("serial", self.serial.to_json()),
+            ("lead_out", self.lead_out.to_json()),
+            ("cd_text", self.cd_text.to_json()),
+            ("rem", self.rem.to_json()),
+        ])
+    }
+}
+
+/// Only formats known to the auto-detector can be read back, their names are `'static`
+impl FromJson for DiscMetadata {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        let name: String = json.member("format")?;
+        let format = match formats().iter().find(|f| f.name() == name) {
+            Some(format) => format.name(),
+            None => return Err(invalid(format!("unknown image format {}", name))),
+        };
+        Ok(DiscMetadata {
+            format,
+            image_size: json.member("image_size")?,
+            catalog: json.member("catalog")?,
+            serial: json.member("serial")?,
+            lead_out: json.member("lead_out")?,
+            cd_text: json.member::<Option<_>>("cd_text")?.unwrap_or_default(),
+            rem: json.member::<Option<_>>("rem")?.unwrap_or_default(),
+        })
+    }
+}
+
+impl ToJson for PlannedOutput {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("filename", self.filename.to_json()),
+            ("size", self.size.to_json()),
+            ("estimated", self.estimated.to_json()),
+        ])
+    }
+}
+
+impl FromJson for PlannedOutput {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(PlannedOutput {
+            filename: json.member("filename")?,
+            size: json.member("size")?,
+            estimated: json.member::<Option<_>>("estimated")?.unwrap_or_default(),
+        })
+    }
+}
+
+impl ToJson for PlannedTrack {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("number", self.number.to_json()),
+            ("mode", self.mode.to_json()),
+            ("start_sector", self.start_sector.to_json()),
+            ("stop_sector", self.stop_sector.to_json()),
+            ("outputs", self.outputs.to_json()),
+        ])
+    }
+}
+
+impl FromJson for PlannedTrack {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(PlannedTrack {
+            number: json.member("number")?,
+            mode: json.member("mode")?,
+            start_sector: json.member("start_sector")?,
+            stop_sector: json.member("stop_sector")?,
+            outputs: json.member("outputs")?,
+        })
+    }
+}
+
+impl ToJson for DiscIds {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("freedb", self.freedb.to_json()),
+            ("musicbrainz", self.musicbrainz.to_json()),
+        ])
+    }
+}
+
+impl FromJson for DiscIds {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(DiscIds {
+            freedb: json.member("freedb")?,
+            musicbrainz: json.member("musicbrainz")?,
+        })
+    }
+}
+
+impl ToJson for Plan {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("tracks", self.tracks.to_json()),
+            ("outputs", self.outputs.to_json()),
+            ("disc_ids", self.disc_ids.to_json()),
+            ("serial", self.serial.to_json()),
+        ])
+    }
+}
+
+impl FromJson for Plan {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(Plan {
+            tracks: json.member("tracks")?,
+            outputs: json.member("outputs")?,
+            disc_ids: json.member("disc_ids")?,
+            serial: json.member("serial")?,
+        })
+    }
+}
+
+/// Checksums are written in hex, like dats list them
+impl ToJson for Hashes {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("size", self.size.to_json()),
+            ("crc32", self.crc32_hex().to_json()),
+            ("md5", self.md5_hex().to_json()),
+            ("sha1", self.sha1_hex().to_json()),
+        ])
+    }
+}
+
+fn unhex<const N: usize>(json: &Json, key: &str) -> io::Result<[u8; N]> {
+    let hex: String = json.member(key)?;
+    let mut bytes = [0u8; N];
+    let digits = hex.as_bytes().chunks(2).map(std::str::from_utf8);
+    for (byte, digits) in bytes.iter_mut().zip(digits) {
+        *byte = match digits.map(|d| u8::from_str_radix(d, 16)) {
+            Ok(Ok(value)) => value,
+            _ => return Err(invalid(format!("{} isn't hexadecimal", key))),
+        };
+    }
+    match hex.len() == N * 2 {
+        true => Ok(bytes),
+        false => Err(invalid(format!(
+            "{} isn't {} hexadecimal digits",
+            key,
+            N * 2
+        ))),
+    }
+}
+
+impl FromJson for Hashes {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(Hashes {
+            size: json.member("size")?,
+            crc32: u32::from_be_bytes(unhex(json, "crc32")?),
+            md5: unhex(json, "md5")?,
+            sha1: unhex(json, "sha1")?,
+        })
+    }
+}
+
+impl ToJson for OutputFile {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("filename", self.filename.to_json()),
+            ("size", self.size.to_json()),
+            ("hashes", self.hashes.to_json()),
+        ])
+    }
+}
+
+impl FromJson for OutputFile {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(OutputFile {
+            filename: json.member("filename")?,
+            size: json.member("size")?,
+            hashes: json.member("hashes")?,
+        })
+    }
+}
+
+impl ToJson for Diagnostic {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("track", self.track.to_json()),
+            ("problem", self.problem.to_json()),
+            ("suggestion", self.suggestion.to_json()),
+        ])
+    }
+}
+
+impl FromJson for Diagnostic {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(Diagnostic {
+            track: json.member("track")?,
+            problem: json.member("problem")?,
+            suggestion: json.member("suggestion")?,
+        })
+    }
+}
+
+impl ToJson for Volume {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("track", self.track.to_json()),
+            ("label", self.label.to_json()),
+            ("publisher", self.publisher.to_json()),
+            ("created", self.created.to_json()),
+            ("blocks", self.blocks.to_json()),
+            ("boot_images", self.boot_images.to_json()),
+        ])
+    }
+}
+
+impl FromJson for Volume {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(Volume {
+            track: json.member("track")?,
+            label: json.member("label")?,
+            publisher: json.member("publisher")?,
+            created: json.member("created")?,
+            blocks: json.member("blocks")?,
+            boot_images: json.member("boot_images")?,
+        })
+    }
+}
+
+impl ToJson for Emulation {
+    fn to_json(&self) -> Json {
+        self.as_ref().to_json()
+    }
+}
+
+impl FromJson for Emulation {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        let name = String::from_json(json)?;
+        [
+            Emulation::NoEmulation,
+            Emulation::Floppy1200,
+            Emulation::Floppy1440,
+            Emulation::Floppy2880,
+            Emulation::HardDisk,
+        ]
+        .into_iter()
+        .find(|emulation| emulation.as_ref() == name)
+        .ok_or_else(|| invalid(format!("unknown emulation {}", name)))
+    }
+}
+
+impl ToJson for BootImage {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("track", self.track.to_json()),
+            ("bootable", self.bootable.to_json()),
+            ("platform", self.platform.to_json()),
+            ("emulation", self.emulation.to_json()),
+            ("load_segment", self.load_segment.to_json()),
+            ("sectors", self.sectors.to_json()),
+            ("extent", self.extent.to_json()),
+            ("size", self.size.to_json()),
+        ])
+    }
+}
+
+impl FromJson for BootImage {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(BootImage {
+            track: json.member("track")?,
+            bootable: json.member("bootable")?,
+            platform: json.member("platform")?,
+            emulation: json.member("emulation")?,
+            load_segment: json.member("load_segment")?,
+            sectors: json.member("sectors")?,
+            extent: json.member("extent")?,
+            size: json.member("size")?,
+        })
+    }
+}
+
+impl ToJson for VolumeFile {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("track", self.track.to_json()),
+            ("path", self.path.to_json()),
+            ("extent", self.extent.to_json()),
+            ("size", self.size.to_json()),
+            ("directory", self.directory.to_json()),
+        ])
+    }
+}
+
+impl FromJson for VolumeFile {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(VolumeFile {
+            track: json.member("track")?,
+            path: json.member("path")?,
+            extent: json.member("extent")?,
+            size: json.member("size")?,
+            directory: json.member("directory")?,
+        })
+    }
+}
+
+/// The throughput is written along with what it's worked out from, for reading at a glance
+impl ToJson for TrackStats {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("number", self.number.to_json()),
+            ("bytes_read", self.bytes_read.to_json()),
+            ("bytes_written", self.bytes_written.to_json()),
+            ("seconds", self.seconds.to_json()),
+            ("throughput", self.throughput().to_json()),
+        ])
+    }
+}
+
+impl FromJson for TrackStats {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(TrackStats {
+            number: json.member("number")?,
+            bytes_read: json.member("bytes_read")?,
+            bytes_written: json.member("bytes_written")?,
+            seconds: json.member("seconds")?,
+        })
+    }
+}
+
+impl ToJson for Stats {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("tracks", self.tracks.to_json()),
+            ("bytes_read", self.bytes_read.to_json()),
+            ("bytes_written", self.bytes_written.to_json()),
+            ("seconds", self.seconds.to_json()),
+            ("throughput", self.throughput().to_json()),
+        ])
+    }
+}
+
+impl FromJson for Stats {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(Stats {
+            tracks: json.member("tracks")?,
+            bytes_read: json.member("bytes_read")?,
+            bytes_written: json.member("bytes_written")?,
+            seconds: json.member("seconds")?,
+        })
+    }
+}
+
+impl ToJson for Report {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("metadata", self.metadata.to_json()),
+            ("tracks", self.tracks.to_json()),
+            ("outputs", self.outputs.to_json()),
+            ("stats", self.stats.to_json()),
+        ])
+    }
+}
+
+/// Reports written before they had stats are read with none
+impl FromJson for Report {
+    fn from_json(json: &Json) -> io::Result<Self> {
+        Ok(Report {
+            metadata: json.member("metadata")?,
+            tracks: json.member("tracks")?,
+            outputs: json.member("outputs")?,
+            stats: json.member::<Option<_>>("stats")?.unwrap_or_default(),
+        })
+    }
+}
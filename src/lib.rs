@@ -1,22 +1,146 @@
-use std::fmt::Display;
-use std::fs;
 use std::io;
-use std::io::{BufReader, Read, Seek, SeekFrom, Write};
-use std::io::{Error, ErrorKind};
+use std::io::{Read, Seek};
 use std::mem::swap;
-use std::ops::IndexMut;
-
-const WAV_RIFF_HEADER_LENGTH: u32 = 12;
-const WAV_FORMAT_HEADER_LENGTH: u32 = 24;
-const WAV_DATA_HEADER_LENGTH: u32 = 8;
-const WAV_HEADER_LENGTH: u32 =
-    WAV_RIFF_HEADER_LENGTH + WAV_FORMAT_HEADER_LENGTH + WAV_DATA_HEADER_LENGTH;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::log::{info, warning};
+use crate::output::OutputWriter;
+use crate::parallel::Reopen;
+
+mod audio;
+mod author;
+#[cfg(feature = "async")]
+mod background;
+mod capabilities;
+mod cdi;
+mod charset;
+#[cfg(feature = "chd")]
+mod chd;
+pub mod cue;
+mod dat;
+mod disc;
+mod discid;
+mod doctor;
+mod ecm;
+mod eltorito;
+#[cfg(feature = "encoder")]
+mod encoder;
+mod env;
+mod error;
+#[cfg(feature = "ffi")]
+mod ffi;
+pub mod hash;
+mod image;
+mod iso9660;
+#[cfg(feature = "json")]
+pub mod json;
+mod log;
+mod longpath;
+mod mds;
+mod memory;
+#[cfg(feature = "musicbrainz")]
+mod musicbrainz;
+pub mod naming;
+mod normalize;
+mod nrg;
+mod options;
+mod output;
+mod pairing;
+mod parallel;
+mod plan;
+mod playlist;
+mod progress;
+mod psx;
+mod reader;
+mod registry;
+mod replaygain;
+mod sector;
+mod source;
+mod stats;
+#[cfg(any(feature = "wav", feature = "ogg", feature = "mp3", feature = "flac"))]
+mod tags;
+#[cfg(any(test, feature = "chd", feature = "mp3", feature = "flac"))]
+mod temp;
+mod template;
+mod toc;
+mod track;
+mod vcd;
+mod verify;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "wav")]
+mod wav;
+
+pub use audio::Gain;
+pub use author::author;
+#[cfg(feature = "async")]
+pub use background::{convert_async, Conversion, NextProgress, ProgressStream};
+pub use capabilities::{capabilities, Capabilities};
+pub use cdi::CdiDisc;
+pub use charset::decode_sheet;
+#[cfg(feature = "chd")]
+pub use chd::ChdDisc;
+pub use cue::{parse_cue, parse_cue_str, CueDisc};
+pub use disc::{CdText, Disc, DiscMetadata, RemMetadata, Surplus};
+pub use discid::{disc_ids, DiscIds};
+pub use doctor::{diagnose, doctor, Diagnostic};
+pub use eltorito::{extract_boot_images, extract_disc_boot_images, BootImage, Emulation};
+pub use error::ErrorCode;
+pub use iso9660::{
+    extract_disc_files, extract_files, inspect, inspect_disc, list_disc_files, list_files, Volume,
+    VolumeFile,
+};
+pub use log::{set_logger, Level, Logger};
+pub use mds::MdsDisc;
+pub use memory::{convert_in_memory, convert_in_memory_with, TrackOutput};
+pub use normalize::{normalize, normalize_disc};
+pub use nrg::NrgDisc;
+#[allow(deprecated)]
+pub use options::{Args, ConvertOptionsBuilder};
+pub use output::{ExistingOutputs, FileSink, OutputFile, OutputSink};
+pub use plan::{plan, plan_disc, Plan, PlannedOutput, PlannedTrack};
+pub use playlist::convert_set;
+pub use progress::{Progress, ProgressCallback};
+pub use reader::TrackReader;
+pub use registry::{formats, open_disc, register_format, DiscFormat};
+pub use stats::{Stats, TrackStats};
+pub use track::{DataTracks, GapPolicy, Mode, PartialSector, Track, TrackFlags, TrackSelection};
 
 const SECTOR_SIZE: u64 = 2352;
 
-#[derive(Default)]
-pub struct Args {
+/// Default size of the buffers the image is read through and the outputs are written through
+pub const BUFFER_SIZE: usize = 1 << 20;
+
+/// Bitrates in kbps MPEG-1 Layer III allows for 44.1 kHz audio
+const MP3_BITRATES: [u32; 14] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+
+/// What a conversion wrote, along with the layout of the disc it was written from
+#[derive(Clone)]
+pub struct Report {
+    /// Every track of the disc, including those that weren't selected
+    pub tracks: Vec<Track>,
+    pub metadata: DiscMetadata,
+    /// Files written, in the order they were finished
+    pub outputs: Vec<OutputFile>,
+    /// Bytes read and written and how long it took
+    pub stats: Stats,
+}
+
+/// Settings of a conversion, set up with `ConvertOptions::builder`
+///
+/// New settings are added without notice, so the options can't be built as a struct
+/// literal outside of this crate, but can still be read and changed field by field.
+#[derive(Default, Clone)]
+#[non_exhaustive]
+pub struct ConvertOptions {
     pub output_name: String,
+    /// Directory the outputs are written to, the output name is relative to it
+    pub output_dir: String,
     pub bin_file: String,
     pub cue_file: String,
     pub verbose: bool,
@@ -24,10 +148,130 @@ pub struct Args {
     pub raw: bool,
     pub swap_audo_bytes: bool,
     pub to_wav: bool,
+    pub export_surplus: bool,
+    /// Byte offset applied to audio tracks without an `REM OFFSET` of their own
+    pub audio_offset: i64,
+    /// Number of the disc within a multi-disc set, detected from a "(Disc N)" tag when unset
+    pub disc: Option<u32>,
+    /// Write the TOC and per-track hashes as DiscImageCreator style `.dat` and `_disc.txt` files
+    pub export_toc: bool,
+    /// Write a Logiqx dat fragment with the sizes and hashes of the produced files
+    pub export_dat: bool,
+    /// Compute the CRC32, MD5 and SHA-1 of every output while writing it, listed with the
+    /// outputs in verbose mode
+    pub checksums: bool,
+    /// Keep an untouched copy of the raw 2352 byte sectors of every converted data track
+    pub keep_raw: bool,
+    /// Compress outputs of raw data sectors with ECM, to `.ecm` files `unecm` restores
+    pub ecm: bool,
+    /// Write a CUE sheet referencing the converted files
+    pub export_cue: bool,
+    /// Leave the files of a failed conversion in place instead of removing them, for debugging.
+    /// The file it failed in keeps its `.part` name.
+    pub keep_partial: bool,
+    /// Check the EDC, ECC, sync and address of raw data sectors as they're extracted, failing
+    /// the conversion with the LBAs of corrupt and misplaced ones
+    pub verify: bool,
+    /// Whether `PREGAP`/`POSTGAP` silence ends up in the audio outputs
+    pub gap_policy: GapPolicy,
+    /// Start audio tracks at `INDEX 00` instead of `INDEX 01`, keeping their stored pregap
+    pub include_index0: bool,
+    /// Write the subcode graphics of `CDG` tracks to a `.cdg` file next to their audio
+    pub export_cdg: bool,
+    /// Encode audio tracks as Ogg Vorbis of this quality, from -1 to 10, instead of PCM
+    pub ogg_quality: Option<f32>,
+    /// Encode audio tracks as constant bitrate MP3 of this many kbps instead of PCM
+    pub mp3_bitrate: Option<u32>,
+    /// Sample rate declared in WAV headers instead of the 44100 Hz of CD audio
+    pub wav_sample_rate: Option<u32>,
+    /// Channel count declared in WAV headers instead of stereo
+    pub wav_channels: Option<u16>,
+    /// Bits per sample declared in WAV headers instead of 16
+    pub wav_bits_per_sample: Option<u16>,
+    /// Whether data tracks get a file each or are written to a single `.iso`
+    pub data_tracks: DataTracks,
+    /// Names of track outputs instead of `{base}{track:02}.{ext}`, with the placeholders
+    /// `{base}`, `{track}`, `{index}`, `{title}`, `{performer}`, `{mode}` and `{ext}`
+    pub name_template: Option<String>,
+    /// Only extract these tracks instead of all of them
+    pub tracks: Option<TrackSelection>,
+    /// Told how many sectors of the track being written are done
+    pub progress: Option<ProgressCallback>,
+    /// Receives the outputs instead of files, e.g. to keep them in memory
+    pub output_sink: Option<Arc<dyn OutputSink>>,
+    /// Tracks written at the same time, each read by a thread of its own; 0 and 1 write one
+    /// track after another. Only `convert` can open the image once more for every thread.
+    pub threads: usize,
+    /// Bytes read from the image at a time instead of `BUFFER_SIZE`, larger ones help with
+    /// spinning disks and network shares
+    pub read_buffer: Option<usize>,
+    /// Bytes collected before writing to an output instead of `BUFFER_SIZE`
+    pub write_buffer: Option<usize>,
+    /// Stops the conversion once set, failing it with `ErrorCode::Cancelled` after removing
+    /// what was written so far
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Whether outputs that are already there fail the conversion, which they do by default,
+    /// are replaced or have their tracks skipped. Outputs sent to an `OutputSink` are
+    /// always replaced.
+    pub existing: ExistingOutputs,
+    /// Whether a file ending partway into a sector pads that sector, leaves it out or fails
+    /// the conversion
+    pub partial_sector: PartialSector,
+    /// Write the subchannel stored after every sector of 2448 byte images to a `.sub` file
+    /// next to the output of each track, 96 bytes per sector
+    pub export_subcode: bool,
+    /// Bytes of every sector of the BIN files, 2048, 2336 for MODE2 sectors without their sync
+    /// and header, 2352 or 2448 for raw sectors followed by their subchannel, instead of what
+    /// the CUE sheet says and the data turns out to hold
+    pub sector_size: Option<u64>,
+    /// Amplify or normalize the samples of audio tracks instead of copying them as they are
+    pub gain: Option<Gain>,
+    /// Leave the pre-emphasis of audio tracks flagged `PRE` in their WAV and encoded files,
+    /// which are otherwise run through a de-emphasis filter
+    pub keep_emphasis: bool,
+    /// Resample audio tracks written as WAV, Ogg Vorbis, MP3 or FLAC to this many Hz instead
+    /// of the 44100 Hz of CD audio
+    pub resample: Option<u32>,
+    /// Write audio tracks with `INDEX 02` onwards to a file per index, like `foo02-01.wav`
+    /// and `foo02-02.wav`, instead of one for the whole track
+    pub split_indexes: bool,
+    /// Encode audio tracks as FLAC at this compression level, 0 to 8, instead of PCM, with
+    /// their CUE sheet and CD-TEXT embedded
+    pub flac_compression: Option<u32>,
+    /// Work out the ReplayGain 2.0 track and album gain of audio tracks written as WAV, Ogg
+    /// Vorbis, MP3 or FLAC, tagging the encoded files with it and listing it for WAV files
+    /// in a `.replaygain.txt` next to them
+    pub replaygain: bool,
+    /// Look audio discs up on MusicBrainz by their disc ID, filling in the titles, performers
+    /// and date their image lacks for tags and name templates
+    pub musicbrainz: bool,
+    /// Directory the answers of MusicBrainz are cached in instead of `rbchunk/musicbrainz`
+    /// in the cache directory of the user
+    pub musicbrainz_cache: Option<String>,
+    /// Read raw tracks whose first sectors are in another mode than the CUE sheet says, like
+    /// `MODE2/2352` sectors of a track declared `MODE1/2352`, as the sectors are instead of
+    /// only warning about them
+    pub trust_sector_modes: bool,
+    /// Write the MPEG streams of Video CD and Super Video CD tracks in their Form 2 sectors
+    /// to `.mpg` files, instead of the raw sectors `raw` writes
+    pub demux_mpeg: bool,
+    /// Keep the SHA-1 of every output in `<name>.resume.sha1` as soon as it's finished, until
+    /// the conversion is, and with `ExistingOutputs::Skip` only leave the files that still
+    /// have the SHA-1 an interrupted conversion kept for them
+    pub verify_existing: bool,
+    /// Seek over blocks of zeroes instead of writing them, leaving sparse files that take up
+    /// less space on file systems that have them, like the empty sectors of data tracks
+    pub sparse: bool,
+    /// Compress disc images, the `.iso`, `.cdr` and `.bin` outputs, to `.gz` files at this
+    /// level, 1 to 9, by piping them through `gzip`
+    pub gzip_level: Option<u32>,
+    /// Compress disc images to `.zst` files at this level, 1 to 19, by piping them through
+    /// `zstd`
+    pub zstd_level: Option<u32>,
 }
 
-impl Args {
-    pub fn new(mut options: Args) -> Self {
+impl ConvertOptions {
+    pub fn new(mut options: ConvertOptions) -> Self {
         /*
          * If binfile is not supplied we can read it from CUE file
          * This could have been done in a better way, but for the sake of
@@ -35,433 +279,513 @@ impl Args {
          */
         if options.cue_file.is_empty() {
             swap(&mut options.cue_file, &mut options.bin_file);
-        }
 
-        if options.output_name.is_empty() {
-            options.output_name = String::from(
-                options
-                    // Get filename without extension
-                    .cue_file
-                    .split('/')
-                    .next_back()
-                    .unwrap()
-                    .split('.')
-                    .next()
-                    .unwrap(),
-            );
-        }
-
-        options
-    }
-}
-
-#[derive(Default)]
-pub struct Track {
-    start_sector: u64,
-    stop_sector: Option<u64>,
-    start: u64,
-    stop: Option<u64>,
-    mode: Mode,
-    extension: Extension,
-    number: u32,
-    audio: bool,
-    data_block_offset: u32,
-    data_block_size: u32,
-}
-
-impl Track {
-    fn get_track_mode(&mut self, a: &Args) {
-        match self.mode {
-            Mode::Unknown => {
-                self.data_block_offset = 0;
-                self.data_block_size = 2352;
-                self.extension = Extension::Ugh;
-            }
-            Mode::Audio => {
-                self.data_block_offset = 0;
-                self.data_block_size = 2352;
-                self.audio = true;
-                if a.to_wav {
-                    self.extension = Extension::Wav;
-                } else {
-                    self.extension = Extension::Cdr;
-                }
-            }
-            Mode::Mode1_2352 => {
-                self.data_block_offset = 16;
-                self.data_block_size = 2048;
-                self.extension = Extension::Iso;
-            }
-            Mode::Mode2_2352 => {
-                self.extension = Extension::Iso;
-                if a.raw {
-                    self.data_block_offset = 0;
-                    self.data_block_size = 2352;
-                } else if a.psx_truncate {
-                    self.data_block_offset = 0;
-                    self.data_block_size = 2336;
-                } else {
-                    self.data_block_offset = 24;
-                    self.data_block_size = 2048;
+            // A lone BIN file is paired with the CUE sheet next to it
+            if let Some(cue) = pairing::cue_for_bin(&options.cue_file) {
+                if options.verbose {
+                    warning!("CUE file not supplied. Using {}", cue);
                 }
-            }
-            Mode::Mode2_2336 => {
-                self.data_block_offset = 16;
-                self.data_block_size = 2336;
-                self.extension = Extension::Iso;
+                options.bin_file = std::mem::replace(&mut options.cue_file, cue);
             }
         }
-    }
-
-    fn wav_header(&self) -> Vec<u8> {
-        // Constructing wav header in vector so that we can write it in a single write
-        let reallen =
-            (self.stop_sector.unwrap() - self.start_sector + 1) * self.data_block_size as u64;
-
-        let wav_header = [
-            // RIFF header
-            "RIFF".as_bytes(),
-            (reallen as u32 + WAV_DATA_HEADER_LENGTH + WAV_FORMAT_HEADER_LENGTH + 4)
-                .to_le_bytes()
-                .as_slice(), // length of file starting from WAVE
-            "WAVE".as_bytes(),
-            // FORMAT HEADER
-            "fmt ".as_bytes(),
-            0x10_u32.to_le_bytes().as_slice(), // length of FORMAT header
-            0x1_u16.to_le_bytes().as_slice(),  // constant
-            0x2_u16.to_le_bytes().as_slice(),  //channels
-            44100_u32.to_le_bytes().as_slice(), // sample rate
-            (44100_u32 * 4).to_le_bytes().as_slice(), // bytes per second
-            0x4_u16.to_le_bytes().as_slice(),  // bytes per sample
-            0x10_u16.to_le_bytes().as_slice(), // bits per channel,
-            //DATA header
-            "data".as_bytes(),
-            (reallen as u32).to_le_bytes().as_slice(),
-        ]
-        .concat();
-        wav_header
-    }
-
-    fn write_to_file(&self, reader: &mut BufReader<&std::fs::File>, a: &Args) -> io::Result<()> {
-        let filename = format!(
-            "{}{:0>2}.{}",
-            a.output_name,
-            self.number,
-            self.extension.as_ref()
-        );
-        let sectors = self.stop_sector.unwrap() - self.start_sector + 1;
-        let mut file_length = sectors * self.data_block_size as u64;
-        let mut sector = [0u8; SECTOR_SIZE as usize];
-
-        let out_file = match fs::File::create(&filename) {
-            Ok(t_file) => t_file,
-            Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Could not write to track: {}", e),
-                ))
-            }
-        };
 
-        let mut writer: std::io::BufWriter<&std::fs::File> =
-            std::io::BufWriter::with_capacity(SECTOR_SIZE as usize * 16, &out_file);
-
-        if let Err(e) = reader.seek(SeekFrom::Start(self.start)) {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Could not seek to track location {}", e),
-            ));
+        let image_name = pairing::image_name(&options.cue_file);
+        if options.disc.is_none() {
+            options.disc = naming::disc_number(&image_name);
         }
 
-        if a.to_wav && self.audio {
-            file_length += WAV_HEADER_LENGTH as u64;
-            if let Err(e) = writer.write(&self.wav_header()) {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Could not write to track {}", e),
-                ));
+        // Discs of a set get the same "Name (Disc N)" layout however their images were tagged
+        if options.output_name.is_empty() {
+            options.output_name = match options.disc {
+                Some(disc) => naming::disc_name(&image_name, disc),
+                None => image_name,
             };
         }
+        if !options.output_dir.is_empty() {
+            options.output_name = Path::new(&options.output_dir)
+                .join(&options.output_name)
+                .to_string_lossy()
+                .into_owned();
+        }
 
-        for _ in 0..sectors {
-            if let Err(e) = reader.read(&mut sector) {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Could not read from {} {}", &a.bin_file, e),
-                ));
-            }
-            if self.audio && a.swap_audo_bytes {
-                for i in (0..SECTOR_SIZE as usize).step_by(2) {
-                    sector.swap(i, i + 1);
-                }
+        options
+    }
+
+    /// Fails once `cancel` has been set
+    pub(crate) fn check_cancelled(&self) -> io::Result<()> {
+        match &self.cancel {
+            Some(cancel) if cancel.load(Ordering::Relaxed) => {
+                Err(ErrorCode::Cancelled.error("The conversion was cancelled"))
             }
-            if let Err(e) = writer.write(
-                &sector[self.data_block_offset as usize
-                    ..(self.data_block_offset + self.data_block_size) as usize],
-            ) {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Could not write to track {}", e),
-                ));
-            };
+            _ => Ok(()),
         }
+    }
 
-        if a.verbose {
-            println!(
-                "{}: {} {}MiB",
-                self.number,
-                filename,
-                file_length / 1024 / 1024
-            );
-        }
+    pub(crate) fn read_buffer_size(&self) -> usize {
+        self.read_buffer.unwrap_or(BUFFER_SIZE)
+    }
 
-        Ok(())
+    pub(crate) fn write_buffer_size(&self) -> usize {
+        self.write_buffer.unwrap_or(BUFFER_SIZE)
     }
 }
 
-#[derive(Default)]
-pub enum Mode {
-    #[default]
-    Unknown,
-    Audio,
-    Mode1_2352,
-    Mode2_2352,
-    Mode2_2336,
+pub fn convert(options: impl Into<ConvertOptions>) -> io::Result<()> {
+    convert_with_report(options).map(|_| ())
 }
 
-impl Mode {
-    const UNKNOWN: &'static str = "UNKNOWN";
-    const AUDIO: &'static str = "AUDIO";
-    const MODE1_2352: &'static str = "MODE1/2352";
-    const MODE2_2352: &'static str = "MODE2/2352";
-    const MODE2_2336: &'static str = "MODE2/2336";
+/// Converts like `convert`, returning the layout of the disc and every file written
+pub fn convert_with_report(options: impl Into<ConvertOptions>) -> io::Result<Report> {
+    let mut args = ConvertOptions::new(options.into());
+    validate(&args)?;
+
+    let mut disc = open_disc(&mut args)?;
+    let reopen = reopen(&args);
+    convert_opened(disc.as_mut(), &args, Some(&reopen))
 }
 
-impl AsRef<str> for Mode {
-    fn as_ref(&self) -> &'static str {
-        match self {
-            Mode::Unknown => Mode::UNKNOWN,
-            Mode::Audio => Mode::AUDIO,
-            Mode::Mode1_2352 => Mode::MODE1_2352,
-            Mode::Mode2_2352 => Mode::MODE2_2352,
-            Mode::Mode2_2336 => Mode::MODE2_2352,
-        }
+/// Opens the image of `args` again, for threads writing tracks from a disc of their own
+fn reopen(args: &ConvertOptions) -> impl Fn() -> io::Result<Box<dyn Disc>> + Sync + '_ {
+    move || {
+        let mut args = args.clone();
+        // The layout was already reported when the image was opened first
+        args.verbose = false;
+        open_disc(&mut args)
     }
 }
 
-impl Display for Mode {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        f.pad(self.as_ref())
+/// Converts the BIN data read from `bin`, laid out by the CUE sheet `cue_sheet`
+///
+/// Nothing is read from the file system, so the outputs are named after
+/// `ConvertOptions::output_name`, or `ConvertOptions::cue_file` if only that is set. With
+/// `ConvertOptions::output_sink` nothing is written to the file system either. CUE sheets
+/// naming more than one FILE need each of them and can only be read from files.
+pub fn convert_reader<R: Read + Seek + 'static>(
+    options: impl Into<ConvertOptions>,
+    cue_sheet: &str,
+    bin: R,
+) -> io::Result<()> {
+    let options = options.into();
+    if options.output_name.is_empty() && options.cue_file.is_empty() {
+        return Err(ErrorCode::InvalidArguments
+            .error("An output name is needed to convert an image read from a reader"));
     }
+    let mut args = ConvertOptions::new(options);
+    validate(&args)?;
+
+    let disc = CueDisc::from_reader(cue_sheet, bin, &mut args)?;
+    let disc = psx::identify(Box::new(disc), &args)?;
+    let mut disc = vcd::identify(disc, &args)?;
+    convert_disc(disc.as_mut(), &args)
 }
 
-impl From<&str> for Mode {
-    fn from(s: &str) -> Self {
-        match s {
-            Mode::AUDIO => Mode::Audio,
-            Mode::MODE1_2352 => Mode::Mode1_2352,
-            Mode::MODE2_2336 => Mode::Mode2_2336,
-            Mode::MODE2_2352 => Mode::Mode2_2352,
-            _ => Mode::Unknown,
+/// Checks the options up front, so a conversion doesn't fail after writing half of the tracks
+fn validate(args: &ConvertOptions) -> io::Result<()> {
+    #[cfg(not(feature = "wav"))]
+    if args.to_wav {
+        return Err(ErrorCode::Unsupported.error("WAV output is not supported in this build"));
+    }
+    #[cfg(feature = "wav")]
+    if args.to_wav {
+        wav::Params::from_args(args).validate()?;
+    }
+    #[cfg(not(feature = "ogg"))]
+    if args.ogg_quality.is_some() {
+        return Err(ErrorCode::Unsupported.error("Ogg output is not supported in this build"));
+    }
+    #[cfg(not(feature = "mp3"))]
+    if args.mp3_bitrate.is_some() {
+        return Err(ErrorCode::Unsupported.error("MP3 output is not supported in this build"));
+    }
+    #[cfg(not(feature = "flac"))]
+    if args.flac_compression.is_some() {
+        return Err(ErrorCode::Unsupported.error("FLAC output is not supported in this build"));
+    }
+    #[cfg(not(feature = "gzip"))]
+    if args.gzip_level.is_some() {
+        return Err(ErrorCode::Unsupported.error("gzip output is not supported in this build"));
+    }
+    #[cfg(not(feature = "zstd"))]
+    if args.zstd_level.is_some() {
+        return Err(ErrorCode::Unsupported.error("zstd output is not supported in this build"));
+    }
+    #[cfg(not(feature = "musicbrainz"))]
+    if args.musicbrainz {
+        return Err(
+            ErrorCode::Unsupported.error("MusicBrainz lookups are not supported in this build")
+        );
+    }
+    if let Some(quality) = args.ogg_quality {
+        if !(-1.0..=10.0).contains(&quality) {
+            return Err(ErrorCode::InvalidArguments.error(format!(
+                "Ogg Vorbis quality {} is out of range, expected -1 to 10",
+                quality
+            )));
+        }
+    }
+    if let Some(bitrate) = args.mp3_bitrate {
+        if !MP3_BITRATES.contains(&bitrate) {
+            return Err(ErrorCode::InvalidArguments.error(format!(
+                "MP3 bitrate {} is not one of {:?}",
+                bitrate, MP3_BITRATES
+            )));
+        }
+    }
+    if let Some(level) = args.flac_compression {
+        if level > 8 {
+            return Err(ErrorCode::InvalidArguments.error(format!(
+                "FLAC compression level {} is out of range, expected 0 to 8",
+                level
+            )));
+        }
+    }
+    if let Some(level) = args.gzip_level {
+        if !(1..=9).contains(&level) {
+            return Err(ErrorCode::InvalidArguments.error(format!(
+                "gzip compression level {} is out of range, expected 1 to 9",
+                level
+            )));
+        }
+    }
+    if let Some(level) = args.zstd_level {
+        if !(1..=19).contains(&level) {
+            return Err(ErrorCode::InvalidArguments.error(format!(
+                "zstd compression level {} is out of range, expected 1 to 19",
+                level
+            )));
+        }
+    }
+    let compressed = output::compressed_suffix(args).is_some();
+    if args.gzip_level.is_some() && args.zstd_level.is_some() {
+        return Err(ErrorCode::InvalidArguments
+            .error("Disc images are compressed with gzip or zstd, not both"));
+    }
+    if compressed && args.ecm {
+        return Err(ErrorCode::InvalidArguments
+            .error("ECM outputs aren't compressed any further with gzip or zstd"));
+    }
+    let formats = [
+        args.to_wav,
+        args.ogg_quality.is_some(),
+        args.mp3_bitrate.is_some(),
+        args.flac_compression.is_some(),
+    ];
+    if formats.iter().filter(|&&f| f).count() > 1 {
+        return Err(ErrorCode::InvalidArguments
+            .error("Only one of WAV, Ogg Vorbis, MP3 and FLAC output can be chosen"));
+    }
+    let encoded = formats[1..].contains(&true);
+    if args.replaygain && !formats.contains(&true) {
+        return Err(ErrorCode::InvalidArguments.error(
+            "ReplayGain is only worked out for audio written as WAV, Ogg Vorbis, MP3 or FLAC",
+        ));
+    }
+    if (encoded || compressed) && args.output_sink.is_some() {
+        return Err(ErrorCode::Unsupported
+            .error("Encoders write their files themselves, they can't write to an output sink"));
+    }
+    if let Some(template) = &args.name_template {
+        template::validate(template)?;
+        if args.split_indexes && !template.contains("{index") {
+            return Err(ErrorCode::InvalidArguments.error(
+                "A name template needs {index} for tracks split at their indexes to get names of their own",
+            ));
         }
     }
+    if args.data_tracks != DataTracks::Separate && args.export_cue {
+        return Err(ErrorCode::InvalidArguments
+            .error("A CUE sheet can only be written when data tracks are kept separate"));
+    }
+    if let Some(gain) = &args.gain {
+        gain.validate()?;
+    }
+    if let Some(rate) = args.resample {
+        let relabeled = args.wav_sample_rate.is_some()
+            || args.wav_channels.is_some()
+            || args.wav_bits_per_sample.is_some();
+        if relabeled {
+            return Err(ErrorCode::InvalidArguments.error(
+                "Resampled audio is 16 bit stereo, its WAV parameters can't be declared otherwise",
+            ));
+        }
+        if !(8000..=384000).contains(&rate) {
+            return Err(ErrorCode::InvalidArguments.error(format!(
+                "Audio can't be resampled to {} Hz, expected 8000 to 384000",
+                rate
+            )));
+        }
+    }
+    if let Some(size) = args.sector_size {
+        if ![2048, 2336, SECTOR_SIZE, 2448].contains(&size) {
+            return Err(ErrorCode::InvalidArguments.error(format!(
+                "Sectors of {} bytes aren't supported, expected 2048, 2336, 2352 or 2448",
+                size
+            )));
+        }
+    }
+    if args.demux_mpeg && !args.raw {
+        return Err(ErrorCode::InvalidArguments
+            .error("MPEG streams are only demuxed from MODE2 tracks converted raw"));
+    }
+    if args.read_buffer == Some(0) || args.write_buffer == Some(0) {
+        return Err(ErrorCode::InvalidArguments.error("Buffer sizes must be at least one byte"));
+    }
+    Ok(())
 }
 
-#[derive(Default)]
-enum Extension {
-    #[default]
-    Ugh,
-    Iso,
-    Cdr,
-    Wav,
+/// Extracts every track of an already opened disc
+///
+/// If the conversion fails the files it already wrote are removed again,
+/// unless `ConvertOptions::keep_partial` is set.
+pub fn convert_disc(disc: &mut dyn Disc, args: &ConvertOptions) -> io::Result<()> {
+    convert_disc_with_report(disc, args).map(|_| ())
 }
 
-impl Extension {
-    const UGH: &'static str = "ugh";
-    const ISO: &'static str = "iso";
-    const CDR: &'static str = "cdr";
-    const WAV: &'static str = "wav";
+/// Extracts every track of an already opened disc like `convert_disc`, returning its layout
+/// and every file written
+pub fn convert_disc_with_report(disc: &mut dyn Disc, args: &ConvertOptions) -> io::Result<Report> {
+    convert_opened(disc, args, None)
 }
 
-impl AsRef<str> for Extension {
-    fn as_ref(&self) -> &str {
-        match self {
-            Extension::Ugh => Extension::UGH,
-            Extension::Iso => Extension::ISO,
-            Extension::Cdr => Extension::CDR,
-            Extension::Wav => Extension::WAV,
+fn convert_opened(
+    disc: &mut dyn Disc,
+    args: &ConvertOptions,
+    reopen: Option<Reopen>,
+) -> io::Result<Report> {
+    let start = Instant::now();
+    let mut written = Vec::new();
+    let tracks = match extract(disc, args, &mut written, reopen) {
+        Ok(tracks) => tracks,
+        Err(e) => {
+            output::roll_back(&written, args);
+            return Err(e);
         }
-    }
+    };
+    output::remove_checksums(args);
+    let stats = Stats {
+        bytes_read: tracks.iter().map(|t| t.bytes_read).sum(),
+        bytes_written: written.iter().map(|output| output.size).sum(),
+        seconds: start.elapsed().as_secs_f64(),
+        tracks,
+    };
+    Ok(Report {
+        tracks: disc.tracks().to_vec(),
+        metadata: disc.metadata().clone(),
+        outputs: written,
+        stats,
+    })
 }
 
-fn read_cue(args: &mut Args) -> io::Result<Vec<Track>> {
-    let mut tracks: Vec<Track> = Vec::with_capacity(32);
+/// Tracks of the disc to extract, with their outputs and gaps worked out
+fn prepare_tracks(disc: &dyn Disc, args: &ConvertOptions) -> io::Result<Vec<Track>> {
+    let mut tracks = disc.tracks().to_vec();
+    for t in tracks.iter_mut() {
+        t.check_layout()?;
+        t.get_track_mode(args);
+    }
+    track::apply_gap_policy(&mut tracks, args.gap_policy);
+    if args.split_indexes {
+        tracks = track::split_at_indexes(tracks);
+    }
+
+    // Gaps are distributed first, so they end up where they would with every track converted
+    if let Some(selection) = &args.tracks {
+        tracks.retain(|t| selection.contains(t.number));
+        if tracks.is_empty() {
+            return Err(ErrorCode::InvalidArguments
+                .error(format!("None of the tracks {} are on the disc", selection)));
+        }
+    }
+    Ok(tracks)
+}
 
-    let cue = match std::fs::read_to_string(&args.cue_file) {
-        Ok(f) => f,
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Could not open CUE file: {}", e),
-            ))
+fn extract(
+    disc: &mut dyn Disc,
+    args: &ConvertOptions,
+    written: &mut Vec<OutputFile>,
+    reopen: Option<Reopen>,
+) -> io::Result<Vec<TrackStats>> {
+    if let Some(surplus) = disc.surplus() {
+        if args.verbose {
+            info!(
+                "{} bytes of data found beyond the end of the disc layout",
+                surplus.length
+            );
         }
-    };
+    }
+    // Layouts that don't add up are told of before anything is written for them
+    for problem in doctor::check_consistency(disc)? {
+        warning!("{}", problem.summary());
+    }
 
-    for s in cue.lines() {
-        for e in s.split_whitespace() {
-            match e {
-                "TRACK" => {
-                    tracks.push(Default::default());
-                    if args.verbose {
-                        println!();
-                    }
-                    let mut t = s.split_whitespace().skip(1);
-                    match t.next() {
-                        Some(num_s) => match num_s.parse() {
-                            Ok(num) => {
-                                tracks.last_mut().unwrap().number = num;
-                                if args.verbose {
-                                    print!("Track {:>2}: ", num);
-                                }
-                            }
-                            Err(e) => {
-                                return Err(Error::new(
-                                    ErrorKind::Other,
-                                    format!("Error parsing track number! {}", e),
-                                ))
-                            }
-                        },
-                        None => return Err(Error::new(ErrorKind::Other, "Unknown error")),
-                    }
-                    match t.next() {
-                        Some(mode) => {
-                            tracks.last_mut().unwrap().mode = mode.into();
-                            tracks.last_mut().unwrap().get_track_mode(args);
-                            if args.verbose {
-                                print!("{:12}", tracks.last().unwrap().mode);
-                            }
-                        }
-                        None => return Err(Error::new(ErrorKind::Other, "Unknown error")),
-                    }
-                    break;
-                }
-                "INDEX" => {
-                    let mut i = s.split_whitespace().skip(1);
-                    match i.next() {
-                        Some(index_s) => {
-                            if args.verbose {
-                                print!("{} ", index_s);
-                            }
-                        }
-                        None => return Err(Error::new(ErrorKind::Other, "Missing index number")),
-                    }
-                    match i.next() {
-                        Some(time) => {
-                            if args.verbose {
-                                print!("{} ", time);
-                            }
-                            tracks.last_mut().unwrap().start_sector = time_to_frames(time).unwrap();
-                            tracks.last_mut().unwrap().start =
-                                tracks.last_mut().unwrap().start_sector * SECTOR_SIZE;
-                            if tracks.len() > 1 && tracks[tracks.len() - 2].stop_sector.is_none() {
-                                tracks.index_mut(tracks.len() - 2).stop_sector =
-                                    Some(tracks.last().unwrap().start_sector - 1);
-                                tracks.index_mut(tracks.len() - 2).stop =
-                                    Some(tracks.last().unwrap().start - 1);
-                            }
-                        }
-                        None => return Err(Error::new(ErrorKind::Other, "Missing INDEX time")),
-                    }
-                    break;
-                }
-                "FILE" => {
-                    let mut f = s.split_whitespace().skip(1);
-                    match f.next() {
-                        Some(fname) => {
-                            let mut filename = fname.chars();
-                            filename.next();
-                            filename.next_back();
-                            if args.bin_file.is_empty() {
-                                args.bin_file = String::from(filename.as_str());
-                                if args.verbose {
-                                    eprintln!(
-                                        "BIN file not supplied. Reading BIN file from CUE file"
-                                    );
-                                }
-                            } else if filename.as_str() != args.bin_file.split('/').last().unwrap()
-                                && args.verbose
-                            {
-                                eprintln!("Filename in CUE file doesn't match filename provided")
-                            }
-                        }
-                        None => return Err(Error::new(ErrorKind::Other, "Error reading FILE row")),
+    let plan = plan_disc(disc, args)?;
+    if let (Some(ids), true) = (&plan.disc_ids, args.verbose) {
+        info!(
+            "Disc IDs: FreeDB {}, MusicBrainz {}",
+            ids.freedb, ids.musicbrainz
+        );
+    }
+    if args.verbose {
+        // Only there for the log, an unreadable filesystem mustn't stop the conversion
+        match inspect_disc(disc) {
+            Ok(volumes) => {
+                for volume in volumes {
+                    info!("{}", volume);
+                    for image in &volume.boot_images {
+                        info!("{}", image);
                     }
-                    break;
                 }
-                _ => continue,
             }
+            Err(e) => warning!("Could not read the filesystems of the data tracks: {}", e),
         }
     }
-    if tracks.is_empty() {
-        return Err(Error::new(ErrorKind::Other, "No valid CUE data found"));
+    let planned = plan.tracks.iter().flat_map(|t| &t.outputs);
+    output::check_existing(&planned.chain(&plan.outputs).collect::<Vec<_>>(), args)?;
+    // What an interrupted conversion finished, files from it are only kept if they still match
+    let checksums = output::read_checksums(args)?;
+    let mut tracks = prepare_tracks(disc, args)?;
+    if args.replaygain {
+        replaygain::analyze(disc, &mut tracks, args)?;
     }
-    // Get last track stopsector form the size of the file
-    let bin_file_size = match fs::metadata(&args.bin_file) {
-        Ok(metadata) => metadata.len(),
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Could not open BIN file\n{}", e),
-            ))
+
+    // Merged data tracks share a writer, which is only finished after the last of them
+    let mut merged = match args.data_tracks {
+        DataTracks::Separate => None,
+        DataTracks::Merge | DataTracks::First => {
+            let suffix = output::compressed_suffix(args).unwrap_or_default();
+            let filename = format!("{}.iso{}", args.output_name, suffix);
+            match OutputWriter::create_compressed(filename, args) {
+                Ok(writer) => Some(writer),
+                Err(e) => {
+                    return Err(
+                        ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track: {}", e))
+                    )
+                }
+            }
         }
     };
-    tracks.last_mut().unwrap().stop = Some(bin_file_size - 1);
-    tracks.last_mut().unwrap().stop_sector =
-        Some(tracks.last().unwrap().stop.unwrap() / SECTOR_SIZE);
 
-    Ok(tracks)
-}
+    // Tracks to write, with whether they go into the merged file, and those left as they are
+    let mut jobs = Vec::new();
+    let mut kept = Vec::new();
+    let mut data_tracks = 0;
+    for t in tracks {
+        let into_merged = !t.is_audio() && merged.is_some();
+        if into_merged {
+            data_tracks += 1;
+            if args.data_tracks == DataTracks::First && data_tracks > 1 {
+                continue;
+            }
+        }
+        let planned = plan
+            .tracks
+            .iter()
+            .find(|p| p.number == t.number && p.start_sector == t.start_sector);
+        match planned.and_then(|p| output::complete(&p.outputs, &checksums, args)) {
+            Some(outputs) if !into_merged => {
+                if args.verbose {
+                    info!("{}: {} is complete, skipped", t.number, outputs[0].filename);
+                }
+                kept.push((t, outputs));
+            }
+            _ => jobs.push((t, into_merged)),
+        }
+    }
 
-fn time_to_frames(s: &str) -> io::Result<u64> {
-    let mut duration = [0u64; 3]; // minutes,seconds,frames
+    // Outputs are taken in track order, whichever thread wrote them
+    let mut converted = Vec::new();
+    // What the dat lists, the kept files along with the written ones
+    let mut listed = Vec::new();
+    // WAV files with the gains their sidecar lists
+    let mut gains = Vec::new();
+    let mut failure = None;
+    let results = parallel::write_tracks(disc, &jobs, merged.as_mut(), args, reopen);
+    let mut tracks: Vec<_> = jobs
+        .into_iter()
+        .zip(results)
+        .map(|((t, _), result)| (t, result, false))
+        .chain(
+            kept.into_iter()
+                .map(|(t, outputs)| (t, Some((Ok(outputs), Duration::ZERO)), true)),
+        )
+        .collect();
+    tracks.sort_by_key(|(t, _, _)| (t.number, t.start_sector));
+    let mut stats = Vec::new();
+    for (t, result, kept) in tracks {
+        match result {
+            Some((Ok(output), time)) => {
+                if !kept {
+                    // Tracks going into the merged file have no outputs of their own
+                    let bytes_written = match output.is_empty() {
+                        true => t.output_size(args),
+                        false => output.iter().map(|output| output.size).sum(),
+                    };
+                    stats.push(TrackStats::new(
+                        t.number,
+                        t.read_size(args),
+                        bytes_written,
+                        time,
+                    ));
+                }
+                if let (Some(gain), true) = (t.replay_gain, args.to_wav) {
+                    gains.push((output[0].clone(), gain));
+                }
+                if args.export_cue {
+                    converted.push((t, output[0].clone()));
+                }
+                listed.extend(output.iter().cloned());
+                // Files that were there before aren't rolled back, nor reported as written
+                if !kept {
+                    written.extend(output)
+                }
+            }
+            Some((Err(err), _)) => failure = failure.or(Some(err)),
+            None => {}
+        }
+    }
+    if let Some(err) = failure {
+        return Err(err);
+    }
 
-    for (c, t) in s.split(':').zip(duration.iter_mut()) {
-        *t = match c.parse() {
-            Ok(t) => t,
+    if let Some(writer) = merged {
+        let output = match writer.finish() {
+            Ok(output) => output,
             Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("parse int error on time_to_frames {}", e),
-                ))
+                return Err(
+                    ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track {}", e))
+                )
             }
         };
+        if args.verbose {
+            info!("iso: {}", output.summary());
+        }
+        listed.push(output.clone());
+        written.push(output);
+    }
+
+    if let (Some(surplus), true) = (disc.surplus(), args.export_surplus) {
+        let surplus = *surplus;
+        let output = surplus.write_to_file(disc, args)?;
+        listed.push(output.clone());
+        written.push(output);
     }
-    Ok(75 * (duration[0] * 60 + duration[1]) + duration[2])
-}
 
-pub fn convert(options: Args) -> io::Result<()> {
-    let mut args = Args::new(options);
+    if !gains.is_empty() {
+        written.push(replaygain::write_sidecar(&gains, args)?);
+    }
 
-    let tracks = match read_cue(&mut args) {
-        Ok(i_tracks) => i_tracks,
-        Err(e) => return Err(e),
-    };
+    if args.export_dat {
+        let fragment = dat::write_fragment(&listed, args)?;
+        written.push(fragment);
+    }
 
-    // Opening file in convert so that reader has a liftime of the convert function
-    // This way we save around 700Kb of memory allocations
-    let in_file = match fs::File::open(&args.bin_file) {
-        Ok(i_file) => i_file,
-        Err(e) => return Err(e),
-    };
-    let mut reader: std::io::BufReader<&std::fs::File> =
-        std::io::BufReader::with_capacity(SECTOR_SIZE as usize * 16, &in_file);
+    if args.export_toc {
+        written.extend(toc::export(disc, args)?);
+    }
 
-    for t in &tracks {
-        match t.write_to_file(&mut reader, &args) {
-            Ok(()) => {}
-            Err(err) => return Err(err),
-        }
+    if args.export_cue {
+        written.push(cue::write_converted(disc.metadata(), &converted, args)?);
     }
 
-    Ok(())
+    Ok(stats)
 }
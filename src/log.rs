@@ -0,0 +1,77 @@
+//! Messages of the library, handed to the application's `Logger` instead of being printed
+
+use std::fmt;
+use std::sync::RwLock;
+
+/// How much a message matters, from the most important
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Level {
+    /// Something was off but the conversion carried on, like an unknown flag of a track
+    Warn,
+    /// What a verbose conversion is doing, like the tracks found and the files written
+    Info,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Warn => "warn",
+            Level::Info => "info",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// Receives the messages of every conversion, each a single line
+pub trait Logger: Send + Sync {
+    fn log(&self, level: Level, message: &str);
+}
+
+impl<F: Fn(Level, &str) + Send + Sync> Logger for F {
+    fn log(&self, level: Level, message: &str) {
+        self(level, message)
+    }
+}
+
+static LOGGER: RwLock<Option<Box<dyn Logger>>> = RwLock::new(None);
+
+/// Hands the messages of the library to `logger` from now on, without one they're dropped
+///
+/// Info messages are only logged by conversions with `ConvertOptions::verbose` set.
+pub fn set_logger(logger: impl Logger + 'static) {
+    let mut current = match LOGGER.write() {
+        Ok(current) => current,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    *current = Some(Box::new(logger));
+}
+
+pub(crate) fn log(level: Level, message: fmt::Arguments) {
+    let logger = match LOGGER.read() {
+        Ok(logger) => logger,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if let Some(logger) = logger.as_ref() {
+        logger.log(level, &message.to_string());
+    }
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+pub(crate) use {info, warning};
@@ -0,0 +1,82 @@
+//! Paths usable beyond the 260 character limit of the Windows API
+
+use std::path::{Path, PathBuf};
+
+/// `path` in a form the OS can open regardless of its length
+///
+/// On Windows this is the `\\?\` (or `\\?\UNC\` for network shares) form of the absolute
+/// path, which lifts the `MAX_PATH` limit. Elsewhere the path is returned unchanged.
+#[cfg(windows)]
+pub(crate) fn long_path(path: impl AsRef<Path>) -> PathBuf {
+    use std::ffi::{OsStr, OsString};
+    use std::path::{Component, Prefix};
+
+    let path = path.as_ref();
+    let absolute = match (path.is_absolute(), std::env::current_dir()) {
+        (true, _) => path.to_path_buf(),
+        // Drive relative paths like `C:foo` depend on per-drive state we can't see
+        (false, Ok(dir)) if !matches!(path.components().next(), Some(Component::Prefix(_))) => {
+            dir.join(path)
+        }
+        _ => return path.to_path_buf(),
+    };
+
+    /*
+     * Verbatim paths are passed to the filesystem as they are, so `/`, `.` and `..`
+     * have to be resolved here instead
+     */
+    let mut long = OsString::new();
+    let mut parts: Vec<&OsStr> = Vec::new();
+    for component in absolute.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                // Already verbatim, or a device rather than a file
+                Prefix::Verbatim(_)
+                | Prefix::VerbatimUNC(..)
+                | Prefix::VerbatimDisk(_)
+                | Prefix::DeviceNS(_) => return path.to_path_buf(),
+                Prefix::Disk(drive) => long = format!(r"\\?\{}:", drive as char).into(),
+                Prefix::UNC(server, share) => {
+                    long = r"\\?\UNC\".into();
+                    long.push(server);
+                    long.push(r"\");
+                    long.push(share);
+                }
+            },
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop();
+            }
+            Component::Normal(part) => parts.push(part),
+        }
+    }
+    if long.is_empty() {
+        return path.to_path_buf();
+    }
+
+    if parts.is_empty() {
+        long.push(r"\");
+    }
+    for part in parts {
+        long.push(r"\");
+        long.push(part);
+    }
+    PathBuf::from(long)
+}
+
+/// `path` in a form the OS can open regardless of its length
+///
+/// On Windows this is the `\\?\` (or `\\?\UNC\` for network shares) form of the absolute
+/// path, which lifts the `MAX_PATH` limit. Elsewhere the path is returned unchanged.
+#[cfg(not(windows))]
+pub(crate) fn long_path(path: impl AsRef<Path>) -> PathBuf {
+    path.as_ref().to_path_buf()
+}
+
+/// Last component of `path`, split on the separators of the platform
+pub(crate) fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
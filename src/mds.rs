@@ -0,0 +1,415 @@
+//! Alcohol 120% images, an MDS descriptor with the layout of the disc and the MDF it describes
+//!
+//! The descriptor is a binary format without official documentation, the layout read here
+//! is the one established by libmirage for version 1 descriptors.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cue::describe_track;
+use crate::disc::{Disc, DiscMetadata};
+use crate::error::ErrorCode;
+use crate::image::ImageFile;
+use crate::log::info;
+use crate::longpath::long_path;
+use crate::registry::DiscFormat;
+use crate::sector::SUBCODE_SIZE;
+use crate::track::{Mode, Track};
+use crate::ConvertOptions;
+
+const SIGNATURE: &[u8; 16] = b"MEDIA DESCRIPTOR";
+const HEADER_SIZE: usize = 88;
+const SESSION_SIZE: usize = 24;
+const TRACK_SIZE: usize = 80;
+// Subchannel byte of tracks storing 96 bytes of interleaved subcode after every sector
+const SUBCHANNEL_PW: u8 = 0x08;
+
+/// Disc backed by an MDS descriptor and the MDF file it describes
+pub struct MdsDisc {
+    tracks: Vec<Track>,
+    metadata: DiscMetadata,
+    mdf: ImageFile,
+}
+
+/// Track entry of the descriptor, before its neighbours are known
+struct Entry {
+    number: u32,
+    session: u32,
+    mode: u8,
+    subchannel: u8,
+    sector_size: u64,
+    start_sector: u64,
+    start_offset: u64,
+    /// Pregap and length in sectors from the extra block of the entry
+    pregap: u64,
+    length: Option<u64>,
+    file: Option<String>,
+}
+
+impl MdsDisc {
+    pub(crate) const FORMAT: &'static str = "MDF/MDS";
+
+    /// Parses the MDS descriptor named by `args.cue_file` and opens the MDF file it names,
+    /// filling in `args.bin_file` with it if it wasn't supplied
+    pub fn open(args: &mut ConvertOptions) -> io::Result<MdsDisc> {
+        let descriptor = match fs::read(long_path(&args.cue_file)) {
+            Ok(descriptor) => descriptor,
+            Err(e) => {
+                return Err(
+                    ErrorCode::ReadFailed.wrap(&e, format!("Could not open MDS file: {}", e))
+                )
+            }
+        };
+        let entries = read_entries(&descriptor)?;
+
+        if args.bin_file.is_empty() {
+            args.bin_file = mdf_file(&args.cue_file, entries[0].file.as_deref());
+        }
+        let in_file = match fs::File::open(long_path(&args.bin_file)) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(
+                    ErrorCode::ReadFailed.wrap(&e, format!("Could not open MDF file\n{}", e))
+                )
+            }
+        };
+        let file_size = in_file.metadata()?.len();
+
+        let mut tracks: Vec<Track> = Vec::with_capacity(entries.len());
+        let mut strides = Vec::with_capacity(entries.len());
+        for (i, e) in entries.iter().enumerate() {
+            // Tracks without a length in the descriptor end where the next one or the file does
+            let length = match (e.length, entries.get(i + 1)) {
+                (Some(length), _) => length,
+                (None, Some(next)) => next.start_sector.saturating_sub(e.start_sector),
+                (None, None) => file_size.saturating_sub(e.start_offset) / e.sector_size,
+            };
+            let mode = match track_mode(e) {
+                Some(mode) => mode,
+                None => {
+                    return Err(ErrorCode::Unsupported.error(format!(
+                        "Track {} has MDS mode {:#04x} with {} byte sectors, which isn't supported",
+                        e.number, e.mode, e.sector_size
+                    )))
+                }
+            };
+            if length == 0 {
+                return Err(
+                    ErrorCode::CueMalformed.error(format!("Track {} has no sectors", e.number))
+                );
+            }
+            let mut track = Track::new(e.number, mode, e.start_sector, e.start_sector + length - 1);
+            track.start = e.start_offset;
+            track.session = e.session;
+            if let Some(previous) = tracks.last() {
+                /*
+                 * The pregap may be stored in front of the track, which is only told apart
+                 * from one that isn't stored by whether the MDF file has room for it
+                 */
+                let end = match entries.get(i + 1) {
+                    Some(next) if next.start_offset > e.start_offset => next.start_offset,
+                    _ => file_size,
+                };
+                let stored = (e.pregap + length) * e.sector_size;
+                if e.pregap > 0 && e.start_offset + stored <= end {
+                    track.index0 = Some(e.start_sector - e.pregap.min(e.start_sector));
+                    track.start += e.pregap * e.sector_size;
+                }
+                // Whatever else lies between tracks isn't stored, like the gap between sessions
+                let first = track.index0.unwrap_or(e.start_sector);
+                if first <= previous.stop_sector() {
+                    return Err(ErrorCode::CueMalformed.error(format!(
+                        "Track {} starts before the end of track {}",
+                        e.number, previous.number
+                    )));
+                }
+                track.pregap = first - previous.stop_sector() - 1;
+            }
+            if args.verbose {
+                info!("{}", describe_track(&track));
+            }
+            tracks.push(track);
+            strides.push(e.sector_size);
+        }
+
+        let mdf = ImageFile::new(in_file, strides, args);
+        // The size only says something about the sectors when nothing else is stored with them
+        let image_size = mdf.is_plain(&tracks).then_some(file_size);
+        Ok(MdsDisc {
+            tracks,
+            metadata: DiscMetadata {
+                format: MdsDisc::FORMAT,
+                image_size,
+                ..Default::default()
+            },
+            mdf,
+        })
+    }
+}
+
+fn field<const N: usize>(descriptor: &[u8], offset: usize) -> io::Result<[u8; N]> {
+    match descriptor.get(offset..offset + N) {
+        Some(bytes) => Ok(bytes.try_into().unwrap()),
+        None => Err(ErrorCode::CueMalformed.error("MDS file ends in the middle of an entry")),
+    }
+}
+
+fn u16_at(descriptor: &[u8], offset: usize) -> io::Result<u16> {
+    Ok(u16::from_le_bytes(field(descriptor, offset)?))
+}
+
+fn u32_at(descriptor: &[u8], offset: usize) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(field(descriptor, offset)?))
+}
+
+fn u64_at(descriptor: &[u8], offset: usize) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(field(descriptor, offset)?))
+}
+
+/// Track entries of every session, in disc order
+fn read_entries(descriptor: &[u8]) -> io::Result<Vec<Entry>> {
+    if descriptor.len() < HEADER_SIZE || &descriptor[..SIGNATURE.len()] != SIGNATURE {
+        return Err(ErrorCode::CueMalformed.error("Not an MDS file"));
+    }
+    if descriptor[16] != 1 {
+        return Err(ErrorCode::Unsupported.error(format!(
+            "MDS version {}.{} isn't supported",
+            descriptor[16], descriptor[17]
+        )));
+    }
+
+    let sessions = u16_at(descriptor, 20)? as usize;
+    let sessions_offset = u32_at(descriptor, 80)? as usize;
+    let mut entries = Vec::new();
+    for s in 0..sessions {
+        let session = sessions_offset + s * SESSION_SIZE;
+        let blocks = field::<1>(descriptor, session + 10)?[0] as usize;
+        let blocks_offset = u32_at(descriptor, session + 20)? as usize;
+        for b in 0..blocks {
+            let block = blocks_offset + b * TRACK_SIZE;
+            let [mode, subchannel, _, _, point] = field(descriptor, block)?;
+            // Lead-in entries describing the session share the layout, with points above 99
+            if !(1..=99).contains(&point) {
+                continue;
+            }
+            let extra_offset = u32_at(descriptor, block + 12)? as usize;
+            let footer_offset = u32_at(descriptor, block + 52)? as usize;
+            entries.push(Entry {
+                number: point as u32,
+                session: s as u32 + 1,
+                mode,
+                subchannel,
+                sector_size: u16_at(descriptor, block + 16)? as u64,
+                start_sector: u32_at(descriptor, block + 36)? as u64,
+                start_offset: u64_at(descriptor, block + 40)?,
+                // The extra block holds the pregap and then the length of the track
+                pregap: match extra_offset {
+                    0 => 0,
+                    offset => u32_at(descriptor, offset)? as u64,
+                },
+                length: match extra_offset {
+                    0 => None,
+                    offset => Some(u32_at(descriptor, offset + 4)? as u64),
+                },
+                file: match footer_offset {
+                    0 => None,
+                    offset => Some(file_name(descriptor, offset)?),
+                },
+            });
+        }
+    }
+    if entries.is_empty() {
+        return Err(ErrorCode::CueMalformed.error("No tracks found in MDS file"));
+    }
+    entries.sort_by_key(|e| e.start_sector);
+    Ok(entries)
+}
+
+/// Name of the MDF file from a track footer, in UTF-16 or the local code page
+fn file_name(descriptor: &[u8], footer: usize) -> io::Result<String> {
+    let offset = u32_at(descriptor, footer)? as usize;
+    let wide = u32_at(descriptor, footer + 4)? != 0;
+    let bytes = descriptor.get(offset..).unwrap_or_default();
+    Ok(match wide {
+        true => {
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&u| u != 0)
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        false => {
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).into_owned()
+        }
+    })
+}
+
+/// Path of the MDF file, relative to the descriptor like the name stored in it
+fn mdf_file(mds: &str, name: Option<&str>) -> String {
+    let mds = Path::new(mds);
+    let path = match name {
+        // `*.mdf` stands for the name of the descriptor itself
+        Some(name) if !name.is_empty() && !name.starts_with('*') => {
+            mds.with_file_name(name.replace('\\', "/"))
+        }
+        _ => mds.with_extension("mdf"),
+    };
+    path.to_string_lossy().into_owned()
+}
+
+/// Mode of a track from the mode in the lowest bits of its entry and its sector size
+fn track_mode(e: &Entry) -> Option<Mode> {
+    let size = match e.subchannel {
+        SUBCHANNEL_PW => e.sector_size.checked_sub(SUBCODE_SIZE as u64)?,
+        _ => e.sector_size,
+    };
+    Some(match (e.mode & 0x07, size) {
+        (1, 2352) => Mode::Audio,
+        (2, 2352) => Mode::Mode1_2352,
+        (2, 2048) => Mode::Mode1_2048,
+        (3..=5, 2352) => Mode::Mode2_2352,
+        (3..=5, 2336) => Mode::Mode2_2336,
+        (5, 2324) => Mode::Mode2_2324,
+        _ => return None,
+    })
+}
+
+impl Disc for MdsDisc {
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn read_sector(&mut self, lba: u64, sector: &mut [u8]) -> io::Result<()> {
+        self.mdf.read_sector(&self.tracks, lba, sector)
+    }
+
+    fn read_subcode(&mut self, lba: u64, subcode: &mut [u8]) -> io::Result<()> {
+        self.mdf.read_subcode(&self.tracks, lba, subcode)
+    }
+
+    fn metadata(&self) -> &DiscMetadata {
+        &self.metadata
+    }
+}
+
+pub(crate) struct MdsFormat;
+
+impl DiscFormat for MdsFormat {
+    fn name(&self) -> &'static str {
+        MdsDisc::FORMAT
+    }
+
+    fn probe(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("mds"))
+    }
+
+    fn open(&self, args: &mut ConvertOptions) -> io::Result<Box<dyn Disc>> {
+        Ok(Box::new(MdsDisc::open(args)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(descriptor: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+        if descriptor.len() < offset + bytes.len() {
+            descriptor.resize(offset + bytes.len(), 0);
+        }
+        descriptor[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Version 1 descriptor of one session with a lead-in entry, then the `(mode, sector
+    /// size, start sector, start offset, pregap, length)` of each track, whose footers name
+    /// `file` in UTF-16
+    fn descriptor(tracks: &[(u8, u16, u32, u64, u32, u32)], file: &str) -> Vec<u8> {
+        let mut d = Vec::new();
+        put(&mut d, 0, SIGNATURE);
+        put(&mut d, 16, &[1, 5]);
+        put(&mut d, 20, &1u16.to_le_bytes());
+        put(&mut d, 80, &(HEADER_SIZE as u32).to_le_bytes());
+
+        let blocks = HEADER_SIZE + SESSION_SIZE;
+        put(&mut d, HEADER_SIZE + 10, &[tracks.len() as u8 + 1]);
+        put(&mut d, HEADER_SIZE + 20, &(blocks as u32).to_le_bytes());
+        put(&mut d, blocks, &[0, 0, 0, 0, 0xa0]);
+        let extras = blocks + (tracks.len() + 1) * TRACK_SIZE;
+        let footer = extras + tracks.len() * 8;
+        let name = footer + 16;
+        for (i, &(mode, size, start, offset, pregap, length)) in tracks.iter().enumerate() {
+            let block = blocks + (i + 1) * TRACK_SIZE;
+            put(&mut d, block, &[mode, 0, 0, 0, i as u8 + 1]);
+            put(&mut d, block + 12, &((extras + i * 8) as u32).to_le_bytes());
+            put(&mut d, block + 16, &size.to_le_bytes());
+            put(&mut d, block + 36, &start.to_le_bytes());
+            put(&mut d, block + 40, &offset.to_le_bytes());
+            put(&mut d, block + 52, &(footer as u32).to_le_bytes());
+            put(&mut d, extras + i * 8, &pregap.to_le_bytes());
+            put(&mut d, extras + i * 8 + 4, &length.to_le_bytes());
+        }
+        put(&mut d, footer, &(name as u32).to_le_bytes());
+        put(&mut d, footer + 4, &1u32.to_le_bytes());
+        let units: Vec<u8> = file
+            .encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        put(&mut d, name, &units);
+        d
+    }
+
+    #[test]
+    fn reads_the_track_entries() {
+        let tracks = [
+            (0xaa, 2048, 0, 0, 0, 1000),
+            (0xa9, 2352, 1150, 1000 * 2048, 150, 3000),
+        ];
+        let entries = read_entries(&descriptor(&tracks, "game.mdf")).unwrap();
+        assert_eq!(entries.len(), 2);
+        let (first, second) = (&entries[0], &entries[1]);
+        assert_eq!((first.number, first.session), (1, 1));
+        assert!(track_mode(first) == Some(Mode::Mode1_2048));
+        assert_eq!(first.length, Some(1000));
+        assert!(track_mode(second) == Some(Mode::Audio));
+        assert_eq!(
+            (second.start_sector, second.start_offset),
+            (1150, 1000 * 2048)
+        );
+        assert_eq!((second.pregap, second.length), (150, Some(3000)));
+        assert_eq!(second.file.as_deref(), Some("game.mdf"));
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        let mut d = descriptor(&[(0xa9, 2352, 0, 0, 0, 100)], "a.mdf");
+        assert!(read_entries(&d[..40]).is_err());
+        d[16] = 2;
+        let e = read_entries(&d).err().unwrap();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::Unsupported);
+        assert!(read_entries(b"MEDIA DESCRIPTOX").is_err());
+    }
+
+    #[test]
+    fn subchannel_is_left_out_of_the_mode() {
+        let mut entry = read_entries(&descriptor(&[(0xac, 2448, 0, 0, 0, 100)], "a.mdf"))
+            .unwrap()
+            .remove(0);
+        assert!(track_mode(&entry).is_none());
+        entry.subchannel = SUBCHANNEL_PW;
+        assert!(track_mode(&entry) == Some(Mode::Mode2_2352));
+    }
+
+    #[test]
+    fn mdf_is_found_next_to_the_descriptor() {
+        assert_eq!(mdf_file("dir/game.mds", Some("*.mdf")), "dir/game.mdf");
+        assert_eq!(mdf_file("dir/game.mds", None), "dir/game.mdf");
+        assert_eq!(
+            mdf_file("dir/game.mds", Some("sub\\image.mdf")),
+            "dir/sub/image.mdf"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Conversions keeping their outputs in memory instead of writing files
+
+use std::io;
+use std::io::{Cursor, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::output::OutputSink;
+use crate::{convert_reader, ConvertOptions};
+
+/// File a conversion in memory produced, a track or one describing the disc like its CUE sheet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackOutput {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+type Buffer = Arc<Mutex<Vec<u8>>>;
+
+/// Outputs in the order they were created, each filled by the writer handed out for it
+#[derive(Default)]
+struct MemorySink {
+    outputs: Mutex<Vec<(String, Buffer)>>,
+}
+
+struct BufferWriter(Buffer);
+
+impl Write for BufferWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl OutputSink for MemorySink {
+    fn create(&self, filename: &str) -> io::Result<Box<dyn Write>> {
+        let buffer = Buffer::default();
+        let mut outputs = self.outputs.lock().unwrap();
+        outputs.retain(|(name, _)| name != filename);
+        outputs.push((String::from(filename), buffer.clone()));
+        Ok(Box::new(BufferWriter(buffer)))
+    }
+
+    fn remove(&self, filename: &str) -> io::Result<()> {
+        self.outputs
+            .lock()
+            .unwrap()
+            .retain(|(name, _)| name != filename);
+        Ok(())
+    }
+}
+
+/// Converts the BIN data `bin` laid out by the CUE sheet `cue`, returning the outputs instead
+/// of writing them
+pub fn convert_in_memory(cue: &str, bin: &[u8]) -> io::Result<Vec<TrackOutput>> {
+    convert_in_memory_with(ConvertOptions::default(), cue, bin)
+}
+
+/// Like `convert_in_memory`, with the options of the conversion
+///
+/// Outputs are named like the files they would be written to, after
+/// `ConvertOptions::output_name` or `track` if it isn't set. Audio can't be encoded to Ogg
+/// Vorbis or MP3 this way, the encoders only write files.
+pub fn convert_in_memory_with(
+    options: impl Into<ConvertOptions>,
+    cue: &str,
+    bin: &[u8],
+) -> io::Result<Vec<TrackOutput>> {
+    let mut options = options.into();
+    if options.output_name.is_empty() && options.cue_file.is_empty() {
+        options.output_name = String::from("track");
+    }
+    let sink = Arc::new(MemorySink::default());
+    options.output_sink = Some(sink.clone());
+    // Reading the sectors needs a reader that may outlive the borrow
+    convert_reader(options, cue, Cursor::new(bin.to_vec()))?;
+
+    let outputs = std::mem::take(&mut *sink.outputs.lock().unwrap());
+    Ok(outputs
+        .into_iter()
+        .map(|(filename, buffer)| TrackOutput {
+            filename,
+            data: std::mem::take(&mut *buffer.lock().unwrap()),
+        })
+        .collect())
+}
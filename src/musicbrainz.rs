@@ -0,0 +1,241 @@
+//! Titles of audio discs looked up on MusicBrainz by their disc ID
+//!
+//! The web service is asked with `curl`, so nothing is linked in for it, and every answer is
+//! kept in a cache directory, so discs converted again don't need the network. Whatever can't
+//! be looked up leaves the disc with the metadata of its image.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use crate::disc::{Disc, Retagged};
+use crate::discid::disc_ids;
+use crate::error::ErrorCode;
+use crate::json::Json;
+use crate::log::{info, warning};
+use crate::ConvertOptions;
+
+const SERVICE: &str = "https://musicbrainz.org/ws/2/discid/";
+
+/// MusicBrainz turns away clients that don't say who they are
+const USER_AGENT: &str = concat!(
+    "rbchunk/",
+    env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/luxtorpeda-dev/rbchunk )"
+);
+
+/// Release of a disc, with the titles of the tracks on its medium
+#[derive(Clone)]
+struct Release {
+    title: Option<String>,
+    artist: Option<String>,
+    date: Option<String>,
+    /// Position on the medium, title and artist of every track
+    tracks: Vec<(u32, Option<String>, Option<String>)>,
+}
+
+/// Releases already looked up by this process, `None` for discs that couldn't be, so images
+/// opened again for more threads don't ask again
+static LOOKUPS: Mutex<Vec<(String, Option<Release>)>> = Mutex::new(Vec::new());
+
+/// `disc` with the titles, performers and date its image lacks filled in from MusicBrainz
+///
+/// What the image has is kept, so CD-TEXT always wins over the database.
+pub(crate) fn look_up(disc: Box<dyn Disc>, a: &ConvertOptions) -> Box<dyn Disc> {
+    let id = match disc_ids(disc.tracks()) {
+        Some(ids) => ids.musicbrainz,
+        None => return disc,
+    };
+    let mut lookups = match LOOKUPS.lock() {
+        Ok(lookups) => lookups,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let release = match lookups.iter().find(|(looked_up, _)| *looked_up == id) {
+        Some((_, release)) => release.clone(),
+        None => {
+            let release = match release(&id, a) {
+                Ok(release) => release,
+                Err(e) => {
+                    warning!(
+                        "Could not look disc {} up on MusicBrainz, going on without: {}",
+                        id,
+                        e
+                    );
+                    None
+                }
+            };
+            lookups.push((id, release.clone()));
+            release
+        }
+    };
+    let release = match release {
+        Some(release) => release,
+        None => return disc,
+    };
+    if a.verbose {
+        info!(
+            "MusicBrainz: {} - {}",
+            release.artist.as_deref().unwrap_or("?"),
+            release.title.as_deref().unwrap_or("?")
+        );
+    }
+
+    let mut metadata = disc.metadata().clone();
+    let fill = |field: &mut Option<String>, value: &Option<String>| {
+        if field.is_none() {
+            field.clone_from(value);
+        }
+    };
+    fill(&mut metadata.cd_text.title, &release.title);
+    fill(&mut metadata.cd_text.performer, &release.artist);
+    fill(&mut metadata.rem.date, &release.date);
+    let mut tracks = disc.tracks().to_vec();
+    for t in tracks.iter_mut() {
+        if let Some((_, title, artist)) = release.tracks.iter().find(|(n, _, _)| *n == t.number) {
+            fill(&mut t.cd_text.title, title);
+            fill(&mut t.cd_text.performer, artist);
+        }
+    }
+    Box::new(Retagged {
+        disc,
+        tracks,
+        metadata,
+    })
+}
+
+/// Directory answers are cached in, `rbchunk/musicbrainz` in the cache directory of the user
+/// unless `ConvertOptions::musicbrainz_cache` says otherwise
+fn cache_directory(a: &ConvertOptions) -> Option<PathBuf> {
+    if let Some(directory) = &a.musicbrainz_cache {
+        return Some(PathBuf::from(directory));
+    }
+    let base = env::var_os("XDG_CACHE_HOME")
+        .or_else(|| env::var_os("LOCALAPPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("rbchunk").join("musicbrainz"))
+}
+
+/// Release of the disc `id` from the cache or the web service, `None` if MusicBrainz
+/// doesn't know it
+fn release(id: &str, a: &ConvertOptions) -> io::Result<Option<Release>> {
+    let cached = cache_directory(a).map(|directory| directory.join(format!("{}.json", id)));
+    if let Some(text) = cached
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+    {
+        if a.verbose {
+            info!("MusicBrainz: disc {} found in the cache", id);
+        }
+        return parse(id, &text);
+    }
+
+    let output = match Command::new("curl")
+        .args(["--silent", "--show-error", "--max-time", "30"])
+        .args(["--user-agent", USER_AGENT])
+        // The status follows the answer, to tell unknown discs from failures
+        .args(["--write-out", "%{http_code}"])
+        .arg(format!(
+            "{}{}?inc=recordings+artist-credits&fmt=json",
+            SERVICE, id
+        ))
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(ErrorCode::Unsupported
+                .error("curl is needed to look discs up on MusicBrainz but could not be found"))
+        }
+        Err(e) => return Err(e),
+    };
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(ErrorCode::ReadFailed.error(format!("curl failed: {}", error.trim())));
+    }
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    let status = text.split_off(text.len().saturating_sub(3));
+    match status.as_str() {
+        "200" => {}
+        "404" => {
+            if a.verbose {
+                info!("MusicBrainz doesn't know disc {}", id);
+            }
+            return Ok(None);
+        }
+        _ => {
+            return Err(ErrorCode::ReadFailed
+                .error(format!("MusicBrainz answered with HTTP status {}", status)))
+        }
+    }
+    let release = parse(id, &text)?;
+    // Caching is only there to spare the network, the lookup worked either way
+    if let Some(path) = &cached {
+        let stored = path.parent().map_or(Ok(()), fs::create_dir_all);
+        if let Err(e) = stored.and_then(|()| fs::write(path, &text)) {
+            warning!(
+                "Could not cache the answer of MusicBrainz in {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+    Ok(release)
+}
+
+/// Release with the medium of disc `id` in the answer `text` of the web service
+fn parse(id: &str, text: &str) -> io::Result<Option<Release>> {
+    let json = Json::parse(text)?;
+    let string = |json: &Json, key: &str| match json.get(key) {
+        Some(Json::String(s)) if !s.is_empty() => Some(s.clone()),
+        _ => None,
+    };
+    let array = |json: &Json, key: &str| match json.get(key) {
+        Some(Json::Array(elements)) => elements.clone(),
+        _ => Vec::new(),
+    };
+    // Names of the artists and the phrases joining them, like "A feat. B"
+    let artist = |json: &Json| {
+        let credits = array(json, "artist-credit");
+        let names: String = credits
+            .iter()
+            .map(|credit| {
+                string(credit, "name").unwrap_or_default()
+                    + &string(credit, "joinphrase").unwrap_or_default()
+            })
+            .collect();
+        Some(names).filter(|names| !names.is_empty())
+    };
+
+    for release in array(&json, "releases") {
+        let media = array(&release, "media");
+        let medium = media.iter().find(|medium| {
+            array(medium, "discs")
+                .iter()
+                .any(|disc| string(disc, "id").as_deref() == Some(id))
+        });
+        let medium = match medium {
+            Some(medium) => medium,
+            None => continue,
+        };
+        let tracks = array(medium, "tracks")
+            .iter()
+            .filter_map(|track| match track.get("position") {
+                Some(Json::Number(position)) => {
+                    Some((*position as u32, string(track, "title"), artist(track)))
+                }
+                _ => None,
+            })
+            .collect();
+        return Ok(Some(Release {
+            title: string(&release, "title"),
+            artist: artist(&release),
+            date: string(&release, "date"),
+            tracks,
+        }));
+    }
+    Ok(None)
+}
@@ -0,0 +1,48 @@
+/// Byte range and number of a disc tag like "(Disc 2)", "[CD2]" or "(Disc 2 of 3)"
+fn find_disc_tag(name: &str) -> Option<(usize, usize, u32)> {
+    for (open, _) in name.match_indices(['(', '[']) {
+        let close = match name[open..].find([')', ']']) {
+            Some(close) => open + close,
+            None => continue,
+        };
+        let tag = name[open + 1..close].trim().to_ascii_lowercase();
+        let rest = match tag.strip_prefix("disc").or_else(|| tag.strip_prefix("cd")) {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let trailing = rest[digits..].trim();
+        if digits == 0 || !(trailing.is_empty() || trailing.starts_with("of ")) {
+            continue;
+        }
+        if let Ok(number) = rest[..digits].parse() {
+            return Some((open, close + 1, number));
+        }
+    }
+    None
+}
+
+/// Disc number from a "(Disc N)" style tag in an image name
+pub fn disc_number(name: &str) -> Option<u32> {
+    find_disc_tag(name).map(|(_, _, number)| number)
+}
+
+/// Name shared by every disc of a set, i.e. the image name without its disc tag
+pub fn set_name(name: &str) -> String {
+    match find_disc_tag(name) {
+        Some((start, end, _)) => {
+            let (head, tail) = (name[..start].trim_end(), name[end..].trim_start());
+            if head.is_empty() || tail.is_empty() {
+                format!("{}{}", head, tail)
+            } else {
+                format!("{} {}", head, tail)
+            }
+        }
+        None => String::from(name),
+    }
+}
+
+/// Image name for disc `number` of the set `name` belongs to
+pub fn disc_name(name: &str, number: u32) -> String {
+    format!("{} (Disc {})", set_name(name), number)
+}
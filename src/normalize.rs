@@ -0,0 +1,112 @@
+//! Rewriting images into a single BIN of raw sectors with a minimal CUE sheet
+
+use std::io;
+
+use crate::disc::Disc;
+use crate::error::ErrorCode;
+use crate::log::info;
+use crate::output::{check_not_input, roll_back, OutputFile, OutputWriter};
+use crate::sector::{add_edc_ecc, Layout};
+use crate::track::Mode;
+use crate::{cue, open_disc, validate, ConvertOptions, SECTOR_SIZE};
+
+/// Rewrites the image of `options` as `<name>.bin` and `<name>.cue`
+pub fn normalize(options: impl Into<ConvertOptions>) -> io::Result<()> {
+    let mut args = ConvertOptions::new(options.into());
+    validate(&args)?;
+
+    let mut disc = open_disc(&mut args)?;
+    normalize_disc(disc.as_mut(), &args)
+}
+
+/// Rewrites an already opened disc as a single BIN of raw 2352 byte sectors and its CUE sheet
+///
+/// Whatever the layout of the image, e.g. cooked `MODE1/2048` tracks, the result has every
+/// track stored raw one after another, cooked sectors with their EDC and ECC generated,
+/// with offsets applied and `PREGAP`/`POSTGAP` kept as commands. Data beyond the disc
+/// layout that `ConvertOptions::export_surplus` trims isn't copied. With
+/// `ConvertOptions::ecm` the BIN is written as `<name>.bin.ecm`, which the CUE sheet still
+/// names `<name>.bin`.
+pub fn normalize_disc(disc: &mut dyn Disc, args: &ConvertOptions) -> io::Result<()> {
+    let mut written = Vec::new();
+    let result = write_image(disc, args, &mut written);
+    if result.is_err() {
+        roll_back(&written, args);
+    }
+    result
+}
+
+fn write_image(
+    disc: &mut dyn Disc,
+    args: &ConvertOptions,
+    written: &mut Vec<OutputFile>,
+) -> io::Result<()> {
+    let filename = format!("{}.bin", args.output_name);
+    let output = match args.ecm {
+        true => format!("{}.ecm", filename),
+        false => filename.clone(),
+    };
+    check_not_input(&output, &args.bin_file)?;
+    let tracks = disc.tracks().to_vec();
+    if let Some(t) = tracks.iter().find(|t| t.mode() == Mode::Unknown) {
+        return Err(ErrorCode::Unsupported.error(format!(
+            "Track {} has an unknown mode that can't be stored raw",
+            t.number()
+        )));
+    }
+
+    let writer = match args.ecm {
+        true => OutputWriter::create_ecm(output, args),
+        false => OutputWriter::create(output, args),
+    };
+    let mut writer = match writer {
+        Ok(writer) => writer,
+        Err(e) => {
+            return Err(ErrorCode::WriteFailed.wrap(&e, format!("Could not write to bin: {}", e)))
+        }
+    };
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    for t in &tracks {
+        // Pregaps that aren't stored stay out of the BIN file too
+        let first = t.index0().unwrap_or(t.start_sector() - t.pregap());
+        let stored = first..t.start_sector() - t.pregap();
+        // Cooked sectors are read back with only their sync and header added
+        let cooked = match t.mode() {
+            Mode::Mode1_2048 => Some(Layout::Mode1),
+            Mode::Mode2_2324 => Some(Layout::Mode2Form2),
+            _ => None,
+        };
+        for lba in stored.chain(t.start_sector()..=t.stop_sector()) {
+            if let Err(e) = disc.read_sector(lba, &mut sector) {
+                return Err(ErrorCode::ReadFailed
+                    .wrap(&e, format!("Could not read from {} {}", &args.bin_file, e)));
+            }
+            if let Some(layout) = cooked {
+                add_edc_ecc(&mut sector, layout);
+            }
+            if let Err(e) = writer.write_all(&sector) {
+                return Err(
+                    ErrorCode::WriteFailed.wrap(&e, format!("Could not write to bin {}", e))
+                );
+            }
+        }
+    }
+    let output = match writer.finish() {
+        Ok(output) => output,
+        Err(e) => {
+            return Err(ErrorCode::WriteFailed.wrap(&e, format!("Could not write to bin {}", e)))
+        }
+    };
+    if args.verbose {
+        info!("bin: {}", output.summary());
+    }
+    written.push(output);
+
+    written.push(cue::write_normalized(
+        disc.metadata(),
+        &tracks,
+        &filename,
+        args,
+    )?);
+    Ok(())
+}
@@ -0,0 +1,453 @@
+//! Nero images, sectors followed by chunks describing the disc and a footer pointing to them
+//!
+//! Version 1 images end with `NERO` and a 32 bit offset of the first chunk, version 2 images
+//! with `NER5` and a 64 bit one, and their chunks widen offsets the same way. Discs burnt
+//! at once are described by `DAOI`/`DAOX` chunks, track at once ones by `ETNF`/`ETN2`
+//! chunks. `CUES`/`CUEX` chunks place the indexes of the tracks on the disc.
+
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::cue::describe_track;
+use crate::disc::{Disc, DiscMetadata};
+use crate::error::ErrorCode;
+use crate::image::ImageFile;
+use crate::log::info;
+use crate::longpath::long_path;
+use crate::registry::DiscFormat;
+use crate::sector::SUBCODE_SIZE;
+use crate::track::{Mode, Track};
+use crate::ConvertOptions;
+
+// Sectors of the lead-in before LBA 0, where the pregap of the first track starts
+const LEAD_IN: i64 = 150;
+
+/// Disc backed by a Nero image
+pub struct NrgDisc {
+    tracks: Vec<Track>,
+    metadata: DiscMetadata,
+    image: ImageFile,
+}
+
+/// Track as described by a DAO or TAO chunk, with byte positions in the image
+struct Entry {
+    number: u32,
+    mode_code: u8,
+    sector_size: u64,
+    /// Where the stored pregap begins, or `start` if there is none
+    pregap: u64,
+    start: u64,
+    end: u64,
+    /// Sector of `INDEX 01`, given by TAO chunks
+    lba: Option<i64>,
+}
+
+/// Nero's mode codes, with the mode of the sectors and whether subcode follows each of them
+fn track_mode(code: u8) -> Option<(Mode, bool)> {
+    Some(match code {
+        0x00 => (Mode::Mode1_2048, false),
+        0x03 => (Mode::Mode2_2336, false),
+        0x05 => (Mode::Mode1_2352, false),
+        0x06 => (Mode::Mode2_2352, false),
+        0x07 => (Mode::Audio, false),
+        0x0f => (Mode::Mode1_2352, true),
+        0x10 => (Mode::Audio, true),
+        0x11 => (Mode::Mode2_2352, true),
+        _ => return None,
+    })
+}
+
+impl NrgDisc {
+    pub(crate) const FORMAT: &'static str = "NRG";
+
+    /// Parses the chunks at the end of the Nero image named by `args.cue_file`
+    pub fn open(args: &mut ConvertOptions) -> io::Result<NrgDisc> {
+        let mut file = match fs::File::open(long_path(&args.cue_file)) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(
+                    ErrorCode::ReadFailed.wrap(&e, format!("Could not open NRG file: {}", e))
+                )
+            }
+        };
+        let file_size = file.metadata()?.len();
+        let chunks = read_chunks(&mut file, file_size)?;
+        // Sectors are stored in the same file that describes them
+        if args.bin_file.is_empty() {
+            args.bin_file = args.cue_file.clone();
+        }
+
+        let mut entries = Vec::new();
+        let mut indexes = Vec::new();
+        for (id, data) in &chunks {
+            match id {
+                b"DAOI" => read_dao(data, false, &mut entries)?,
+                b"DAOX" => read_dao(data, true, &mut entries)?,
+                b"ETNF" => read_tao(data, false, &mut entries)?,
+                b"ETN2" => read_tao(data, true, &mut entries)?,
+                b"CUES" => read_cue(data, false, &mut indexes)?,
+                b"CUEX" => read_cue(data, true, &mut indexes)?,
+                _ => {}
+            }
+        }
+        if entries.is_empty() {
+            return Err(ErrorCode::CueMalformed.error("No tracks found in NRG file"));
+        }
+
+        let mut tracks: Vec<Track> = Vec::with_capacity(entries.len());
+        let mut strides = Vec::with_capacity(entries.len());
+        for e in entries {
+            let mode = match track_mode(e.mode_code) {
+                Some((mode, subcode)) if e.sector_size == stride(mode, subcode) => mode,
+                _ => {
+                    return Err(ErrorCode::Unsupported.error(format!(
+                    "Track {} has Nero mode {:#04x} with {} byte sectors, which isn't supported",
+                    e.number, e.mode_code, e.sector_size
+                )))
+                }
+            };
+            let length = e.end.saturating_sub(e.start) / e.sector_size;
+            let pregap = e.start.saturating_sub(e.pregap) / e.sector_size;
+            if length == 0 || e.end > file_size {
+                return Err(ErrorCode::CueMalformed
+                    .error(format!("Track {} lies outside of the NRG file", e.number)));
+            }
+
+            // Indexes from the cue chunks, otherwise tracks follow each other with their pregaps
+            let index = |i: u8| {
+                indexes
+                    .iter()
+                    .find(|(track, index, _)| *track == e.number && *index == i)
+                    .map(|(_, _, lba)| *lba)
+            };
+            let next = tracks.last().map_or(0, |t| t.stop_sector() as i64 + 1);
+            let start = index(1).or(e.lba).unwrap_or(next + pregap as i64);
+            // Sectors before LBA 0 are in the lead-in, like the pregap of the first track
+            let start = match u64::try_from(start) {
+                Ok(start) if start as i64 >= next => start,
+                _ => {
+                    return Err(ErrorCode::CueMalformed.error(format!(
+                        "Track {} starts before the end of the previous track",
+                        e.number
+                    )))
+                }
+            };
+
+            let mut track = Track::new(e.number, mode, start, start + length - 1);
+            track.start = e.start;
+            let stored_pregap = pregap.min(start - next as u64);
+            if stored_pregap > 0 {
+                track.index0 = Some(start - stored_pregap);
+            }
+            // Whatever else lies between tracks isn't stored, like the gap between sessions
+            if !tracks.is_empty() {
+                track.pregap = start - stored_pregap - next as u64;
+            }
+            if args.verbose {
+                info!("{}", describe_track(&track));
+            }
+            tracks.push(track);
+            strides.push(e.sector_size);
+        }
+
+        let image = ImageFile::new(file, strides, args);
+        Ok(NrgDisc {
+            tracks,
+            metadata: DiscMetadata {
+                format: NrgDisc::FORMAT,
+                // Chunks are stored after the sectors, so the size says nothing about them
+                image_size: None,
+                ..Default::default()
+            },
+            image,
+        })
+    }
+}
+
+fn stride(mode: Mode, subcode: bool) -> u64 {
+    match subcode {
+        true => mode.sector_size() + SUBCODE_SIZE as u64,
+        false => mode.sector_size(),
+    }
+}
+
+fn malformed() -> io::Error {
+    ErrorCode::CueMalformed.error("NRG file ends in the middle of a chunk")
+}
+
+fn be<const N: usize>(data: &[u8], offset: usize) -> io::Result<[u8; N]> {
+    match data.get(offset..offset + N) {
+        Some(bytes) => Ok(bytes.try_into().unwrap()),
+        None => Err(malformed()),
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> io::Result<u32> {
+    Ok(u32::from_be_bytes(be(data, offset)?))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> io::Result<u64> {
+    Ok(u64::from_be_bytes(be(data, offset)?))
+}
+
+/// Offset, 32 bits wide in version 1 chunks and 64 bits in version 2 ones
+fn offset_at(data: &[u8], offset: usize, wide: bool) -> io::Result<u64> {
+    match wide {
+        true => u64_at(data, offset),
+        false => Ok(u32_at(data, offset)? as u64),
+    }
+}
+
+/// Chunks from the one the footer points to up to `END!`, as ids and data
+fn read_chunks(file: &mut fs::File, file_size: u64) -> io::Result<Vec<([u8; 4], Vec<u8>)>> {
+    let mut footer = [0u8; 12];
+    if file_size < footer.len() as u64 {
+        return Err(ErrorCode::CueMalformed.error("Not an NRG file"));
+    }
+    file.seek(SeekFrom::End(-(footer.len() as i64)))?;
+    file.read_exact(&mut footer)?;
+    let mut position = match (&footer[..4], &footer[4..8]) {
+        (b"NER5", _) => u64_at(&footer, 4)?,
+        (_, b"NERO") => u32_at(&footer, 8)? as u64,
+        _ => return Err(ErrorCode::CueMalformed.error("Not an NRG file")),
+    };
+
+    let mut chunks = Vec::new();
+    loop {
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(position))?;
+        if file.read_exact(&mut header).is_err() {
+            return Err(malformed());
+        }
+        let id: [u8; 4] = be(&header, 0)?;
+        if &id == b"END!" {
+            break;
+        }
+        let size = u32_at(&header, 4)? as u64;
+        if position + 8 + size > file_size {
+            return Err(malformed());
+        }
+        let mut data = vec![0u8; size as usize];
+        file.read_exact(&mut data)?;
+        chunks.push((id, data));
+        position += 8 + size;
+    }
+    Ok(chunks)
+}
+
+/// Tracks of a disc-at-once chunk, after a header with the catalog number and track range
+fn read_dao(data: &[u8], wide: bool, entries: &mut Vec<Entry>) -> io::Result<()> {
+    const HEADER: usize = 22;
+    let first_track = be::<1>(data, 20)?[0] as u32;
+    let size = if wide { 42 } else { 30 };
+    let count = data.len().saturating_sub(HEADER) / size;
+    for i in 0..count {
+        // ISRC, sector size, mode and padding, then the pregap, start and end offsets
+        let block = HEADER + i * size;
+        let offsets = block + 18;
+        let width = if wide { 8 } else { 4 };
+        entries.push(Entry {
+            number: first_track + i as u32,
+            mode_code: be::<1>(data, block + 14)?[0],
+            sector_size: u16::from_be_bytes(be(data, block + 12)?) as u64,
+            pregap: offset_at(data, offsets, wide)?,
+            start: offset_at(data, offsets + width, wide)?,
+            end: offset_at(data, offsets + 2 * width, wide)?,
+            lba: None,
+        });
+    }
+    Ok(())
+}
+
+/// Tracks of a track-at-once chunk, numbered on from those of earlier chunks
+fn read_tao(data: &[u8], wide: bool, entries: &mut Vec<Entry>) -> io::Result<()> {
+    let size = if wide { 32 } else { 20 };
+    let width = if wide { 8 } else { 4 };
+    for block in (0..data.len() / size).map(|i| i * size) {
+        // Offset and length in bytes, then the mode and the sector the track starts at
+        let start = offset_at(data, block, wide)?;
+        let length = offset_at(data, block + width, wide)?;
+        let mode_code = u32_at(data, block + 2 * width)? as u8;
+        let sector_size = match track_mode(mode_code) {
+            Some((mode, subcode)) => stride(mode, subcode),
+            None => 0,
+        };
+        entries.push(Entry {
+            number: entries.last().map_or(1, |e| e.number + 1),
+            mode_code,
+            sector_size,
+            pregap: start,
+            start,
+            end: start + length,
+            // Stored as unsigned, but counted from LBA 0 like the cue chunks
+            lba: Some(u32_at(data, block + 2 * width + 4)? as i64),
+        });
+    }
+    Ok(())
+}
+
+/// Track and index of every entry of a cue chunk with the LBA they start at
+fn read_cue(data: &[u8], wide: bool, indexes: &mut Vec<(u32, u8, i64)>) -> io::Result<()> {
+    let bcd = |b: u8| (b >> 4) as u32 * 10 + (b & 0x0f) as u32;
+    for entry in data.chunks_exact(8) {
+        // Lead-in and lead-out entries use track numbers 0 and 0xaa
+        let track = bcd(entry[1]);
+        if !(1..=99).contains(&track) {
+            continue;
+        }
+        let lba = match wide {
+            true => i32::from_be_bytes(be(entry, 4)?) as i64,
+            // Version 1 stores an absolute MSF address instead
+            false => (entry[5] as i64 * 60 + entry[6] as i64) * 75 + entry[7] as i64 - LEAD_IN,
+        };
+        indexes.push((track, bcd(entry[2]) as u8, lba));
+    }
+    Ok(())
+}
+
+impl Disc for NrgDisc {
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn read_sector(&mut self, lba: u64, sector: &mut [u8]) -> io::Result<()> {
+        self.image.read_sector(&self.tracks, lba, sector)
+    }
+
+    fn read_subcode(&mut self, lba: u64, subcode: &mut [u8]) -> io::Result<()> {
+        self.image.read_subcode(&self.tracks, lba, subcode)
+    }
+
+    fn metadata(&self) -> &DiscMetadata {
+        &self.metadata
+    }
+}
+
+pub(crate) struct NrgFormat;
+
+impl DiscFormat for NrgFormat {
+    fn name(&self) -> &'static str {
+        NrgDisc::FORMAT
+    }
+
+    fn probe(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("nrg"))
+    }
+
+    fn open(&self, args: &mut ConvertOptions) -> io::Result<Box<dyn Disc>> {
+        Ok(Box::new(NrgDisc::open(args)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TemporaryDirectory;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        [&id[..], &(data.len() as u32).to_be_bytes(), data].concat()
+    }
+
+    /// DAOX block of a track with `sector_size` byte sectors of Nero mode `code`
+    fn dao_track(sector_size: u16, code: u8, pregap: u64, start: u64, end: u64) -> Vec<u8> {
+        let mut block = vec![0u8; 18];
+        block[12..14].copy_from_slice(&sector_size.to_be_bytes());
+        block[14] = code;
+        for offset in [pregap, start, end] {
+            block.extend_from_slice(&offset.to_be_bytes());
+        }
+        block
+    }
+
+    #[test]
+    fn opens_a_disc_at_once_image() {
+        // 20 cooked data sectors, then an audio track of 30 sectors after 5 of stored pregap
+        let mut image: Vec<u8> = (0..20 * 2048).map(|i| (i / 2048) as u8).collect();
+        image.resize(20 * 2048 + 35 * 2352, 0xaa);
+        let audio = 20 * 2048;
+        let mut dao = vec![0u8; 22];
+        dao[20..22].copy_from_slice(&[1, 2]);
+        dao.extend(dao_track(2048, 0x00, 0, 0, audio));
+        dao.extend(dao_track(
+            2352,
+            0x07,
+            audio,
+            audio + 5 * 2352,
+            audio + 35 * 2352,
+        ));
+        let chunks = image.len() as u64;
+        image.extend(chunk(b"DAOX", &dao));
+        image.extend(chunk(b"END!", &[]));
+        image.extend(b"NER5");
+        image.extend(chunks.to_be_bytes());
+
+        let directory = TemporaryDirectory::create().unwrap();
+        let path = directory.0.join("image.nrg");
+        fs::write(&path, image).unwrap();
+        let mut args = ConvertOptions {
+            cue_file: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let mut disc = NrgDisc::open(&mut args).unwrap();
+        assert_eq!(args.bin_file, args.cue_file);
+
+        let tracks = disc.tracks();
+        assert!(tracks[0].mode == Mode::Mode1_2048 && tracks[1].mode == Mode::Audio);
+        assert_eq!((tracks[0].start_sector, tracks[0].stop_sector()), (0, 19));
+        assert_eq!((tracks[1].start_sector, tracks[1].stop_sector()), (25, 54));
+        assert_eq!((tracks[1].index0, tracks[1].pregap), (Some(20), 0));
+
+        let mut sector = [0u8; 2352];
+        disc.read_sector(7, &mut sector).unwrap();
+        assert!(sector[16..16 + 2048].iter().all(|&b| b == 7));
+    }
+
+    #[test]
+    fn reads_track_at_once_and_cue_chunks() {
+        // Offset, length, mode and LBA of each track, 32 bits wide in version 1
+        let mut etnf = Vec::new();
+        for (start, length, code, lba) in [
+            (0u32, 2352 * 100, 0x07, 0),
+            (2352 * 100, 2048 * 50, 0x00, 250),
+        ] {
+            for value in [start, length, code, lba, 0] {
+                etnf.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        let mut entries = Vec::new();
+        read_tao(&etnf, false, &mut entries).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[1].number, entries[1].sector_size), (2, 2048));
+        assert_eq!(
+            (entries[1].start, entries[1].end),
+            (235200, 235200 + 2048 * 50)
+        );
+        assert_eq!(entries[1].lba, Some(250));
+
+        // Version 1 cue entries give absolute MSF addresses, version 2 ones LBAs
+        let cues = [[1, 0x00, 0, 0, 0, 0, 0, 0], [1, 0x12, 0x01, 0, 0, 0, 4, 0]];
+        let mut indexes = Vec::new();
+        read_cue(&cues.concat(), false, &mut indexes).unwrap();
+        assert_eq!(indexes, [(12, 1, 4 * 75 - LEAD_IN)]);
+        let cuex = [[1, 0x02, 0x00, 0, 0xff, 0xff, 0xff, 0xb5]];
+        indexes.clear();
+        read_cue(&cuex.concat(), true, &mut indexes).unwrap();
+        assert_eq!(indexes, [(2, 0, -75)]);
+    }
+
+    #[test]
+    fn nero_modes_match_their_sector_sizes() {
+        let sizes: Vec<u64> = [0x00, 0x03, 0x05, 0x07, 0x10]
+            .iter()
+            .map(|&code| {
+                let (mode, subcode) = track_mode(code).unwrap();
+                stride(mode, subcode)
+            })
+            .collect();
+        assert_eq!(sizes, [2048, 2336, 2352, 2352, 2448]);
+        assert!(track_mode(0x01).is_none());
+    }
+}
@@ -0,0 +1,436 @@
+//! Building `ConvertOptions`, and the `Args` they replace
+
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::{
+    validate, ConvertOptions, DataTracks, ExistingOutputs, Gain, GapPolicy, OutputSink,
+    PartialSector, ProgressCallback, TrackSelection,
+};
+
+impl ConvertOptions {
+    /// Options with every setting defaulted, to be changed one at a time
+    pub fn builder() -> ConvertOptionsBuilder {
+        ConvertOptionsBuilder::default()
+    }
+
+    /// Builder starting from these options, e.g. the ones of `ConvertOptions::from_env`
+    pub fn to_builder(self) -> ConvertOptionsBuilder {
+        ConvertOptionsBuilder { options: self }
+    }
+}
+
+/// Sets up `ConvertOptions`, checking them once they're built
+///
+/// Every setting is named after the field of `ConvertOptions` it sets, those that are
+/// optional take the value itself.
+#[derive(Default, Clone)]
+pub struct ConvertOptionsBuilder {
+    options: ConvertOptions,
+}
+
+impl ConvertOptionsBuilder {
+    /// The options, failing on the same combinations a conversion would fail on up front
+    pub fn build(self) -> io::Result<ConvertOptions> {
+        validate(&self.options)?;
+        Ok(self.options)
+    }
+
+    pub fn output_name(mut self, name: impl Into<String>) -> Self {
+        self.options.output_name = name.into();
+        self
+    }
+
+    pub fn output_dir(mut self, dir: impl Into<String>) -> Self {
+        self.options.output_dir = dir.into();
+        self
+    }
+
+    pub fn bin_file(mut self, path: impl Into<String>) -> Self {
+        self.options.bin_file = path.into();
+        self
+    }
+
+    pub fn cue_file(mut self, path: impl Into<String>) -> Self {
+        self.options.cue_file = path.into();
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.options.verbose = verbose;
+        self
+    }
+
+    pub fn psx_truncate(mut self, truncate: bool) -> Self {
+        self.options.psx_truncate = truncate;
+        self
+    }
+
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.options.raw = raw;
+        self
+    }
+
+    /// Sets `ConvertOptions::swap_audo_bytes`
+    pub fn swap_audio_bytes(mut self, swap: bool) -> Self {
+        self.options.swap_audo_bytes = swap;
+        self
+    }
+
+    pub fn to_wav(mut self, wav: bool) -> Self {
+        self.options.to_wav = wav;
+        self
+    }
+
+    pub fn export_surplus(mut self, export: bool) -> Self {
+        self.options.export_surplus = export;
+        self
+    }
+
+    pub fn audio_offset(mut self, offset: i64) -> Self {
+        self.options.audio_offset = offset;
+        self
+    }
+
+    pub fn disc(mut self, disc: u32) -> Self {
+        self.options.disc = Some(disc);
+        self
+    }
+
+    pub fn export_toc(mut self, export: bool) -> Self {
+        self.options.export_toc = export;
+        self
+    }
+
+    pub fn export_dat(mut self, export: bool) -> Self {
+        self.options.export_dat = export;
+        self
+    }
+
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.options.checksums = checksums;
+        self
+    }
+
+    pub fn keep_raw(mut self, keep: bool) -> Self {
+        self.options.keep_raw = keep;
+        self
+    }
+
+    pub fn ecm(mut self, ecm: bool) -> Self {
+        self.options.ecm = ecm;
+        self
+    }
+
+    pub fn export_cue(mut self, export: bool) -> Self {
+        self.options.export_cue = export;
+        self
+    }
+
+    pub fn keep_partial(mut self, keep: bool) -> Self {
+        self.options.keep_partial = keep;
+        self
+    }
+
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.options.verify = verify;
+        self
+    }
+
+    pub fn gap_policy(mut self, policy: GapPolicy) -> Self {
+        self.options.gap_policy = policy;
+        self
+    }
+
+    pub fn include_index0(mut self, include: bool) -> Self {
+        self.options.include_index0 = include;
+        self
+    }
+
+    pub fn export_cdg(mut self, export: bool) -> Self {
+        self.options.export_cdg = export;
+        self
+    }
+
+    pub fn ogg_quality(mut self, quality: f32) -> Self {
+        self.options.ogg_quality = Some(quality);
+        self
+    }
+
+    pub fn mp3_bitrate(mut self, bitrate: u32) -> Self {
+        self.options.mp3_bitrate = Some(bitrate);
+        self
+    }
+
+    pub fn wav_sample_rate(mut self, rate: u32) -> Self {
+        self.options.wav_sample_rate = Some(rate);
+        self
+    }
+
+    pub fn wav_channels(mut self, channels: u16) -> Self {
+        self.options.wav_channels = Some(channels);
+        self
+    }
+
+    pub fn wav_bits_per_sample(mut self, bits: u16) -> Self {
+        self.options.wav_bits_per_sample = Some(bits);
+        self
+    }
+
+    pub fn data_tracks(mut self, data_tracks: DataTracks) -> Self {
+        self.options.data_tracks = data_tracks;
+        self
+    }
+
+    pub fn name_template(mut self, template: impl Into<String>) -> Self {
+        self.options.name_template = Some(template.into());
+        self
+    }
+
+    pub fn tracks(mut self, tracks: TrackSelection) -> Self {
+        self.options.tracks = Some(tracks);
+        self
+    }
+
+    pub fn progress(mut self, progress: ProgressCallback) -> Self {
+        self.options.progress = Some(progress);
+        self
+    }
+
+    pub fn output_sink(mut self, sink: Arc<dyn OutputSink>) -> Self {
+        self.options.output_sink = Some(sink);
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.threads = threads;
+        self
+    }
+
+    pub fn read_buffer(mut self, bytes: usize) -> Self {
+        self.options.read_buffer = Some(bytes);
+        self
+    }
+
+    pub fn write_buffer(mut self, bytes: usize) -> Self {
+        self.options.write_buffer = Some(bytes);
+        self
+    }
+
+    pub fn cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.options.cancel = Some(cancel);
+        self
+    }
+
+    pub fn existing(mut self, existing: ExistingOutputs) -> Self {
+        self.options.existing = existing;
+        self
+    }
+
+    pub fn partial_sector(mut self, policy: PartialSector) -> Self {
+        self.options.partial_sector = policy;
+        self
+    }
+
+    pub fn export_subcode(mut self, export: bool) -> Self {
+        self.options.export_subcode = export;
+        self
+    }
+
+    pub fn sector_size(mut self, bytes: u64) -> Self {
+        self.options.sector_size = Some(bytes);
+        self
+    }
+
+    pub fn gain(mut self, gain: Gain) -> Self {
+        self.options.gain = Some(gain);
+        self
+    }
+
+    pub fn keep_emphasis(mut self, keep: bool) -> Self {
+        self.options.keep_emphasis = keep;
+        self
+    }
+
+    pub fn resample(mut self, rate: u32) -> Self {
+        self.options.resample = Some(rate);
+        self
+    }
+
+    pub fn split_indexes(mut self, split: bool) -> Self {
+        self.options.split_indexes = split;
+        self
+    }
+
+    pub fn flac_compression(mut self, level: u32) -> Self {
+        self.options.flac_compression = Some(level);
+        self
+    }
+
+    pub fn replaygain(mut self, replaygain: bool) -> Self {
+        self.options.replaygain = replaygain;
+        self
+    }
+
+    pub fn musicbrainz(mut self, musicbrainz: bool) -> Self {
+        self.options.musicbrainz = musicbrainz;
+        self
+    }
+
+    pub fn musicbrainz_cache(mut self, directory: impl Into<String>) -> Self {
+        self.options.musicbrainz_cache = Some(directory.into());
+        self
+    }
+
+    pub fn trust_sector_modes(mut self, trust: bool) -> Self {
+        self.options.trust_sector_modes = trust;
+        self
+    }
+
+    pub fn demux_mpeg(mut self, demux: bool) -> Self {
+        self.options.demux_mpeg = demux;
+        self
+    }
+
+    pub fn verify_existing(mut self, verify: bool) -> Self {
+        self.options.verify_existing = verify;
+        self
+    }
+
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.options.sparse = sparse;
+        self
+    }
+
+    pub fn gzip_level(mut self, level: u32) -> Self {
+        self.options.gzip_level = Some(level);
+        self
+    }
+
+    pub fn zstd_level(mut self, level: u32) -> Self {
+        self.options.zstd_level = Some(level);
+        self
+    }
+}
+
+/// Options of a conversion as they were before `ConvertOptions`
+///
+/// Converted into `ConvertOptions` wherever they're still taken by value. Settings added
+/// since are only found on `ConvertOptions`.
+#[deprecated(note = "use ConvertOptions::builder() instead, Args gets no new settings")]
+#[derive(Default, Clone)]
+pub struct Args {
+    pub output_name: String,
+    pub output_dir: String,
+    pub bin_file: String,
+    pub cue_file: String,
+    pub verbose: bool,
+    pub psx_truncate: bool,
+    pub raw: bool,
+    pub swap_audo_bytes: bool,
+    pub to_wav: bool,
+    pub export_surplus: bool,
+    pub audio_offset: i64,
+    pub disc: Option<u32>,
+    pub export_toc: bool,
+    pub export_dat: bool,
+    pub checksums: bool,
+    pub keep_raw: bool,
+    pub ecm: bool,
+    pub export_cue: bool,
+    pub keep_partial: bool,
+    pub verify: bool,
+    pub gap_policy: GapPolicy,
+    pub include_index0: bool,
+    pub export_cdg: bool,
+    pub ogg_quality: Option<f32>,
+    pub mp3_bitrate: Option<u32>,
+    pub wav_sample_rate: Option<u32>,
+    pub wav_channels: Option<u16>,
+    pub wav_bits_per_sample: Option<u16>,
+    pub data_tracks: DataTracks,
+    pub name_template: Option<String>,
+    pub tracks: Option<TrackSelection>,
+    pub progress: Option<ProgressCallback>,
+    pub output_sink: Option<Arc<dyn OutputSink>>,
+    pub threads: usize,
+    pub read_buffer: Option<usize>,
+    pub write_buffer: Option<usize>,
+}
+
+#[allow(deprecated)]
+impl Args {
+    /// Resolves the file names like `ConvertOptions::new`, for code opening discs itself
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(options: Args) -> ConvertOptions {
+        ConvertOptions::new(options.into())
+    }
+
+    pub fn from_env() -> io::Result<ConvertOptions> {
+        ConvertOptions::from_env()
+    }
+}
+
+#[allow(deprecated)]
+impl From<Args> for ConvertOptions {
+    fn from(args: Args) -> ConvertOptions {
+        ConvertOptions {
+            output_name: args.output_name,
+            output_dir: args.output_dir,
+            bin_file: args.bin_file,
+            cue_file: args.cue_file,
+            verbose: args.verbose,
+            psx_truncate: args.psx_truncate,
+            raw: args.raw,
+            swap_audo_bytes: args.swap_audo_bytes,
+            to_wav: args.to_wav,
+            export_surplus: args.export_surplus,
+            audio_offset: args.audio_offset,
+            disc: args.disc,
+            export_toc: args.export_toc,
+            export_dat: args.export_dat,
+            checksums: args.checksums,
+            keep_raw: args.keep_raw,
+            ecm: args.ecm,
+            export_cue: args.export_cue,
+            keep_partial: args.keep_partial,
+            verify: args.verify,
+            gap_policy: args.gap_policy,
+            include_index0: args.include_index0,
+            export_cdg: args.export_cdg,
+            ogg_quality: args.ogg_quality,
+            mp3_bitrate: args.mp3_bitrate,
+            wav_sample_rate: args.wav_sample_rate,
+            wav_channels: args.wav_channels,
+            wav_bits_per_sample: args.wav_bits_per_sample,
+            data_tracks: args.data_tracks,
+            name_template: args.name_template,
+            tracks: args.tracks,
+            progress: args.progress,
+            output_sink: args.output_sink,
+            threads: args.threads,
+            read_buffer: args.read_buffer,
+            write_buffer: args.write_buffer,
+            cancel: None,
+            existing: ExistingOutputs::default(),
+            partial_sector: PartialSector::default(),
+            export_subcode: false,
+            sector_size: None,
+            gain: None,
+            keep_emphasis: false,
+            resample: None,
+            split_indexes: false,
+            flac_compression: None,
+            replaygain: false,
+            musicbrainz: false,
+            musicbrainz_cache: None,
+            trust_sector_modes: false,
+            demux_mpeg: false,
+            verify_existing: false,
+            sparse: false,
+            gzip_level: None,
+            zstd_level: None,
+        }
+    }
+}
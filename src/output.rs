@@ -0,0 +1,597 @@
+use std::fs;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use crate::ecm::EcmWriter;
+#[cfg(feature = "encoder")]
+use crate::encoder::Encoder;
+use crate::error::ErrorCode;
+use crate::hash::{Hasher, Hashes};
+use crate::log::info;
+use crate::longpath::{file_name, long_path};
+use crate::plan::PlannedOutput;
+use crate::ConvertOptions;
+
+/// A file written by a conversion
+#[derive(Clone)]
+pub struct OutputFile {
+    pub filename: String,
+    pub size: u64,
+    /// Checksums of the file, computed while writing it when a dat or
+    /// `ConvertOptions::checksums` is requested
+    pub hashes: Option<Hashes>,
+}
+
+impl OutputFile {
+    /// Name and size of the file, with its checksums if they were computed
+    pub(crate) fn summary(&self) -> String {
+        let mut summary = format!("{} {}MiB", self.filename, self.size / 1024 / 1024);
+        if let Some(hashes) = &self.hashes {
+            summary += &format!(
+                " crc32 {} md5 {} sha1 {}",
+                hashes.crc32_hex(),
+                hashes.md5_hex(),
+                hashes.sha1_hex()
+            );
+        }
+        summary
+    }
+}
+
+/// What a conversion does about outputs that are already there
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExistingOutputs {
+    /// Fail before writing anything, with `ErrorCode::OutputExists`
+    #[default]
+    Fail,
+    /// Replace them
+    Overwrite,
+    /// Leave tracks alone whose files are all there in full, writing the rest. Files for the
+    /// whole disc, like a merged `.iso` or the converted CUE sheet, are written again.
+    Skip,
+}
+
+/// Appended to the names of files while they're written, which they only lose once complete
+pub(crate) const PART_SUFFIX: &str = ".part";
+
+/// Path an output is written to until it's complete, `None` for those going to an
+/// `OutputSink`, which are written as they are
+pub(crate) fn part_path(filename: &str, a: &ConvertOptions) -> Option<String> {
+    match a.output_sink {
+        Some(_) => None,
+        None => Some(format!("{}{}", filename, PART_SUFFIX)),
+    }
+}
+
+/// Suffix of the files disc images are compressed to with `ConvertOptions::zstd_level` or
+/// `gzip_level`, like the `.zst` of `game01.iso.zst`
+pub(crate) fn compressed_suffix(a: &ConvertOptions) -> Option<&'static str> {
+    match (a.zstd_level, a.gzip_level) {
+        (Some(_), _) => Some(".zst"),
+        (None, Some(_)) => Some(".gz"),
+        (None, None) => None,
+    }
+}
+
+/// Bytes of the blocks of zeroes `ConvertOptions::sparse` seeks over, the block size of most
+/// file systems
+const SPARSE_BLOCK: u64 = 4096;
+
+/// Whether outputs are hashed while they're written
+fn hashes(a: &ConvertOptions) -> bool {
+    a.export_dat || a.checksums || a.verify_existing
+}
+
+/// Where a conversion writes its outputs, the file system unless
+/// `ConvertOptions::output_sink` is set
+///
+/// Outputs are named by the path they would be written to, e.g. `game01.iso`. Audio
+/// encoded by an external encoder is written by the encoder itself, so it can only go to files.
+pub trait OutputSink: Send + Sync {
+    /// Opens the output `filename`, replacing whatever it held
+    fn create(&self, filename: &str) -> io::Result<Box<dyn Write>>;
+
+    /// Discards the output `filename` of a failed conversion
+    fn remove(&self, filename: &str) -> io::Result<()>;
+}
+
+/// Writes every output to the file it's named after
+pub struct FileSink;
+
+impl OutputSink for FileSink {
+    fn create(&self, filename: &str) -> io::Result<Box<dyn Write>> {
+        Ok(Box::new(fs::File::create(long_path(filename))?))
+    }
+
+    fn remove(&self, filename: &str) -> io::Result<()> {
+        fs::remove_file(long_path(filename))
+    }
+}
+
+fn sink(a: &ConvertOptions) -> Arc<dyn OutputSink> {
+    match &a.output_sink {
+        Some(sink) => sink.clone(),
+        None => Arc::new(FileSink),
+    }
+}
+
+/// Writer for output files keeping track of what has been written to them
+///
+/// Files are written to `<filename>.part` and only renamed once `finish` is done with them,
+/// so an interrupted conversion never leaves a truncated file looking just like a valid one.
+/// A writer dropped before `finish` removes its truncated file, unless
+/// `ConvertOptions::keep_partial` is set.
+pub(crate) struct OutputWriter {
+    filename: String,
+    /// File written until it's finished, see `part_path`
+    part: Option<String>,
+    // Only taken when the writer is done with, so the file is closed before removing it
+    writer: Option<Writer>,
+    sink: Arc<dyn OutputSink>,
+    #[cfg(feature = "encoder")]
+    hash: bool,
+    finished: bool,
+    keep_partial: bool,
+}
+
+/// Where the data written to an output ends up
+enum Writer {
+    Plain(BufWriter<Measured>),
+    /// ECM compressed output, of the data that's written to it
+    Ecm(EcmWriter<BufWriter<Measured>>),
+    /// An encoder writing the file from the data it is fed
+    #[cfg(feature = "encoder")]
+    Encoder(Encoder),
+}
+
+/// Output opened by the sink, with the size and hashes of what reached it
+struct Measured {
+    inner: Target,
+    size: u64,
+    hasher: Option<Hasher>,
+    /// Seek over blocks of zeroes in files instead of writing them, see `ConvertOptions::sparse`
+    sparse: bool,
+}
+
+enum Target {
+    /// File written without an `OutputSink`, which data can be copied into directly
+    File(fs::File),
+    Sink(Box<dyn Write>),
+}
+
+impl Write for Target {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Target::File(file) => file.write(buf),
+            Target::Sink(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Target::File(file) => file.flush(),
+            Target::Sink(writer) => writer.flush(),
+        }
+    }
+}
+
+impl Measured {
+    /// Writes `buf` at the end of the file, leaving holes where whole blocks of it are zeroes
+    fn write_sparse(file: &mut fs::File, position: u64, buf: &[u8]) -> io::Result<()> {
+        let mut at = 0;
+        // Runs of blocks that are all zeroes or not, the first and last cut at block boundaries
+        while at < buf.len() {
+            let mut end = at;
+            let mut zeroes = None;
+            while end < buf.len() {
+                let boundary = SPARSE_BLOCK - (position + end as u64) % SPARSE_BLOCK;
+                let next = buf.len().min(end + boundary as usize);
+                let block = &buf[end..next];
+                let hole = block.len() as u64 == SPARSE_BLOCK && block.iter().all(|&b| b == 0);
+                if *zeroes.get_or_insert(hole) != hole {
+                    break;
+                }
+                end = next;
+            }
+            match zeroes {
+                Some(true) => {
+                    file.seek(SeekFrom::Current((end - at) as i64))?;
+                }
+                _ => file.write_all(&buf[at..end])?,
+            }
+            at = end;
+        }
+        Ok(())
+    }
+
+    /// Makes a file ending in a hole as long as what was written to it
+    fn finish(&mut self) -> io::Result<()> {
+        match (&self.inner, self.sparse) {
+            (Target::File(file), true) => file.set_len(self.size),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Write for Measured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = match (&mut self.inner, self.sparse) {
+            (Target::File(file), true) => {
+                Measured::write_sparse(file, self.size, buf)?;
+                buf.len()
+            }
+            (inner, _) => inner.write(buf)?,
+        };
+        self.size += n as u64;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl OutputWriter {
+    pub(crate) fn create(filename: String, a: &ConvertOptions) -> io::Result<OutputWriter> {
+        let (writer, sink) = OutputWriter::open(&filename, a)?;
+        Ok(OutputWriter::new(filename, Writer::Plain(writer), sink, a))
+    }
+
+    /// Writer compressing what it's given to the ECM file `filename`
+    pub(crate) fn create_ecm(filename: String, a: &ConvertOptions) -> io::Result<OutputWriter> {
+        let (writer, sink) = OutputWriter::open(&filename, a)?;
+        let writer = EcmWriter::new(writer)?;
+        Ok(OutputWriter::new(filename, Writer::Ecm(writer), sink, a))
+    }
+
+    /// Writer piping what it's given through `gzip` or `zstd` into `filename`, whichever `a`
+    /// picks, or writing it as it is if it picks neither
+    pub(crate) fn create_compressed(
+        filename: String,
+        a: &ConvertOptions,
+    ) -> io::Result<OutputWriter> {
+        #[cfg(any(feature = "gzip", feature = "zstd"))]
+        if let Some(command) = crate::encoder::compressor(a) {
+            let path = part_path(&filename, a).unwrap_or_else(|| filename.clone());
+            let encoder = Encoder::spawn_writing(command, long_path(&path).as_path())?;
+            return Ok(OutputWriter::encode(filename, encoder, a));
+        }
+        OutputWriter::create(filename, a)
+    }
+
+    /// Writer feeding `encoder`, which writes the file `filename` itself, to its `part_path`
+    #[cfg(feature = "encoder")]
+    pub(crate) fn encode(filename: String, encoder: Encoder, a: &ConvertOptions) -> OutputWriter {
+        OutputWriter::new(filename, Writer::Encoder(encoder), sink(a), a)
+    }
+
+    fn open(
+        filename: &str,
+        a: &ConvertOptions,
+    ) -> io::Result<(BufWriter<Measured>, Arc<dyn OutputSink>)> {
+        let sink = sink(a);
+        let inner = match (&a.output_sink, part_path(filename, a)) {
+            (Some(sink), _) => Target::Sink(sink.create(filename)?),
+            // Checked up front too, this catches files that showed up since
+            (None, _) if a.existing == ExistingOutputs::Fail && long_path(filename).exists() => {
+                return Err(exists(filename))
+            }
+            // Left over by a conversion that was interrupted, so replaced
+            (None, Some(part)) => Target::File(fs::File::create(long_path(part))?),
+            (None, None) => Target::File(fs::File::create(long_path(filename))?),
+        };
+        let measured = Measured {
+            inner,
+            size: 0,
+            hasher: hashes(a).then(Hasher::new),
+            sparse: a.sparse,
+        };
+        let writer = BufWriter::with_capacity(a.write_buffer_size(), measured);
+        Ok((writer, sink))
+    }
+
+    fn new(
+        filename: String,
+        writer: Writer,
+        sink: Arc<dyn OutputSink>,
+        a: &ConvertOptions,
+    ) -> OutputWriter {
+        OutputWriter {
+            part: part_path(&filename, a),
+            filename,
+            writer: Some(writer),
+            sink,
+            #[cfg(feature = "encoder")]
+            hash: hashes(a),
+            finished: false,
+            keep_partial: a.keep_partial,
+        }
+    }
+
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.writer.as_mut().unwrap() {
+            Writer::Plain(writer) => writer.write_all(data),
+            Writer::Ecm(writer) => writer.write_all(data),
+            #[cfg(feature = "encoder")]
+            Writer::Encoder(encoder) => encoder.write_all(data),
+        }
+    }
+
+    /// Hands the file of a plain output to `copy`, once what was written before is flushed to
+    /// it, which appends to it directly and returns how many bytes it wrote
+    ///
+    /// Returns `None` without calling `copy` for outputs that are compressed, hashed, sparse or
+    /// don't go to a file.
+    pub(crate) fn write_directly(
+        &mut self,
+        copy: impl FnOnce(&mut fs::File) -> io::Result<u64>,
+    ) -> io::Result<Option<u64>> {
+        let writer = match self.writer.as_mut() {
+            Some(Writer::Plain(writer))
+                if writer.get_ref().hasher.is_none() && !writer.get_ref().sparse =>
+            {
+                writer
+            }
+            _ => return Ok(None),
+        };
+        if let Target::Sink(_) = writer.get_ref().inner {
+            return Ok(None);
+        }
+        writer.flush()?;
+        let measured = writer.get_mut();
+        let copied = match &mut measured.inner {
+            Target::File(file) => copy(file)?,
+            Target::Sink(_) => return Ok(None),
+        };
+        measured.size += copied;
+        Ok(Some(copied))
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<OutputFile> {
+        // Sizes and hashes are of the output as it's stored, after compressing it
+        let measured = match self.writer.as_mut().unwrap() {
+            Writer::Plain(writer) => {
+                writer.flush()?;
+                writer.get_mut()
+            }
+            Writer::Ecm(writer) => {
+                writer.finish()?;
+                writer.get_mut().get_mut()
+            }
+            #[cfg(feature = "encoder")]
+            Writer::Encoder(encoder) => {
+                encoder.finish()?;
+                self.rename()?;
+                return self.read_back();
+            }
+        };
+        measured.finish()?;
+        let (size, hasher) = (measured.size, measured.hasher.take());
+        // Closed before it's renamed, which Windows doesn't do to open files
+        drop(self.writer.take());
+        self.rename()?;
+        self.finished = true;
+        Ok(OutputFile {
+            filename: std::mem::take(&mut self.filename),
+            size,
+            hashes: hasher.map(Hasher::finish),
+        })
+    }
+
+    /// Gives the complete file its own name, replacing whatever had it
+    fn rename(&mut self) -> io::Result<()> {
+        if let Some(part) = self.part.take() {
+            if let Err(e) = fs::rename(long_path(&part), long_path(&self.filename)) {
+                let _ = fs::remove_file(long_path(&part));
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the size and hashes of a file written by an encoder
+    #[cfg(feature = "encoder")]
+    fn read_back(mut self) -> io::Result<OutputFile> {
+        let output = read_back(std::mem::take(&mut self.filename), self.hash)?;
+        self.finished = true;
+        Ok(output)
+    }
+}
+
+impl Drop for OutputWriter {
+    fn drop(&mut self) {
+        drop(self.writer.take());
+        // A truncated track looks just like a valid one, so don't leave it behind
+        if !self.finished && !self.keep_partial {
+            let _ = match &self.part {
+                Some(part) => fs::remove_file(long_path(part)),
+                None => self.sink.remove(&self.filename),
+            };
+        }
+    }
+}
+
+/// Size and, if `hash` is set, hashes of the file `filename` as it is
+pub(crate) fn read_back(filename: String, hash: bool) -> io::Result<OutputFile> {
+    use std::io::Read;
+
+    let mut file = fs::File::open(long_path(&filename))?;
+    let size = file.metadata()?.len();
+    let mut hasher = hash.then(Hasher::new);
+    if let Some(hasher) = &mut hasher {
+        let mut buffer = vec![0u8; crate::SECTOR_SIZE as usize * 16];
+        loop {
+            match file.read(&mut buffer)? {
+                0 => break,
+                n => hasher.update(&buffer[..n]),
+            }
+        }
+    }
+    Ok(OutputFile {
+        filename,
+        size,
+        hashes: hasher.map(Hasher::finish),
+    })
+}
+
+fn exists(filename: &str) -> io::Error {
+    ErrorCode::OutputExists.error(format!("{} already exists", filename))
+}
+
+/// Fails if one of the `planned` outputs is there already, before anything is written
+pub(crate) fn check_existing(planned: &[&PlannedOutput], a: &ConvertOptions) -> io::Result<()> {
+    if a.output_sink.is_some() || a.existing != ExistingOutputs::Fail {
+        return Ok(());
+    }
+    match planned.iter().find(|p| long_path(&p.filename).exists()) {
+        Some(p) => Err(exists(&p.filename)),
+        None => Ok(()),
+    }
+}
+
+/// The files of `planned` if every one of them is there in full, for `ExistingOutputs::Skip`
+///
+/// With `ConvertOptions::verify_existing` they must also still have the SHA-1 `checksums`
+/// last lists for their file name.
+pub(crate) fn complete(
+    planned: &[PlannedOutput],
+    checksums: &[(String, String)],
+    a: &ConvertOptions,
+) -> Option<Vec<OutputFile>> {
+    if a.output_sink.is_some() || a.existing != ExistingOutputs::Skip || planned.is_empty() {
+        return None;
+    }
+    let mut outputs = Vec::new();
+    for p in planned {
+        let size = fs::metadata(long_path(&p.filename)).ok()?.len();
+        // A file cut short by an earlier conversion is written again, estimates can't tell
+        if p.size
+            .is_some_and(|planned| !p.estimated && planned != size)
+        {
+            return None;
+        }
+        let output = read_back(p.filename.clone(), hashes(a)).ok()?;
+        if a.verify_existing {
+            let name = file_name(&p.filename);
+            let (_, sha1) = checksums.iter().rev().find(|(listed, _)| listed == name)?;
+            if !output
+                .hashes
+                .as_ref()?
+                .sha1_hex()
+                .eq_ignore_ascii_case(sha1)
+            {
+                if a.verbose {
+                    info!("{} doesn't match its earlier checksum, written again", name);
+                }
+                return None;
+            }
+        }
+        outputs.push(output);
+    }
+    Some(outputs)
+}
+
+/// List of the SHA-1s of the outputs finished so far, for `ConvertOptions::verify_existing`
+fn checksums_path(a: &ConvertOptions) -> String {
+    format!("{}.resume.sha1", a.output_name)
+}
+
+/// Adds the SHA-1s of the just finished `outputs` to the list a resumed conversion checks the
+/// files it finds against, which `sha1sum -c` reads as well
+pub(crate) fn record_checksums(outputs: &[OutputFile], a: &ConvertOptions) -> io::Result<()> {
+    if !a.verify_existing || a.output_sink.is_some() || outputs.is_empty() {
+        return Ok(());
+    }
+    let mut lines = String::new();
+    for output in outputs {
+        if let Some(hashes) = &output.hashes {
+            lines += &format!("{}  {}\n", hashes.sha1_hex(), file_name(&output.filename));
+        }
+    }
+    let path = checksums_path(a);
+    let result = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(long_path(&path))
+        .and_then(|mut file| file.write_all(lines.as_bytes()));
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) => Err(ErrorCode::WriteFailed.wrap(&e, format!("Could not write {}: {}", path, e))),
+    }
+}
+
+/// File names and SHA-1s of the outputs an interrupted conversion finished, in the order it
+/// did, none without `ConvertOptions::verify_existing`
+pub(crate) fn read_checksums(a: &ConvertOptions) -> io::Result<Vec<(String, String)>> {
+    if !a.verify_existing || a.output_sink.is_some() {
+        return Ok(Vec::new());
+    }
+    let list = match fs::read_to_string(long_path(checksums_path(a))) {
+        Ok(list) => list,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(list
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(sha1, name)| (String::from(name), String::from(sha1)))
+        .collect())
+}
+
+/// Removes the list of finished outputs once the conversion is complete
+pub(crate) fn remove_checksums(a: &ConvertOptions) {
+    if a.verify_existing && a.output_sink.is_none() {
+        let _ = fs::remove_file(long_path(checksums_path(a)));
+    }
+}
+
+/// Writes a small file describing the conversion in one go
+pub(crate) fn write_file(
+    filename: String,
+    contents: &str,
+    a: &ConvertOptions,
+) -> io::Result<OutputFile> {
+    let result = OutputWriter::create(filename.clone(), a).and_then(|mut writer| {
+        writer.write_all(contents.as_bytes())?;
+        writer.finish()
+    });
+    match result {
+        Ok(output) => Ok(output),
+        Err(e) => {
+            Err(ErrorCode::WriteFailed.wrap(&e, format!("Could not write {}: {}", filename, e)))
+        }
+    }
+}
+
+/// Fails if writing `filename` would replace the `input` being converted
+pub(crate) fn check_not_input(filename: &str, input: &str) -> io::Result<()> {
+    if let (Ok(output), Ok(input)) = (
+        fs::canonicalize(long_path(filename)),
+        fs::canonicalize(long_path(input)),
+    ) {
+        if output == input {
+            return Err(ErrorCode::InvalidArguments.error(format!(
+                "Refusing to overwrite the input file {} with an output of the conversion",
+                filename
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Removes the files of a failed conversion, unless asked to keep them for debugging
+pub(crate) fn roll_back(outputs: &[OutputFile], a: &ConvertOptions) {
+    if a.keep_partial {
+        return;
+    }
+    let sink = sink(a);
+    for output in outputs {
+        if sink.remove(&output.filename).is_ok() && a.verbose {
+            info!("removed {}", output.filename);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Locating the other half of a CUE/BIN pair when only one of them is known
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::longpath::long_path;
+
+/// Extensions BIN files commonly come with
+pub(crate) const BIN_EXTENSIONS: [&str; 2] = ["bin", "img"];
+
+/// Extension added to BIN files compressed with ECM
+const ECM_EXTENSION: &str = "ecm";
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .is_some_and(|e| extensions.iter().any(|x| e.eq_ignore_ascii_case(x)))
+}
+
+/// File next to `path` with the same stem and one of `extensions`, ignoring case
+pub(crate) fn find_sibling(path: &Path, extensions: &[&str]) -> Option<PathBuf> {
+    let stem = path.file_stem()?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut siblings: Vec<PathBuf> = fs::read_dir(long_path(dir))
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_stem() == Some(stem) && has_extension(p, extensions) && long_path(p).is_file()
+        })
+        .collect();
+    // Directory order is arbitrary, keep the choice stable between runs
+    siblings.sort();
+    siblings.into_iter().next()
+}
+
+/// CUE sheet for an image given as a lone BIN file
+pub(crate) fn cue_for_bin(bin: &str) -> Option<String> {
+    let mut bin = PathBuf::from(bin);
+    // `game.bin.ecm` goes with `game.cue`, just like `game.bin`
+    if has_extension(&bin, &[ECM_EXTENSION]) {
+        bin.set_extension("");
+    }
+    if !has_extension(&bin, &BIN_EXTENSIONS) {
+        return None;
+    }
+    find_sibling(&bin, &["cue"]).map(|p| p.to_string_lossy().into_owned())
+}
+
+/// BIN file for a CUE sheet whose FILE entry doesn't point to an existing file
+pub(crate) fn bin_for_cue(cue: &str, file: &str) -> Option<String> {
+    file_for_cue(cue, file).or_else(|| {
+        find_sibling(Path::new(cue), &BIN_EXTENSIONS).map(|p| p.to_string_lossy().into_owned())
+    })
+}
+
+/// Name of an image without its extension, e.g. `game` for `game.cue` or `game.bin.ecm`
+pub(crate) fn image_name(path: &str) -> String {
+    let mut path = PathBuf::from(path);
+    if has_extension(&path, &[ECM_EXTENSION]) {
+        path.set_extension("");
+    }
+    match path.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Path a FILE entry names on this platform
+///
+/// Sheets written on Windows separate directories with backslashes, which are only part of
+/// the file name elsewhere.
+pub(crate) fn entry_path(file: &str) -> PathBuf {
+    match cfg!(windows) {
+        true => PathBuf::from(file),
+        false => PathBuf::from(file.replace('\\', "/")),
+    }
+}
+
+/// File named by a FILE entry of a CUE sheet, when it doesn't point to an existing file as is
+pub(crate) fn file_for_cue(cue: &str, file: &str) -> Option<String> {
+    let dir = Path::new(cue).parent().unwrap_or(Path::new(""));
+
+    // FILE entries are relative to the CUE sheet rather than the working directory
+    let entry = entry_path(file);
+    let mut candidates = vec![dir.join(file), dir.join(&entry)];
+    candidates.extend(entry.file_name().map(|name| dir.join(name)));
+    candidates.dedup();
+    // The BIN file may only be around compressed, with the FILE entry still naming it
+    let compressed: Vec<PathBuf> = candidates
+        .iter()
+        .map(|p| {
+            let mut name = p.clone().into_os_string();
+            name.push(".");
+            name.push(ECM_EXTENSION);
+            PathBuf::from(name)
+        })
+        .collect();
+    candidates.extend(compressed);
+    candidates
+        .into_iter()
+        .find(|p| long_path(p).is_file())
+        .map(|p| p.to_string_lossy().into_owned())
+}
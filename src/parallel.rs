@@ -0,0 +1,108 @@
+//! Writing several tracks at once, each thread reading from a disc it opened for itself
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::disc::Disc;
+use crate::output::{self, OutputFile, OutputWriter};
+use crate::track::Track;
+use crate::ConvertOptions;
+
+/// Opens the image being converted once more
+pub(crate) type Reopen<'a> = &'a (dyn Fn() -> io::Result<Box<dyn Disc>> + Sync);
+
+/// Outputs of a track, or why they couldn't be written, and how long writing it took
+type Written = Option<(io::Result<Vec<OutputFile>>, Duration)>;
+
+/// Writes every track of `jobs`, those marked as going into the merged file to `merged`
+///
+/// Tracks with files of their own are spread over `ConvertOptions::threads` threads when
+/// `reopen` can open discs for them, the merged ones share a writer and are written in
+/// order meanwhile. Once a track fails no more are started, those that weren't written are
+/// `None`.
+pub(crate) fn write_tracks(
+    disc: &mut dyn Disc,
+    jobs: &[(Track, bool)],
+    mut merged: Option<&mut OutputWriter>,
+    args: &ConvertOptions,
+    reopen: Option<Reopen>,
+) -> Vec<Written> {
+    let own_files = jobs.iter().filter(|(_, into_merged)| !into_merged).count();
+    let threads = match reopen {
+        Some(reopen) if args.threads > 1 && own_files > 1 => {
+            Some((reopen, args.threads.min(own_files)))
+        }
+        _ => None,
+    };
+    let results = Mutex::new((0..jobs.len()).map(|_| None).collect::<Vec<Written>>());
+    let failed = AtomicBool::new(false);
+    let record = |i: usize, start: Instant, result: io::Result<Vec<OutputFile>>| {
+        // Kept as soon as the track is finished, for resuming a conversion ended halfway
+        let result = result.and_then(|outputs| {
+            output::record_checksums(&outputs, args)?;
+            Ok(outputs)
+        });
+        if result.is_err() {
+            failed.store(true, Ordering::Relaxed);
+        }
+        results.lock().unwrap()[i] = Some((result, start.elapsed()));
+    };
+
+    let (reopen, threads) = match threads {
+        Some(threads) => threads,
+        None => {
+            for (i, (t, into_merged)) in jobs.iter().enumerate() {
+                let writer = merged.as_deref_mut().filter(|_| *into_merged);
+                let start = Instant::now();
+                record(i, start, t.write_to_file(disc, writer, args));
+                if failed.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+            return results.into_inner().unwrap();
+        }
+    };
+
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let mut own_disc = None;
+                while !failed.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let t = match jobs.get(i) {
+                        Some((_, true)) => continue,
+                        Some((t, false)) => t,
+                        None => break,
+                    };
+                    let start = Instant::now();
+                    let disc = match &mut own_disc {
+                        Some(disc) => disc,
+                        None => match reopen() {
+                            Ok(disc) => own_disc.insert(disc),
+                            Err(e) => {
+                                record(i, start, Err(e));
+                                break;
+                            }
+                        },
+                    };
+                    record(i, start, t.write_to_file(disc.as_mut(), None, args));
+                }
+            });
+        }
+        // Merged tracks have to be written in order, so they stay on this thread
+        for (i, (t, into_merged)) in jobs.iter().enumerate() {
+            if failed.load(Ordering::Relaxed) {
+                break;
+            }
+            if *into_merged {
+                let start = Instant::now();
+                record(i, start, t.write_to_file(disc, merged.as_deref_mut(), args));
+            }
+        }
+    });
+    results.into_inner().unwrap()
+}
@@ -0,0 +1,161 @@
+//! What a conversion would write, worked out from the layout without touching any sector
+
+use std::fmt::Display;
+use std::io;
+
+use crate::disc::Disc;
+use crate::discid::{disc_ids, DiscIds};
+use crate::output;
+use crate::track::{DataTracks, Mode};
+use crate::{open_disc, prepare_tracks, validate, ConvertOptions};
+
+/// File a conversion would write
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedOutput {
+    pub filename: String,
+    /// Size in bytes, unknown for outputs whose size depends on how well they compress
+    pub size: Option<u64>,
+    /// `size` is only a guess, like that of constant bitrate MP3s whose headers aren't known
+    pub estimated: bool,
+}
+
+/// Track a conversion would extract, with the files written for it
+#[derive(Clone)]
+pub struct PlannedTrack {
+    pub number: u32,
+    pub mode: Mode,
+    /// Sectors of `INDEX 01` and the end of the track on the disc
+    pub start_sector: u64,
+    pub stop_sector: u64,
+    pub outputs: Vec<PlannedOutput>,
+}
+
+/// Everything a conversion would write
+#[derive(Clone, Default)]
+pub struct Plan {
+    pub tracks: Vec<PlannedTrack>,
+    /// Files for the whole disc, like a merged `.iso` or the converted CUE sheet
+    pub outputs: Vec<PlannedOutput>,
+    /// IDs to look the disc up by in FreeDB and MusicBrainz, if it has audio tracks
+    pub disc_ids: Option<DiscIds>,
+    /// Serial of the game on a PlayStation disc, like `SLUS-01234`
+    pub serial: Option<String>,
+}
+
+/// Works out what `convert` would write for `options`, without writing anything
+pub fn plan(options: impl Into<ConvertOptions>) -> io::Result<Plan> {
+    let mut args = ConvertOptions::new(options.into());
+    validate(&args)?;
+
+    let disc = open_disc(&mut args)?;
+    plan_disc(disc.as_ref(), &args)
+}
+
+/// Works out what `convert_disc` would write for an already opened disc
+pub fn plan_disc(disc: &dyn Disc, args: &ConvertOptions) -> io::Result<Plan> {
+    let mut plan = Plan {
+        disc_ids: disc_ids(disc.tracks()),
+        serial: disc.metadata().serial.clone(),
+        ..Default::default()
+    };
+    let mut merged = match args.data_tracks {
+        DataTracks::Separate => None,
+        DataTracks::Merge | DataTracks::First => Some(0),
+    };
+    let mut data_tracks = 0;
+    let mut played_back = false;
+    for t in prepare_tracks(disc, args)? {
+        played_back |= t.played_back(args);
+        let into_merged = !t.is_audio() && merged.is_some();
+        if into_merged {
+            data_tracks += 1;
+            if args.data_tracks == DataTracks::First && data_tracks > 1 {
+                continue;
+            }
+            let size = t.output_sectors(args) * t.block_size();
+            merged = merged.map(|merged| merged + size);
+        }
+        plan.tracks.push(PlannedTrack {
+            number: t.number,
+            mode: t.mode,
+            start_sector: t.start_sector,
+            stop_sector: t.stop_sector(),
+            outputs: t.planned_outputs(into_merged, disc.metadata(), args)?,
+        });
+    }
+
+    let disc_file = |suffix: &str, size| PlannedOutput {
+        filename: format!("{}{}", args.output_name, suffix),
+        size,
+        estimated: false,
+    };
+    if let Some(size) = merged {
+        let output = match output::compressed_suffix(args) {
+            Some(suffix) => disc_file(&format!(".iso{}", suffix), None),
+            None => disc_file(".iso", Some(size)),
+        };
+        plan.outputs.push(output);
+    }
+    if let (Some(surplus), true) = (disc.surplus(), args.export_surplus) {
+        plan.outputs
+            .push(disc_file("-surplus.bin", Some(surplus.length)));
+    }
+    // Tracks too quiet to measure are left out, a disc of nothing but is left without
+    if args.replaygain && args.to_wav && played_back {
+        plan.outputs.push(disc_file(".replaygain.txt", None));
+    }
+    if args.export_dat {
+        plan.outputs.push(disc_file(".game.xml", None));
+    }
+    if args.export_toc {
+        plan.outputs.push(disc_file(".dat", None));
+        plan.outputs.push(disc_file("_disc.txt", None));
+    }
+    if args.export_cue {
+        plan.outputs.push(disc_file(".cue", None));
+    }
+    Ok(plan)
+}
+
+impl Display for Plan {
+    /// Table of the tracks and the files written for them, one file per line
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let size = |output: &PlannedOutput| match output.size {
+            Some(size) if output.estimated => format!("~{}", size),
+            Some(size) => size.to_string(),
+            None => String::from("?"),
+        };
+        writeln!(
+            f,
+            "{:>5}  {:12}{:>8}  {:>8}  {:>12}  File",
+            "Track", "Mode", "Start", "Stop", "Bytes"
+        )?;
+        for t in &self.tracks {
+            let mut outputs = t.outputs.iter();
+            let first = outputs.next();
+            write!(
+                f,
+                "{:>5}  {:12}{:>8}  {:>8}",
+                t.number, t.mode, t.start_sector, t.stop_sector
+            )?;
+            match first {
+                Some(output) => writeln!(f, "  {:>12}  {}", size(output), output.filename)?,
+                // Written into the merged file
+                None => writeln!(f, "  {:>12}  -", "")?,
+            }
+            for output in outputs {
+                writeln!(f, "{:37}  {:>12}  {}", "", size(output), output.filename)?;
+            }
+        }
+        for output in &self.outputs {
+            writeln!(f, "{:37}  {:>12}  {}", "", size(output), output.filename)?;
+        }
+        if let Some(ids) = &self.disc_ids {
+            writeln!(f, "FreeDB {}  MusicBrainz {}", ids.freedb, ids.musicbrainz)?;
+        }
+        if let Some(serial) = &self.serial {
+            writeln!(f, "Serial {}", serial)?;
+        }
+        Ok(())
+    }
+}
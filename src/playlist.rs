@@ -0,0 +1,79 @@
+//! Multi-disc sets converted in one go and listed in an `.m3u` playlist
+
+use std::io;
+use std::path::Path;
+
+use crate::error::ErrorCode;
+use crate::log::info;
+use crate::longpath::file_name;
+use crate::output::{check_not_input, roll_back, write_file, OutputFile};
+use crate::{extract, naming, open_disc, reopen, validate, ConvertOptions};
+
+/// Converts every disc of a set and writes a playlist of their CUE sheets
+///
+/// Discs are listed in the order of their "(Disc N)" tags (or `ConvertOptions::disc`),
+/// untagged ones after them in the order given, so emulators like RetroArch and DuckStation
+/// can swap between them. The playlist is named after the set and written next to the first
+/// disc. If any disc fails, the files of the whole set are removed again. Tagged images are
+/// named like their converted discs, so sets are converted into another
+/// `ConvertOptions::output_dir`.
+pub fn convert_set<O: Into<ConvertOptions>>(discs: Vec<O>) -> io::Result<OutputFile> {
+    let mut discs: Vec<ConvertOptions> = discs
+        .into_iter()
+        .map(|options| {
+            let mut options = options.into();
+            // The playlist can only point at discs with a CUE sheet of their own
+            options.export_cue = true;
+            ConvertOptions::new(options)
+        })
+        .collect();
+    discs.sort_by_key(|a| a.disc.unwrap_or(u32::MAX));
+    if discs.is_empty() {
+        return Err(ErrorCode::InvalidArguments.error("No discs to convert"));
+    }
+
+    let mut written = Vec::new();
+    let result = convert_discs(&mut discs, &mut written);
+    if result.is_err() {
+        roll_back(&written, &discs[0]);
+    }
+    result
+}
+
+fn convert_discs(
+    discs: &mut [ConvertOptions],
+    written: &mut Vec<OutputFile>,
+) -> io::Result<OutputFile> {
+    for args in discs.iter() {
+        validate(args)?;
+        check_not_input(&format!("{}.cue", args.output_name), &args.cue_file)?;
+    }
+
+    let first = Path::new(&discs[0].output_name);
+    let set_name = naming::set_name(file_name(&discs[0].output_name));
+    let filename = first
+        .with_file_name(format!("{}.m3u", set_name))
+        .to_string_lossy()
+        .into_owned();
+    let directory = first.parent().map(Path::to_path_buf);
+
+    let mut playlist = String::new();
+    for args in discs.iter_mut() {
+        let mut disc = open_disc(args)?;
+        extract(disc.as_mut(), args, written, Some(&reopen(args)))?;
+
+        // Entries are relative to the playlist when the disc is next to it
+        let cue = format!("{}.cue", args.output_name);
+        match Path::new(&cue).parent().map(Path::to_path_buf) == directory {
+            true => playlist += file_name(&cue),
+            false => playlist += &cue,
+        }
+        playlist += "\n";
+    }
+
+    let output = write_file(filename, &playlist, &discs[0])?;
+    if discs[0].verbose {
+        info!("m3u: {}", output.filename);
+    }
+    Ok(output)
+}
@@ -0,0 +1,23 @@
+//! Reports on how far a conversion has come, for frontends to draw progress bars from
+
+use std::sync::Arc;
+
+/// Sectors of a track written so far
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub track: u32,
+    pub sectors_done: u64,
+    pub sectors_total: u64,
+}
+
+impl Progress {
+    pub fn is_done(&self) -> bool {
+        self.sectors_done == self.sectors_total
+    }
+}
+
+/// Called while tracks are written, at least once when every track is started and finished
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+// Sectors between reports, a second of CD audio
+pub(crate) const INTERVAL: u64 = 75;
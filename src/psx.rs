@@ -0,0 +1,107 @@
+//! PlayStation discs, told by the license Sony put on them, and the serial of their game
+//!
+//! The serial, like `SLUS-01234`, names the executable `SYSTEM.CNF` boots, which is what
+//! ROM managers and emulators tell games apart by. It's read from the ISO9660 file system of
+//! the first track, so images without one, or with a game booting `PSX.EXE`, have none.
+
+use std::io;
+
+use crate::disc::{Disc, Retagged};
+use crate::iso9660::{directory, le32, read_block, read_extent, BLOCK_SIZE};
+use crate::log::info;
+use crate::track::Track;
+use crate::ConvertOptions;
+
+/// Start of the text of the license in sector 4 of every pressed disc
+const LICENSE: &[u8] = b"Sony Computer Entertainment";
+
+/// System identifier of the primary volume descriptor
+const SYSTEM: &[u8] = b"PLAYSTATION";
+
+/// Blocks of a directory or `SYSTEM.CNF` read at most, both are a block or two long
+const MAX_BLOCKS: u64 = 16;
+
+/// `disc` with the serial of its game in the metadata, if it's a PlayStation disc
+pub(crate) fn identify(disc: Box<dyn Disc>, a: &ConvertOptions) -> io::Result<Box<dyn Disc>> {
+    let track = match disc.tracks().first() {
+        Some(t) if !t.is_audio() => t.clone(),
+        _ => return Ok(disc),
+    };
+    let mut disc = disc;
+    let serial = match read_serial(disc.as_mut(), &track, a)? {
+        Some(serial) => serial,
+        None => return Ok(disc),
+    };
+    if a.verbose {
+        info!("PlayStation game {}", serial);
+    }
+    let mut metadata = disc.metadata().clone();
+    metadata.serial = Some(serial);
+    let tracks = disc.tracks().to_vec();
+    Ok(Box::new(Retagged {
+        disc,
+        tracks,
+        metadata,
+    }))
+}
+
+/// Serial of the game the first `track` of a PlayStation disc boots
+fn read_serial(
+    disc: &mut dyn Disc,
+    track: &Track,
+    a: &ConvertOptions,
+) -> io::Result<Option<String>> {
+    let mut license = [0u8; BLOCK_SIZE];
+    let mut descriptor = [0u8; BLOCK_SIZE];
+    if !read_block(disc, track, 4, &mut license)? || !read_block(disc, track, 16, &mut descriptor)?
+    {
+        return Ok(None);
+    }
+    let licensed = license.windows(LICENSE.len()).any(|w| w == LICENSE);
+    let volume = descriptor[0] == 1 && &descriptor[1..6] == b"CD001";
+    if !volume || !(licensed || descriptor[8..].starts_with(SYSTEM)) {
+        return Ok(None);
+    }
+
+    // The record of the root directory is part of the volume descriptor
+    let root = &descriptor[156..];
+    let size = le32(&root[10..]).min(MAX_BLOCKS * BLOCK_SIZE as u64);
+    let config = directory(disc, track, le32(&root[2..]), size)?
+        .into_iter()
+        .find(|(name, _, _, _)| name.eq_ignore_ascii_case("SYSTEM.CNF"));
+    let (extent, size) = match config {
+        Some((_, extent, size, _)) => (extent, size.min(MAX_BLOCKS * BLOCK_SIZE as u64)),
+        None => {
+            if a.verbose {
+                info!("PlayStation disc without a SYSTEM.CNF to tell its game by");
+            }
+            return Ok(None);
+        }
+    };
+    let mut text = Vec::new();
+    read_extent(disc, track, extent, size, |data| {
+        text.extend_from_slice(data);
+        Ok(())
+    })?;
+    Ok(String::from_utf8_lossy(&text)
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(key, _)| ["BOOT", "BOOT2"].contains(&key.trim()))
+        .find_map(|(_, path)| serial(path)))
+}
+
+/// Serial in the name of the executable at `path`, `cdrom:\SLUS_012.34;1` being `SLUS-01234`
+fn serial(path: &str) -> Option<String> {
+    let name = path.trim().rsplit(['\\', '/', ':']).next()?;
+    let name = name.split(';').next()?;
+    let (prefix, number) = name.split_at(name.find(['_', '-'])?);
+    let digits: String = number
+        .chars()
+        .filter(|&c| c != '_' && c != '-' && c != '.')
+        .collect();
+    let valid = prefix.len() == 4
+        && prefix.chars().all(|c| c.is_ascii_alphabetic())
+        && digits.len() == 5
+        && digits.chars().all(|c| c.is_ascii_digit());
+    valid.then(|| format!("{}-{}", prefix.to_ascii_uppercase(), digits))
+}
@@ -0,0 +1,235 @@
+//! Streaming a single track the way a conversion would write it, without any output file
+
+use std::io;
+use std::io::Read;
+use std::ops::Range;
+
+use crate::audio::{Amplifier, Deemphasis, Resampler};
+use crate::disc::Disc;
+use crate::error::ErrorCode;
+use crate::track::Track;
+use crate::verify::SectorCheck;
+use crate::{prepare_tracks, validate, ConvertOptions, SECTOR_SIZE};
+
+/// What the output of a track is made of, in order
+enum Part {
+    /// Bytes that aren't read from the disc, like the WAV header
+    Bytes(Vec<u8>),
+    /// Zeroes of gaps of silence
+    Silence(u64),
+    /// Sectors read from the disc, of which the track's block is passed on
+    Sectors(Range<u64>),
+}
+
+/// Bytes of the output `convert` would write for one track, read from the disc on demand
+///
+/// Data tracks come without their sync and headers like the `.iso`, audio tracks without
+/// their pre-emphasis, amplified by `ConvertOptions::gain`, resampled to
+/// `ConvertOptions::resample`, with their bytes swapped for `ConvertOptions::swap_audo_bytes`
+/// and a WAV header with `ConvertOptions::to_wav`, along with the gaps and pregaps the
+/// options ask for. Audio encoded by an external encoder can't be streamed, and ECM
+/// compression is left out.
+pub struct TrackReader<'a> {
+    disc: &'a mut dyn Disc,
+    track: Track,
+    swap: bool,
+    deemphasis: Option<Deemphasis>,
+    amplifier: Option<Amplifier>,
+    resampler: Option<Resampler>,
+    /// Resampled audio not passed on yet
+    resampled: Vec<u8>,
+    parts: Vec<Part>,
+    /// Sector read last and how much of its block was passed on
+    sector: [u8; SECTOR_SIZE as usize],
+    sector_at: usize,
+    check: Option<SectorCheck>,
+    size: u64,
+}
+
+impl<'a> TrackReader<'a> {
+    /// Reader of track `number` of `disc`, converted following `options`
+    pub fn new(
+        disc: &'a mut dyn Disc,
+        number: u32,
+        options: &ConvertOptions,
+    ) -> io::Result<TrackReader<'a>> {
+        validate(options)?;
+        let tracks = prepare_tracks(disc, options)?;
+        let track = match tracks.into_iter().find(|t| t.number() == number) {
+            Some(track) => track,
+            None => {
+                return Err(ErrorCode::InvalidArguments
+                    .error(format!("There is no track {} to read", number)))
+            }
+        };
+        if track.split_index > 0 {
+            return Err(ErrorCode::Unsupported.error(format!(
+                "Track {} is split into a file per index, it can't be read as one",
+                number
+            )));
+        }
+        if track.is_encoded() {
+            return Err(ErrorCode::Unsupported
+                .error("Encoders write their files themselves, they can't be read from"));
+        }
+
+        #[allow(unused_mut)]
+        let mut header = Vec::new();
+        #[cfg(feature = "wav")]
+        if options.to_wav && track.is_audio() {
+            header = track.wav_header(disc.metadata(), options);
+        }
+        let size = header.len() as u64 + track.output_size(options);
+        let mut parts = vec![Part::Bytes(header)];
+        let (gap_before, gap_after) = track.gaps();
+        let block_size = track.block_size();
+        // Stored pregaps come before the gaps of silence, like `write_to_file` writes them
+        parts.push(Part::Sectors(
+            track.first_sector(options)..track.start_sector() - track.pregap(),
+        ));
+        parts.push(Part::Silence(gap_before * block_size));
+        parts.push(Part::Sectors(track.start_sector()..track.stop_sector() + 1));
+        parts.push(Part::Silence(gap_after * block_size));
+        // Taken from the front as they're read
+        parts.reverse();
+
+        let amplifier = Amplifier::new(disc, &track, options)?;
+        Ok(TrackReader {
+            disc,
+            swap: track.is_audio() && options.swap_audo_bytes,
+            deemphasis: Deemphasis::new(&track, options),
+            amplifier,
+            resampler: Resampler::new(&track, options),
+            resampled: Vec::new(),
+            check: track.verifies(options).then(|| SectorCheck::new(number)),
+            track,
+            parts,
+            sector: [0; SECTOR_SIZE as usize],
+            sector_at: SECTOR_SIZE as usize,
+            size,
+        })
+    }
+
+    /// Bytes the whole track takes, as the output file would
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Reads the sector at `lba` into the buffer, as it goes into the output
+    fn next_sector(&mut self, lba: u64) -> io::Result<()> {
+        if let Err(e) = self.disc.read_sector(lba, &mut self.sector) {
+            return Err(ErrorCode::ReadFailed.wrap(
+                &e,
+                format!("Could not read track {}: {}", self.track.number(), e),
+            ));
+        }
+        if let Some(check) = &mut self.check {
+            check.check(lba, &self.sector);
+        }
+        if let Some(deemphasis) = &mut self.deemphasis {
+            deemphasis.apply(&mut self.sector);
+        }
+        if let Some(amplifier) = &mut self.amplifier {
+            amplifier.apply(&mut self.sector);
+        }
+        let block = self.track.block();
+        match &mut self.resampler {
+            Some(resampler) => {
+                queue(
+                    &mut self.resampled,
+                    resampler.push(&self.sector[block.clone()]),
+                    self.swap,
+                );
+                self.sector_at = block.end;
+            }
+            None => {
+                if self.swap {
+                    swap_bytes(&mut self.sector);
+                }
+                self.sector_at = block.start;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Appends resampled `samples` to `resampled`, with their bytes swapped if asked
+fn queue(resampled: &mut Vec<u8>, samples: &mut [u8], swap: bool) {
+    if swap {
+        swap_bytes(samples);
+    }
+    resampled.extend_from_slice(samples);
+}
+
+fn swap_bytes(samples: &mut [u8]) {
+    for pair in samples.chunks_exact_mut(2) {
+        pair.swap(0, 1);
+    }
+}
+
+impl Read for TrackReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let block = self.track.block();
+        // What's left of the sector read last goes first
+        if self.sector_at < block.end {
+            let length = buf.len().min(block.end - self.sector_at);
+            buf[..length].copy_from_slice(&self.sector[self.sector_at..self.sector_at + length]);
+            self.sector_at += length;
+            return Ok(length);
+        }
+        if !self.resampled.is_empty() {
+            let length = buf.len().min(self.resampled.len());
+            buf[..length].copy_from_slice(&self.resampled[..length]);
+            self.resampled.drain(..length);
+            return Ok(length);
+        }
+        loop {
+            let part = match self.parts.last_mut() {
+                Some(part) => part,
+                None => {
+                    // Audio still ringing at the end of the resampled track
+                    if let Some(mut resampler) = self.resampler.take() {
+                        queue(&mut self.resampled, resampler.finish(), self.swap);
+                        return self.read(buf);
+                    }
+                    // Problems are only known once every sector has been checked
+                    if let Some(check) = self.check.take() {
+                        check.finish()?;
+                    }
+                    return Ok(0);
+                }
+            };
+            match part {
+                Part::Bytes(bytes) if !bytes.is_empty() => {
+                    let length = buf.len().min(bytes.len());
+                    buf[..length].copy_from_slice(&bytes[..length]);
+                    bytes.drain(..length);
+                    return Ok(length);
+                }
+                Part::Silence(remaining) if *remaining > 0 && self.resampler.is_some() => {
+                    // Silence goes through the resampler in sectors, like the audio around it
+                    *remaining -= SECTOR_SIZE;
+                    let resampler = self.resampler.as_mut().unwrap();
+                    let samples = resampler.push(&[0; SECTOR_SIZE as usize]);
+                    queue(&mut self.resampled, samples, self.swap);
+                    return self.read(buf);
+                }
+                Part::Silence(remaining) if *remaining > 0 => {
+                    let length = (buf.len() as u64).min(*remaining) as usize;
+                    buf[..length].fill(0);
+                    *remaining -= length as u64;
+                    return Ok(length);
+                }
+                Part::Sectors(lbas) if !lbas.is_empty() => {
+                    let lba = lbas.start;
+                    lbas.start += 1;
+                    self.next_sector(lba)?;
+                    return self.read(buf);
+                }
+                _ => {
+                    self.parts.pop();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::cue::CueDisc;
+use crate::disc::Disc;
+use crate::log::info;
+use crate::ConvertOptions;
+use crate::{psx, vcd};
+
+/// An image format the auto-detector can recognize and open as a `Disc`
+pub trait DiscFormat: Send + Sync {
+    /// Short name of the format, e.g. "CUE/BIN"
+    fn name(&self) -> &'static str;
+
+    /// Whether the file at `path` looks like an image of this format
+    fn probe(&self, path: &Path) -> bool;
+
+    /// Opens the image named by `args.cue_file`
+    fn open(&self, args: &mut ConvertOptions) -> io::Result<Box<dyn Disc>>;
+}
+
+struct CueFormat;
+
+impl DiscFormat for CueFormat {
+    fn name(&self) -> &'static str {
+        CueDisc::FORMAT
+    }
+
+    fn probe(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("cue"))
+    }
+
+    fn open(&self, args: &mut ConvertOptions) -> io::Result<Box<dyn Disc>> {
+        Ok(Box::new(CueDisc::open(args)?))
+    }
+}
+
+// Formats registered by downstream crates, probed before the built-in ones
+static REGISTERED: Mutex<Vec<Arc<dyn DiscFormat>>> = Mutex::new(Vec::new());
+
+fn builtin() -> Vec<Arc<dyn DiscFormat>> {
+    #[allow(unused_mut)]
+    let mut formats: Vec<Arc<dyn DiscFormat>> = vec![
+        Arc::new(CueFormat),
+        Arc::new(crate::mds::MdsFormat),
+        Arc::new(crate::nrg::NrgFormat),
+        Arc::new(crate::cdi::CdiFormat),
+    ];
+    #[cfg(feature = "chd")]
+    formats.push(Arc::new(crate::chd::ChdFormat));
+    formats
+}
+
+/// Makes an additional image format available to the auto-detector
+pub fn register_format(format: impl DiscFormat + 'static) {
+    REGISTERED.lock().unwrap().push(Arc::new(format));
+}
+
+/// Every format known to the auto-detector, in the order they are probed
+pub fn formats() -> Vec<Arc<dyn DiscFormat>> {
+    let mut formats = REGISTERED.lock().unwrap().clone();
+    formats.extend(builtin());
+    formats
+}
+
+/// Opens the image named by `args.cue_file` with the first format recognizing it
+///
+/// PlayStation discs get the serial of their game in the metadata. With
+/// `ConvertOptions::musicbrainz` the metadata of audio discs is completed from there.
+pub fn open_disc(args: &mut ConvertOptions) -> io::Result<Box<dyn Disc>> {
+    let formats = formats();
+    let path = Path::new(&args.cue_file);
+
+    let disc = match formats.iter().find(|f| f.probe(path)) {
+        Some(format) => {
+            if args.verbose {
+                info!("Reading {} as {}", args.cue_file, format.name());
+            }
+            format.open(args)?
+        }
+        // Anything unrecognized is treated as a CUE sheet, like it always has been
+        None => CueFormat.open(args)?,
+    };
+    let disc = psx::identify(disc, args)?;
+    let disc = vcd::identify(disc, args)?;
+    #[cfg(feature = "musicbrainz")]
+    if args.musicbrainz {
+        return Ok(crate::musicbrainz::look_up(disc, args));
+    }
+    Ok(disc)
+}
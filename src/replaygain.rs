@@ -0,0 +1,264 @@
+//! ReplayGain 2.0 analysis of audio tracks, so players can even out their loudness
+//!
+//! Loudness is measured as EBU R 128 does: the samples are K-weighted, their energy taken
+//! over 400 ms blocks overlapping by 300 ms, and blocks quieter than -70 LUFS or 10 LU below
+//! the average of the rest are gated out. The gain brings a track, or the whole album, to
+//! -18 LUFS.
+
+use std::io;
+
+use crate::audio::{Amplifier, Deemphasis, CD_RATE};
+use crate::disc::Disc;
+use crate::error::ErrorCode;
+use crate::log::info;
+use crate::longpath::file_name;
+use crate::output::{write_file, OutputFile};
+use crate::track::Track;
+use crate::{ConvertOptions, SECTOR_SIZE};
+
+/// Loudness in LUFS every track is brought to
+const REFERENCE: f64 = -18.0;
+
+/// Frames in the 100 ms steps the blocks move by
+const STEP: usize = CD_RATE as usize / 10;
+
+/// Gains bringing a track and its album to the reference loudness, with their peaks
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ReplayGain {
+    pub(crate) track_gain: f64,
+    /// Largest magnitude of the samples, relative to full scale
+    pub(crate) track_peak: f64,
+    pub(crate) album_gain: f64,
+    pub(crate) album_peak: f64,
+}
+
+impl ReplayGain {
+    /// `REPLAYGAIN_*` tags of the gains, as Vorbis comments and ID3 `TXXX` frames hold them
+    pub(crate) fn tags(&self) -> Vec<(String, String)> {
+        [
+            (
+                "REPLAYGAIN_TRACK_GAIN",
+                format!("{:+.2} dB", self.track_gain),
+            ),
+            ("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", self.track_peak)),
+            (
+                "REPLAYGAIN_ALBUM_GAIN",
+                format!("{:+.2} dB", self.album_gain),
+            ),
+            ("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", self.album_peak)),
+        ]
+        .into_iter()
+        .map(|(name, value)| (String::from(name), value))
+        .collect()
+    }
+}
+
+/// Biquad of the K-weighting, run over the left and right channel each
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    /// Last two inputs and outputs of each channel
+    history: [[f64; 4]; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Biquad {
+        Biquad {
+            b,
+            a,
+            history: [[0.0; 4]; 2],
+        }
+    }
+
+    fn filter(&mut self, channel: usize, x: f64) -> f64 {
+        let [x1, x2, y1, y2] = &mut self.history[channel];
+        let y =
+            self.b[0] * x + self.b[1] * *x1 + self.b[2] * *x2 - self.a[0] * *y1 - self.a[1] * *y2;
+        (*x2, *x1, *y2, *y1) = (*x1, x, *y1, y);
+        y
+    }
+}
+
+/// The two stages of the K-weighting of ITU-R BS.1770, a high shelf modelling the head and
+/// a high pass, designed for `rate` from the analog filters the 48 kHz coefficients of the
+/// standard were taken from
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let k = |corner: f64| (std::f64::consts::PI * corner / rate as f64).tan();
+
+    let (k1, q) = (k(1681.974450955533), 0.7071752369554196);
+    let high = 10f64.powf(3.999843853973347 / 20.0);
+    let band = high.powf(0.4996667741545416);
+    let a0 = 1.0 + k1 / q + k1 * k1;
+    let shelf = Biquad::new(
+        [
+            (high + band * k1 / q + k1 * k1) / a0,
+            2.0 * (k1 * k1 - high) / a0,
+            (high - band * k1 / q + k1 * k1) / a0,
+        ],
+        [2.0 * (k1 * k1 - 1.0) / a0, (1.0 - k1 / q + k1 * k1) / a0],
+    );
+
+    let (k2, q) = (k(38.13547087602444), 0.5003270373238773);
+    let a0 = 1.0 + k2 / q + k2 * k2;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k2 * k2 - 1.0) / a0, (1.0 - k2 / q + k2 * k2) / a0],
+    );
+    [shelf, high_pass]
+}
+
+/// Mean square energy of every 400 ms block of a track, and its peak
+struct Measurement {
+    blocks: Vec<f64>,
+    peak: f64,
+}
+
+/// Measures the samples written of `track`, after their de-emphasis and gain
+///
+/// Resampled tracks are measured at the rate of CD audio, which their loudness doesn't
+/// depend on.
+fn measure(disc: &mut dyn Disc, track: &Track, a: &ConvertOptions) -> io::Result<Measurement> {
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    let mut deemphasis = Deemphasis::new(track, a);
+    let mut amplifier = Amplifier::new(disc, track, a)?;
+    let [mut shelf, mut high_pass] = k_weighting(CD_RATE);
+    let mut steps = Vec::new();
+    let (mut energy, mut frames) = (0.0, 0);
+    let mut peak = 0;
+    for lba in track.first_sector(a)..=track.stop_sector() {
+        a.check_cancelled()?;
+        if let Err(e) = disc.read_sector(lba, &mut sector) {
+            return Err(ErrorCode::ReadFailed.wrap(
+                &e,
+                format!("Could not read track {}: {}", track.number(), e),
+            ));
+        }
+        if let Some(deemphasis) = &mut deemphasis {
+            deemphasis.apply(&mut sector);
+        }
+        if let Some(amplifier) = &mut amplifier {
+            amplifier.apply(&mut sector);
+        }
+        for frame in sector.chunks_exact(4) {
+            for (channel, sample) in frame.chunks_exact(2).enumerate() {
+                let sample = i16::from_le_bytes([sample[0], sample[1]]);
+                peak = peak.max(sample.unsigned_abs() as u32);
+                let x = sample as f64 / 32768.0;
+                let y = high_pass.filter(channel, shelf.filter(channel, x));
+                energy += y * y;
+            }
+            frames += 1;
+            if frames == STEP {
+                steps.push(energy);
+                (energy, frames) = (0.0, 0);
+            }
+        }
+    }
+    let blocks = steps
+        .windows(4)
+        .map(|block| block.iter().sum::<f64>() / (4 * STEP) as f64)
+        .collect();
+    Ok(Measurement {
+        blocks,
+        peak: peak as f64 / 32768.0,
+    })
+}
+
+/// Gated loudness of `blocks` in LUFS, `None` for silence
+fn loudness(blocks: &[f64]) -> Option<f64> {
+    let lufs = |energy: f64| -0.691 + 10.0 * energy.log10();
+    let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+    let audible: Vec<f64> = blocks
+        .iter()
+        .copied()
+        .filter(|&e| lufs(e) > -70.0)
+        .collect();
+    if audible.is_empty() {
+        return None;
+    }
+    let gate = lufs(mean(&audible)) - 10.0;
+    // The loudest block is never below the mean, so some are always left
+    let gated: Vec<f64> = audible.into_iter().filter(|&e| lufs(e) > gate).collect();
+    Some(lufs(mean(&gated)))
+}
+
+/// Works out the ReplayGain of the audio `tracks` written as WAV or encoded files, their
+/// album being all of them
+///
+/// Every one of those tracks is read once up front, and tracks too short or quiet to
+/// measure are left without.
+pub(crate) fn analyze(
+    disc: &mut dyn Disc,
+    tracks: &mut [Track],
+    a: &ConvertOptions,
+) -> io::Result<()> {
+    let mut measured = Vec::new();
+    for (n, t) in tracks.iter().enumerate() {
+        if t.played_back(a) {
+            measured.push((n, measure(disc, t, a)?));
+        }
+    }
+    let album: Vec<f64> = measured
+        .iter()
+        .flat_map(|(_, m)| m.blocks.iter().copied())
+        .collect();
+    let album_gain = match loudness(&album) {
+        Some(loudness) => REFERENCE - loudness,
+        None => return Ok(()),
+    };
+    let album_peak = measured.iter().map(|(_, m)| m.peak).fold(0.0, f64::max);
+    if a.verbose {
+        info!(
+            "ReplayGain of the album: {:+.2} dB, peak {:.6}",
+            album_gain, album_peak
+        );
+    }
+    for (n, m) in measured {
+        let t = &mut tracks[n];
+        if let Some(loudness) = loudness(&m.blocks) {
+            let gain = ReplayGain {
+                track_gain: REFERENCE - loudness,
+                track_peak: m.peak,
+                album_gain,
+                album_peak,
+            };
+            if a.verbose {
+                info!(
+                    "{}: ReplayGain {:+.2} dB, peak {:.6}",
+                    t.number, gain.track_gain, gain.track_peak
+                );
+            }
+            t.replay_gain = Some(gain);
+        }
+    }
+    Ok(())
+}
+
+/// Writes the gains of WAV files, which have no tags to hold them, to `<name>.replaygain.txt`
+///
+/// A tab separated table with a line per file, headed by the names of the tags.
+pub(crate) fn write_sidecar(
+    outputs: &[(OutputFile, ReplayGain)],
+    a: &ConvertOptions,
+) -> io::Result<OutputFile> {
+    let mut table = String::from("FILE");
+    for (name, _) in ReplayGain::default().tags() {
+        table += "\t";
+        table += &name;
+    }
+    table += "\n";
+    for (output, gain) in outputs {
+        table += file_name(&output.filename);
+        for (_, value) in gain.tags() {
+            table += "\t";
+            table += &value;
+        }
+        table += "\n";
+    }
+
+    let output = write_file(format!("{}.replaygain.txt", a.output_name), &table, a)?;
+    if a.verbose {
+        info!("replaygain: {}", output.filename);
+    }
+    Ok(output)
+}
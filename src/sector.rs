@@ -0,0 +1,253 @@
+//! Helpers for looking into the contents of raw 2352 byte sectors
+
+use std::io;
+
+use crate::disc::Disc;
+use crate::track::{Mode, Track};
+use crate::SECTOR_SIZE;
+
+/// Sync pattern starting every raw data sector
+pub(crate) const SYNC: [u8; 12] = [
+    0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00,
+];
+
+pub(crate) fn has_sync(sector: &[u8]) -> bool {
+    sector.len() >= SYNC.len() && sector[..SYNC.len()] == SYNC
+}
+
+/// Mode byte from the header of a raw data sector
+pub(crate) fn data_mode(sector: &[u8]) -> Option<u8> {
+    if has_sync(sector) && sector.len() > 15 {
+        Some(sector[15])
+    } else {
+        None
+    }
+}
+
+/// Mode a raw sector was written in by the look of it, `None` for blank ones, which could
+/// be in any
+pub(crate) fn raw_mode(sector: &[u8]) -> Option<Mode> {
+    match data_mode(sector) {
+        Some(1) => Some(Mode::Mode1_2352),
+        Some(2) => Some(Mode::Mode2_2352),
+        // Mode 0 sectors hold only zeroes
+        Some(_) => None,
+        None if sector.iter().all(|&b| b == 0) => None,
+        None => Some(Mode::Audio),
+    }
+}
+
+/// Bytes of subcode stored after each sector of a `CDG` track
+pub(crate) const SUBCODE_SIZE: usize = 96;
+
+// Form 2 bit of the subheader submode byte
+pub(crate) const FORM2: u8 = 0x20;
+
+fn bcd(n: u64) -> u8 {
+    (n / 10 * 16 + n % 10) as u8
+}
+
+fn from_bcd(b: u8) -> Option<u64> {
+    let (tens, ones) = (b >> 4, b & 0x0f);
+    (tens < 10 && ones < 10).then_some(tens as u64 * 10 + ones as u64)
+}
+
+/// Sector the header of a raw data sector addresses, `None` if it isn't a valid address
+pub(crate) fn header_lba(sector: &[u8]) -> Option<u64> {
+    let minutes = from_bcd(sector[12])?;
+    let seconds = from_bcd(sector[13])?;
+    let frames = from_bcd(sector[14])?;
+    if seconds >= 60 || frames >= 75 {
+        return None;
+    }
+    (minutes * 60 * 75 + seconds * 75 + frames).checked_sub(150)
+}
+
+/// Offset of the first sync pattern in `sector`, wherever it starts
+pub(crate) fn find_sync(sector: &[u8]) -> Option<usize> {
+    sector.windows(SYNC.len()).position(|w| w == SYNC)
+}
+
+/// Adds the sync pattern and headers around the user data of a cooked `mode` sector
+///
+/// EDC and ECC stay zeroed, this only makes cooked sectors look like raw ones.
+pub(crate) fn add_header(sector: &mut [u8], lba: u64, mode: Mode) {
+    // Sector addresses are counted from the 2 second lead-in
+    let address = lba + 150;
+    sector[..SYNC.len()].copy_from_slice(&SYNC);
+    sector[12] = bcd(address / 75 / 60);
+    sector[13] = bcd(address / 75 % 60);
+    sector[14] = bcd(address % 75);
+    sector[15] = match mode {
+        Mode::Mode1_2048 => 1,
+        _ => 2,
+    };
+    if mode == Mode::Mode2_2324 {
+        // Subheader, twice, with the submode marking a Form 2 sector
+        sector[18] = FORM2;
+        sector[22] = FORM2;
+    }
+}
+
+/// Volume size in blocks from the ISO9660 primary volume descriptor of a data track
+pub(crate) fn volume_blocks(disc: &mut dyn Disc, track: &Track) -> io::Result<Option<u64>> {
+    let user_data_offset = match track.mode() {
+        Mode::Mode1_2352 | Mode::Mode1_2048 => 16,
+        Mode::Mode2_2352 | Mode::Mode2_2336 => 24,
+        _ => return Ok(None),
+    };
+    if track.stop_sector() < track.start_sector() + 16 {
+        return Ok(None);
+    }
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+
+    disc.read_sector(track.start_sector() + 16, &mut sector)?;
+    let descriptor = &sector[user_data_offset..];
+    if descriptor[0] != 1 || &descriptor[1..6] != b"CD001" {
+        return Ok(None);
+    }
+    Ok(Some(u32::from_le_bytes([
+        descriptor[80],
+        descriptor[81],
+        descriptor[82],
+        descriptor[83],
+    ]) as u64))
+}
+
+/// Lookup tables of the EDC checksum and the Reed-Solomon product code of raw data sectors
+struct Tables {
+    ecc_f: [u8; 256],
+    ecc_b: [u8; 256],
+    edc: [u32; 256],
+}
+
+const TABLES: Tables = build_tables();
+
+const fn build_tables() -> Tables {
+    let mut tables = Tables {
+        ecc_f: [0; 256],
+        ecc_b: [0; 256],
+        edc: [0; 256],
+    };
+    let mut i = 0;
+    while i < 256 {
+        // Multiplication by 2 in GF(2^8) with the polynomial x^8 + x^4 + x^3 + x^2 + 1
+        let j = ((i << 1) ^ if i & 0x80 != 0 { 0x11d } else { 0 }) as u8;
+        tables.ecc_f[i] = j;
+        tables.ecc_b[i ^ j as usize] = i as u8;
+        let mut edc = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            edc = (edc >> 1) ^ if edc & 1 != 0 { 0xd801_8001 } else { 0 };
+            k += 1;
+        }
+        tables.edc[i] = edc;
+        i += 1;
+    }
+    tables
+}
+
+/// EDC checksum of `data`, continuing from a previous checksum `edc`
+pub(crate) fn edc(edc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(edc, |edc, &b| {
+        (edc >> 8) ^ TABLES.edc[((edc ^ b as u32) & 0xff) as usize]
+    })
+}
+
+/// Parity bytes of one of the P and Q codes over the header and data from byte 12 onwards
+fn ecc_block(
+    sector: &mut [u8],
+    major_count: usize,
+    minor_count: usize,
+    major_mult: usize,
+    minor_inc: usize,
+    dest: usize,
+) {
+    let size = major_count * minor_count;
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_mult + (major & 1);
+        let mut ecc_a = 0u8;
+        let mut ecc_b = 0u8;
+        for _ in 0..minor_count {
+            let temp = sector[12 + index];
+            index += minor_inc;
+            if index >= size {
+                index -= size;
+            }
+            ecc_a ^= temp;
+            ecc_b ^= temp;
+            ecc_a = TABLES.ecc_f[ecc_a as usize];
+        }
+        ecc_a = TABLES.ecc_b[(TABLES.ecc_f[ecc_a as usize] ^ ecc_b) as usize];
+        sector[dest + major] = ecc_a;
+        sector[dest + major + major_count] = ecc_a ^ ecc_b;
+    }
+}
+
+fn ecc(sector: &mut [u8], zero_address: bool) {
+    // Mode 2 ECC leaves the address out, so sectors can be moved without recomputing it
+    let address: [u8; 4] = sector[12..16].try_into().unwrap();
+    if zero_address {
+        sector[12..16].fill(0);
+    }
+    ecc_block(sector, 86, 24, 2, 86, 0x81c);
+    ecc_block(sector, 52, 43, 86, 88, 0x8c8);
+    sector[12..16].copy_from_slice(&address);
+}
+
+/// Layouts of the error detection and correction data of raw data sectors
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Layout {
+    Mode1,
+    Mode2Form1,
+    Mode2Form2,
+}
+
+/// Whether the EDC and ECC stored in a raw data sector match its header and data
+///
+/// Sectors without sync, like unstored gaps, have nothing to check, and Mode 2 Form 2
+/// sectors may leave their EDC out as zero.
+pub(crate) fn check_edc_ecc(sector: &[u8]) -> bool {
+    let (layout, edc) = match data_mode(sector) {
+        Some(1) => (Layout::Mode1, 0x810),
+        Some(2) if sector[18] & FORM2 == 0 => (Layout::Mode2Form1, 0x818),
+        Some(2) => (Layout::Mode2Form2, 0x92c),
+        _ => return true,
+    };
+    if layout == Layout::Mode2Form2 && sector[edc..edc + 4] == [0; 4] {
+        return true;
+    }
+    let mut expected = [0u8; SECTOR_SIZE as usize];
+    expected.copy_from_slice(&sector[..SECTOR_SIZE as usize]);
+    add_edc_ecc(&mut expected, layout);
+    // Mode 1 keeps 8 reserved bytes between the EDC and the ECC
+    let ecc = match layout {
+        Layout::Mode1 => 0x81c,
+        _ => edc + 4,
+    };
+    sector[edc..edc + 4] == expected[edc..edc + 4]
+        && sector[ecc..SECTOR_SIZE as usize] == expected[ecc..]
+}
+
+/// Fills in the EDC, and ECC if `layout` has any, from the header, subheader and user data
+/// already in a raw data sector
+pub(crate) fn add_edc_ecc(sector: &mut [u8], layout: Layout) {
+    match layout {
+        Layout::Mode1 => {
+            let checksum = edc(0, &sector[..0x810]);
+            sector[0x810..0x814].copy_from_slice(&checksum.to_le_bytes());
+            sector[0x814..0x81c].fill(0);
+            ecc(sector, false);
+        }
+        Layout::Mode2Form1 => {
+            let checksum = edc(0, &sector[16..0x818]);
+            sector[0x818..0x81c].copy_from_slice(&checksum.to_le_bytes());
+            ecc(sector, true);
+        }
+        // Form 2 trades the ECC for more user data
+        Layout::Mode2Form2 => {
+            let checksum = edc(0, &sector[16..0x92c]);
+            sector[0x92c..0x930].copy_from_slice(&checksum.to_le_bytes());
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Files a CUE sheet takes its sectors from, read back to back as if they were one BIN file
+//!
+//! `WAVE` files only contribute the PCM in their `data` chunk and `MP3` files are decoded to
+//! WAVE by `lame` first, so audio ripped to either is read like raw audio sectors.
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::ecm::BinFile;
+use crate::error::ErrorCode;
+
+/// Source of BIN data that isn't necessarily a file
+pub(crate) trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Type of a `FILE` entry, the word following its name
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FileType {
+    Binary,
+    Wave,
+    Mp3,
+    Aiff,
+}
+
+impl FileType {
+    pub(crate) fn parse(word: Option<&str>) -> FileType {
+        match word.map(str::to_ascii_uppercase).as_deref() {
+            Some("WAVE") => FileType::Wave,
+            Some("MP3") => FileType::Mp3,
+            Some("AIFF") => FileType::Aiff,
+            // Including MOTOROLA, whose byte order is left to `-s`
+            _ => FileType::Binary,
+        }
+    }
+}
+
+/// One of the files, with where its data lies in it and in the whole
+struct Source {
+    reader: BinFile,
+    /// Where the data starts in the file, past the header of WAVE files
+    offset: u64,
+    len: u64,
+    /// Where the data starts among all files, each padded to a whole number of sectors
+    start: u64,
+    padded_len: u64,
+    /// Position of `reader` relative to `offset`, saving seeks while reading sequentially
+    position: u64,
+}
+
+/// Sectors of every `FILE` of a CUE sheet in order
+pub(crate) struct Sources {
+    files: Vec<Source>,
+    position: u64,
+    /// Capacity of the buffers ECM files are decoded through
+    buffer_size: usize,
+    // Declared last, so decoded files are closed before they're removed
+    #[cfg(feature = "mp3")]
+    _directory: Option<crate::temp::TemporaryDirectory>,
+}
+
+impl Sources {
+    pub(crate) fn new(buffer_size: usize) -> Sources {
+        Sources {
+            files: Vec::new(),
+            position: 0,
+            buffer_size,
+            #[cfg(feature = "mp3")]
+            _directory: None,
+        }
+    }
+
+    /// Opens the file at `path` and appends its data
+    ///
+    /// The data before it is padded with zeroes to whole sectors of `sector_size` first, so
+    /// a track ending partway through a sector doesn't move the start of the next file.
+    pub(crate) fn push(
+        &mut self,
+        path: &Path,
+        file_type: FileType,
+        sector_size: u64,
+    ) -> io::Result<()> {
+        let (reader, offset, len) = match file_type {
+            FileType::Binary => {
+                let mut reader = BinFile::open(path, self.buffer_size)?;
+                let len = reader.len()?;
+                (reader, 0, len)
+            }
+            FileType::Wave => open_wave(path)?,
+            FileType::Mp3 => self.decode_mp3(path)?,
+            FileType::Aiff => {
+                return Err(ErrorCode::Unsupported.error(format!(
+                    "AIFF file {} isn't supported, convert it to WAVE first",
+                    path.display()
+                )))
+            }
+        };
+        self.append(reader, offset, len, sector_size);
+        Ok(())
+    }
+
+    /// Appends the BIN data `name` read from `reader` rather than a file
+    pub(crate) fn push_reader(
+        &mut self,
+        reader: Box<dyn ReadSeek>,
+        name: &str,
+        sector_size: u64,
+    ) -> io::Result<()> {
+        let mut reader = BinFile::from_reader(reader, name, self.buffer_size)?;
+        let len = reader.len()?;
+        self.append(reader, 0, len, sector_size);
+        Ok(())
+    }
+
+    fn append(&mut self, reader: BinFile, offset: u64, len: u64, sector_size: u64) {
+        let start = match self.files.last_mut() {
+            Some(last) => {
+                last.padded_len = last.len.div_ceil(sector_size) * sector_size;
+                last.start + last.padded_len
+            }
+            None => 0,
+        };
+        self.files.push(Source {
+            reader,
+            offset,
+            len,
+            start,
+            // Trailing bytes of the last file are kept as they are, they may be surplus
+            padded_len: len,
+            position: u64::MAX,
+        });
+    }
+
+    /// Where the data of every file starts, padded like it's read
+    pub(crate) fn starts(&self) -> Vec<u64> {
+        self.files.iter().map(|f| f.start).collect()
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.files.last().map_or(0, |f| f.start + f.padded_len)
+    }
+
+    /// Copies `len` bytes from `position` straight into `output`, if they lie in a plain file
+    ///
+    /// Returns false without copying anything for data that has to be read, like padding,
+    /// decoded audio or ECM. Between files, `io::copy` has the kernel do the copying.
+    pub(crate) fn copy_to(
+        &mut self,
+        position: u64,
+        len: u64,
+        output: &mut File,
+    ) -> io::Result<bool> {
+        let file = match self
+            .files
+            .iter_mut()
+            .find(|f| position < f.start + f.padded_len)
+        {
+            Some(file) => file,
+            None => return Ok(false),
+        };
+        let relative = position - file.start;
+        let reader = match &mut file.reader {
+            BinFile::Plain(reader) if relative + len <= file.len => reader,
+            _ => return Ok(false),
+        };
+        reader.seek(SeekFrom::Start(file.offset + relative))?;
+        file.position = u64::MAX;
+        let copied = io::copy(&mut Read::take(&*reader, len), output)?;
+        if copied < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file ended while copying from it",
+            ));
+        }
+        file.position = relative + len;
+        Ok(true)
+    }
+
+    #[cfg(feature = "mp3")]
+    fn decode_mp3(&mut self, path: &Path) -> io::Result<(BinFile, u64, u64)> {
+        use std::process::{Command, Stdio};
+
+        let directory = match &self._directory {
+            Some(directory) => directory,
+            None => self
+                ._directory
+                .insert(crate::temp::TemporaryDirectory::create()?),
+        };
+        let wave = directory.0.join(format!("{}.wav", self.files.len()));
+        let status = Command::new("lame")
+            .args(["--quiet", "--decode"])
+            .arg(path)
+            .arg(&wave)
+            .stdout(Stdio::null())
+            .status();
+        match status {
+            Ok(status) if status.success() => open_wave(&wave),
+            Ok(status) => Err(ErrorCode::ReadFailed.error(format!(
+                "lame could not decode {}: {}",
+                path.display(),
+                status
+            ))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(ErrorCode::Unsupported
+                .error("lame is needed to decode MP3 files but could not be found")),
+            Err(e) => Err(e),
+        }
+    }
+
+    #[cfg(not(feature = "mp3"))]
+    fn decode_mp3(&mut self, path: &Path) -> io::Result<(BinFile, u64, u64)> {
+        Err(ErrorCode::Unsupported.error(format!(
+            "MP3 file {} can't be decoded, MP3 is not supported in this build",
+            path.display()
+        )))
+    }
+}
+
+/// Opens a WAVE file holding CD audio, with the location of its PCM data
+fn open_wave(path: &Path) -> io::Result<(BinFile, u64, u64)> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let malformed =
+        |problem: &str| ErrorCode::CueMalformed.error(format!("{}: {}", path.display(), problem));
+
+    let mut riff = [0u8; 12];
+    if file.read_exact(&mut riff).is_err() || &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return Err(malformed("not a WAVE file"));
+    }
+    let mut offset = riff.len() as u64;
+    let mut format = false;
+    loop {
+        let mut header = [0u8; 8];
+        if file.read_exact(&mut header).is_err() {
+            return Err(malformed("WAVE file has no data chunk"));
+        }
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as u64;
+        offset += header.len() as u64;
+        match &header[..4] {
+            b"fmt " => {
+                let mut fmt = [0u8; 16];
+                if len < fmt.len() as u64 || file.read_exact(&mut fmt).is_err() {
+                    return Err(malformed("WAVE format chunk is too short"));
+                }
+                // PCM, either plainly or as the extensible format
+                let tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                let rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                if ![0x0001, 0xfffe].contains(&tag) || (channels, rate, bits) != (2, 44100, 16) {
+                    return Err(ErrorCode::Unsupported.error(format!(
+                        "{}: {} bit PCM with {} channels at {} Hz isn't CD audio",
+                        path.display(),
+                        bits,
+                        channels,
+                        rate
+                    )));
+                }
+                format = true;
+            }
+            // Streamed files may not know their length, so the data runs to the end
+            b"data" if format => {
+                let len = len.min(size.saturating_sub(offset));
+                file.rewind()?;
+                return Ok((BinFile::Plain(file), offset, len));
+            }
+            b"data" => return Err(malformed("WAVE data chunk comes before its format")),
+            _ => {}
+        }
+        // Chunks are padded to an even number of bytes
+        offset += len + len % 2;
+        file.seek(SeekFrom::Start(offset))?;
+    }
+}
+
+impl Read for Sources {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let position = self.position;
+        let file = match self
+            .files
+            .iter_mut()
+            .find(|f| position < f.start + f.padded_len)
+        {
+            Some(file) => file,
+            None => return Ok(0),
+        };
+        let relative = position - file.start;
+        let n = match relative < file.len {
+            true => {
+                if file.position != relative {
+                    file.reader.seek(SeekFrom::Start(file.offset + relative))?;
+                }
+                let available = (file.len - relative).min(buf.len() as u64) as usize;
+                let n = file.reader.read(&mut buf[..available])?;
+                file.position = relative + n as u64;
+                n
+            }
+            // Padding up to the next sector
+            false => {
+                let n = (file.padded_len - relative).min(buf.len() as u64) as usize;
+                buf[..n].fill(0);
+                n
+            }
+        };
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Sources {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.len().checked_add_signed(delta),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            )),
+        }
+    }
+}
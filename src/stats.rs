@@ -0,0 +1,93 @@
+//! Bytes a conversion read and wrote and how long it took, to compare storage backends and
+//! buffer sizes by
+
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Bytes one track read from the image and wrote to its outputs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackStats {
+    pub number: u32,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Wall time writing the track took, in seconds
+    pub seconds: f64,
+}
+
+/// Bytes a conversion read and wrote, for every track written and overall
+///
+/// Tracks written at the same time by several threads overlap, so their seconds add up to
+/// more than the conversion took.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Tracks written, without those that were complete already
+    pub tracks: Vec<TrackStats>,
+    pub bytes_read: u64,
+    /// Bytes of every file written, CUE sheets, dats and the like included
+    pub bytes_written: u64,
+    /// Wall time of the whole conversion, in seconds
+    pub seconds: f64,
+}
+
+/// MiB per second moved in `seconds`, 0 for no time at all
+fn throughput(bytes: u64, seconds: f64) -> f64 {
+    match seconds > 0.0 {
+        true => bytes as f64 / 1024.0 / 1024.0 / seconds,
+        false => 0.0,
+    }
+}
+
+impl TrackStats {
+    pub(crate) fn new(number: u32, bytes_read: u64, bytes_written: u64, time: Duration) -> Self {
+        TrackStats {
+            number,
+            bytes_read,
+            bytes_written,
+            seconds: time.as_secs_f64(),
+        }
+    }
+
+    /// MiB read from the image per second
+    pub fn throughput(&self) -> f64 {
+        throughput(self.bytes_read, self.seconds)
+    }
+}
+
+impl Stats {
+    /// MiB read from the image per second, over the whole conversion
+    pub fn throughput(&self) -> f64 {
+        throughput(self.bytes_read, self.seconds)
+    }
+}
+
+impl Display for TrackStats {
+    /// One line like `Track  1: read 700.0 MiB, wrote 700.0 MiB in 2.50 s, 280.0 MiB/s`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Track {:>2}: read {:.1} MiB, wrote {:.1} MiB in {:.2} s, {:.1} MiB/s",
+            self.number,
+            self.bytes_read as f64 / 1024.0 / 1024.0,
+            self.bytes_written as f64 / 1024.0 / 1024.0,
+            self.seconds,
+            self.throughput()
+        )
+    }
+}
+
+impl Display for Stats {
+    /// A line per track and one for the whole conversion
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for t in &self.tracks {
+            writeln!(f, "{}", t)?;
+        }
+        writeln!(
+            f,
+            "Total:     read {:.1} MiB, wrote {:.1} MiB in {:.2} s, {:.1} MiB/s",
+            self.bytes_read as f64 / 1024.0 / 1024.0,
+            self.bytes_written as f64 / 1024.0 / 1024.0,
+            self.seconds,
+            self.throughput()
+        )
+    }
+}
@@ -0,0 +1,63 @@
+//! Tags of audio outputs, from the CD-TEXT and `REM` lines of their track and disc
+//!
+//! They're gathered under the names of Vorbis comments, which Ogg Vorbis and FLAC files
+//! take as they are, and mapped to ID3v2 frames for MP3 and to the `INFO` list of WAV.
+
+use crate::disc::DiscMetadata;
+use crate::track::Track;
+
+/// Tags of track `t` from the CD-TEXT and `REM` lines of it and its disc, named like Vorbis
+/// comments
+///
+/// The track's own entries take precedence, and `REM` lines that aren't known, like the
+/// `REPLAYGAIN_ALBUM_GAIN` of EAC, become tags of the same name. The ReplayGain worked out
+/// for the track replaces the one of the sheet.
+pub(crate) fn comments(t: &Track, metadata: &DiscMetadata) -> Vec<(String, String)> {
+    let (track, disc) = (&t.cd_text, &metadata.cd_text);
+    let (track_rem, disc_rem) = (&t.rem, &metadata.rem);
+    let tags = [
+        ("TITLE", track.title.as_ref()),
+        (
+            "ARTIST",
+            track.performer.as_ref().or(disc.performer.as_ref()),
+        ),
+        ("ALBUM", disc.title.as_ref()),
+        ("ALBUMARTIST", disc.performer.as_ref()),
+        (
+            "COMPOSER",
+            track.songwriter.as_ref().or(disc.songwriter.as_ref()),
+        ),
+        ("ISRC", t.isrc.as_ref()),
+        (
+            "GENRE",
+            track_rem.genre.as_ref().or(disc_rem.genre.as_ref()),
+        ),
+        ("DATE", track_rem.date.as_ref().or(disc_rem.date.as_ref())),
+        (
+            "COMMENT",
+            track_rem.comment.as_ref().or(disc_rem.comment.as_ref()),
+        ),
+        ("DISCID", disc_rem.disc_id.as_ref()),
+    ];
+    let mut comments: Vec<(String, String)> = tags
+        .into_iter()
+        .filter_map(|(name, value)| Some((String::from(name), value?.clone())))
+        .collect();
+    // Right after the title, if there is one
+    let after_title = track.title.is_some() as usize;
+    comments.insert(
+        after_title,
+        (String::from("TRACKNUMBER"), t.number.to_string()),
+    );
+    let track_keys: Vec<&String> = track_rem.other.iter().map(|(key, _)| key).collect();
+    let disc_other = disc_rem
+        .other
+        .iter()
+        .filter(|(key, _)| !track_keys.contains(&key));
+    comments.extend(disc_other.chain(&track_rem.other).cloned());
+    if let Some(gain) = &t.replay_gain {
+        comments.retain(|(name, _)| !name.starts_with("REPLAYGAIN_"));
+        comments.extend(gain.tags());
+    }
+    comments
+}
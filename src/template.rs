@@ -0,0 +1,171 @@
+//! Names of track outputs expanded from a template like `{base} - {track:02} {title}.{ext}`
+//!
+//! - `{base}`: the output name
+//! - `{track}`: number of the track, `{track:02}` pads it with zeroes to two digits
+//! - `{index}`: index a file of a track split at its indexes starts at, 1 for whole tracks,
+//!   padded like `{track}`
+//! - `{disc}`: number of the disc in its set, from `ConvertOptions::disc` or a "(Disc N)"
+//!   tag in the output name, 1 for a disc without either, padded like `{track}`
+//! - `{title}`, `{performer}`: CD-TEXT of the track, the title defaulting to `Track NN`
+//! - `{mode}`: mode of the track in the CUE sheet, e.g. `MODE1_2352`
+//! - `{serial}`: serial of the game on a PlayStation disc, e.g. `SLUS-01234`, empty for
+//!   other discs
+//! - `{ext}`: extension of the output, without the dot
+//!
+//! `{{` and `}}` stand for literal braces. Values taken from the image have characters that
+//! aren't allowed in file names replaced, so a title can't point the output elsewhere.
+
+use std::io;
+use std::path::Path;
+
+use crate::disc::DiscMetadata;
+use crate::error::ErrorCode;
+use crate::naming;
+use crate::track::Track;
+
+/// `{name}` or `{name:spec}` found in a template, with the text before it
+enum Part<'a> {
+    Text(&'a str),
+    Brace(char),
+    Value(&'a str, Option<&'a str>),
+}
+
+fn invalid(template: &str, problem: &str) -> io::Error {
+    ErrorCode::InvalidArguments.error(format!("Invalid name template {}: {}", template, problem))
+}
+
+fn parse(template: &str) -> io::Result<Vec<Part<'_>>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        if i > 0 {
+            parts.push(Part::Text(&rest[..i]));
+        }
+        let brace = rest[i..].chars().next().unwrap();
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix(brace) {
+            parts.push(Part::Brace(brace));
+            rest = after;
+            continue;
+        }
+        if brace == '}' {
+            return Err(invalid(template, "unmatched }, write }} for a literal one"));
+        }
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => return Err(invalid(template, "unterminated {")),
+        };
+        let (name, spec) = match rest[..end].split_once(':') {
+            Some((name, spec)) => (name, Some(spec)),
+            None => (&rest[..end], None),
+        };
+        parts.push(Part::Value(name, spec));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+    Ok(parts)
+}
+
+/// Replaces path separators and other characters file systems reject
+pub(crate) fn sanitize(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows drops trailing dots and spaces, and a lone `..` would still name the parent
+    String::from(value.trim().trim_end_matches('.'))
+}
+
+/// `value` of the placeholder `name`, padded to the width of `spec` like `02`
+fn number(template: &str, name: &str, value: u32, spec: Option<&str>) -> io::Result<String> {
+    let spec = match spec {
+        None => return Ok(value.to_string()),
+        Some(spec) => spec,
+    };
+    match spec.strip_prefix('0').unwrap_or(spec).parse() {
+        Ok(width) if spec.starts_with('0') => Ok(format!("{:0>width$}", value, width = width)),
+        Ok(width) => Ok(format!("{:>width$}", value, width = width)),
+        Err(_) => Err(invalid(
+            template,
+            &format!("{} takes a width like 02", name),
+        )),
+    }
+}
+
+/// Name of the output of `track` of the disc of `metadata` with extension `ext`, in the
+/// directory of `base`, for disc number `disc` of a set
+pub(crate) fn track_file_name(
+    template: &str,
+    base: &str,
+    disc: Option<u32>,
+    track: &Track,
+    metadata: &DiscMetadata,
+    ext: &str,
+) -> io::Result<String> {
+    let name = Path::new(base);
+    let mut expanded = String::new();
+    for part in parse(template)? {
+        let value = match part {
+            Part::Text(text) => String::from(text),
+            Part::Brace(brace) => String::from(brace),
+            Part::Value("track", spec) => number(template, "track", track.number, spec)?,
+            Part::Value("index", spec) => {
+                number(template, "index", track.split_index.max(1), spec)?
+            }
+            Part::Value("disc", spec) => {
+                let tagged = || naming::disc_number(&name.to_string_lossy());
+                number(template, "disc", disc.or_else(tagged).unwrap_or(1), spec)?
+            }
+            Part::Value(name, Some(_)) => {
+                return Err(invalid(template, &format!("{} takes no format", name)))
+            }
+            Part::Value("base", None) => name
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Part::Value("title", None) => match &track.cd_text.title {
+                Some(title) => sanitize(title),
+                None => format!("Track {:0>2}", track.number),
+            },
+            Part::Value("performer", None) => {
+                sanitize(track.cd_text.performer.as_deref().unwrap_or_default())
+            }
+            Part::Value("mode", None) => sanitize(track.mode.as_ref()),
+            Part::Value("serial", None) => sanitize(metadata.serial.as_deref().unwrap_or_default()),
+            Part::Value("ext", None) => String::from(ext),
+            Part::Value(name, None) => {
+                return Err(invalid(
+                    template,
+                    &format!("unknown placeholder {{{}}}", name),
+                ))
+            }
+        };
+        expanded += &value;
+    }
+    if expanded.is_empty() {
+        return Err(invalid(template, "it expands to an empty name"));
+    }
+    Ok(match name.parent() {
+        Some(dir) => dir.join(expanded).to_string_lossy().into_owned(),
+        None => expanded,
+    })
+}
+
+/// Checks `template` up front, so a conversion doesn't stop at the first track it's used for
+pub(crate) fn validate(template: &str) -> io::Result<()> {
+    track_file_name(
+        template,
+        "",
+        None,
+        &Track::default(),
+        &DiscMetadata::default(),
+        "bin",
+    )
+    .map(|_| ())
+}
@@ -0,0 +1,112 @@
+//! TOC and hash export in the layout DiscImageCreator uses for Redump submissions
+
+use std::io;
+
+use crate::disc::Disc;
+use crate::error::ErrorCode;
+use crate::hash::{Hasher, Hashes};
+use crate::log::info;
+use crate::longpath::file_name;
+use crate::output::{write_file, OutputFile};
+use crate::track::Track;
+use crate::{ConvertOptions, SECTOR_SIZE};
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Redump name of the split BIN file holding a track
+fn track_file_name(name: &str, track: &Track, tracks: usize) -> String {
+    match tracks {
+        1 => format!("{}.bin", name),
+        2..=9 => format!("{} (Track {}).bin", name, track.number()),
+        _ => format!("{} (Track {:02}).bin", name, track.number()),
+    }
+}
+
+/// Hashes of the sectors of a track, as they are stored in the image
+fn hash_track(disc: &mut dyn Disc, track: &Track) -> io::Result<Hashes> {
+    let mut hasher = Hasher::new();
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+
+    for lba in track.start_sector()..=track.stop_sector() {
+        disc.read_sector(lba, &mut sector)?;
+        hasher.update(&sector[track.stored_range()]);
+    }
+    Ok(hasher.finish())
+}
+
+/// Writes `<name>.dat` with per-track hashes and `<name>_disc.txt` with the TOC
+pub(crate) fn export(disc: &mut dyn Disc, a: &ConvertOptions) -> io::Result<Vec<OutputFile>> {
+    let tracks = disc.tracks().to_vec();
+    let name = file_name(&a.output_name);
+
+    let mut dat = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>
+<!DOCTYPE datafile PUBLIC \"-//Logiqx//DTD ROM Management Datafile//EN\" \"http://www.logiqx.com/Dats/datafile.dtd\">
+<datafile>
+\t<header>
+\t\t<description>rbchunk v{}</description>
+\t</header>
+\t<game name=\"{}\">
+\t\t<description>{}</description>
+",
+        env!("CARGO_PKG_VERSION"),
+        xml_escape(name),
+        xml_escape(name)
+    );
+    let mut toc = String::from("========== TOC ==========\n");
+
+    for t in &tracks {
+        let hashes = match hash_track(disc, t) {
+            Ok(hashes) => hashes,
+            Err(e) => {
+                return Err(ErrorCode::ReadFailed.wrap(&e, format!("Could not hash track {}", e)))
+            }
+        };
+        dat += &format!(
+            "\t\t<rom name=\"{}\" size=\"{}\" crc=\"{}\" md5=\"{}\" sha1=\"{}\"/>\n",
+            xml_escape(&track_file_name(name, t, tracks.len())),
+            hashes.size,
+            hashes.crc32_hex(),
+            hashes.md5_hex(),
+            hashes.sha1_hex()
+        );
+        toc += &format!(
+            "\t{:>10} Track {:>2}, LBA {:>8} - {:>8}, Length {:>8}\n",
+            if t.is_audio() { "Audio" } else { "Data" },
+            t.number(),
+            t.start_sector(),
+            t.stop_sector(),
+            t.stop_sector() - t.start_sector() + 1
+        );
+    }
+    dat += "\t</game>\n</datafile>\n";
+    if let Some(last) = tracks.last() {
+        toc += &format!(
+            "\t{:>40} {:>8}\n",
+            "Total",
+            last.stop_sector() + 1 - tracks[0].start_sector()
+        );
+    }
+
+    let dat = write_file(format!("{}.dat", a.output_name), &dat, a)?;
+    let toc = write_file(format!("{}_disc.txt", a.output_name), &toc, a)?;
+
+    if a.verbose {
+        info!("TOC: {} {}", dat.filename, toc.filename);
+    }
+
+    Ok(vec![dat, toc])
+}
@@ -0,0 +1,1303 @@
+use std::fmt::Display;
+#[cfg(feature = "flac")]
+use std::fs;
+use std::io;
+use std::ops::{Range, RangeInclusive};
+use std::str::FromStr;
+
+use crate::audio::{self, Amplifier, Deemphasis, Resampler};
+#[cfg(feature = "flac")]
+use crate::cue;
+use crate::disc::{CdText, Disc, DiscMetadata, RemMetadata};
+#[cfg(any(feature = "ogg", feature = "mp3", feature = "flac"))]
+use crate::encoder::{self, Encoder};
+use crate::error::ErrorCode;
+use crate::log::{info, warning};
+#[cfg(any(feature = "ogg", feature = "mp3", feature = "flac"))]
+use crate::longpath::long_path;
+use crate::output::{self, OutputFile, OutputWriter};
+use crate::plan::PlannedOutput;
+use crate::progress::{self, Progress};
+use crate::replaygain::ReplayGain;
+use crate::sector::SUBCODE_SIZE;
+#[cfg(any(feature = "wav", feature = "ogg", feature = "mp3", feature = "flac"))]
+use crate::tags;
+#[cfg(feature = "flac")]
+use crate::temp::TemporaryDirectory;
+use crate::template;
+use crate::vcd;
+use crate::verify::SectorCheck;
+#[cfg(feature = "wav")]
+use crate::wav;
+use crate::{ConvertOptions, SECTOR_SIZE};
+
+#[derive(Default, Clone)]
+pub struct Track {
+    pub(crate) start_sector: u64,
+    pub(crate) stop_sector: Option<u64>,
+    /// Byte position of the track in its BIN file
+    pub(crate) start: u64,
+    pub(crate) mode: Mode,
+    extension: Extension,
+    pub(crate) number: u32,
+    data_block_offset: u32,
+    data_block_size: u32,
+    pub(crate) byte_offset: Option<i64>,
+    pub(crate) flags: TrackFlags,
+    pub(crate) cd_text: CdText,
+    pub(crate) rem: RemMetadata,
+    pub(crate) isrc: Option<String>,
+    /// Sector of `INDEX 00`, where the pregap stored in the BIN file begins
+    pub(crate) index0: Option<u64>,
+    /// Sectors of `INDEX 02` onwards, subdividing the track
+    pub(crate) indexes: Vec<u64>,
+    /// Sectors of silence before the track that aren't stored in the BIN file
+    pub(crate) pregap: u64,
+    /// Sectors of silence after the track that aren't stored in the BIN file
+    pub(crate) postgap: u64,
+    /// Session the track is in, counting from 1, or 0 for images that don't say
+    pub(crate) session: u32,
+    /// Whether 96 bytes of subchannel follow every sector in the image, as in 2448 byte
+    /// dumps. `Mode::Cdg` always has them, without this.
+    pub(crate) subcode: bool,
+    /// Index this part of a track split with `ConvertOptions::split_indexes` starts at, 0 for
+    /// whole tracks
+    pub(crate) split_index: u32,
+    /// Loudness of the track worked out for `ConvertOptions::replaygain`
+    pub(crate) replay_gain: Option<ReplayGain>,
+    /// Whether the Form 2 sectors of the track hold the MPEG stream of a Video CD, found for
+    /// `ConvertOptions::demux_mpeg`
+    pub(crate) mpeg: bool,
+    // Sectors of silence written before and after the track, following the gap policy
+    gap_before: u64,
+    gap_after: u64,
+}
+
+impl Track {
+    /// Track spanning the sectors `start_sector..=stop_sector` of a disc
+    pub fn new(number: u32, mode: Mode, start_sector: u64, stop_sector: u64) -> Self {
+        Track {
+            number,
+            mode,
+            start_sector,
+            stop_sector: Some(stop_sector),
+            start: start_sector * SECTOR_SIZE,
+            ..Default::default()
+        }
+    }
+
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn start_sector(&self) -> u64 {
+        self.start_sector
+    }
+
+    pub fn stop_sector(&self) -> u64 {
+        self.stop_sector.unwrap_or(self.start_sector)
+    }
+
+    pub fn is_audio(&self) -> bool {
+        matches!(self.mode, Mode::Audio | Mode::Cdg)
+    }
+
+    /// Bytes each sector of the track takes up in the image
+    pub fn sector_size(&self) -> u64 {
+        match self.subcode {
+            true => self.mode.sector_size() + SUBCODE_SIZE as u64,
+            false => self.mode.sector_size(),
+        }
+    }
+
+    /// Whether the image stores the subchannel of every sector after it
+    pub fn has_subcode(&self) -> bool {
+        self.subcode || self.mode == Mode::Cdg
+    }
+
+    /// Stores the track in sectors of `size` bytes, 2448 for raw ones followed by their
+    /// subchannel, if its mode has sectors of that size
+    pub(crate) fn set_sector_size(&mut self, size: u64) -> bool {
+        let subcode = size == SECTOR_SIZE + SUBCODE_SIZE as u64;
+        let raw = if subcode { SECTOR_SIZE } else { size };
+        match self.mode.with_sector_size(raw) {
+            Some(mode) => {
+                self.mode = mode;
+                self.subcode = subcode;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Part of the raw sectors read from the disc that is stored in the image
+    pub(crate) fn stored_range(&self) -> Range<usize> {
+        self.mode.stored_range()
+    }
+
+    /// CD-TEXT of the track, like its title
+    pub fn cd_text(&self) -> &CdText {
+        &self.cd_text
+    }
+
+    /// Metadata from the `REM` lines of the track
+    pub fn rem(&self) -> &RemMetadata {
+        &self.rem
+    }
+
+    /// International Standard Recording Code from the `ISRC` command
+    pub fn isrc(&self) -> Option<&str> {
+        self.isrc.as_deref()
+    }
+
+    /// Subcode flags from the `FLAGS` command
+    pub fn flags(&self) -> TrackFlags {
+        self.flags
+    }
+
+    /// Sector of `INDEX 00`, if the track has a pregap stored in the image
+    ///
+    /// `start_sector` is always the `INDEX 01` the track really starts at.
+    pub fn index0(&self) -> Option<u64> {
+        self.index0
+    }
+
+    /// Sectors of the indexes after `INDEX 01`, starting with `INDEX 02`
+    pub fn indexes(&self) -> &[u64] {
+        &self.indexes
+    }
+
+    /// Length of the `PREGAP` in sectors
+    pub fn pregap(&self) -> u64 {
+        self.pregap
+    }
+
+    /// Length of the `POSTGAP` in sectors
+    pub fn postgap(&self) -> u64 {
+        self.postgap
+    }
+
+    /// Session the track is in, 1 for discs of a single session
+    ///
+    /// The lead-out and lead-in before the first track of a later session are part of its
+    /// `pregap`, as they aren't stored in the image either.
+    pub fn session(&self) -> u32 {
+        self.session.max(1)
+    }
+
+    /// Whether the track is the first of a later session than `previous`
+    pub(crate) fn starts_session(&self, previous: Option<&Track>) -> bool {
+        previous.is_some_and(|p| self.session() > p.session())
+    }
+
+    /// First sector of the stored `INDEX 00` pregap written with the track, if it is
+    ///
+    /// Only audio tracks get their pregap, data tracks would shift their filesystem.
+    pub(crate) fn first_sector(&self, a: &ConvertOptions) -> u64 {
+        match self.index0 {
+            Some(index0) if a.include_index0 && self.is_audio() => index0,
+            _ => self.start_sector - self.pregap,
+        }
+    }
+
+    /// Fails for sectors that don't lead up to `INDEX 01` and on to the end of the track in
+    /// order, which the sector counts of the outputs rely on
+    pub(crate) fn check_layout(&self) -> io::Result<()> {
+        let gap_start = self.start_sector.checked_sub(self.pregap);
+        let problem = match (gap_start, self.index0) {
+            _ if self.stop_sector() < self.start_sector => "ends before it starts",
+            (None, _) => "has a pregap longer than the sectors before it",
+            (Some(gap_start), Some(index0)) if index0 > gap_start => {
+                "has INDEX 00 after its pregap"
+            }
+            _ => return Ok(()),
+        };
+        Err(ErrorCode::CueMalformed.error(format!("Track {} {}", self.number, problem)))
+    }
+
+    /// Sectors written before `INDEX 01`, the stored pregap and gaps of silence
+    pub(crate) fn lead_in(&self, a: &ConvertOptions) -> u64 {
+        self.start_sector - self.pregap - self.first_sector(a) + self.gap_before
+    }
+
+    /// Sectors written to the output, including pregaps and gaps of silence
+    pub(crate) fn output_sectors(&self, a: &ConvertOptions) -> u64 {
+        self.lead_in(a) + (self.stop_sector() - self.start_sector + 1) + self.gap_after
+    }
+
+    /// Bytes of the stored sectors read from the image for the output
+    pub(crate) fn read_size(&self, a: &ConvertOptions) -> u64 {
+        let sectors =
+            self.lead_in(a) - self.gap_before + (self.stop_sector() - self.start_sector + 1);
+        sectors * self.sector_size()
+    }
+
+    pub(crate) fn get_track_mode(&mut self, a: &ConvertOptions) {
+        match self.mode {
+            Mode::Unknown => {
+                self.data_block_offset = 0;
+                self.data_block_size = 2352;
+                self.extension = Extension::Ugh;
+            }
+            Mode::Audio | Mode::Cdg => {
+                self.data_block_offset = 0;
+                self.data_block_size = 2352;
+                if a.to_wav {
+                    self.extension = Extension::Wav;
+                } else if cfg!(feature = "ogg") && a.ogg_quality.is_some() {
+                    self.extension = Extension::Ogg;
+                } else if cfg!(feature = "mp3") && a.mp3_bitrate.is_some() {
+                    self.extension = Extension::Mp3;
+                } else if cfg!(feature = "flac") && a.flac_compression.is_some() {
+                    self.extension = Extension::Flac;
+                } else {
+                    self.extension = Extension::Cdr;
+                }
+            }
+            Mode::Mode1_2352 => {
+                self.data_block_offset = 16;
+                self.data_block_size = 2048;
+                self.extension = Extension::Iso;
+            }
+            // CD-i sectors share the Mode 2 layout
+            Mode::Mode2_2352 | Mode::Cdi2352 => {
+                self.extension = Extension::Iso;
+                if a.demux_mpeg && self.mpeg {
+                    self.data_block_offset = 24;
+                    self.data_block_size = 2324;
+                    self.extension = Extension::Mpg;
+                } else if a.raw {
+                    self.data_block_offset = 0;
+                    self.data_block_size = 2352;
+                } else if a.psx_truncate {
+                    self.data_block_offset = 0;
+                    self.data_block_size = 2336;
+                } else {
+                    self.data_block_offset = 24;
+                    self.data_block_size = 2048;
+                }
+            }
+            Mode::Mode1_2048 => {
+                self.data_block_offset = 16;
+                self.data_block_size = 2048;
+                self.extension = Extension::Iso;
+            }
+            Mode::Mode2_2324 => {
+                self.data_block_offset = 24;
+                self.data_block_size = 2324;
+                self.extension = Extension::Iso;
+            }
+            Mode::Mode2_2336 | Mode::Cdi2336 => {
+                self.data_block_offset = 16;
+                self.data_block_size = 2336;
+                self.extension = Extension::Iso;
+            }
+        }
+    }
+
+    /// FILE and TRACK types of the converted track in a CUE sheet, if its layout has one
+    pub(crate) fn converted_cue_types(
+        &self,
+        a: &ConvertOptions,
+    ) -> Option<(&'static str, &'static str)> {
+        if self.is_audio() {
+            let encoded = self.is_encoded();
+            let file_type = match (a.to_wav || encoded, a.swap_audo_bytes) {
+                (true, _) => "WAVE",
+                (false, true) => "MOTOROLA",
+                (false, false) => "BINARY",
+            };
+            return Some((file_type, Mode::AUDIO));
+        }
+        if self.demuxes_mpeg() {
+            return None;
+        }
+
+        let track_type = match (self.data_block_offset, self.data_block_size) {
+            (0, 2352) if self.mode == Mode::Mode1_2352 => Mode::MODE1_2352,
+            (0, 2352) if self.mode == Mode::Mode2_2352 => Mode::MODE2_2352,
+            (0, 2352) if self.mode == Mode::Cdi2352 => Mode::CDI_2352,
+            (16, 2336) if self.mode == Mode::Cdi2336 => Mode::CDI_2336,
+            (16 | 24, 2048) => Mode::MODE1_2048,
+            (16, 2336) => Mode::MODE2_2336,
+            (24, 2324) => Mode::MODE2_2324,
+            _ => return None,
+        };
+        Some(("BINARY", track_type))
+    }
+
+    #[cfg(feature = "wav")]
+    pub(crate) fn wav_header(&self, metadata: &DiscMetadata, a: &ConvertOptions) -> Vec<u8> {
+        let reallen = self.output_size(a);
+        let info = wav::info_chunk(&tags::comments(self, metadata));
+        wav::header(reallen as u32, &info, &wav::Params::from_args(a))
+    }
+
+    /// Encoder writing the track to `path`, if it is written in a compressed format
+    #[cfg(any(feature = "ogg", feature = "mp3", feature = "flac"))]
+    fn encoder(
+        &self,
+        path: &std::path::Path,
+        metadata: &DiscMetadata,
+        a: &ConvertOptions,
+    ) -> io::Result<Option<Encoder>> {
+        let big_endian = a.swap_audo_bytes;
+        let rate = self.resampled_rate(a).unwrap_or(audio::CD_RATE);
+        let tags = tags::comments(self, metadata);
+        let command = match self.extension {
+            #[cfg(feature = "ogg")]
+            Extension::Ogg => a
+                .ogg_quality
+                .map(|quality| encoder::vorbis(path, quality, rate, big_endian, &tags)),
+            #[cfg(feature = "mp3")]
+            Extension::Mp3 => a
+                .mp3_bitrate
+                .map(|bitrate| encoder::mp3(path, bitrate, rate, big_endian, &tags)),
+            #[cfg(feature = "flac")]
+            Extension::Flac => {
+                return self.flac_encoder(path, metadata, rate, big_endian, &tags, a)
+            }
+            _ => None,
+        };
+        command.map(Encoder::spawn).transpose()
+    }
+
+    /// `flac` writing the track to `path` with its CUE sheet and tags
+    #[cfg(feature = "flac")]
+    fn flac_encoder(
+        &self,
+        path: &std::path::Path,
+        metadata: &DiscMetadata,
+        rate: u32,
+        big_endian: bool,
+        tags: &[(String, String)],
+        a: &ConvertOptions,
+    ) -> io::Result<Option<Encoder>> {
+        let level = match a.flac_compression {
+            Some(level) => level,
+            None => return Ok(None),
+        };
+        // A CUESHEET block describes CD audio, which resampled audio isn't anymore
+        let scratch = match rate == audio::CD_RATE {
+            false => None,
+            true => {
+                let scratch = TemporaryDirectory::create()?;
+                let file = path.file_name().unwrap_or_default().to_string_lossy();
+                // Named as the file it's written to will be once it's complete
+                let file = file.strip_suffix(output::PART_SUFFIX).unwrap_or(&file);
+                fs::write(
+                    scratch.0.join("track.cue"),
+                    cue::track_sheet(self, metadata, file, a),
+                )?;
+                Some(scratch)
+            }
+        };
+        let cuesheet = scratch.as_ref().map(|scratch| scratch.0.join("track.cue"));
+        let command = encoder::flac(path, level, rate, big_endian, tags, cuesheet.as_deref());
+        Ok(Some(Encoder::spawn(command)?.keeping(scratch)))
+    }
+
+    /// Whether an output of the sectors in `range` of this track is compressed with ECM
+    fn ecm(&self, range: &Range<usize>, a: &ConvertOptions) -> bool {
+        // Only sectors with their sync or subheader are recognized and stripped
+        a.ecm && !self.is_audio() && range.len() >= SECTOR_SIZE as usize - 16
+    }
+
+    /// Bytes written to the output for every sector of the track
+    pub(crate) fn block_size(&self) -> u64 {
+        self.data_block_size as u64
+    }
+
+    /// Part of every raw sector written to the output
+    pub(crate) fn block(&self) -> Range<usize> {
+        self.data_block_offset as usize..(self.data_block_offset + self.data_block_size) as usize
+    }
+
+    /// Sectors of silence written before and after the track
+    pub(crate) fn gaps(&self) -> (u64, u64) {
+        (self.gap_before, self.gap_after)
+    }
+
+    /// Whether the output is compressed by an external encoder
+    pub(crate) fn is_encoded(&self) -> bool {
+        matches!(
+            self.extension,
+            Extension::Ogg | Extension::Mp3 | Extension::Flac
+        )
+    }
+
+    /// Whether only the MPEG stream in the Form 2 sectors of the track is written
+    pub(crate) fn demuxes_mpeg(&self) -> bool {
+        matches!(self.extension, Extension::Mpg)
+    }
+
+    /// Whether the track is audio written to a file that's played back as it is, as WAV or
+    /// encoded, and not one to burn
+    pub(crate) fn played_back(&self, a: &ConvertOptions) -> bool {
+        self.is_audio() && (a.to_wav || self.is_encoded())
+    }
+
+    /// Whether the pre-emphasis of the track is undone, only for files played back and not
+    /// ones to burn, whose `PRE` flag has players do it
+    pub(crate) fn deemphasized(&self, a: &ConvertOptions) -> bool {
+        self.played_back(a) && self.flags.pre_emphasis && !a.keep_emphasis
+    }
+
+    /// Sample rate the audio of the track is resampled to, for files played back
+    pub(crate) fn resampled_rate(&self, a: &ConvertOptions) -> Option<u32> {
+        let rate = a.resample.filter(|&rate| rate != audio::CD_RATE)?;
+        self.played_back(a).then_some(rate)
+    }
+
+    /// Bytes of the sectors and gaps written, without headers, as long as nothing compresses
+    /// them
+    pub(crate) fn output_size(&self, a: &ConvertOptions) -> u64 {
+        let size = self.output_sectors(a) * self.data_block_size as u64;
+        match self.resampled_rate(a) {
+            Some(rate) => audio::resampled_frames(size / 4, rate) * 4,
+            None => size,
+        }
+    }
+
+    /// Whether an untouched copy of the raw sectors is written next to the output in `block`
+    fn keeps_raw(&self, block: &Range<usize>, a: &ConvertOptions) -> bool {
+        // Nothing to keep when the track is already written the way it's stored
+        a.keep_raw && !self.is_audio() && *block != self.stored_range()
+    }
+
+    /// Whether the sectors of the track are checked against their EDC and ECC
+    pub(crate) fn verifies(&self, a: &ConvertOptions) -> bool {
+        // Cooked sectors were stored without theirs
+        a.verify && !self.is_audio() && self.stored_range().len() == SECTOR_SIZE as usize
+    }
+
+    /// Extension of the file the subcode of every sector is written to, if it's exported
+    fn subcode_output(&self, a: &ConvertOptions) -> Option<&'static str> {
+        if a.export_cdg && self.mode == Mode::Cdg {
+            Some(Extension::CDG)
+        } else if a.export_subcode && self.subcode {
+            Some(Extension::SUB)
+        } else {
+            None
+        }
+    }
+
+    fn output_filename(
+        &self,
+        extension: &str,
+        ecm: bool,
+        metadata: &DiscMetadata,
+        a: &ConvertOptions,
+    ) -> io::Result<String> {
+        let filename = match &a.name_template {
+            Some(template) => {
+                let (base, disc) = (&a.output_name, a.disc);
+                template::track_file_name(template, base, disc, self, metadata, extension)?
+            }
+            None if self.split_index > 0 => format!(
+                "{}{:0>2}-{:0>2}.{}",
+                a.output_name, self.number, self.split_index, extension
+            ),
+            None => format!("{}{:0>2}.{}", a.output_name, self.number, extension),
+        };
+        let filename = match ecm {
+            true => format!("{}.ecm", filename),
+            false => filename,
+        };
+        Ok(match self.compressed(extension, ecm, a) {
+            Some(suffix) => format!("{}{}", filename, suffix),
+            None => filename,
+        })
+    }
+
+    /// Suffix of the compressed file an output with `extension` is written to, for disc images
+    /// compressed with gzip or zstd
+    fn compressed(&self, extension: &str, ecm: bool, a: &ConvertOptions) -> Option<&'static str> {
+        let image = [Extension::ISO, Extension::CDR, Extension::BIN].contains(&extension);
+        output::compressed_suffix(a).filter(|_| image && !ecm)
+    }
+
+    fn create_writer(
+        &self,
+        extension: &str,
+        ecm: bool,
+        metadata: &DiscMetadata,
+        a: &ConvertOptions,
+    ) -> io::Result<OutputWriter> {
+        let filename = self.output_filename(extension, ecm, metadata, a)?;
+        if ecm {
+            return match OutputWriter::create_ecm(filename, a) {
+                Ok(t_writer) => Ok(t_writer),
+                Err(e) => {
+                    Err(ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track: {}", e)))
+                }
+            };
+        }
+        if self.compressed(extension, ecm, a).is_some() {
+            return match OutputWriter::create_compressed(filename, a) {
+                Ok(t_writer) => Ok(t_writer),
+                Err(e) => {
+                    Err(ErrorCode::WriteFailed.wrap(&e, format!("Could not compress track: {}", e)))
+                }
+            };
+        }
+        #[cfg(any(feature = "ogg", feature = "mp3", feature = "flac"))]
+        let path = output::part_path(&filename, a).unwrap_or_else(|| filename.clone());
+        #[cfg(any(feature = "ogg", feature = "mp3", feature = "flac"))]
+        match self.encoder(long_path(&path).as_path(), metadata, a) {
+            Ok(Some(encoder)) => return Ok(OutputWriter::encode(filename, encoder, a)),
+            Ok(None) => {}
+            Err(e) => {
+                return Err(
+                    ErrorCode::WriteFailed.wrap(&e, format!("Could not encode track: {}", e))
+                )
+            }
+        }
+        match OutputWriter::create(filename, a) {
+            Ok(t_writer) => Ok(t_writer),
+            Err(e) => {
+                Err(ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track: {}", e)))
+            }
+        }
+    }
+
+    fn finish_writer(&self, writer: OutputWriter, a: &ConvertOptions) -> io::Result<OutputFile> {
+        let output = match writer.finish() {
+            Ok(output) => output,
+            Err(e) => {
+                return Err(
+                    ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track {}", e))
+                )
+            }
+        };
+        if a.verbose {
+            info!("{}: {}", self.number, output.summary());
+        }
+        Ok(output)
+    }
+
+    fn write_silence(
+        &self,
+        writer: &mut OutputWriter,
+        sectors: u64,
+        resampler: &mut Option<Resampler>,
+        a: &ConvertOptions,
+    ) -> io::Result<()> {
+        // Silence is no part of an MPEG stream
+        if self.demuxes_mpeg() {
+            return Ok(());
+        }
+        if resampler.is_none() {
+            return write_zeros(writer, sectors * self.data_block_size as u64);
+        }
+        // Silence is resampled along with the audio, whose end still rings into it
+        for _ in 0..sectors {
+            self.write_block(writer, &mut [0; SECTOR_SIZE as usize], resampler, a)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the block of a sector, resampled and with the bytes of audio swapped as asked
+    fn write_block(
+        &self,
+        writer: &mut OutputWriter,
+        block: &mut [u8],
+        resampler: &mut Option<Resampler>,
+        a: &ConvertOptions,
+    ) -> io::Result<()> {
+        let block = match resampler {
+            Some(resampler) => resampler.push(block),
+            None => block,
+        };
+        self.write_samples(writer, block, a)
+    }
+
+    fn write_samples(
+        &self,
+        writer: &mut OutputWriter,
+        samples: &mut [u8],
+        a: &ConvertOptions,
+    ) -> io::Result<()> {
+        if self.is_audio() && a.swap_audo_bytes {
+            for pair in samples.chunks_exact_mut(2) {
+                pair.swap(0, 1);
+            }
+        }
+        if let Err(e) = writer.write_all(samples) {
+            return Err(ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track {}", e)));
+        }
+        Ok(())
+    }
+
+    /// Copies the sectors of the track straight from the image into `writer` for as long as
+    /// both are plain files, returning the sector it stopped at
+    fn copy_sectors(
+        &self,
+        disc: &mut dyn Disc,
+        writer: &mut OutputWriter,
+        progress: &mut Progress,
+        report: &impl Fn(&Progress),
+        a: &ConvertOptions,
+    ) -> io::Result<u64> {
+        let mut lba = self.start_sector;
+        while lba <= self.stop_sector() {
+            a.check_cancelled()?;
+            let end = (lba + progress::INTERVAL).min(self.stop_sector() + 1);
+            let copied =
+                writer.write_directly(|file| Ok(disc.copy_sectors(lba..end, file)? * SECTOR_SIZE));
+            let sectors = match copied {
+                Ok(Some(bytes)) if bytes > 0 => bytes / SECTOR_SIZE,
+                Ok(_) => break,
+                Err(e) => {
+                    return Err(ErrorCode::WriteFailed
+                        .wrap(&e, format!("Could not copy track {}: {}", self.number, e)))
+                }
+            };
+            lba += sectors;
+            progress.sectors_done += sectors;
+            if !progress.is_done() {
+                report(progress);
+            }
+        }
+        Ok(lba)
+    }
+
+    /// Writes the track, along with an untouched copy of its raw sectors if requested
+    ///
+    /// The track goes into `merged` instead of a file of its own when given, which is left
+    /// for the caller to finish.
+    pub(crate) fn write_to_file(
+        &self,
+        disc: &mut dyn Disc,
+        merged: Option<&mut OutputWriter>,
+        a: &ConvertOptions,
+    ) -> io::Result<Vec<OutputFile>> {
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+
+        let block = self.block();
+        let mut own_writer = None;
+        let writer = match merged {
+            Some(writer) => writer,
+            None => own_writer.insert(self.create_writer(
+                self.extension.as_ref(),
+                self.ecm(&block, a),
+                disc.metadata(),
+                a,
+            )?),
+        };
+        let stored = self.stored_range();
+        let mut raw_writer = match self.keeps_raw(&block, a) {
+            true => Some(self.create_writer(
+                Extension::BIN,
+                self.ecm(&stored, a),
+                disc.metadata(),
+                a,
+            )?),
+            false => None,
+        };
+        let mut subcode_writer = match self.subcode_output(a) {
+            Some(extension) => Some(self.create_writer(extension, false, disc.metadata(), a)?),
+            None => None,
+        };
+        // Subcode stays in step with the sectors, so gaps of silence get empty subcode
+        let gap_before = self.gap_before * SUBCODE_SIZE as u64;
+        let gap_after = self.gap_after * SUBCODE_SIZE as u64;
+        let mut subcode = [0u8; SUBCODE_SIZE];
+
+        #[cfg(feature = "wav")]
+        if a.to_wav && self.is_audio() {
+            if let Err(e) = writer.write_all(&self.wav_header(disc.metadata(), a)) {
+                return Err(
+                    ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track {}", e))
+                );
+            };
+        }
+
+        let pregap = self.first_sector(a)..self.start_sector - self.pregap;
+        let track = self.start_sector..=self.stop_sector();
+        let mut progress = Progress {
+            track: self.number,
+            sectors_done: 0,
+            sectors_total: self.stop_sector() + 1 - pregap.start,
+        };
+        let report = |progress: &Progress| {
+            if let Some(callback) = &a.progress {
+                callback(progress);
+            }
+        };
+        report(&progress);
+        let mut check = self.verifies(a).then(|| SectorCheck::new(self.number));
+        let mut deemphasis = Deemphasis::new(self, a);
+        if deemphasis.is_some() && a.verbose {
+            info!("{}: undoing pre-emphasis", self.number);
+        }
+        let mut amplifier = Amplifier::new(disc, self, a)?;
+        let mut resampler = Resampler::new(self, a);
+        if let (Some(rate), true) = (self.resampled_rate(a), a.verbose) {
+            info!("{}: resampling to {} Hz", self.number, rate);
+        }
+        // Sectors written as they are read can be copied between the files without reading them
+        let verbatim = raw_writer.is_none()
+            && subcode_writer.is_none()
+            && check.is_none()
+            && deemphasis.is_none()
+            && amplifier.is_none()
+            && resampler.is_none()
+            && block.len() == SECTOR_SIZE as usize
+            && !(self.is_audio() && a.swap_audo_bytes);
+        let mut copied_until = 0;
+        for lba in pregap.chain(track) {
+            if lba < copied_until {
+                continue;
+            }
+            a.check_cancelled()?;
+            if lba == self.start_sector {
+                self.write_silence(writer, self.gap_before, &mut resampler, a)?;
+                if let Some(subcode_writer) = &mut subcode_writer {
+                    write_zeros(subcode_writer, gap_before)?;
+                }
+                if verbatim {
+                    copied_until = self.copy_sectors(disc, writer, &mut progress, &report, a)?;
+                    if lba < copied_until {
+                        continue;
+                    }
+                }
+            }
+            if let Err(e) = disc.read_sector(lba, &mut sector) {
+                return Err(ErrorCode::ReadFailed
+                    .wrap(&e, format!("Could not read from {} {}", &a.bin_file, e)));
+            }
+            if let Some(check) = &mut check {
+                check.check(lba, &sector);
+            }
+            if let Some(subcode_writer) = &mut subcode_writer {
+                if let Err(e) = disc.read_subcode(lba, &mut subcode) {
+                    return Err(ErrorCode::ReadFailed
+                        .wrap(&e, format!("Could not read from {} {}", &a.bin_file, e)));
+                }
+                if let Err(e) = subcode_writer.write_all(&subcode) {
+                    return Err(
+                        ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track {}", e))
+                    );
+                }
+            }
+            if let Some(deemphasis) = &mut deemphasis {
+                deemphasis.apply(&mut sector);
+            }
+            if let Some(amplifier) = &mut amplifier {
+                amplifier.apply(&mut sector);
+            }
+            if let Some(raw_writer) = &mut raw_writer {
+                if let Err(e) = raw_writer.write_all(&sector[stored.clone()]) {
+                    return Err(
+                        ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track {}", e))
+                    );
+                }
+            }
+            // Empty sectors around the stream are left out of it
+            if !self.demuxes_mpeg() || vcd::in_stream(&sector) {
+                self.write_block(writer, &mut sector[block.clone()], &mut resampler, a)?;
+            }
+            progress.sectors_done += 1;
+            let leftover = progress.sectors_done % progress::INTERVAL;
+            if leftover == 0 && !progress.is_done() {
+                report(&progress);
+            }
+        }
+        self.write_silence(writer, self.gap_after, &mut resampler, a)?;
+        if let Some(resampler) = &mut resampler {
+            self.write_samples(writer, resampler.finish(), a)?;
+        }
+        report(&progress);
+        if let Some(check) = check {
+            check.finish()?;
+        }
+        if let Some(amplifier) = amplifier.filter(|amplifier| amplifier.clipped > 0) {
+            warning!(
+                "{}: {} samples clipped by the gain",
+                self.number,
+                amplifier.clipped
+            );
+        }
+
+        let mut outputs = Vec::new();
+        if let Some(writer) = own_writer {
+            outputs.push(self.finish_writer(writer, a)?);
+        }
+        if let Some(raw_writer) = raw_writer {
+            outputs.push(self.finish_writer(raw_writer, a)?);
+        }
+        if let Some(mut subcode_writer) = subcode_writer {
+            write_zeros(&mut subcode_writer, gap_after)?;
+            outputs.push(self.finish_writer(subcode_writer, a)?);
+        }
+
+        Ok(outputs)
+    }
+
+    /// Files `write_to_file` would write and their sizes, where they're known up front
+    pub(crate) fn planned_outputs(
+        &self,
+        merged: bool,
+        metadata: &DiscMetadata,
+        a: &ConvertOptions,
+    ) -> io::Result<Vec<PlannedOutput>> {
+        let block = self.block();
+        let stored = self.stored_range();
+        let sectors = self.output_sectors(a);
+        let mut outputs = Vec::new();
+        if !merged {
+            let ecm = self.ecm(&block, a);
+            #[allow(unused_mut)]
+            let mut size = self.output_size(a);
+            #[cfg(feature = "wav")]
+            if a.to_wav && self.is_audio() {
+                size += self.wav_header(metadata, a).len() as u64;
+            }
+            let size = match self.extension {
+                // Constant bitrate, so only the few bytes of headers are left to guess
+                Extension::Mp3 => a.mp3_bitrate.map(|kbps| sectors * kbps as u64 * 125 / 75),
+                // Sectors outside the stream aren't written
+                Extension::Ogg | Extension::Flac | Extension::Mpg => None,
+                _ if ecm || self.compressed(self.extension.as_ref(), ecm, a).is_some() => None,
+                _ => Some(size),
+            };
+            outputs.push(PlannedOutput {
+                filename: self.output_filename(self.extension.as_ref(), ecm, metadata, a)?,
+                size,
+                estimated: matches!(self.extension, Extension::Mp3),
+            });
+        }
+        if self.keeps_raw(&block, a) {
+            let ecm = self.ecm(&stored, a);
+            // Gaps of silence only go into the converted output
+            let read = sectors - self.gap_before - self.gap_after;
+            outputs.push(PlannedOutput {
+                filename: self.output_filename(Extension::BIN, ecm, metadata, a)?,
+                size: (!ecm && self.compressed(Extension::BIN, ecm, a).is_none())
+                    .then_some(read * stored.len() as u64),
+                estimated: false,
+            });
+        }
+        if let Some(extension) = self.subcode_output(a) {
+            outputs.push(PlannedOutput {
+                filename: self.output_filename(extension, false, metadata, a)?,
+                size: Some(sectors * SUBCODE_SIZE as u64),
+                estimated: false,
+            });
+        }
+        Ok(outputs)
+    }
+}
+
+fn write_zeros(writer: &mut OutputWriter, length: u64) -> io::Result<()> {
+    let zeros = [0u8; SECTOR_SIZE as usize];
+    let mut remaining = length;
+    while remaining > 0 {
+        let chunk = remaining.min(SECTOR_SIZE);
+        if let Err(e) = writer.write_all(&zeros[..chunk as usize]) {
+            return Err(ErrorCode::WriteFailed.wrap(&e, format!("Could not write to track {}", e)));
+        }
+        remaining -= chunk;
+    }
+    Ok(())
+}
+
+/// Subcode Q control flags of a track, as given by the `FLAGS` command
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TrackFlags {
+    /// `DCP`: digital copy permitted
+    pub digital_copy: bool,
+    /// `4CH`: four channel audio
+    pub four_channel: bool,
+    /// `PRE`: audio was recorded with pre-emphasis
+    pub pre_emphasis: bool,
+    /// `SCMS`: serial copy management system
+    pub scms: bool,
+}
+
+impl TrackFlags {
+    const DCP: &'static str = "DCP";
+    const FOUR_CHANNEL: &'static str = "4CH";
+    const PRE: &'static str = "PRE";
+    const SCMS: &'static str = "SCMS";
+
+    /// Sets the flag named `flag`, returning false for flags that don't exist
+    pub(crate) fn set(&mut self, flag: &str) -> bool {
+        match flag {
+            TrackFlags::DCP => self.digital_copy = true,
+            TrackFlags::FOUR_CHANNEL => self.four_channel = true,
+            TrackFlags::PRE => self.pre_emphasis = true,
+            TrackFlags::SCMS => self.scms = true,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == TrackFlags::default()
+    }
+}
+
+/// The flags in `FLAGS` command syntax, e.g. `DCP PRE`
+impl Display for TrackFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let flags = [
+            (self.digital_copy, TrackFlags::DCP),
+            (self.four_channel, TrackFlags::FOUR_CHANNEL),
+            (self.pre_emphasis, TrackFlags::PRE),
+            (self.scms, TrackFlags::SCMS),
+        ];
+        let names: Vec<&str> = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| *name)
+            .collect();
+        f.pad(&names.join(" "))
+    }
+}
+
+/// What to do with `PREGAP` and `POSTGAP` silence, which isn't stored in the BIN file
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GapPolicy {
+    /// Leave gaps out of the outputs
+    #[default]
+    Skip,
+    /// Write each gap as silence at the start or end of its own track
+    Silence,
+    /// Write pregaps as silence at the end of the previous track and postgaps at
+    /// the start of the next one, like CD rippers do with their "append gaps" mode
+    Append,
+}
+
+/// What to do with bytes at the end of a file that don't fill a whole sector, which CUE
+/// sheets leave to the size of the file
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PartialSector {
+    /// Keep them as a last sector of the track, padded with zeroes
+    #[default]
+    Pad,
+    /// Leave them out, the track ends with its last whole sector
+    Truncate,
+    /// Fail the conversion with `ErrorCode::CorruptSectors`, as the image was likely cut short
+    Error,
+}
+
+/// Distributes the gaps of audio `tracks` as silence according to `policy`
+///
+/// Gaps are never written into data tracks, where they would shift the filesystem.
+pub(crate) fn apply_gap_policy(tracks: &mut [Track], policy: GapPolicy) {
+    for t in tracks.iter_mut() {
+        t.gap_before = 0;
+        t.gap_after = 0;
+    }
+    if policy == GapPolicy::Skip {
+        return;
+    }
+
+    for i in 0..tracks.len() {
+        if !tracks[i].is_audio() {
+            continue;
+        }
+        let previous_audio = i > 0 && tracks[i - 1].is_audio();
+        let next_audio = i + 1 < tracks.len() && tracks[i + 1].is_audio();
+
+        let (mut pregap, postgap) = (tracks[i].pregap, tracks[i].postgap);
+        // The lead-out and lead-in between sessions are no silence to write
+        if tracks[i].starts_session(i.checked_sub(1).map(|p| &tracks[p])) {
+            pregap = 0;
+        }
+        if policy == GapPolicy::Append && previous_audio {
+            tracks[i - 1].gap_after += pregap;
+        } else {
+            tracks[i].gap_before += pregap;
+        }
+        if policy == GapPolicy::Append && next_audio {
+            tracks[i + 1].gap_before += postgap;
+        } else {
+            tracks[i].gap_after += postgap;
+        }
+    }
+}
+
+/// `tracks` with every audio track that has `INDEX 02` onwards split into a part per index
+///
+/// The pregap stays with the part of `INDEX 01` and the postgap goes with the last part, like
+/// the gaps of silence around the track.
+pub(crate) fn split_at_indexes(tracks: Vec<Track>) -> Vec<Track> {
+    let mut split = Vec::with_capacity(tracks.len());
+    for t in tracks {
+        if !t.is_audio() || t.indexes.is_empty() {
+            split.push(t);
+            continue;
+        }
+        let starts: Vec<u64> = std::iter::once(t.start_sector)
+            .chain(t.indexes.iter().copied())
+            .collect();
+        for (n, &start) in starts.iter().enumerate() {
+            let mut part = t.clone();
+            part.indexes.clear();
+            part.split_index = n as u32 + 1;
+            part.start_sector = start;
+            part.start = t.start + (start - t.start_sector) * t.sector_size();
+            part.stop_sector = match starts.get(n + 1) {
+                Some(next) => Some(next - 1),
+                None => Some(t.stop_sector()),
+            };
+            if n > 0 {
+                part.index0 = None;
+                part.pregap = 0;
+                part.gap_before = 0;
+            }
+            if n + 1 < starts.len() {
+                part.postgap = 0;
+                part.gap_after = 0;
+            }
+            split.push(part);
+        }
+    }
+    split
+}
+
+/// How the data tracks of a disc are written
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DataTracks {
+    /// Every data track to a numbered file of its own
+    #[default]
+    Separate,
+    /// All data tracks one after another into a single `.iso`
+    Merge,
+    /// Only the first data track, to a single `.iso`
+    First,
+}
+
+/// Tracks picked by number, e.g. parsed from `1,3-5`
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
+pub struct TrackSelection {
+    ranges: Vec<RangeInclusive<u32>>,
+}
+
+impl TrackSelection {
+    pub fn new(ranges: impl IntoIterator<Item = RangeInclusive<u32>>) -> Self {
+        TrackSelection {
+            ranges: ranges.into_iter().collect(),
+        }
+    }
+
+    pub fn contains(&self, number: u32) -> bool {
+        self.ranges.iter().any(|r| r.contains(&number))
+    }
+}
+
+impl FromStr for TrackSelection {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = || {
+            ErrorCode::InvalidArguments.error(format!(
+                "Invalid track selection {}, expected numbers and ranges like 1,3-5",
+                s
+            ))
+        };
+        let number = |n: &str| n.trim().parse::<u32>().map_err(|_| invalid());
+        let mut ranges = Vec::new();
+        for part in s.split(',') {
+            let range = match part.split_once('-') {
+                Some((first, last)) => number(first)?..=number(last)?,
+                None => number(part)?..=number(part)?,
+            };
+            if range.is_empty() || *range.start() == 0 {
+                return Err(invalid());
+            }
+            ranges.push(range);
+        }
+        Ok(TrackSelection { ranges })
+    }
+}
+
+impl Display for TrackSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let ranges: Vec<String> = self
+            .ranges
+            .iter()
+            .map(|r| match r.start() == r.end() {
+                true => r.start().to_string(),
+                false => format!("{}-{}", r.start(), r.end()),
+            })
+            .collect();
+        f.pad(&ranges.join(","))
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Unknown,
+    Audio,
+    Mode1_2352,
+    /// Cooked MODE1 sectors holding only the 2048 bytes of user data
+    Mode1_2048,
+    Mode2_2352,
+    /// MODE2 sectors without their sync and header, holding the 8 byte subheader and 2328
+    /// bytes after it
+    Mode2_2336,
+    /// Cooked Form 2 sectors holding only the 2324 bytes of user data, as on Video CDs
+    Mode2_2324,
+    /// Audio followed by 96 bytes of subcode graphics per sector, as on karaoke CD+G discs
+    Cdg,
+    /// CD-i tracks, laid out like `Mode2_2352` and `Mode2_2336`
+    Cdi2352,
+    Cdi2336,
+}
+
+impl Mode {
+    const UNKNOWN: &'static str = "UNKNOWN";
+    const AUDIO: &'static str = "AUDIO";
+    const MODE1_2352: &'static str = "MODE1/2352";
+    const MODE1_2048: &'static str = "MODE1/2048";
+    const MODE2_2352: &'static str = "MODE2/2352";
+    const MODE2_2336: &'static str = "MODE2/2336";
+    const MODE2_2324: &'static str = "MODE2/2324";
+    const CDG: &'static str = "CDG";
+    const CDI_2352: &'static str = "CDI/2352";
+    const CDI_2336: &'static str = "CDI/2336";
+
+    /// Bytes a sector of this mode takes up in an image
+    pub fn sector_size(&self) -> u64 {
+        match self {
+            Mode::Mode1_2048 => 2048,
+            Mode::Mode2_2324 => 2324,
+            Mode::Mode2_2336 | Mode::Cdi2336 => 2336,
+            Mode::Cdg => SECTOR_SIZE + SUBCODE_SIZE as u64,
+            _ => SECTOR_SIZE,
+        }
+    }
+
+    /// Mode of the track once its sectors are stored raw, like `MODE1/2048` becomes `MODE1/2352`
+    pub(crate) fn raw(&self) -> Mode {
+        match self {
+            Mode::Mode1_2048 => Mode::Mode1_2352,
+            Mode::Mode2_2324 | Mode::Mode2_2336 => Mode::Mode2_2352,
+            Mode::Cdi2336 => Mode::Cdi2352,
+            // The subcode of CDG tracks isn't part of their raw sectors
+            Mode::Cdg => Mode::Audio,
+            mode => *mode,
+        }
+    }
+
+    /// The same kind of track stored in sectors of `size` bytes, if there is one
+    pub(crate) fn with_sector_size(&self, size: u64) -> Option<Mode> {
+        match (self, size) {
+            (Mode::Mode1_2352 | Mode::Mode1_2048, 2048) => Some(Mode::Mode1_2048),
+            (Mode::Mode1_2352 | Mode::Mode1_2048, SECTOR_SIZE) => Some(Mode::Mode1_2352),
+            (Mode::Mode2_2324 | Mode::Mode2_2336, SECTOR_SIZE) => Some(Mode::Mode2_2352),
+            (Mode::Cdi2336, SECTOR_SIZE) => Some(Mode::Cdi2352),
+            (Mode::Mode2_2352 | Mode::Mode2_2336, 2336) => Some(Mode::Mode2_2336),
+            (Mode::Cdi2352 | Mode::Cdi2336, 2336) => Some(Mode::Cdi2336),
+            (Mode::Unknown | Mode::Cdg, _) => None,
+            (mode, size) if mode.sector_size() == size => Some(*mode),
+            _ => None,
+        }
+    }
+
+    /// Part of a raw sector that is stored in an image
+    pub(crate) fn stored_range(&self) -> Range<usize> {
+        match self {
+            Mode::Mode1_2048 => 16..2064,
+            // Form 2 user data follows the 8 byte subheader
+            Mode::Mode2_2324 => 24..2348,
+            // The subheader is stored along with the user data, only the sync and header aren't
+            Mode::Mode2_2336 | Mode::Cdi2336 => 16..2352,
+            _ => 0..SECTOR_SIZE as usize,
+        }
+    }
+}
+
+impl AsRef<str> for Mode {
+    fn as_ref(&self) -> &'static str {
+        match self {
+            Mode::Unknown => Mode::UNKNOWN,
+            Mode::Audio => Mode::AUDIO,
+            Mode::Mode1_2352 => Mode::MODE1_2352,
+            Mode::Mode1_2048 => Mode::MODE1_2048,
+            Mode::Mode2_2352 => Mode::MODE2_2352,
+            Mode::Mode2_2336 => Mode::MODE2_2336,
+            Mode::Mode2_2324 => Mode::MODE2_2324,
+            Mode::Cdg => Mode::CDG,
+            Mode::Cdi2352 => Mode::CDI_2352,
+            Mode::Cdi2336 => Mode::CDI_2336,
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(self.as_ref())
+    }
+}
+
+impl From<&str> for Mode {
+    fn from(s: &str) -> Self {
+        match s {
+            Mode::AUDIO => Mode::Audio,
+            Mode::MODE1_2352 => Mode::Mode1_2352,
+            Mode::MODE1_2048 => Mode::Mode1_2048,
+            Mode::MODE2_2336 => Mode::Mode2_2336,
+            Mode::MODE2_2324 => Mode::Mode2_2324,
+            Mode::CDG => Mode::Cdg,
+            Mode::CDI_2352 => Mode::Cdi2352,
+            Mode::CDI_2336 => Mode::Cdi2336,
+            Mode::MODE2_2352 => Mode::Mode2_2352,
+            _ => Mode::Unknown,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+enum Extension {
+    #[default]
+    Ugh,
+    Iso,
+    Cdr,
+    Wav,
+    Ogg,
+    Mp3,
+    Flac,
+    Mpg,
+}
+
+impl Extension {
+    const UGH: &'static str = "ugh";
+    const ISO: &'static str = "iso";
+    const CDR: &'static str = "cdr";
+    const WAV: &'static str = "wav";
+    const OGG: &'static str = "ogg";
+    const MP3: &'static str = "mp3";
+    const FLAC: &'static str = "flac";
+    const MPG: &'static str = "mpg";
+    // Raw copies of data tracks kept next to the converted ones
+    const BIN: &'static str = "bin";
+    // Subcode graphics of CDG tracks
+    const CDG: &'static str = "cdg";
+    // Subchannel of the other tracks of 2448 byte images
+    const SUB: &'static str = "sub";
+}
+
+impl AsRef<str> for Extension {
+    fn as_ref(&self) -> &str {
+        match self {
+            Extension::Ugh => Extension::UGH,
+            Extension::Iso => Extension::ISO,
+            Extension::Cdr => Extension::CDR,
+            Extension::Wav => Extension::WAV,
+            Extension::Ogg => Extension::OGG,
+            Extension::Mp3 => Extension::MP3,
+            Extension::Flac => Extension::FLAC,
+            Extension::Mpg => Extension::MPG,
+        }
+    }
+}
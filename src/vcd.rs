@@ -0,0 +1,73 @@
+//! Video CDs and Super Video CDs, whose MPEG tracks can be demuxed to `.mpg` files
+//!
+//! The MPEG program stream of a VCD track is stored in the 2324 bytes of MODE2 Form 2
+//! sectors, a pack starting each, with empty sectors in the margins around it. Demuxing
+//! keeps the packs and drops everything else, leaving a file players take as it is.
+
+use std::io;
+
+use crate::disc::{Disc, Retagged};
+use crate::log::info;
+use crate::sector::{data_mode, FORM2};
+use crate::track::{Mode, Track};
+use crate::{ConvertOptions, SECTOR_SIZE};
+
+/// Sectors from the start of a track searched for the first pack, past the front margin
+const CHECKED_SECTORS: u64 = 300;
+
+/// Where the user data of a Form 2 sector starts, after its sync, header and subheader
+const FORM2_DATA: usize = 24;
+
+/// Whether the raw `sector` holds part of an MPEG program stream, not an empty one
+pub(crate) fn in_stream(sector: &[u8]) -> bool {
+    let data = &sector[FORM2_DATA..];
+    // Packs, the end code and the system headers all have start codes of 0xb9 and above
+    data_mode(sector) == Some(2)
+        && sector[18] & FORM2 != 0
+        && data[..3] == [0, 0, 1]
+        && data[3] >= 0xb9
+}
+
+/// Whether the sectors at the start of the MODE2 `track` hold an MPEG program stream
+fn holds_stream(disc: &mut dyn Disc, track: &Track) -> io::Result<bool> {
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    let last = track
+        .stop_sector()
+        .min(track.start_sector() + CHECKED_SECTORS - 1);
+    for lba in track.start_sector()..=last {
+        disc.read_sector(lba, &mut sector)?;
+        if in_stream(&sector) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// `disc` with its MPEG tracks marked to be demuxed, if `ConvertOptions::demux_mpeg` is set
+pub(crate) fn identify(disc: Box<dyn Disc>, a: &ConvertOptions) -> io::Result<Box<dyn Disc>> {
+    if !a.demux_mpeg {
+        return Ok(disc);
+    }
+    let mut disc = disc;
+    let mut tracks = disc.tracks().to_vec();
+    let mut found = false;
+    for t in tracks
+        .iter_mut()
+        .filter(|t| matches!(t.mode, Mode::Mode2_2352 | Mode::Cdi2352))
+    {
+        t.mpeg = holds_stream(disc.as_mut(), t)?;
+        if t.mpeg && a.verbose {
+            info!("{}: MPEG program stream, demuxed to .mpg", t.number);
+        }
+        found |= t.mpeg;
+    }
+    if !found {
+        return Ok(disc);
+    }
+    let metadata = disc.metadata().clone();
+    Ok(Box::new(Retagged {
+        disc,
+        tracks,
+        metadata,
+    }))
+}
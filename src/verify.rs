@@ -0,0 +1,123 @@
+//! Checking raw data sectors as they are extracted, for corrupt dumps and misaligned images
+
+use std::fmt::Display;
+use std::io;
+
+use crate::error::ErrorCode;
+use crate::sector::{check_edc_ecc, find_sync, has_sync, header_lba};
+
+/// Sectors and runs of sectors listed in an error, the rest are only counted
+const LISTED: usize = 20;
+
+/// How the sectors read are out of step with where they should be in the image
+#[derive(Clone, Copy, PartialEq)]
+enum Drift {
+    /// The sync pattern starts this many bytes into the sector
+    Shifted(usize),
+    /// The header addresses a sector this many sectors away from the one read
+    Addressed(i64),
+    /// There is no sync pattern in the sector, yet it isn't empty either
+    NoSync,
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Drift::Shifted(bytes) => write!(f, "sync {} bytes into the sector", bytes),
+            Drift::Addressed(sectors) if *sectors > 0 => {
+                write!(f, "header {} sectors ahead", sectors)
+            }
+            Drift::Addressed(sectors) => write!(f, "header {} sectors behind", -sectors),
+            Drift::NoSync => f.write_str("no sync pattern"),
+        }
+    }
+}
+
+/// Results of checking the sectors of a track one after another
+pub(crate) struct SectorCheck {
+    track: u32,
+    /// Sectors in step that failed their EDC/ECC check
+    corrupt: Vec<u64>,
+    /// Runs of consecutive sectors out of step the same way, with their first and last LBA
+    drifts: Vec<(u64, u64, Drift)>,
+}
+
+impl SectorCheck {
+    pub(crate) fn new(track: u32) -> SectorCheck {
+        SectorCheck {
+            track,
+            corrupt: Vec::new(),
+            drifts: Vec::new(),
+        }
+    }
+
+    /// Checks the raw sector read for `lba`
+    pub(crate) fn check(&mut self, lba: u64, sector: &[u8]) {
+        let drift = match has_sync(sector) {
+            true => header_lba(sector)
+                .filter(|&addressed| addressed != lba)
+                .map(|addressed| Drift::Addressed(addressed as i64 - lba as i64)),
+            false => match find_sync(sector) {
+                Some(bytes) => Some(Drift::Shifted(bytes)),
+                // Gaps that aren't stored read as zeroes
+                None if sector.iter().all(|&b| b == 0) => return,
+                None => Some(Drift::NoSync),
+            },
+        };
+        let drift = match drift {
+            Some(drift) => drift,
+            None => {
+                if !check_edc_ecc(sector) {
+                    self.corrupt.push(lba);
+                }
+                return;
+            }
+        };
+        match self.drifts.last_mut() {
+            Some((_, last, previous)) if *last + 1 == lba && *previous == drift => *last = lba,
+            _ => self.drifts.push((lba, lba, drift)),
+        }
+    }
+
+    /// Error describing what was found, once every sector has been checked
+    pub(crate) fn finish(self) -> io::Result<()> {
+        let mut problems = Vec::new();
+        if !self.drifts.is_empty() {
+            let runs = self
+                .drifts
+                .iter()
+                .map(|(first, last, drift)| match first == last {
+                    true => format!("{} ({})", first, drift),
+                    false => format!("{}-{} ({})", first, last, drift),
+                });
+            problems.push(format!(
+                "misplaced sectors, at LBA {}",
+                list(runs, self.drifts.len())
+            ));
+        }
+        if !self.corrupt.is_empty() {
+            let lbas = self.corrupt.iter().map(u64::to_string);
+            problems.push(format!(
+                "sectors failing their EDC/ECC check, at LBA {}",
+                list(lbas, self.corrupt.len())
+            ));
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ErrorCode::CorruptSectors.error(format!(
+                "Track {} has {}",
+                self.track,
+                problems.join(", and ")
+            ))),
+        }
+    }
+}
+
+/// First `LISTED` of the `count` entries of `entries`, separated by commas
+fn list(entries: impl Iterator<Item = String>, count: usize) -> String {
+    let mut list = entries.take(LISTED).collect::<Vec<_>>().join(", ");
+    if count > LISTED {
+        list += &format!(" and {} more", count - LISTED);
+    }
+    list
+}
@@ -0,0 +1,104 @@
+//! Exports for WebAssembly, converting byte buffers handed over by JavaScript
+//!
+//! Everything goes through linear memory by pointer and length, so no bindings generator
+//! is needed: JavaScript copies the CUE sheet and the BIN into buffers of `rbchunk_alloc`,
+//! calls `rbchunk_convert_buffers` and reads the outputs back from the result.
+
+use std::io;
+use std::ptr;
+use std::slice;
+
+use crate::error::ErrorCode;
+use crate::{convert_in_memory_with, decode_sheet, ConvertOptions, TrackOutput};
+
+/// Flags of `rbchunk_convert_buffers`, one bit for each setting of `ConvertOptions`
+const TO_WAV: u32 = 1;
+const SWAP_AUDIO_BYTES: u32 = 1 << 1;
+const PSX_TRUNCATE: u32 = 1 << 2;
+const RAW: u32 = 1 << 3;
+const EXPORT_CUE: u32 = 1 << 4;
+
+/// Buffer of `len` zeroes for JavaScript to fill, freed with `rbchunk_free`
+#[no_mangle]
+pub extern "C" fn rbchunk_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// Frees a buffer of `rbchunk_alloc` or `rbchunk_convert_buffers`
+///
+/// # Safety
+///
+/// `ptr` and `len` must be those of a buffer returned by this library that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rbchunk_free(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+/// Converts the BIN `bin` laid out by the CUE sheet `cue` like `convert_in_memory`, the
+/// sheet in any encoding `decode_sheet` reads
+///
+/// Returns a buffer of `*result_len` bytes, of little endian `u32`s followed by bytes:
+/// 0 and the number of outputs, then the length and UTF-8 bytes of the name and the
+/// length and bytes of the data of every output. A failed conversion gives the number of
+/// its `ErrorCode` instead of 0, followed by the length and UTF-8 bytes of the message.
+///
+/// # Safety
+///
+/// `cue` and `bin` must hold `cue_len` and `bin_len` bytes, and `result_len` must be valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rbchunk_convert_buffers(
+    cue: *const u8,
+    cue_len: usize,
+    bin: *const u8,
+    bin_len: usize,
+    flags: u32,
+    result_len: *mut usize,
+) -> *mut u8 {
+    let cue = decode_sheet(slice::from_raw_parts(cue, cue_len));
+    let result = match convert(&cue, slice::from_raw_parts(bin, bin_len), flags) {
+        Ok(result) => result,
+        Err(e) => {
+            let mut result = Vec::new();
+            push_u32(&mut result, ErrorCode::of(&e).number() as u32);
+            push_bytes(&mut result, e.to_string().as_bytes());
+            result
+        }
+    };
+    *result_len = result.len();
+    Box::into_raw(result.into_boxed_slice()) as *mut u8
+}
+
+fn convert(cue: &str, bin: &[u8], flags: u32) -> io::Result<Vec<u8>> {
+    let options = ConvertOptions::builder()
+        .to_wav(flags & TO_WAV != 0)
+        .swap_audio_bytes(flags & SWAP_AUDIO_BYTES != 0)
+        .psx_truncate(flags & PSX_TRUNCATE != 0)
+        .raw(flags & RAW != 0)
+        .export_cue(flags & EXPORT_CUE != 0)
+        .build()?;
+    let outputs = convert_in_memory_with(options, cue, bin)?;
+
+    let mut result = Vec::new();
+    push_u32(&mut result, 0);
+    push_u32(&mut result, outputs.len() as u32);
+    for TrackOutput { filename, data } in &outputs {
+        // Lengths are `u32`s, like the pointers of 32 bit WebAssembly
+        if data.len() > u32::MAX as usize {
+            return Err(ErrorCode::Unsupported
+                .error(format!("{} is too large to be handed over", filename)));
+        }
+        push_bytes(&mut result, filename.as_bytes());
+        push_bytes(&mut result, data);
+    }
+    Ok(result)
+}
+
+fn push_u32(result: &mut Vec<u8>, value: u32) {
+    result.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_bytes(result: &mut Vec<u8>, bytes: &[u8]) {
+    push_u32(result, bytes.len() as u32);
+    result.extend_from_slice(bytes);
+}
@@ -0,0 +1,122 @@
+use std::io;
+
+use crate::audio::CD_RATE;
+use crate::error::ErrorCode;
+use crate::{ConvertOptions, SECTOR_SIZE};
+
+const WAV_FORMAT_HEADER_LENGTH: u32 = 24;
+const WAV_DATA_HEADER_LENGTH: u32 = 8;
+
+/// PCM layout the WAV header declares, CD audio unless overridden or resampled in
+/// `ConvertOptions`
+pub(crate) struct Params {
+    sample_rate: u32,
+    channels: u16,
+    bits_per_sample: u16,
+}
+
+impl Params {
+    pub(crate) fn from_args(a: &ConvertOptions) -> Params {
+        Params {
+            sample_rate: a.resample.or(a.wav_sample_rate).unwrap_or(CD_RATE),
+            channels: a.wav_channels.unwrap_or(2),
+            bits_per_sample: a.wav_bits_per_sample.unwrap_or(16),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        let leftover = SECTOR_SIZE % self.block_align().max(1) as u64;
+        let problem = if !(1..=384000).contains(&self.sample_rate) {
+            "sample rate must be between 1 and 384000 Hz"
+        } else if !(1..=8).contains(&self.channels) {
+            "channel count must be between 1 and 8"
+        } else if ![8, 16, 24, 32].contains(&self.bits_per_sample) {
+            "bit depth must be 8, 16, 24 or 32"
+        } else if leftover > 0 {
+            // The data isn't converted, so whole frames have to fit into every sector
+            "frames of this many channels and bits don't divide a 2352 byte sector"
+        } else {
+            return Ok(());
+        };
+        Err(ErrorCode::InvalidArguments.error(format!(
+            "Invalid WAV parameters {} Hz, {} channels, {} bits: {}",
+            self.sample_rate, self.channels, self.bits_per_sample, problem
+        )))
+    }
+
+    /// Bytes of a frame holding one sample of every channel
+    fn block_align(&self) -> u32 {
+        self.channels as u32 * self.bits_per_sample as u32 / 8
+    }
+}
+
+/// `LIST` chunk of the `INFO` entries the Vorbis comments `tags` have, empty when there's
+/// nothing to it but the track number
+pub(crate) fn info_chunk(tags: &[(String, String)]) -> Vec<u8> {
+    let entries: Vec<(&str, &String)> = tags
+        .iter()
+        .filter_map(|(name, value)| {
+            let id = match name.as_str() {
+                "TITLE" => "INAM",
+                "ARTIST" => "IART",
+                "ALBUM" => "IPRD",
+                "DATE" => "ICRD",
+                "TRACKNUMBER" => "ITRK",
+                "GENRE" => "IGNR",
+                "COMMENT" => "ICMT",
+                _ => return None,
+            };
+            Some((id, value))
+        })
+        .collect();
+    if entries.iter().all(|(id, _)| *id == "ITRK") {
+        return Vec::new();
+    }
+    let mut info = Vec::from(*b"INFO");
+    for (id, value) in entries {
+        // NUL terminated, and padded to an even length like every chunk
+        let mut text = Vec::from(value.as_bytes());
+        text.push(0);
+        let length = text.len() as u32;
+        if text.len() % 2 == 1 {
+            text.push(0);
+        }
+        info.extend_from_slice(id.as_bytes());
+        info.extend_from_slice(&length.to_le_bytes());
+        info.extend_from_slice(&text);
+    }
+    [
+        "LIST".as_bytes(),
+        (info.len() as u32).to_le_bytes().as_slice(),
+        &info,
+    ]
+    .concat()
+}
+
+/// Header for PCM data of the given length in bytes, with the `info` chunk before the data
+pub(crate) fn header(reallen: u32, info: &[u8], params: &Params) -> Vec<u8> {
+    let block_align = params.block_align();
+    // Constructing wav header in vector so that we can write it in a single write
+    [
+        // RIFF header
+        "RIFF".as_bytes(),
+        (reallen + WAV_DATA_HEADER_LENGTH + WAV_FORMAT_HEADER_LENGTH + 4 + info.len() as u32)
+            .to_le_bytes()
+            .as_slice(), // length of file starting from WAVE
+        "WAVE".as_bytes(),
+        // FORMAT HEADER
+        "fmt ".as_bytes(),
+        0x10_u32.to_le_bytes().as_slice(), // length of FORMAT header
+        0x1_u16.to_le_bytes().as_slice(),  // constant
+        params.channels.to_le_bytes().as_slice(), //channels
+        params.sample_rate.to_le_bytes().as_slice(), // sample rate
+        (params.sample_rate * block_align).to_le_bytes().as_slice(), // bytes per second
+        (block_align as u16).to_le_bytes().as_slice(), // bytes per sample
+        params.bits_per_sample.to_le_bytes().as_slice(), // bits per channel,
+        info,
+        //DATA header
+        "data".as_bytes(),
+        reallen.to_le_bytes().as_slice(),
+    ]
+    .concat()
+}